@@ -0,0 +1,145 @@
+use std::collections::BTreeMap;
+use std::fs;
+
+/// One labelled range of totals, inclusive at both ends.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Band {
+    pub low: i32,
+    pub high: i32,
+    pub label: String,
+}
+
+/// Labelled ranges of totals such as `1-5:miss,6-15:hit,16-20:crit`, sorted by range.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bands {
+    bands: Vec<Band>,
+}
+
+impl Bands {
+    /// Parse comma separated `LOW-HIGH:LABEL` entries (or `N:LABEL` for a single
+    /// total). Overlapping ranges are an error.
+    pub fn parse(text: &str) -> Result<Bands, String> {
+        let mut entries = Vec::new();
+        for entry in text.split(',') {
+            let (range, label) = entry
+                .split_once(':')
+                .ok_or_else(|| format!("Invalid band '{}': must be in format 'LOW-HIGH:LABEL'", entry.trim()))?;
+            entries.push((range, label));
+        }
+        Bands::from_entries(entries)
+    }
+
+    /// Read an effect table: a TOML file mapping ranges of totals to the effect of
+    /// rolling them, such as `"1-3" = "minor"` and `"4-7" = "moderate"`. The ranges
+    /// follow the same rules as [`Bands::parse`].
+    pub fn load_effect_table(path: &str) -> Result<Bands, String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("Cannot read effect table '{}': {}", path, err))?;
+        let effects: BTreeMap<String, String> =
+            toml::from_str(&text).map_err(|err| format!("Invalid effect table '{}': {}", path, err))?;
+        Bands::from_entries(effects.iter().map(|(range, effect)| (range.as_str(), effect.as_str())))
+    }
+
+    /// Build bands from `(range, label)` pairs, where a range is `LOW-HIGH` or `N`.
+    fn from_entries<'a>(entries: impl IntoIterator<Item = (&'a str, &'a str)>) -> Result<Bands, String> {
+        let mut bands = Vec::new();
+        for (range, label) in entries {
+            let range = range.trim();
+            let entry = format!("{}:{}", range, label.trim());
+            // Split at a '-' after the first character so a negative low end stays whole
+            let (low, high) = match range.char_indices().skip(1).find(|&(_, c)| c == '-') {
+                Some((index, _)) => (&range[..index], &range[index + 1..]),
+                None => (range, range),
+            };
+            let parse = |value: &str| {
+                value
+                    .trim()
+                    .parse::<i32>()
+                    .map_err(|_| format!("Invalid band '{}': '{}' is not a valid number", entry, value.trim()))
+            };
+            let (low, high) = (parse(low)?, parse(high)?);
+            if low > high {
+                return Err(format!("Invalid band '{}': range must run from low to high", entry));
+            }
+            bands.push(Band {
+                low,
+                high,
+                label: label.trim().to_string(),
+            });
+        }
+
+        bands.sort_by_key(|band| (band.low, band.high));
+        for pair in bands.windows(2) {
+            if pair[1].low <= pair[0].high {
+                return Err(format!(
+                    "Bands '{}' and '{}' overlap: {}-{} and {}-{}",
+                    pair[0].label, pair[1].label, pair[0].low, pair[0].high, pair[1].low, pair[1].high
+                ));
+            }
+        }
+        Ok(Bands { bands })
+    }
+
+    /// The ranges of totals between bands that no band covers.
+    pub fn gaps(&self) -> Vec<(i32, i32)> {
+        self.bands
+            .windows(2)
+            .filter(|pair| pair[1].low > pair[0].high + 1)
+            .map(|pair| (pair[0].high + 1, pair[1].low - 1))
+            .collect()
+    }
+
+    /// The label of the band containing `total`.
+    pub fn label_for(&self, total: i32) -> Option<&str> {
+        self.bands
+            .iter()
+            .find(|band| (band.low..=band.high).contains(&total))
+            .map(|band| band.label.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_band_assignment() {
+        let bands = Bands::parse("16-20:crit, 1-5:miss, 6-15:hit").unwrap();
+        assert_eq!(bands.label_for(1), Some("miss"));
+        assert_eq!(bands.label_for(5), Some("miss"));
+        assert_eq!(bands.label_for(6), Some("hit"));
+        assert_eq!(bands.label_for(15), Some("hit"));
+        assert_eq!(bands.label_for(20), Some("crit"));
+        assert_eq!(bands.label_for(21), None);
+        assert!(bands.gaps().is_empty());
+    }
+
+    #[test]
+    fn test_band_overlaps_and_gaps() {
+        assert!(Bands::parse("1-10:miss,10-20:hit").is_err());
+        assert!(Bands::parse("1-5").is_err());
+        assert!(Bands::parse("5-1:miss").is_err());
+
+        let bands = Bands::parse("-3--1:bad,0:even,4-6:good").unwrap();
+        assert_eq!(bands.label_for(-2), Some("bad"));
+        assert_eq!(bands.label_for(0), Some("even"));
+        assert_eq!(bands.gaps(), vec![(1, 3)]);
+    }
+
+    #[test]
+    fn test_effect_table_lookup() {
+        let path = std::env::temp_dir().join(format!("droll-effects-{}.toml", std::process::id()));
+        fs::write(&path, "\"1-3\" = \"minor\"\n\"4-7\" = \"moderate, bleeding\"\n\"8-99\" = \"severe\"\n").unwrap();
+        let effects = Bands::load_effect_table(path.to_str().unwrap()).unwrap();
+        assert_eq!(effects.label_for(1), Some("minor"));
+        assert_eq!(effects.label_for(3), Some("minor"));
+        assert_eq!(effects.label_for(4), Some("moderate, bleeding"));
+        assert_eq!(effects.label_for(7), Some("moderate, bleeding"));
+        assert_eq!(effects.label_for(12), Some("severe"));
+        assert_eq!(effects.label_for(0), None);
+
+        fs::write(&path, "\"1-5\" = \"minor\"\n\"5-7\" = \"moderate\"\n").unwrap();
+        assert!(Bands::load_effect_table(path.to_str().unwrap()).unwrap_err().contains("overlap"));
+        fs::remove_file(&path).unwrap();
+        assert!(Bands::load_effect_table(path.to_str().unwrap()).is_err());
+    }
+}
@@ -0,0 +1,77 @@
+use droll::Dice;
+use std::io::{self, BufRead};
+
+/// A dice specification read from a batch file, with the line it came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchLine {
+    pub line_number: usize,
+    pub spec: String,
+}
+
+/// Read one dice specification per line, skipping blank lines and '#' comments.
+pub fn read_spec_lines<R: BufRead>(reader: R) -> io::Result<Vec<BatchLine>> {
+    let mut lines = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let spec = line.trim();
+        if spec.is_empty() || spec.starts_with('#') {
+            continue;
+        }
+        lines.push(BatchLine {
+            line_number: index + 1,
+            spec: spec.to_string(),
+        });
+    }
+    Ok(lines)
+}
+
+/// The result of parsing a batch with invalid lines skipped rather than fatal.
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    /// Every valid specification with its parsed dice, in file order
+    pub parsed: Vec<(String, Dice)>,
+    /// Every invalid line with the reason it was skipped
+    pub failed: Vec<(BatchLine, String)>,
+}
+
+/// Parse every line of a batch, collecting the failures instead of stopping at the first.
+pub fn parse_batch(lines: &[BatchLine], parse: impl Fn(&str) -> Result<Dice, String>) -> BatchReport {
+    let mut report = BatchReport::default();
+    for line in lines {
+        match parse(&line.spec) {
+            Ok(dice) => report.parsed.push((line.spec.clone(), dice)),
+            Err(err) => report.failed.push((line.clone(), err)),
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_spec_lines() {
+        let input = "1d6\n\n# a comment\n  2d8+1  \n";
+        let lines = read_spec_lines(input.as_bytes()).unwrap();
+        assert_eq!(
+            lines,
+            vec![
+                BatchLine { line_number: 1, spec: "1d6".to_string() },
+                BatchLine { line_number: 4, spec: "2d8+1".to_string() },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_batch_skips_invalid_lines() {
+        let input = "1d6\n2x6\n3d8+2\n0d4\n1d20\n";
+        let lines = read_spec_lines(input.as_bytes()).unwrap();
+        let report = parse_batch(&lines, |spec| Dice::parse(spec).map_err(|err| err.to_string()));
+
+        let valid: Vec<&str> = report.parsed.iter().map(|(spec, _)| spec.as_str()).collect();
+        assert_eq!(valid, vec!["1d6", "3d8+2", "1d20"]);
+        let failed_lines: Vec<usize> = report.failed.iter().map(|(line, _)| line.line_number).collect();
+        assert_eq!(failed_lines, vec![2, 4]);
+    }
+}
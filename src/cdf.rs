@@ -0,0 +1,76 @@
+use crate::Dice;
+
+/// The cumulative distribution of a roll's total, computed once so that any number of
+/// threshold questions can be answered without working out the distribution again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cdf {
+    /// Every possible total, in ascending order
+    totals: Vec<i32>,
+    /// The probability that the total is at most the total at the same index
+    at_most: Vec<f64>,
+}
+
+impl Cdf {
+    /// The cumulative distribution of the dice.
+    pub fn new(dice: &Dice) -> Self {
+        let mut running = 0.0;
+        let (totals, at_most) = dice
+            .distribution_map()
+            .into_iter()
+            .map(|(total, probability)| {
+                running += probability;
+                (total, running)
+            })
+            .unzip();
+        Cdf { totals, at_most }
+    }
+
+    /// Every possible total paired with the probability that the total is at most it,
+    /// in ascending order of total.
+    pub fn points(&self) -> impl Iterator<Item = (i32, f64)> + '_ {
+        self.totals.iter().copied().zip(self.at_most.iter().copied())
+    }
+
+    /// The probability (0.0 to 1.0) that the total is at most `total`.
+    pub fn at_most(&self, total: i32) -> f64 {
+        // The number of possible totals no higher than `total`
+        match self.totals.partition_point(|&possible| possible <= total) {
+            0 => 0.0,
+            // The last entry is clamped so rounding never reports more than certainty
+            below if below == self.totals.len() => 1.0,
+            below => self.at_most[below - 1],
+        }
+    }
+
+    /// The probability (0.0 to 1.0) that the total is at least `total`.
+    pub fn at_least(&self, total: i32) -> f64 {
+        match total.checked_sub(1) {
+            Some(below) => 1.0 - self.at_most(below),
+            None => 1.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cached_queries_match_distribution() {
+        let dice = Dice::parse("3d6").unwrap();
+        let cdf = dice.cdf();
+        let distribution = dice.distribution_map();
+        for threshold in 0..=20 {
+            let expected: f64 = distribution.range(threshold..).map(|(_, probability)| probability).sum();
+            assert!((cdf.at_least(threshold) - expected).abs() < 1e-12);
+            // Asking again answers from the same cache
+            assert_eq!(cdf.at_least(threshold), cdf.at_least(threshold));
+        }
+        assert_eq!(cdf.at_least(3), 1.0);
+        assert_eq!(cdf.at_least(19), 0.0);
+        assert_eq!(cdf.at_most(18), 1.0);
+        assert!((cdf.at_least(18) - 1.0 / 216.0).abs() < 1e-12);
+        assert_eq!(cdf.points().count(), 16);
+        assert_eq!(cdf.at_least(i32::MIN), 1.0);
+    }
+}
@@ -0,0 +1,139 @@
+use droll::{DiceError, DieRegistry};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Settings read from the optional `config.toml`.
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    /// Custom dice by name, each listing the values on its faces (e.g. `fate = [-1, 0, 1]`)
+    #[serde(default)]
+    pub dice: BTreeMap<String, Vec<i32>>,
+    /// Named rolls that stand for a full specification (e.g. `attack = "1d20+5"`)
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+}
+
+/// The commented config file written by `--export-config`.
+pub const TEMPLATE: &str = r#"# droll configuration
+
+# Named rolls: use the name in place of a dice specification,
+# e.g. `droll attack` or `droll 3#attack`.
+[aliases]
+attack = "1d20+5"
+fireball = "8d6"
+stats = "4d6r1"
+
+# Custom dice: list the value on each face, repeating a value to make it
+# more likely, then roll them by name, e.g. `droll 4dfate`.
+[dice]
+fate = [-1, 0, 1]
+avg6 = [2, 3, 3, 4, 4, 5]
+"#;
+
+impl Config {
+    /// Parse the contents of a config file.
+    pub fn parse(text: &str) -> Result<Config, String> {
+        toml::from_str(text).map_err(|err| err.to_string())
+    }
+
+    /// Read and parse the config file at `path`.
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let text = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        Config::parse(&text)
+    }
+
+    /// A registry holding every custom die in the config.
+    pub fn registry(&self) -> Result<DieRegistry, DiceError> {
+        let mut registry = DieRegistry::new();
+        for (name, faces) in &self.dice {
+            registry.insert(name, faces.clone())?;
+        }
+        Ok(registry)
+    }
+}
+
+/// Write [`TEMPLATE`] to `path`, creating its directory. An existing file is only
+/// replaced when `force` is set.
+pub fn write_template(path: &Path, force: bool) -> io::Result<()> {
+    if path.exists() && !force {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "the file already exists (use --force to overwrite it)",
+        ));
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, TEMPLATE)
+}
+
+/// Where the config file lives when `--config` is not given:
+/// `$XDG_CONFIG_HOME/droll/config.toml`, falling back to `~/.config/droll/config.toml`.
+pub fn default_path() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("droll").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use droll::Dice;
+
+    #[test]
+    fn test_config_dice_resolve_in_specs() {
+        let config = Config::parse("[dice]\nfate = [-1, 0, 1]\navg6 = [2, 3, 3, 4, 4, 5]\n").unwrap();
+        let registry = config.registry().unwrap();
+
+        let dice = Dice::parse_with("4dfate+1", &registry).unwrap();
+        assert_eq!((dice.count, dice.sides, dice.modifier), (4, 3, 1));
+        let (totals, _) = dice.roll_distribution();
+        assert_eq!(totals, (-3..=5).collect::<Vec<i32>>());
+
+        let dice = Dice::parse_with("2davg6", &registry).unwrap();
+        assert_eq!(dice.custom_faces, Some(vec![2, 3, 3, 4, 4, 5]));
+    }
+
+    #[test]
+    fn test_template_is_a_valid_config() {
+        let dir = std::env::temp_dir().join(format!("droll-config-{}", std::process::id()));
+        let path = dir.join("config.toml");
+        write_template(&path, false).unwrap();
+
+        let config = Config::load(&path).unwrap();
+        let registry = config.registry().unwrap();
+        assert!(registry.get("fate").is_some());
+        for spec in config.aliases.values() {
+            assert!(Dice::parse_with(spec, &registry).is_ok(), "{}", spec);
+        }
+
+        // An existing file is only replaced with force
+        fs::write(&path, "[dice]\n").unwrap();
+        assert!(write_template(&path, false).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[dice]\n");
+        write_template(&path, true).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), TEMPLATE);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_unknown_custom_die_is_an_error() {
+        let registry = Config::parse("[dice]\nfate = [-1, 0, 1]\n").unwrap().registry().unwrap();
+        let err = Dice::parse_with("4dfudge", &registry).unwrap_err();
+        assert_eq!(
+            err,
+            DiceError::UnknownDie {
+                spec: "4dfudge".to_string(),
+                name: "fudge".to_string(),
+            }
+        );
+        assert!(Config::parse("[dice]\nfate = []\n").unwrap().registry().is_err());
+        assert!(Config::parse("[dice]\nfate = \"-1,0,1\"\n").is_err());
+    }
+}
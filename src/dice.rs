@@ -0,0 +1,3956 @@
+use crate::{Cdf, DiceError, DiceRollError, DiceSampler, DieRegistry};
+use rand::Rng;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// A dice expression such as `2d6+3`: roll `count` dice with `sides` faces each and add `modifier`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dice {
+    pub sides: u8,
+    pub count: u8,
+    pub modifier: i32,
+    /// When set, the roll counts the dice showing at least this value instead of summing them.
+    pub success_target: Option<i32>,
+    /// How many successes each die meeting the success target counts as, so that
+    /// in a weighted pool like `2d6>=5 + 1d10>=8*2` a success on the d10 counts twice.
+    pub success_weight: i32,
+    /// A face that counts as two successes rather than one (`10d10>=7^10`), as with
+    /// the tens of an Exalted pool.
+    pub double_face: Option<i32>,
+    /// Faces that are rerolled until the die shows a face outside this set.
+    pub reroll: BTreeSet<i32>,
+    /// Whether a die showing its highest face (or lowest, see `explode_face`) is rolled
+    /// again as an extra die.
+    pub explode: bool,
+    /// Which face makes an exploding die roll again.
+    pub explode_face: ExplodeFace,
+    /// Which dice of the pool may explode.
+    pub explode_which: ExplodeWhich,
+    /// Whether each d100 is rolled as a pair of percentile d10s (`d%`, see [`percentile_value`]).
+    pub percentile: bool,
+    /// The values printed on a custom die (see [`DieRegistry`]), each face equally
+    /// likely, or `None` for a standard die numbered 1 to `sides`.
+    pub custom_faces: Option<Vec<i32>>,
+    /// The name the custom die was registered under, used to write the dice back as notation.
+    pub custom_name: Option<String>,
+    /// Further terms of a mixed pool such as `2d6>=5 + 1d10>=8`, each rolled with
+    /// its own sides, target and modifier and added to the result.
+    pub extra_terms: Vec<Dice>,
+    /// Which dice count towards the total when only some of them are kept (`4d6kh3`, `4d6dl1`).
+    pub keep: Option<Keep>,
+    /// Which of several equal dice is kept when they tie for the last kept place.
+    pub keep_tie: KeepTie,
+    /// Whether keep notation chooses among the dice before or after they explode.
+    pub keep_order: KeepOrder,
+    /// The roll that decides the number of sides for dice like `1d(1d6)`, in which case
+    /// `sides` is the largest die it can make.
+    pub sides_roll: Option<Box<Dice>>,
+    /// The lowest result a single die can show (`4d6min3`); lower faces are raised to it.
+    pub die_floor: Option<i32>,
+    /// Whether the die floor raises every die or only the kept ones.
+    pub floor_scope: FloorScope,
+    /// How the kept dice are combined into the result.
+    pub reduce: Reduce,
+    /// The most extra dice a single exploding die can add (see [`MAX_EXPLOSIONS`]).
+    pub explosion_limit: u32,
+    /// The most times a single die is rerolled before it keeps its face (see [`MAX_REROLLS`]).
+    pub reroll_limit: u32,
+    /// Whether the dice of a term are drawn like cards from a deck of the die's faces,
+    /// so that no face shows on more than one die.
+    pub no_replacement: bool,
+}
+
+/// How many of the dice rolled count towards the total. Drop notation is stored as
+/// the matching keep, so `4d6dl1` keeps the highest 3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keep {
+    /// Keep this many of the highest dice
+    Highest(u8),
+    /// Keep this many of the lowest dice
+    Lowest(u8),
+}
+
+impl Keep {
+    /// The number of dice kept. Parsing rejects notation that keeps no dice, and a
+    /// keep of 0 built by hand still keeps one die rather than leaving no result.
+    fn kept(self) -> usize {
+        match self {
+            Keep::Highest(count) | Keep::Lowest(count) => usize::from(count.max(1)),
+        }
+    }
+}
+
+/// Which die is marked kept when equal dice tie for the last kept place. The total
+/// is the same either way; only the detailed roll differs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeepTie {
+    /// Keep the die rolled first (the default)
+    #[default]
+    First,
+    /// Keep the die rolled last
+    Last,
+}
+
+/// When keep notation chooses its dice in a pool that also explodes, such as `4d6!kh3`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeepOrder {
+    /// Every die, including those added by explosions, competes for a kept place (the default)
+    #[default]
+    AfterExplode,
+    /// The dice are kept by the face they first show, and each kept die brings every
+    /// die its explosions add
+    BeforeExplode,
+}
+
+/// Which dice a die floor (`4d6kh3min3`) raises. Raising a face never changes its
+/// place among the others, so the same dice are kept and the totals are the same
+/// either way; only the faces shown for dropped dice differ.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FloorScope {
+    /// Every die is raised to the floor as it is rolled (the default)
+    #[default]
+    AllDice,
+    /// Only the kept dice are raised, and dropped dice show their natural faces
+    KeptDice,
+}
+
+/// The face that makes a die explode, for systems where rolling low is good.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExplodeFace {
+    /// Explode on the highest face (the default)
+    #[default]
+    Highest,
+    /// Explode on the lowest face, as in roll-under systems
+    Lowest,
+}
+
+/// Which dice of a pool may explode, going by the faces they first show.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExplodeWhich {
+    /// Every die (the default)
+    #[default]
+    All,
+    /// Only the die showing the highest face, the first of them on a tie
+    Highest,
+    /// Only the die showing the lowest face, the first of them on a tie
+    Lowest,
+}
+
+/// A single die from a detailed roll.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DieRoll {
+    /// The face the die finally showed
+    pub face: i32,
+    /// What the die added to the total: its face, or 1/0 for a success/failure in a success pool
+    pub score: i32,
+    /// Whether the die was added to the roll by an explosion
+    pub from_explosion: bool,
+    /// Whether the die counts as a success in a success pool
+    pub success: bool,
+    /// Whether the die is a success showing the highest face of its die, a critical
+    /// success; every critical also counts as a success
+    pub critical: bool,
+    /// Whether the die counts towards the total, rather than being dropped by keep notation
+    pub kept: bool,
+    /// How many times `r` notation rerolled the die before it showed its face
+    pub rerolls: u32,
+    /// Which term of a mixed pool rolled the die: 0 for the first, 1 for the first of
+    /// [`Dice::extra_terms`], and so on
+    pub term: usize,
+}
+
+/// The outcome of a roll with every individual die kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RollResult {
+    pub dice: Vec<DieRoll>,
+    pub modifier: i32,
+    pub total: i32,
+    /// The number of sides rolled first for dice like `1d(1d6)`
+    pub rolled_sides: Option<u8>,
+}
+
+impl RollResult {
+    /// The highest face shown by any die in the pool, including dice added by
+    /// explosions. The modifier is not applied.
+    pub fn highest_face(&self) -> Option<i32> {
+        self.dice.iter().map(|die| die.face).max()
+    }
+
+    /// The lowest face shown by any die in the pool, including dice added by
+    /// explosions. The modifier is not applied.
+    pub fn lowest_face(&self) -> Option<i32> {
+        self.dice.iter().map(|die| die.face).min()
+    }
+
+    /// The gap between the highest and lowest faces in the pool, including dice added by
+    /// explosions, or `None` for a pool without dice.
+    pub fn spread(&self) -> Option<i32> {
+        Some(self.highest_face()? - self.lowest_face()?)
+    }
+
+    /// The total of the dice alone, before the modifier is applied.
+    pub fn raw_total(&self) -> i32 {
+        self.total - self.modifier
+    }
+
+    /// The number of dice in the pool that are critical successes, showing the highest
+    /// face of their die. These are counted among the successes as well.
+    pub fn criticals(&self) -> usize {
+        self.dice.iter().filter(|die| die.critical).count()
+    }
+
+    /// The number of rerolls made by `r` notation over every die in the pool.
+    pub fn rerolls(&self) -> u32 {
+        self.dice.iter().map(|die| die.rerolls).sum()
+    }
+
+    /// The sum of the faces of every die in the pool. For a success pool this is the
+    /// sum the dice would have made without a target; the modifier is not applied.
+    pub fn face_sum(&self) -> i32 {
+        self.dice.iter().map(|die| die.face).sum()
+    }
+}
+
+/// How the kept dice of a term are combined into its result, before the modifier
+/// is added. The terms of a mixed pool are always added together.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Reduce {
+    /// Add the dice
+    #[default]
+    Sum,
+    /// Multiply the dice, saturating at the limits of an `i32`
+    Product,
+    /// Take the highest die
+    Max,
+    /// Take the lowest die
+    Min,
+}
+
+impl Reduce {
+    /// Combine the result so far with one more die.
+    fn combine(self, left: i32, right: i32) -> i32 {
+        match self {
+            Reduce::Sum => left.saturating_add(right),
+            Reduce::Product => left.saturating_mul(right),
+            Reduce::Max => left.max(right),
+            Reduce::Min => left.min(right),
+        }
+    }
+
+    /// Combine the result so far with one more die, or `None` if it does not fit in an `i32`.
+    fn checked_combine(self, left: i32, right: i32) -> Option<i32> {
+        match self {
+            Reduce::Sum => left.checked_add(right),
+            Reduce::Product => left.checked_mul(right),
+            Reduce::Max | Reduce::Min => Some(self.combine(left, right)),
+        }
+    }
+
+    /// Combine every score, or 0 when there are none.
+    fn apply(self, scores: impl IntoIterator<Item = i32>) -> i32 {
+        scores.into_iter().reduce(|left, right| self.combine(left, right)).unwrap_or(0)
+    }
+
+    /// Combine every score like [`Reduce::apply`], or `None` if the result does not fit in an `i32`.
+    fn checked_apply(self, scores: impl IntoIterator<Item = i32>) -> Option<i32> {
+        let mut scores = scores.into_iter();
+        let first = scores.next().unwrap_or(0);
+        scores.try_fold(first, |left, right| self.checked_combine(left, right))
+    }
+}
+
+/// How a critical hit changes a damage roll.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CritRule {
+    /// Roll twice as many dice, adding the modifier once (`2d6+3` rolls as `4d6+3`)
+    DoubleDice,
+    /// Roll normally and double the whole total, modifier included
+    DoubleTotal,
+    /// Add the highest total the dice can show, without the modifier, to a normal
+    /// roll (`2d6+3` is 12 plus a roll of `2d6+3`)
+    MaxPlusRoll,
+}
+
+/// Which notation a strict parse rejects, for [`Dice::parse_with_strictness`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Strictness {
+    /// Reject notation that is easy to get wrong with [`DiceError::AmbiguousNotation`],
+    /// as [`Dice::parse_strict_with`] does
+    pub notation: bool,
+    /// Reject modifiers that are not written as a single signed number after the rest
+    /// of the notation (`2d6+3-1`, `2d6+03`, `2d6+3>=5`) with [`DiceError::AmbiguousModifier`]
+    pub modifier_sign: bool,
+}
+
+/// A pattern of faces a pool can show, for [`Dice::pattern_probability`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FacePattern {
+    /// Every die shows its highest face
+    AllMax,
+    /// Every die shows its lowest face
+    AllMin,
+    /// Every die shows the same face
+    AllSame,
+    /// No two dice show the same face
+    AllDifferent,
+    /// The dice show a run of consecutive faces, in any order (`3d6` showing 4, 2, 3)
+    Straight,
+}
+
+#[cfg(test)]
+thread_local! {
+    /// How many term distributions this thread has worked out, to check they are reused
+    static TERM_DISTRIBUTIONS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// The statistics of the distribution of totals, from [`Dice::distribution_stats`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DistributionStats {
+    /// The mean total, as [`Dice::expected_value`] gives
+    pub mean: f64,
+    /// The variance of the total, as [`Dice::variance`] gives
+    pub variance: f64,
+    /// The most likely total, the lowest of them when several are equally likely
+    pub mode: i32,
+    /// The lowest total, as [`Dice::min_roll`] gives
+    pub min: i32,
+    /// The highest total, as [`Dice::max_roll`] gives
+    pub max: i32,
+}
+
+/// Combine a tens d10 (showing 00 to 90, given here as 0 to 9) and a units d10
+/// (0 to 9) into a percentile result from 1 to 100.
+///
+/// This follows the common convention that 00 and 0 together read as 100 rather
+/// than 0, while 00 with any other units die is just the units (00 and 7 is 7).
+pub fn percentile_value(tens: u8, units: u8) -> u8 {
+    match (tens, units) {
+        (0, 0) => 100,
+        _ => tens * 10 + units,
+    }
+}
+
+/// The most extra dice a single exploding die adds to a roll unless
+/// [`Dice::set_explosion_limit`] says otherwise.
+pub const MAX_EXPLOSIONS: u32 = 100;
+
+/// The most times a single die is rerolled unless [`Dice::set_reroll_limit`] says
+/// otherwise, after which it keeps whatever it shows.
+pub const MAX_REROLLS: u32 = 100;
+
+/// Explosion chains less likely than this are left out of distributions.
+const EXPLOSION_CUTOFF: f64 = 1e-12;
+
+/// The faces of the built-in Fate (Fudge) die, `dF`.
+const FUDGE_FACES: [i32; 3] = [-1, 0, 1];
+
+/// Split a string into its leading run of ASCII digits and the remainder.
+fn split_digits(input: &str) -> (&str, &str) {
+    let end = input.find(|c: char| !c.is_ascii_digit()).unwrap_or(input.len());
+    input.split_at(end)
+}
+
+/// Parse the number at the start of `input`, returning it with the rest of the input.
+fn parse_number<'a>(spec: &str, field: &'static str, input: &'a str) -> Result<(i32, &'a str), DiceError> {
+    let (digits, rest) = split_digits(input);
+    let value = digits.parse::<i32>().map_err(|_| DiceError::InvalidNumber {
+        spec: spec.to_string(),
+        field,
+        value: if digits.is_empty() { input } else { digits }.to_string(),
+    })?;
+    Ok((value, rest))
+}
+
+/// Split a specification into the terms of a mixed pool at each `+` that is
+/// followed by another `NdS` term (`2d6>=5 + 1d10>=8`); other `+` signs are modifiers.
+fn split_terms(spec: &str) -> Vec<&str> {
+    let mut terms = Vec::new();
+    let mut start = 0;
+    let mut depth = 0;
+    for (index, c) in spec.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        // A '+' inside parentheses belongs to the sides roll of a term like 1d(1d4+1d6)
+        if c != '+' || depth > 0 {
+            continue;
+        }
+        let (count, after) = split_digits(spec[index + 1..].trim_start());
+        if !count.is_empty() && after.starts_with(['d', 'D']) {
+            terms.push(&spec[start..index]);
+            start = index + 1;
+        }
+    }
+    terms.push(&spec[start..]);
+    terms
+}
+
+/// Split the text after an opening '(' at its matching ')' into the text inside and
+/// the text after, or `None` when the parenthesis is never closed.
+fn split_parenthesized(input: &str) -> Option<(&str, &str)> {
+    let mut depth = 0;
+    for (index, c) in input.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return Some((&input[..index], &input[index + 1..])),
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Recognize keep and drop notation (`kh`, `kl`, `k`, `dh`, `dl`), returning whether
+/// it is about the highest dice, whether it drops rather than keeps them, and the rest.
+fn strip_keep(input: &str) -> Option<(&'static str, bool, bool, &str)> {
+    [("kh", true, false), ("kl", false, false), ("dh", true, true), ("dl", false, true), ("k", true, false)]
+        .into_iter()
+        .find_map(|(prefix, highest, drop)| input.strip_prefix(prefix).map(|after| (prefix, highest, drop, after)))
+}
+
+/// Add one distribution to another: the probability of every sum of a total from each.
+fn convolve(left: &BTreeMap<i32, f64>, right: &BTreeMap<i32, f64>) -> BTreeMap<i32, f64> {
+    let mut totals = BTreeMap::new();
+    for (&total, &probability) in left {
+        for (&score, &other_probability) in right {
+            *totals.entry(total.saturating_add(score)).or_insert(0.0) += probability * other_probability;
+        }
+    }
+    totals
+}
+
+/// The chance that a standard normal variable is above `x`, from the complementary
+/// error function approximation of Numerical Recipes, whose relative error stays
+/// below 1.2e-7 even far out in the tail.
+fn normal_upper_tail(x: f64) -> f64 {
+    let z = x.abs() / std::f64::consts::SQRT_2;
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let polynomial = [
+        -1.265_512_23,
+        1.000_023_68,
+        0.374_091_96,
+        0.096_784_18,
+        -0.186_288_06,
+        0.278_868_07,
+        -1.135_203_98,
+        1.488_515_87,
+        -0.822_152_23,
+        0.170_872_77,
+    ]
+    .iter()
+    .rev()
+    .fold(0.0, |sum, &coefficient| sum * t + coefficient);
+    let erfc = t * (-z * z + polynomial).exp();
+    if x >= 0.0 { erfc / 2.0 } else { 1.0 - erfc / 2.0 }
+}
+
+/// Parse the faces listed after an `r`: a single face (`r1`), a range (`r1-2`)
+/// or a braced list of faces and ranges (`r{1,3}`, `r{1-2,6}`). Ranges are cut down
+/// to the `shown` faces, from the lowest to the highest the die can show, so that
+/// `r1-2000000000` does not list two billion faces the die never shows.
+fn parse_reroll<'a>(spec: &str, input: &'a str, shown: (i32, i32)) -> Result<(BTreeSet<i32>, &'a str), DiceError> {
+    let (items, rest) = match input.strip_prefix('{') {
+        Some(braced) => {
+            let (list, rest) = braced.split_once('}').ok_or(DiceError::InvalidReroll {
+                spec: spec.to_string(),
+                reason: "missing closing '}'",
+            })?;
+            (list.split(',').collect::<Vec<&str>>(), rest)
+        }
+        None => {
+            // Without braces the item runs up to the end of the digits, including a range
+            let (low, after_low) = split_digits(input);
+            let item_len = match after_low.strip_prefix('-') {
+                Some(after_dash) if after_dash.starts_with(|c: char| c.is_ascii_digit()) => {
+                    low.len() + 1 + split_digits(after_dash).0.len()
+                }
+                _ => low.len(),
+            };
+            let (item, rest) = input.split_at(item_len);
+            (vec![if item.is_empty() { input } else { item }], rest)
+        }
+    };
+
+    let mut faces = BTreeSet::new();
+    for item in items {
+        let (low, high) = match item.split_once('-') {
+            Some((low, high)) => (low, high),
+            None => (item, item),
+        };
+        let parse_face = |value: &str| {
+            value.trim().parse::<i32>().map_err(|_| DiceError::InvalidNumber {
+                spec: spec.to_string(),
+                field: "reroll",
+                value: item.to_string(),
+            })
+        };
+        let (low, high) = (parse_face(low)?, parse_face(high)?);
+        if low > high {
+            return Err(DiceError::InvalidReroll {
+                spec: spec.to_string(),
+                reason: "range must run from low to high",
+            });
+        }
+        faces.extend(low.max(shown.0)..=high.min(shown.1));
+    }
+    Ok((faces, rest))
+}
+
+impl Dice {
+    /// Plain dice of `count` dice with `sides` sides and a modifier, as `parse` would
+    /// make from `2d6+3`, without writing out a specification. No dice or no sides
+    /// is an error, as it is when parsing.
+    pub fn new(count: u8, sides: u8, modifier: i32) -> Result<Self, DiceError> {
+        let spec = || match modifier {
+            0 => format!("{}d{}", count, sides),
+            _ => format!("{}d{}{:+}", count, sides, modifier),
+        };
+        if count == 0 {
+            return Err(DiceError::ZeroCount { spec: spec() });
+        }
+        if sides == 0 {
+            return Err(DiceError::ZeroSides { spec: spec() });
+        }
+        Ok(Dice {
+            sides,
+            count,
+            modifier,
+            success_target: None,
+            success_weight: 1,
+            double_face: None,
+            reroll: BTreeSet::new(),
+            explode: false,
+            percentile: false,
+            custom_faces: None,
+            custom_name: None,
+            extra_terms: Vec::new(),
+            keep: None,
+            keep_tie: KeepTie::default(),
+            keep_order: KeepOrder::default(),
+            explode_face: ExplodeFace::default(),
+            explode_which: ExplodeWhich::default(),
+            sides_roll: None,
+            die_floor: None,
+            floor_scope: FloorScope::default(),
+            reduce: Reduce::default(),
+            explosion_limit: MAX_EXPLOSIONS,
+            reroll_limit: MAX_REROLLS,
+            no_replacement: false,
+        })
+    }
+
+    /// Parse a dice specification such as `2d6`, `2d6+3`, `1d20-1`, `5d10>=8` or `4d6r1-2`.
+    ///
+    /// After `NdS` the specification may carry a `+M`/`-M` modifier, a `>=T`
+    /// success target, which turns the roll into a count of dice showing `T` or more,
+    /// with a `*W` weight making each success count `W` times (`2d10>=8*2`) and a
+    /// `^F` making a die showing `F` count as two successes (`10d10>=7^10`),
+    /// an `r` reroll of a face, range or braced list (`r1`, `r1-2`, `r{1,3}`), and
+    /// a `!` to explode dice that show their highest face (`6d10>=8!`), or `!l` their
+    /// lowest (`2d6!l`), and keep or
+    /// drop notation for the highest or lowest N dice (`4d6kh3`, `2d20kl1`, `4d6dl1`,
+    /// where a missing N means 1), and a `minN` floor raising lower dice to N
+    /// (`4d6min3`). Percentile dice are written `d%` and roll 1 to 100 from a tens and a units d10,
+    /// and Fate (Fudge) dice are written `dF` and show -1, 0 or 1.
+    /// A `-` straight after a reroll face reads as a range, so write `4d6-2r1` or
+    /// `4d6r{1}-2` to combine a reroll with a negative modifier.
+    pub fn parse(spec: &str) -> Result<Self, DiceError> {
+        Self::parse_with(spec, &DieRegistry::new())
+    }
+
+    /// Parse every specification with [`Dice::parse`], giving either all of the dice or
+    /// every specification that failed with its error, so that all of the problems in a
+    /// batch can be reported at once.
+    pub fn try_parse_all(specs: &[String]) -> Result<Vec<Dice>, Vec<(String, DiceError)>> {
+        let mut parsed = Vec::new();
+        let mut failed = Vec::new();
+        for spec in specs {
+            match Dice::parse(spec) {
+                Ok(dice) => parsed.push(dice),
+                Err(err) => failed.push((spec.clone(), err)),
+            }
+        }
+        if failed.is_empty() { Ok(parsed) } else { Err(failed) }
+    }
+
+    /// Parse a dice specification like [`Dice::parse`], also accepting the names of
+    /// custom dice from the registry in place of the sides (`4dfate`).
+    ///
+    /// Terms joined by `+` form a mixed pool (`2d6>=5 + 1d10>=8`): a `+` followed by
+    /// another `NdS` starts a new term rather than a modifier.
+    pub fn parse_with(spec: &str, registry: &DieRegistry) -> Result<Self, DiceError> {
+        Self::parse_terms(spec, registry, Strictness::default())
+    }
+
+    /// Parse a dice specification like [`Dice::parse_with`], but reject notation that
+    /// is easy to get wrong with [`DiceError::AmbiguousNotation`]: a bare `k` rather
+    /// than `kh` or `kl`, keep or drop without a number of dice, keeping every die, and
+    /// giving a success target, explosion, keep or floor more than once.
+    pub fn parse_strict_with(spec: &str, registry: &DieRegistry) -> Result<Self, DiceError> {
+        Self::parse_terms(spec, registry, Strictness { notation: true, ..Default::default() })
+    }
+
+    /// Parse a dice specification like [`Dice::parse_with`], rejecting whatever
+    /// `strictness` asks for.
+    pub fn parse_with_strictness(spec: &str, registry: &DieRegistry, strictness: Strictness) -> Result<Self, DiceError> {
+        Self::parse_terms(spec, registry, strictness)
+    }
+
+    /// Parse every term of a specification, as strictly as asked.
+    fn parse_terms(spec: &str, registry: &DieRegistry, strict: Strictness) -> Result<Self, DiceError> {
+        let mut terms = split_terms(spec).into_iter();
+        let mut dice = Self::parse_term(terms.next().unwrap_or(spec), registry, strict)?;
+        for term in terms {
+            dice.extra_terms.push(Self::parse_term(term, registry, strict)?);
+        }
+        Ok(dice)
+    }
+
+    /// Parse a single `NdS` term of a specification.
+    fn parse_term(spec: &str, registry: &DieRegistry, strict: Strictness) -> Result<Self, DiceError> {
+        // Trim whitespace
+        let spec = spec.trim().to_lowercase();
+
+        // Split the count from the rest of the specification at the first "d"
+        let (count_str, rest) = spec
+            .split_once('d')
+            .ok_or_else(|| DiceError::InvalidFormat { spec: spec.clone() })?;
+
+        // Store the first part as the count of the number of dice to roll
+        let count = count_str.parse::<u8>().map_err(|_| DiceError::InvalidNumber {
+            spec: spec.clone(),
+            field: "count",
+            value: count_str.to_string(),
+        })?;
+
+        // If the count is 0 the format is invalid
+        if count == 0 {
+            return Err(DiceError::ZeroCount { spec: spec.clone() });
+        }
+
+        // The sides are the run of digits straight after the "d", "%" for percentile
+        // dice or the name of a custom die
+        let percentile = rest.starts_with('%');
+        let mut custom_faces = None;
+        let mut custom_name = None;
+        let mut sides_roll = None;
+        let mut fewest_sides = 1;
+        let (sides, mut rest) = if let Some(after) = rest.strip_prefix('%') {
+            (100, after)
+        } else if let Some(after) = rest.strip_prefix('(') {
+            let (inner, rest) = split_parenthesized(after).ok_or_else(|| DiceError::InvalidSidesRoll {
+                spec: spec.clone(),
+                reason: "missing closing ')'",
+            })?;
+            let inner = Dice::parse_terms(inner, registry, strict)?;
+            // Rule out large pools before working out their whole distribution, which
+            // for something like 1d(255d100dl1) would take more memory than there is
+            if inner.highest_total_at_least().is_some_and(|max| max > i64::from(u8::MAX)) {
+                return Err(DiceError::InvalidSidesRoll {
+                    spec: spec.clone(),
+                    reason: "the sides roll can make more than 255 sides",
+                });
+            }
+            let totals = inner.distribution_map();
+            let min = totals.keys().next().copied().unwrap_or(0);
+            let max = totals.keys().next_back().copied().unwrap_or(0);
+            if min < 1 {
+                return Err(DiceError::InvalidSidesRoll {
+                    spec: spec.clone(),
+                    reason: "the sides roll can make 0 or fewer sides",
+                });
+            }
+            if max > i32::from(u8::MAX) {
+                return Err(DiceError::InvalidSidesRoll {
+                    spec: spec.clone(),
+                    reason: "the sides roll can make more than 255 sides",
+                });
+            }
+            sides_roll = Some(Box::new(inner));
+            fewest_sides = min as u8;
+            (max as u8, rest)
+        } else if rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            let name_len = rest.chars().take_while(|c| c.is_ascii_alphanumeric() || *c == '_').count();
+            let (name, faces) = match registry.longest_prefix(rest) {
+                Some(found) => found,
+                // Fudge dice are built in, unless the registry has a die of its own by the name
+                None if name_len == 1 && rest.starts_with('f') => (&rest[..1], &FUDGE_FACES[..]),
+                None => {
+                    return Err(DiceError::UnknownDie {
+                        spec: spec.clone(),
+                        name: rest[..name_len].to_string(),
+                    });
+                }
+            };
+            custom_faces = Some(faces.to_vec());
+            custom_name = Some(name.to_string());
+            // The registry only accepts dice with at most 255 faces
+            (faces.len() as u8, &rest[name.len()..])
+        } else {
+            let (sides_str, rest) = split_digits(rest);
+            let sides = sides_str.parse::<u8>().map_err(|_| DiceError::InvalidNumber {
+                spec: spec.clone(),
+                field: "sides",
+                value: if sides_str.is_empty() { rest } else { sides_str }.to_string(),
+            })?;
+            (sides, rest)
+        };
+
+        if sides == 0 {
+            return Err(DiceError::ZeroSides { spec: spec.clone() });
+        }
+
+        // Everything after the sides is optional notation, in any order
+        let mut modifier: i32 = 0;
+        let mut success_target = None;
+        let mut success_weight = None;
+        let mut double_face = None;
+        let mut reroll = BTreeSet::new();
+        let mut explode = false;
+        let mut explode_face = ExplodeFace::default();
+        let mut keep = None;
+        let mut die_floor = None;
+        let mut modifier_given = false;
+        let ambiguous = |reason| DiceError::AmbiguousNotation {
+            spec: spec.clone(),
+            reason,
+        };
+        let ambiguous_modifier = |reason| DiceError::AmbiguousModifier {
+            spec: spec.clone(),
+            reason,
+        };
+        // The modifier must be a single signed number with no leading zeros, at the end
+        let check_modifier = |given: bool, after: &str| {
+            if !strict.modifier_sign {
+                Ok(())
+            } else if given {
+                Err(ambiguous_modifier("give a single modifier, e.g. +2 rather than +3-1"))
+            } else if after.starts_with('0') && after[1..].starts_with(|c: char| c.is_ascii_digit()) {
+                Err(ambiguous_modifier("write the modifier without leading zeros"))
+            } else {
+                Ok(())
+            }
+        };
+        let given_twice = |given: bool| {
+            if strict.notation && given {
+                Err(ambiguous("the same notation is given twice"))
+            } else {
+                Ok(())
+            }
+        };
+        while !rest.is_empty() {
+            if strict.modifier_sign && modifier_given && !rest.starts_with(['+', '-']) {
+                return Err(ambiguous_modifier("put the modifier after the rest of the notation"));
+            }
+            if let Some(after) = rest.strip_prefix(">=") {
+                given_twice(success_target.is_some())?;
+                let (target, remaining) = parse_number(&spec, "success target", after)?;
+                success_target = Some(target);
+                rest = remaining;
+            } else if let Some(after) = rest.strip_prefix('*') {
+                given_twice(success_weight.is_some())?;
+                let (weight, remaining) = parse_number(&spec, "success weight", after)?;
+                success_weight = Some(weight);
+                rest = remaining;
+            } else if let Some(after) = rest.strip_prefix('^') {
+                given_twice(double_face.is_some())?;
+                let (face, remaining) = parse_number(&spec, "double success face", after)?;
+                double_face = Some(face);
+                rest = remaining;
+            } else if let Some(after) = rest.strip_prefix("min") {
+                given_twice(die_floor.is_some())?;
+                let (floor, remaining) = parse_number(&spec, "die floor", after)?;
+                die_floor = Some(floor);
+                rest = remaining;
+            } else if let Some(after) = rest.strip_prefix('!') {
+                given_twice(explode)?;
+                explode = true;
+                rest = match after.strip_prefix('l') {
+                    Some(after) => {
+                        explode_face = ExplodeFace::Lowest;
+                        after
+                    }
+                    None => after,
+                };
+            } else if let Some((prefix, highest, drop, after)) = strip_keep(rest) {
+                given_twice(keep.is_some())?;
+                let (digits, remaining) = split_digits(after);
+                if strict.notation && prefix == "k" {
+                    return Err(ambiguous("write kh or kl to say which dice to keep"));
+                }
+                if strict.notation && digits.is_empty() {
+                    return Err(ambiguous("give the number of dice to keep or drop"));
+                }
+                let dice = if digits.is_empty() {
+                    1
+                } else {
+                    digits.parse::<u8>().map_err(|_| DiceError::InvalidNumber {
+                        spec: spec.clone(),
+                        field: "keep",
+                        value: digits.to_string(),
+                    })?
+                };
+                let invalid = |reason| DiceError::InvalidKeep {
+                    spec: spec.clone(),
+                    reason,
+                };
+                if dice == 0 || dice > count {
+                    return Err(invalid("must keep or drop between 1 and the number of dice"));
+                }
+                if drop && dice == count {
+                    return Err(invalid("every die would be dropped"));
+                }
+                if strict.notation && !drop && dice == count {
+                    return Err(ambiguous("keeping every die does nothing"));
+                }
+                // Dropping the highest dice keeps the lowest of the rest, and the other way round
+                keep = Some(match (highest, drop) {
+                    (true, false) => Keep::Highest(dice),
+                    (false, false) => Keep::Lowest(dice),
+                    (true, true) => Keep::Lowest(count - dice),
+                    (false, true) => Keep::Highest(count - dice),
+                });
+                rest = remaining;
+            } else if let Some(after) = rest.strip_prefix('r') {
+                let shown = match &custom_faces {
+                    Some(faces) => (faces.iter().copied().min().unwrap_or(0), faces.iter().copied().max().unwrap_or(0)),
+                    None => (1, i32::from(sides)),
+                };
+                let (faces, remaining) = parse_reroll(&spec, after, shown)?;
+                reroll.extend(faces);
+                rest = remaining;
+            } else if let Some(after) = rest.strip_prefix('+') {
+                check_modifier(modifier_given, after)?;
+                modifier_given = true;
+                let (value, remaining) = parse_number(&spec, "modifier", after)?;
+                modifier = modifier.checked_add(value).ok_or(DiceError::ModifierOverflow)?;
+                rest = remaining;
+            } else if let Some(after) = rest.strip_prefix('-') {
+                check_modifier(modifier_given, after)?;
+                modifier_given = true;
+                let (value, remaining) = parse_number(&spec, "modifier", after)?;
+                modifier = modifier.checked_sub(value).ok_or(DiceError::ModifierOverflow)?; // Make the modifier negative
+                rest = remaining;
+            } else {
+                return Err(DiceError::UnknownNotation {
+                    spec: spec.clone(),
+                    notation: rest.to_string(),
+                });
+            }
+        }
+
+        if let Some(weight) = success_weight {
+            let invalid = |reason| DiceError::InvalidWeight {
+                spec: spec.clone(),
+                reason,
+            };
+            if success_target.is_none() {
+                return Err(invalid("only dice with a success target can be weighted"));
+            }
+            if weight < 1 {
+                return Err(invalid("a success must count at least once"));
+            }
+        }
+        if let Some(face) = double_face {
+            let invalid = |reason| DiceError::InvalidDoubleFace {
+                spec: spec.clone(),
+                reason,
+            };
+            match success_target {
+                None => return Err(invalid("only dice with a success target can count a face twice")),
+                Some(target) if face < target => return Err(invalid("the face counted twice must meet the success target")),
+                Some(_) => {}
+            }
+        }
+
+        let dice = Dice {
+            sides,
+            count,
+            modifier,
+            success_target,
+            success_weight: success_weight.unwrap_or(1),
+            double_face,
+            reroll,
+            explode,
+            percentile,
+            custom_faces,
+            custom_name,
+            extra_terms: Vec::new(),
+            keep,
+            keep_tie: KeepTie::default(),
+            keep_order: KeepOrder::default(),
+            explode_face,
+            explode_which: ExplodeWhich::default(),
+            sides_roll,
+            die_floor,
+            floor_scope: FloorScope::default(),
+            reduce: Reduce::default(),
+            explosion_limit: MAX_EXPLOSIONS,
+            reroll_limit: MAX_REROLLS,
+            no_replacement: false,
+        };
+
+        // Every die the sides roll can make has to be a valid die
+        match &dice.sides_roll {
+            Some(_) => {
+                for sides in fewest_sides..=dice.sides {
+                    dice.fixed_sides(sides).check_faces(&spec)?;
+                }
+            }
+            None => dice.check_faces(&spec)?,
+        }
+        Ok(dice)
+    }
+
+    /// Check that rolling a die will finish, for the dice as they are after parsing.
+    fn check_faces(&self, spec: &str) -> Result<(), DiceError> {
+        // Rerolling every face would never finish
+        if self.faces().next().is_none() {
+            return Err(DiceError::InvalidReroll {
+                spec: spec.to_string(),
+                reason: "every face would be rerolled",
+            });
+        }
+
+        // A die that can only show its highest face would explode forever
+        if self.explode && self.faces().all(|face| self.explodes_on(face)) {
+            return Err(DiceError::InfiniteExplosion { spec: spec.to_string() });
+        }
+        Ok(())
+    }
+
+    /// This term alone with a fixed number of sides, as rolled for dice like `1d(1d6)`.
+    fn fixed_sides(&self, sides: u8) -> Dice {
+        Dice {
+            sides,
+            sides_roll: None,
+            extra_terms: Vec::new(),
+            ..self.clone()
+        }
+    }
+
+    /// A copy of the dice with `delta` added to the modifier, leaving these dice as they
+    /// are, for bonuses that only apply to one roll. The modifier saturates at the limits
+    /// of an `i32`.
+    pub fn with_modifier(&self, delta: i32) -> Dice {
+        Dice {
+            modifier: self.modifier.saturating_add(delta),
+            ..self.clone()
+        }
+    }
+
+    /// A copy of the dice rolling `count` dice in the first term, leaving every other
+    /// setting and term as it is, for building variations of a roll.
+    ///
+    /// Fails when `count` is zero, keep notation would keep more dice than are
+    /// rolled, or dice drawn without replacement would outnumber their faces.
+    pub fn with_count(&self, count: u8) -> Result<Dice, DiceError> {
+        let mut dice = Dice { count, ..self.clone() };
+        if count == 0 {
+            return Err(DiceError::ZeroCount { spec: dice.to_string() });
+        }
+        if let Some(Keep::Highest(kept) | Keep::Lowest(kept)) = dice.keep
+            && kept > count
+        {
+            return Err(DiceError::InvalidKeep {
+                spec: dice.to_string(),
+                reason: "must keep or drop between 1 and the number of dice",
+            });
+        }
+        if dice.no_replacement {
+            dice.set_no_replacement()?;
+        }
+        Ok(dice)
+    }
+
+    /// A copy of the dice rolling plain dice with `sides` sides in the first term, in
+    /// place of percentile, custom or rolled sides, leaving every other setting and
+    /// term as it is.
+    ///
+    /// Fails when `sides` is zero, the new dice could never finish rolling (every face
+    /// rerolled, or every face exploding), or dice drawn without replacement would
+    /// outnumber their faces.
+    pub fn with_sides(&self, sides: u8) -> Result<Dice, DiceError> {
+        let mut dice = Dice {
+            sides,
+            percentile: false,
+            custom_faces: None,
+            custom_name: None,
+            sides_roll: None,
+            ..self.clone()
+        };
+        let spec = dice.to_string();
+        if sides == 0 {
+            return Err(DiceError::ZeroSides { spec });
+        }
+        dice.check_faces(&spec)?;
+        if dice.no_replacement {
+            dice.set_no_replacement()?;
+        }
+        Ok(dice)
+    }
+
+    /// A copy of the dice with the number of dice and the modifier of every term
+    /// multiplied by `factor` and rounded to the nearest whole number, for quick
+    /// balancing. Scaling the count rather than the total keeps the result ordinary
+    /// dice that can be rolled, drawn and analyzed like any other, so the expected
+    /// value is exactly `factor` times the original only when the scaled counts and
+    /// modifiers come out whole. Keep notation keeps the same share of the dice.
+    ///
+    /// Fails when `factor` is not a positive number, a term would be left with no
+    /// dice or more than 255, or dice drawn without replacement would outnumber their faces.
+    pub fn scale(&self, factor: f64) -> Result<Dice, DiceError> {
+        if !(factor.is_finite() && factor > 0.0) {
+            return Err(DiceError::InvalidScale {
+                reason: "the factor must be a positive number",
+            });
+        }
+        let mut scaled = self.clone();
+        scaled.scale_term(factor)?;
+        for term in &mut scaled.extra_terms {
+            term.scale_term(factor)?;
+        }
+        if scaled.no_replacement {
+            scaled.set_no_replacement()?;
+        }
+        Ok(scaled)
+    }
+
+    /// Scale the count, keep and modifier of this term alone, for [`Dice::scale`].
+    fn scale_term(&mut self, factor: f64) -> Result<(), DiceError> {
+        let count = (f64::from(self.count) * factor).round();
+        if count < 1.0 {
+            return Err(DiceError::InvalidScale {
+                reason: "a term would be left with no dice",
+            });
+        }
+        if count > f64::from(u8::MAX) {
+            return Err(DiceError::InvalidScale {
+                reason: "a term would have more than 255 dice",
+            });
+        }
+        self.count = count as u8;
+        let kept = |kept: u8| ((f64::from(kept) * factor).round() as u8).clamp(1, self.count);
+        self.keep = self.keep.map(|keep| match keep {
+            Keep::Highest(count) => Keep::Highest(kept(count)),
+            Keep::Lowest(count) => Keep::Lowest(kept(count)),
+        });
+        // The conversion saturates at the limits of an i32
+        self.modifier = (f64::from(self.modifier) * factor).round() as i32;
+        Ok(())
+    }
+
+    /// Set which of several tied dice is kept, for this term and every other term of a mixed pool.
+    pub fn set_keep_tie(&mut self, keep_tie: KeepTie) {
+        self.keep_tie = keep_tie;
+        for term in &mut self.extra_terms {
+            term.set_keep_tie(keep_tie);
+        }
+    }
+
+    /// Set whether keep notation chooses its dice before or after they explode, for
+    /// this term and every other term of a mixed pool.
+    pub fn set_keep_order(&mut self, keep_order: KeepOrder) {
+        self.keep_order = keep_order;
+        for term in &mut self.extra_terms {
+            term.set_keep_order(keep_order);
+        }
+    }
+
+    /// Set which face makes exploding dice roll again, for this term and every other
+    /// term of a mixed pool.
+    ///
+    /// Fails when a term could then only show the face it explodes on.
+    pub fn set_explode_face(&mut self, explode_face: ExplodeFace) -> Result<(), DiceError> {
+        for term in std::iter::once(&*self).chain(&self.extra_terms) {
+            let changed = Dice { explode_face, ..term.clone() };
+            if changed.explode && changed.faces().all(|face| changed.explodes_on(face)) {
+                return Err(DiceError::InfiniteExplosion { spec: changed.to_string() });
+            }
+        }
+        self.explode_face = explode_face;
+        for term in &mut self.extra_terms {
+            term.explode_face = explode_face;
+        }
+        Ok(())
+    }
+
+    /// Set which dice of the pool may explode, for this term and every other term of a
+    /// mixed pool.
+    ///
+    /// Fails when a term that explodes also keeps or drops dice, unless every die may explode.
+    pub fn set_explode_which(&mut self, explode_which: ExplodeWhich) -> Result<(), DiceError> {
+        if explode_which != ExplodeWhich::All
+            && let Some(term) = std::iter::once(&*self).chain(&self.extra_terms).find(|term| term.explode && term.keep.is_some())
+        {
+            return Err(DiceError::InvalidKeep {
+                spec: term.to_string(),
+                reason: "cannot keep or drop dice when only the highest or lowest die explodes",
+            });
+        }
+        self.explode_which = explode_which;
+        for term in &mut self.extra_terms {
+            term.explode_which = explode_which;
+        }
+        Ok(())
+    }
+
+    /// Set how the dice are combined, for this term and every other term of a mixed pool.
+    pub fn set_reduce(&mut self, reduce: Reduce) {
+        self.reduce = reduce;
+        for term in &mut self.extra_terms {
+            term.set_reduce(reduce);
+        }
+    }
+
+    /// Set the most extra dice a single exploding die can add, for this term and every
+    /// other term of a mixed pool. Distributions follow explosion chains to the same depth.
+    pub fn set_explosion_limit(&mut self, limit: u32) {
+        self.explosion_limit = limit;
+        for term in &mut self.extra_terms {
+            term.set_explosion_limit(limit);
+        }
+    }
+
+    /// Set the most times a single die is rerolled before keeping whatever it shows,
+    /// for this term and every other term of a mixed pool. The limit guards against
+    /// runaway rolls; distributions treat rerolls as always finding a face to keep.
+    pub fn set_reroll_limit(&mut self, limit: u32) {
+        self.reroll_limit = limit;
+        for term in &mut self.extra_terms {
+            term.set_reroll_limit(limit);
+        }
+    }
+
+    /// Raise every die of this term and every other term of a mixed pool to at least
+    /// `floor`, as the `min` notation does.
+    ///
+    /// Fails for exploding dice when the floor would leave them only their highest face.
+    pub fn set_die_floor(&mut self, floor: i32) -> Result<(), DiceError> {
+        for term in std::iter::once(&*self).chain(&self.extra_terms) {
+            // Dice with rolled sides can be as small as the lowest sides roll
+            let fewest = match &term.sides_roll {
+                Some(inner) => inner.distribution_map().keys().next().copied().unwrap_or(1) as u8,
+                None => term.sides,
+            };
+            let mut smallest = term.fixed_sides(fewest);
+            smallest.die_floor = Some(term.floored(floor));
+            if smallest.explode && smallest.faces().all(|face| smallest.explodes_on(face)) {
+                return Err(DiceError::InvalidFloor {
+                    floor,
+                    reason: "exploding dice could only show the face they explode on",
+                });
+            }
+        }
+        // A floor already given in the notation still applies if it is higher
+        self.die_floor = Some(self.floored(floor));
+        for term in &mut self.extra_terms {
+            term.die_floor = Some(term.floored(floor));
+        }
+        Ok(())
+    }
+
+    /// Count the dice showing at least `target` instead of adding them, as `>=`
+    /// notation does, for this term and every other term of a mixed pool that has no
+    /// success target of its own.
+    pub fn set_success_target(&mut self, target: i32) {
+        self.success_target.get_or_insert(target);
+        for term in &mut self.extra_terms {
+            term.set_success_target(target);
+        }
+    }
+
+    /// Set which dice the die floor raises, for this term and every other term of a mixed pool.
+    pub fn set_floor_scope(&mut self, floor_scope: FloorScope) {
+        self.floor_scope = floor_scope;
+        for term in &mut self.extra_terms {
+            term.set_floor_scope(floor_scope);
+        }
+    }
+
+    /// Draw the dice of this term and every other term of a mixed pool without
+    /// replacement, so that each face of a term's die shows at most once in the roll.
+    ///
+    /// Fails when a term has more dice than faces to draw, or its dice explode.
+    pub fn set_no_replacement(&mut self) -> Result<(), DiceError> {
+        for term in std::iter::once(&*self).chain(&self.extra_terms) {
+            if term.explode {
+                return Err(DiceError::InvalidDraw {
+                    reason: "exploding dice would need more faces after the last is drawn",
+                });
+            }
+            // Dice with rolled sides can be as small as the lowest sides roll
+            let fewest = match &term.sides_roll {
+                Some(inner) => inner.distribution_map().keys().next().copied().unwrap_or(1) as u8,
+                None => term.sides,
+            };
+            if term.fixed_sides(fewest).faces().count() < usize::from(term.count) {
+                return Err(DiceError::InvalidDraw {
+                    reason: "there are more dice than faces to draw",
+                });
+            }
+        }
+        self.no_replacement = true;
+        for term in &mut self.extra_terms {
+            term.no_replacement = true;
+        }
+        Ok(())
+    }
+
+    /// Check that every die of this term and every other term of a mixed pool has
+    /// between `min` and `max` sides, for tools that only accept sensible die sizes.
+    ///
+    /// Dice with rolled sides like `1d(1d6)` must fit for every number of sides they can roll.
+    /// A `min` above `max` is an error whatever the dice.
+    pub fn check_sides(&self, min: u8, max: u8) -> Result<(), DiceError> {
+        if min > max {
+            return Err(DiceError::InvalidSidesRange { min, max });
+        }
+        for term in std::iter::once(self).chain(&self.extra_terms) {
+            let fewest = match &term.sides_roll {
+                Some(inner) => inner.distribution_map().keys().next().copied().unwrap_or(1) as u8,
+                None => term.sides,
+            };
+            for sides in [fewest, term.sides] {
+                if !(min..=max).contains(&sides) {
+                    return Err(DiceError::SidesOutOfRange { sides, min, max });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The faces a single die can finally show once rerolls are done, each equally likely.
+    fn faces(&self) -> impl Iterator<Item = i32> + '_ {
+        let (custom, standard) = match &self.custom_faces {
+            Some(faces) => (faces.as_slice(), None),
+            None => (&[][..], Some(1..=i32::from(self.sides))),
+        };
+        custom
+            .iter()
+            .copied()
+            .chain(standard.into_iter().flatten())
+            .filter(|face| !self.reroll.contains(face))
+            .map(|face| self.floored(face))
+    }
+
+    /// A face raised to the die floor, if there is one.
+    fn floored(&self, face: i32) -> i32 {
+        self.die_floor.map_or(face, |floor| face.max(floor))
+    }
+
+    /// A total the dice can certainly reach, found without working out their
+    /// distribution: every kept die of every term showing its highest face. Success
+    /// pools and dice combined other than by adding have no such quick bound.
+    fn highest_total_at_least(&self) -> Option<i64> {
+        let mut total = 0;
+        for term in std::iter::once(self).chain(&self.extra_terms) {
+            if term.success_target.is_some() || term.reduce != Reduce::Sum {
+                return None;
+            }
+            let kept = term.keep.map_or(usize::from(term.count), Keep::kept);
+            total += kept as i64 * i64::from(term.faces().max()?) + i64::from(term.modifier);
+        }
+        Some(total)
+    }
+
+    /// The highest face a single die can show before rerolls.
+    pub fn highest_face(&self) -> i32 {
+        match &self.custom_faces {
+            Some(faces) => faces.iter().copied().max().unwrap_or(0),
+            None => i32::from(self.sides),
+        }
+    }
+
+    /// The lowest face a single die can show before rerolls.
+    pub fn lowest_face(&self) -> i32 {
+        match &self.custom_faces {
+            Some(faces) => faces.iter().copied().min().unwrap_or(0),
+            None => 1,
+        }
+    }
+
+    /// Roll a single die, rerolling any face in the reroll set up to the reroll limit,
+    /// also giving the number of rerolls made.
+    fn roll_face<R: Rng + ?Sized>(&self, rng: &mut R) -> (i32, u32) {
+        let mut rerolls = 0;
+        loop {
+            let face = if let Some(faces) = &self.custom_faces {
+                faces[rng.random_range(0..faces.len())]
+            } else if self.percentile {
+                let tens = rng.random_range(0..=9);
+                i32::from(percentile_value(tens, rng.random_range(0..=9)))
+            } else {
+                i32::from(rng.random_range(1..=self.sides))
+            };
+            if !self.reroll.contains(&face) || rerolls == self.reroll_limit {
+                return (self.floored(face), rerolls);
+            }
+            rerolls += 1;
+        }
+    }
+
+    /// The score a single die contributes to the result: its face value, or its
+    /// success weight (usually 1, doubled on the double success face) or 0 for a
+    /// success or failure when the dice have a success target.
+    fn score(&self, face: i32) -> i32 {
+        match self.success_target {
+            Some(_) if self.double_face == Some(face) => self.success_weight.saturating_mul(2),
+            Some(target) if face >= target => self.success_weight,
+            Some(_) => 0,
+            None => face,
+        }
+    }
+
+    /// The expected number of successes for a success pool (`count` times the expected
+    /// successes from a single die), or `None` when the dice have no target.
+    ///
+    /// In a mixed pool the expected successes of every term with a target are added.
+    pub fn expected_successes(&self) -> Option<f64> {
+        let terms: Vec<f64> = std::iter::once(self)
+            .chain(&self.extra_terms)
+            .filter_map(Dice::expected_term_successes)
+            .collect();
+        (!terms.is_empty()).then(|| terms.iter().sum())
+    }
+
+    /// The chance of each number of dice meeting the success target, from none up to
+    /// every die (and more with explosions), or `None` when the dice have no target.
+    ///
+    /// Every success counts once here: weights, doubled faces and the modifier are left
+    /// out, as are the terms of a mixed pool without a target. Only kept dice count.
+    pub fn success_count_distribution(&self) -> Option<BTreeMap<u32, f64>> {
+        let mut counted = std::iter::once(self).chain(&self.extra_terms).filter(|term| term.success_target.is_some()).map(|term| Dice {
+            success_weight: 1,
+            double_face: None,
+            modifier: 0,
+            reduce: Reduce::Sum,
+            extra_terms: Vec::new(),
+            ..term.clone()
+        });
+        let mut pool = counted.next()?;
+        pool.extra_terms = counted.collect();
+        // Counts of successes are never negative
+        Some(pool.distribution_map().into_iter().map(|(count, probability)| (count as u32, probability)).collect())
+    }
+
+    /// The expected number of successes from this term alone.
+    fn expected_term_successes(&self) -> Option<f64> {
+        let target = self.success_target?;
+        // Only kept dice count, so read the expectation off the distribution
+        let single_explosion = self.explode && self.explode_which != ExplodeWhich::All;
+        // A rerolled exploding face is never shown, so the die explodes less than one in `faces`
+        let exploding_face = match self.explode_face {
+            ExplodeFace::Highest => self.highest_face(),
+            ExplodeFace::Lowest => self.lowest_face(),
+        };
+        let rerolled_explosion = self.explode && self.reroll.contains(&exploding_face);
+        if self.keep.is_some()
+            || self.sides_roll.is_some()
+            || self.reduce != Reduce::Sum
+            || single_explosion
+            || rerolled_explosion
+        {
+            let successes = self
+                .term_distribution()
+                .iter()
+                .map(|(&total, &probability)| f64::from(total - self.modifier) * probability)
+                .sum();
+            return Some(successes);
+        }
+        let faces = self.faces().count() as f64;
+        let successes: f64 = self.faces().filter(|&face| face >= target).map(|face| f64::from(self.score(face))).sum();
+        let mut per_die = successes / faces;
+        // Every explosion adds another die, so a die is worth 1/(1 - P(explode)) dice on average
+        if self.explode {
+            per_die /= 1.0 - 1.0 / faces;
+        }
+        Some(f64::from(self.count) * per_die)
+    }
+
+    /// The chance that a roll of the first term is a critical: at least one of its
+    /// kept dice shows a natural `crit_on` or higher before any explosion, as with a
+    /// natural 20 on `1d20`, on either die of `2d20kh1` (advantage) or on both dice of
+    /// `2d20kl1` (disadvantage).
+    pub fn critical_chance(&self, crit_on: i32) -> f64 {
+        let faces = self.faces().count() as f64;
+        let high = self.faces().filter(|&face| face >= crit_on).count() as f64 / faces;
+        let count = i32::from(self.count);
+        match self.keep {
+            // Keeping the lowest dice leaves a critical die in only while fewer dice
+            // than are kept roll below it
+            Some(keep @ Keep::Lowest(_)) => {
+                let kept = keep.kept() as i32;
+                let mut ways = 1.0;
+                let mut chance = 0.0;
+                for low in 0..kept.min(count + 1) {
+                    chance += ways * (1.0 - high).powi(low) * high.powi(count - low);
+                    ways = ways * f64::from(count - low) / f64::from(low + 1);
+                }
+                chance
+            }
+            // The highest die is always kept, so any critical die counts
+            _ => 1.0 - (1.0 - high).powi(count),
+        }
+    }
+
+    /// The chance that the faces every die of the pool first shows, kept or not and
+    /// before any explosion, make the pattern, such as `(1/6)^3` for `3d6` showing
+    /// all sixes.
+    ///
+    /// Returns `None` unless every term rolls the same kind of die, or when the dice
+    /// have rolled sides or are drawn without replacement.
+    pub fn pattern_probability(&self, pattern: FacePattern) -> Option<f64> {
+        let terms: Vec<&Dice> = std::iter::once(self).chain(&self.extra_terms).collect();
+        let faces: Vec<i32> = self.faces().collect();
+        if terms
+            .iter()
+            .any(|term| term.sides_roll.is_some() || term.no_replacement || term.faces().ne(faces.iter().copied()))
+        {
+            return None;
+        }
+        let count: u32 = terms.iter().map(|term| u32::from(term.count)).sum();
+        // A die floor can put the same value on several faces
+        let mut chances: BTreeMap<i32, f64> = BTreeMap::new();
+        for &face in &faces {
+            *chances.entry(face).or_insert(0.0) += 1.0 / faces.len() as f64;
+        }
+        let all = |chance: f64| chance.powi(count as i32);
+        // More dice than faces, or than the faces span, can never all differ or run in a straight
+        let span = chances.keys().next_back().zip(chances.keys().next()).map_or(0, |(high, low)| i64::from(high - low) + 1);
+        if matches!(pattern, FacePattern::AllDifferent | FacePattern::Straight)
+            && (count as usize > chances.len() || i64::from(count) > span)
+        {
+            return Some(0.0);
+        }
+        Some(match pattern {
+            FacePattern::AllMax => chances.values().next_back().map_or(0.0, |&chance| all(chance)),
+            FacePattern::AllMin => chances.values().next().map_or(0.0, |&chance| all(chance)),
+            FacePattern::AllSame => chances.values().fold(0.0, |sum, &chance| sum + all(chance)),
+            FacePattern::AllDifferent => {
+                // The chance of each number of distinct faces chosen so far, in any order.
+                // Counting the orders as each face is added keeps the numbers from overflowing
+                let mut chosen = vec![0.0; count as usize + 1];
+                chosen[0] = 1.0;
+                for &chance in chances.values() {
+                    for taken in (1..chosen.len()).rev() {
+                        chosen[taken] += chosen[taken - 1] * chance * taken as f64;
+                    }
+                }
+                chosen[count as usize]
+            }
+            FacePattern::Straight => chances
+                .keys()
+                .filter_map(|&low| {
+                    // Each face of the run in any of the orders left for it
+                    (low..low.saturating_add(count as i32))
+                        .zip(1..)
+                        .map(|(face, place)| chances.get(&face).map(|chance| chance * f64::from(place)))
+                        .product::<Option<f64>>()
+                })
+                .fold(0.0, |sum, run| sum + run),
+        })
+    }
+
+    /// Whether a face makes the die explode into another die.
+    fn explodes_on(&self, face: i32) -> bool {
+        let exploding_face = match self.explode_face {
+            ExplodeFace::Highest => self.highest_face(),
+            ExplodeFace::Lowest => self.lowest_face(),
+        };
+        self.explode && face == exploding_face
+    }
+
+    /// Roll the dice using the thread-local random number generator, or the one set by
+    /// `set_rng_override` with the `test-rng` feature.
+    ///
+    /// Returns [`DiceRollError::Overflow`] when the total does not fit in an `i32`.
+    pub fn roll(&self) -> Result<RollResult, DiceRollError> {
+        #[cfg(any(test, feature = "test-rng"))]
+        if let Some(result) = crate::rng_override::with_rng_override(|rng| self.try_roll_with(rng)) {
+            return result;
+        }
+        self.try_roll_with(&mut rand::rng())
+    }
+
+    /// Roll the dice like [`Dice::roll_detailed_with`], failing with
+    /// [`DiceRollError::Overflow`] instead of saturating when the total does not fit
+    /// in an `i32`.
+    pub fn try_roll_with<R: Rng + ?Sized>(&self, rng: &mut R) -> Result<RollResult, DiceRollError> {
+        match self.roll_tracked(rng) {
+            (_, true) => Err(DiceRollError::Overflow),
+            (result, false) => Ok(result),
+        }
+    }
+
+    /// Roll the dice using the given random number generator.
+    ///
+    /// Exploding dice roll again on their highest face, up to the explosion limit
+    /// ([`MAX_EXPLOSIONS`] unless set) of extra dice each. Every extra die is scored on its own, so in a success pool
+    /// an explosion can add further successes.
+    pub fn roll_with<R: Rng + ?Sized>(&self, rng: &mut R) -> i32 {
+        self.roll_detailed_with(rng).total
+    }
+
+    /// Roll the dice, rolling again whenever the total matches `previous`, so the
+    /// same total never comes up twice in a row. Dice that can only make one total
+    /// repeat it anyway after [`MAX_REROLLS`] tries.
+    pub fn roll_avoiding<R: Rng + ?Sized>(&self, previous: Option<i32>, rng: &mut R) -> i32 {
+        let mut total = self.roll_with(rng);
+        for _ in 0..MAX_REROLLS {
+            if Some(total) != previous {
+                break;
+            }
+            total = self.roll_with(rng);
+        }
+        total
+    }
+
+    /// Roll the dice `count` times, replacing the contents of `buf` with the totals.
+    ///
+    /// The buffer keeps its allocation, so a simulation loop can pass the same one to
+    /// every batch and only allocate when a batch is larger than any before it.
+    pub fn roll_batch_into<R: Rng + ?Sized>(&self, rng: &mut R, count: usize, buf: &mut Vec<i32>) {
+        buf.clear();
+        buf.extend((0..count).map(|_| self.roll_with(rng)));
+    }
+
+    /// The kind of die the term rolls, as written in a specification: `d6`, `d%`, the
+    /// name of a custom die like `dfate`, or `d(1d6)` for sides decided by a roll.
+    pub fn die_type(&self) -> String {
+        match (&self.sides_roll, &self.custom_name) {
+            (Some(inner), _) => format!("d({})", inner),
+            (None, Some(name)) => format!("d{}", name),
+            (None, None) if self.percentile => "d%".to_string(),
+            (None, None) => format!("d{}", self.sides),
+        }
+    }
+
+    /// Roll the dice like [`Dice::roll_with`], keeping every individual die in the result.
+    ///
+    /// With keep notation every die is still in the result, and the dropped ones are
+    /// marked as not kept. Dice added by explosions can be kept or dropped like any other.
+    /// A total too large for an `i32` saturates; see [`Dice::try_roll_with`].
+    pub fn roll_detailed_with<R: Rng + ?Sized>(&self, rng: &mut R) -> RollResult {
+        self.roll_tracked(rng).0
+    }
+
+    /// Roll the dice, also saying whether the total or modifier had to saturate.
+    fn roll_tracked<R: Rng + ?Sized>(&self, rng: &mut R) -> (RollResult, bool) {
+        let (mut result, mut overflowed) = match &self.sides_roll {
+            Some(inner) => {
+                // The sides roll can only make 1 to 255 sides, as checked when parsing
+                let sides = inner.roll_with(rng) as u8;
+                let (mut result, overflowed) = self.fixed_sides(sides).roll_term(rng);
+                result.rolled_sides = Some(sides);
+                (result, overflowed)
+            }
+            None => self.roll_term(rng),
+        };
+        for (index, term) in self.extra_terms.iter().enumerate() {
+            let (term_result, term_overflowed) = term.roll_tracked(rng);
+            result.dice.extend(term_result.dice.into_iter().map(|die| DieRoll { term: index + 1, ..die }));
+            let modifier = result.modifier.checked_add(term_result.modifier);
+            let total = result.total.checked_add(term_result.total);
+            overflowed |= term_overflowed || modifier.is_none() || total.is_none();
+            result.modifier = result.modifier.saturating_add(term_result.modifier);
+            result.total = result.total.saturating_add(term_result.total);
+        }
+        (result, overflowed)
+    }
+
+    /// Roll this term alone, leaving out the other terms of a mixed pool, also saying
+    /// whether the total had to saturate.
+    fn roll_term<R: Rng + ?Sized>(&self, rng: &mut R) -> (RollResult, bool) {
+        if let (FloorScope::KeptDice, Some(floor)) = (self.floor_scope, self.die_floor) {
+            // Roll the natural faces, then raise only the dice that were kept
+            let natural = Dice {
+                die_floor: None,
+                floor_scope: FloorScope::default(),
+                ..self.clone()
+            };
+            let (result, _) = natural.roll_term(rng);
+            let mut dice = result.dice;
+            for die in dice.iter_mut().filter(|die| die.kept) {
+                die.face = die.face.max(floor);
+                die.score = self.score(die.face);
+                die.success = self.success_target.is_some() && die.score > 0;
+                die.critical = die.success && die.face == self.highest_face();
+            }
+            return self.scored(dice);
+        }
+        if self.explode && self.explode_which != ExplodeWhich::All {
+            return self.scored(self.roll_single_explosion(rng));
+        }
+        let mut dice = Vec::new();
+        // The faces still in the deck when drawing without replacement
+        let mut deck: Vec<i32> = if self.no_replacement { self.faces().collect() } else { Vec::new() };
+        for _ in 0..self.count {
+            let mut explosions = 0;
+            loop {
+                let (face, rerolls) = if self.no_replacement {
+                    (deck.swap_remove(rng.random_range(0..deck.len())), 0)
+                } else {
+                    self.roll_face(rng)
+                };
+                let score = self.score(face);
+                dice.push(DieRoll {
+                    face,
+                    score,
+                    from_explosion: explosions > 0,
+                    success: self.success_target.is_some() && score > 0,
+                    critical: self.success_target.is_some() && score > 0 && face == self.highest_face(),
+                    kept: true,
+                    rerolls,
+                    term: 0,
+                });
+                if !self.explodes_on(face) || explosions == self.explosion_limit {
+                    break;
+                }
+                explosions += 1;
+            }
+        }
+        let kept = match self.keep_order {
+            KeepOrder::AfterExplode => {
+                let faces: Vec<i32> = dice.iter().map(|die| die.face).collect();
+                self.keep_mask(&faces)
+            }
+            // Keep by the first face of each die, and every explosion with the die it came from
+            KeepOrder::BeforeExplode => {
+                let first_faces: Vec<i32> = dice.iter().filter(|die| !die.from_explosion).map(|die| die.face).collect();
+                let first_kept = self.keep_mask(&first_faces);
+                let mut die_index = 0;
+                dice.iter()
+                    .map(|die| {
+                        if !die.from_explosion {
+                            die_index += 1;
+                        }
+                        first_kept[die_index - 1]
+                    })
+                    .collect()
+            }
+        };
+        for (die, kept) in dice.iter_mut().zip(kept) {
+            die.kept = kept;
+            die.success &= kept;
+            die.critical &= kept;
+        }
+        self.scored(dice)
+    }
+
+    /// Roll the dice of a term where only the die showing the highest or lowest face may
+    /// explode, with that die's explosions following it.
+    fn roll_single_explosion<R: Rng + ?Sized>(&self, rng: &mut R) -> Vec<DieRoll> {
+        let die = |face: i32, rerolls: u32, from_explosion: bool| {
+            let score = self.score(face);
+            DieRoll {
+                face,
+                score,
+                from_explosion,
+                success: self.success_target.is_some() && score > 0,
+                critical: self.success_target.is_some() && score > 0 && face == self.highest_face(),
+                kept: true,
+                rerolls,
+                term: 0,
+            }
+        };
+        let first: Vec<(i32, u32)> = (0..self.count).map(|_| self.roll_face(rng)).collect();
+        let faces = first.iter().map(|&(face, _)| face).enumerate();
+        // min_by_key and max_by_key pick the first and last of a tie, so the highest is
+        // found as the lowest of the negated faces
+        let eligible = match self.explode_which {
+            ExplodeWhich::Lowest => faces.min_by_key(|&(_, face)| face),
+            _ => faces.min_by_key(|&(_, face)| std::cmp::Reverse(face)),
+        }
+        .map(|(index, _)| index);
+
+        let mut dice = Vec::new();
+        for (index, &(face, rerolls)) in first.iter().enumerate() {
+            dice.push(die(face, rerolls, false));
+            if Some(index) != eligible {
+                continue;
+            }
+            let mut shown = face;
+            let mut explosions = 0;
+            while self.explodes_on(shown) && explosions < self.explosion_limit {
+                let (face, rerolls) = self.roll_face(rng);
+                dice.push(die(face, rerolls, true));
+                shown = face;
+                explosions += 1;
+            }
+        }
+        dice
+    }
+
+    /// The result of this term once its dice are rolled and marked kept or dropped,
+    /// also saying whether the total had to saturate.
+    fn scored(&self, dice: Vec<DieRoll>) -> (RollResult, bool) {
+        let kept_scores = dice.iter().filter(|die| die.kept).map(|die| die.score);
+        let exact = self
+            .reduce
+            .checked_apply(kept_scores.clone())
+            .and_then(|total| total.checked_add(self.modifier));
+        let total = self.reduce.apply(kept_scores).saturating_add(self.modifier);
+        let result = RollResult {
+            dice,
+            modifier: self.modifier,
+            total,
+            rolled_sides: None,
+        };
+        (result, exact.is_none())
+    }
+
+    /// Which of the given faces, in the order rolled, are kept by the keep notation.
+    fn keep_mask(&self, faces: &[i32]) -> Vec<bool> {
+        let Some(keep) = self.keep else {
+            return vec![true; faces.len()];
+        };
+        let highest = matches!(keep, Keep::Highest(_));
+        let mut order: Vec<usize> = (0..faces.len()).collect();
+        order.sort_by(|&a, &b| {
+            let by_face = if highest { faces[b].cmp(&faces[a]) } else { faces[a].cmp(&faces[b]) };
+            let by_position = match self.keep_tie {
+                KeepTie::First => a.cmp(&b),
+                KeepTie::Last => b.cmp(&a),
+            };
+            by_face.then(by_position)
+        });
+        let mut kept = vec![false; faces.len()];
+        for &index in order.iter().take(keep.kept()) {
+            kept[index] = true;
+        }
+        kept
+    }
+
+    /// Add newly rolled faces to the faces kept so far, leaving only the best ones.
+    fn keep_best(kept: &[i32], faces: &[i32], keep: Keep) -> Vec<i32> {
+        let mut next = [kept, faces].concat();
+        match keep {
+            Keep::Highest(_) => next.sort_unstable_by(|a, b| b.cmp(a)),
+            Keep::Lowest(_) => next.sort_unstable(),
+        }
+        next.truncate(keep.kept());
+        next
+    }
+
+    /// The total made by a set of kept faces, including the modifier.
+    fn kept_total(&self, kept: &[i32]) -> i32 {
+        self.reduce
+            .apply(kept.iter().map(|&face| self.score(face)))
+            .saturating_add(self.modifier)
+    }
+
+    /// Every sequence of faces a single die can show as it explodes, with its probability.
+    fn die_chains(&self) -> Vec<(Vec<i32>, f64)> {
+        fn add_faces(dice: &Dice, chain: &mut Vec<i32>, probability: f64, chains: &mut Vec<(Vec<i32>, f64)>) {
+            let depth = chain.len() as u32;
+            let face_probability = probability / dice.faces().count() as f64;
+            for face in dice.faces() {
+                chain.push(face);
+                // Stop following explosions once they can no longer affect the result
+                if dice.explodes_on(face) && depth < dice.explosion_limit && face_probability > EXPLOSION_CUTOFF {
+                    add_faces(dice, chain, face_probability, chains);
+                } else {
+                    chains.push((chain.clone(), face_probability));
+                }
+                chain.pop();
+            }
+        }
+
+        let mut chains = Vec::new();
+        add_faces(self, &mut Vec::new(), 1.0, &mut chains);
+        chains
+    }
+
+    /// Roll the dice as the damage of a critical hit under the given rule. Doubling
+    /// the dice doubles the count of every term of a mixed pool, up to 255 dice, and
+    /// leaves any keep notation keeping the same number of dice.
+    pub fn roll_crit_with<R: Rng + ?Sized>(&self, rule: CritRule, rng: &mut R) -> RollResult {
+        match rule {
+            CritRule::DoubleDice => self.with_doubled_dice().roll_detailed_with(rng),
+            CritRule::DoubleTotal => {
+                let mut result = self.roll_detailed_with(rng);
+                result.total = result.total.saturating_mul(2);
+                result
+            }
+            CritRule::MaxPlusRoll => {
+                let mut result = self.roll_detailed_with(rng);
+                result.total = result.total.saturating_add(self.max_roll().saturating_sub(result.modifier));
+                result
+            }
+        }
+    }
+
+    /// The mean damage of a critical hit with these dice under the given rule.
+    pub fn crit_expected_value(&self, rule: CritRule) -> f64 {
+        match rule {
+            CritRule::DoubleDice => self.with_doubled_dice().expected_value(),
+            CritRule::DoubleTotal => 2.0 * self.expected_value(),
+            CritRule::MaxPlusRoll => {
+                let modifiers: i32 = std::iter::once(self).chain(&self.extra_terms).map(|term| term.modifier).sum();
+                self.expected_value() + f64::from(self.max_roll().saturating_sub(modifiers))
+            }
+        }
+    }
+
+    /// These dice with the count of every term doubled.
+    fn with_doubled_dice(&self) -> Dice {
+        Dice {
+            count: self.count.saturating_mul(2),
+            extra_terms: self.extra_terms.iter().map(Dice::with_doubled_dice).collect(),
+            ..self.clone()
+        }
+    }
+
+    /// The probability of each score a single die (including any explosions) contributes.
+    fn die_distribution(&self) -> BTreeMap<i32, f64> {
+        let mut outcomes = BTreeMap::new();
+        for (chain, probability) in self.die_chains() {
+            let score = self.reduce.apply(chain.iter().map(|&face| self.score(face)));
+            *outcomes.entry(score).or_insert(0.0) += probability;
+        }
+        outcomes
+    }
+
+    /// The distribution of totals when only the die showing the highest or lowest face
+    /// may explode. That die explodes when the highest or lowest face is the one dice
+    /// explode on, so the dice are added one at a time tracking the result so far and
+    /// that face, and the explosions follow as a fresh exploding die.
+    fn single_explosion_distribution(&self) -> BTreeMap<i32, f64> {
+        let face_probability = 1.0 / self.faces().count() as f64;
+        let mut states: BTreeMap<(i32, i32), f64> = BTreeMap::new();
+        for face in self.faces() {
+            *states.entry((self.score(face), face)).or_insert(0.0) += face_probability;
+        }
+        for _ in 1..self.count {
+            let mut next = BTreeMap::new();
+            for (&(so_far, extreme), &probability) in &states {
+                for face in self.faces() {
+                    let extreme = match self.explode_which {
+                        ExplodeWhich::Lowest => extreme.min(face),
+                        _ => extreme.max(face),
+                    };
+                    let combined = self.reduce.combine(so_far, self.score(face));
+                    *next.entry((combined, extreme)).or_insert(0.0) += probability * face_probability;
+                }
+            }
+            states = next;
+        }
+
+        // The die after the first explosion has one explosion fewer left to make
+        let following = match self.explosion_limit.checked_sub(1) {
+            Some(explosion_limit) => Dice { explosion_limit, ..self.clone() }.die_distribution(),
+            None => BTreeMap::new(),
+        };
+        let mut totals = BTreeMap::new();
+        for ((so_far, extreme), probability) in states {
+            if self.explodes_on(extreme) && !following.is_empty() {
+                for (&score, &following_probability) in &following {
+                    let total = self.reduce.combine(so_far, score).saturating_add(self.modifier);
+                    *totals.entry(total).or_insert(0.0) += probability * following_probability;
+                }
+            } else {
+                *totals.entry(so_far.saturating_add(self.modifier)).or_insert(0.0) += probability;
+            }
+        }
+        totals
+    }
+
+    /// The distribution of totals when only some dice are kept, found by tracking the
+    /// best faces so far as each die is added rather than every sequence of faces.
+    fn kept_distribution(&self, keep: Keep) -> BTreeMap<i32, f64> {
+        if self.explode && self.keep_order == KeepOrder::BeforeExplode {
+            return self.kept_chains_distribution(keep);
+        }
+        let chains = self.die_chains();
+        let mut states = BTreeMap::from([(Vec::new(), 1.0)]);
+        for _ in 0..self.count {
+            let mut next = BTreeMap::new();
+            for (kept, &probability) in &states {
+                for (chain, chain_probability) in &chains {
+                    *next.entry(Self::keep_best(kept, chain, keep)).or_insert(0.0) += probability * chain_probability;
+                }
+            }
+            states = next;
+        }
+
+        let mut totals = BTreeMap::new();
+        for (kept, probability) in states {
+            *totals.entry(self.kept_total(&kept)).or_insert(0.0) += probability;
+        }
+        totals
+    }
+
+    /// The distribution of totals when the dice are drawn without replacement. Every
+    /// set of distinct faces is equally likely, and the order they are drawn in does not
+    /// change the total, so each face of the deck is either drawn or not in turn while
+    /// tracking the number drawn and the kept faces (or the result so far).
+    fn drawn_distribution(&self) -> BTreeMap<i32, f64> {
+        let mut states: BTreeMap<(u8, Vec<i32>), f64> = BTreeMap::from([((0, Vec::new()), 1.0)]);
+        for face in self.faces() {
+            let mut next = BTreeMap::new();
+            for ((drawn, so_far), &weight) in &states {
+                *next.entry((*drawn, so_far.clone())).or_insert(0.0) += weight;
+                if *drawn < self.count {
+                    let with_face = match (self.keep, so_far.first()) {
+                        (Some(keep), _) => Self::keep_best(so_far, &[face], keep),
+                        (None, Some(&result)) => vec![self.reduce.combine(result, self.score(face))],
+                        (None, None) => vec![self.score(face)],
+                    };
+                    *next.entry((drawn + 1, with_face)).or_insert(0.0) += weight;
+                }
+            }
+            states = next;
+        }
+
+        let mut totals = BTreeMap::new();
+        let mut sets = 0.0;
+        for ((drawn, so_far), weight) in states {
+            if drawn == self.count {
+                let total = match self.keep {
+                    Some(_) => self.kept_total(&so_far),
+                    None => so_far[0].saturating_add(self.modifier),
+                };
+                *totals.entry(total).or_insert(0.0) += weight;
+                sets += weight;
+            }
+        }
+        totals.values_mut().for_each(|weight| *weight /= sets);
+        totals
+    }
+
+    /// The distribution of totals when exploding dice are kept by their first face. The
+    /// kept first faces are found as for dice that never explode, and since the chains
+    /// that follow equal first faces are alike it does not matter which tied die is kept,
+    /// so each kept first face then brings the distribution of its chain's score.
+    fn kept_chains_distribution(&self, keep: Keep) -> BTreeMap<i32, f64> {
+        let mut chain_scores: BTreeMap<i32, BTreeMap<i32, f64>> = BTreeMap::new();
+        for (chain, probability) in self.die_chains() {
+            let score = self.reduce.apply(chain.iter().map(|&face| self.score(face)));
+            *chain_scores.entry(chain[0]).or_default().entry(score).or_insert(0.0) += probability;
+        }
+        let face_probability = 1.0 / self.faces().count() as f64;
+
+        let mut states = BTreeMap::from([(Vec::new(), 1.0)]);
+        for _ in 0..self.count {
+            let mut next = BTreeMap::new();
+            for (kept, &probability) in &states {
+                for face in self.faces() {
+                    *next.entry(Self::keep_best(kept, &[face], keep)).or_insert(0.0) += probability * face_probability;
+                }
+            }
+            states = next;
+        }
+
+        let mut totals = BTreeMap::new();
+        for (kept, probability) in states {
+            // The scores of the kept chains so far, each weighted by the chance of its first face
+            let mut scores: BTreeMap<Option<i32>, f64> = BTreeMap::from([(None, probability)]);
+            for face in kept {
+                let mut next = BTreeMap::new();
+                for (&so_far, &weight) in &scores {
+                    for (&score, &chain_probability) in &chain_scores[&face] {
+                        let combined = so_far.map_or(score, |so_far| self.reduce.combine(so_far, score));
+                        *next.entry(Some(combined)).or_insert(0.0) += weight * chain_probability / face_probability;
+                    }
+                }
+                scores = next;
+            }
+            for (score, weight) in scores {
+                *totals.entry(score.unwrap_or(0).saturating_add(self.modifier)).or_insert(0.0) += weight;
+            }
+        }
+        totals
+    }
+
+    /// The probability (0.0 to 1.0) of every possible total, keyed by total.
+    ///
+    /// For exploding dice, chains of explosions less likely than one in 10^12 are
+    /// cut short, so the largest totals are approximate.
+    pub fn distribution_map(&self) -> BTreeMap<i32, f64> {
+        // Terms that differ only in their modifier share a distribution shifted by it,
+        // so a term repeated in a mixed pool like `1d6 + 1d6 + 1d6+1` is worked out once
+        let mut worked_out: Vec<(Dice, BTreeMap<i32, f64>)> = Vec::new();
+        let mut totals: Option<BTreeMap<i32, f64>> = None;
+        for term in std::iter::once(self).chain(&self.extra_terms) {
+            let unmodified = Dice {
+                modifier: 0,
+                extra_terms: Vec::new(),
+                ..term.clone()
+            };
+            let index = match worked_out.iter().position(|(seen, _)| *seen == unmodified) {
+                Some(index) => index,
+                None => {
+                    let distribution = unmodified.term_distribution();
+                    worked_out.push((unmodified, distribution));
+                    worked_out.len() - 1
+                }
+            };
+            let shifted: BTreeMap<i32, f64> = worked_out[index]
+                .1
+                .iter()
+                .map(|(&total, &probability)| (total.saturating_add(term.modifier), probability))
+                .collect();
+            totals = Some(match totals {
+                Some(totals) => convolve(&totals, &shifted),
+                None => shifted,
+            });
+        }
+        totals.unwrap_or_default()
+    }
+
+    /// The distribution of this term alone, leaving out the other terms of a mixed pool.
+    fn term_distribution(&self) -> BTreeMap<i32, f64> {
+        #[cfg(test)]
+        TERM_DISTRIBUTIONS.with(|count| count.set(count.get() + 1));
+        // Weigh the distribution for every number of sides by the chance of rolling it
+        if let Some(inner) = &self.sides_roll {
+            let mut totals = BTreeMap::new();
+            for (&sides, &sides_probability) in &inner.distribution_map() {
+                for (total, probability) in self.fixed_sides(sides as u8).term_distribution() {
+                    *totals.entry(total).or_insert(0.0) += sides_probability * probability;
+                }
+            }
+            return totals;
+        }
+        if self.no_replacement {
+            return self.drawn_distribution();
+        }
+
+        match self.keep {
+            Some(keep) => self.kept_distribution(keep),
+            None if self.explode && self.explode_which != ExplodeWhich::All => self.single_explosion_distribution(),
+            None => {
+                let die = self.die_distribution();
+
+                // Combine one die at a time with the distribution of the dice so far
+                let mut totals = die.clone();
+                for _ in 1..self.count {
+                    let mut next = BTreeMap::new();
+                    for (&total, &probability) in &totals {
+                        for (&score, &die_probability) in &die {
+                            *next.entry(self.reduce.combine(total, score)).or_insert(0.0) += probability * die_probability;
+                        }
+                    }
+                    totals = next;
+                }
+                totals
+                    .into_iter()
+                    .map(|(total, probability)| (total.saturating_add(self.modifier), probability))
+                    .collect()
+            }
+        }
+    }
+
+    /// Every possible total paired with the number of equally likely outcomes that
+    /// produce it, in ascending order of total.
+    ///
+    /// Returns `None` when there is no finite set of equally likely outcomes (exploding
+    /// dice or dice with rolled sides), the dice are drawn without replacement, or the
+    /// number of outcomes does not fit in a `u64`.
+    pub fn outcome_frequencies(&self) -> Option<(Vec<i32>, Vec<u64>)> {
+        Some(self.probability_mass()?.into_iter().unzip())
+    }
+
+    /// The exact number of equally likely outcomes that produce every possible total,
+    /// keyed by total. Dividing each count by their sum gives [`Dice::distribution_map`].
+    ///
+    /// Returns `None` in the same cases as [`Dice::outcome_frequencies`].
+    pub fn probability_mass(&self) -> Option<BTreeMap<i32, u64>> {
+        if self.explode || self.sides_roll.is_some() || self.no_replacement {
+            return None;
+        }
+
+        // Store all possible rolls
+        let mut all_rolls = match self.keep {
+            Some(keep) => self.kept_frequencies(keep)?,
+            None => {
+                let mut all_rolls: BTreeMap<i32, u64> = BTreeMap::new();
+                for face in self.faces() {
+                    *all_rolls.entry(self.score(face)).or_insert(0) += 1;
+                }
+                for _ in 1..self.count {
+                    let mut next: BTreeMap<i32, u64> = BTreeMap::new();
+                    for (&total, &frequency) in &all_rolls {
+                        for face in self.faces() {
+                            let entry = next.entry(self.reduce.combine(total, self.score(face))).or_insert(0);
+                            *entry = entry.checked_add(frequency)?;
+                        }
+                    }
+                    all_rolls = next;
+                }
+                all_rolls
+                    .into_iter()
+                    .map(|(total, frequency)| (total.saturating_add(self.modifier), frequency))
+                    .collect()
+            }
+        };
+        for term in &self.extra_terms {
+            let term_rolls = term.probability_mass()?;
+            let mut next: BTreeMap<i32, u64> = BTreeMap::new();
+            for (&total, &frequency) in &all_rolls {
+                for (&term_total, &term_frequency) in &term_rolls {
+                    let entry = next.entry(total + term_total).or_insert(0);
+                    *entry = entry.checked_add(frequency.checked_mul(term_frequency)?)?;
+                }
+            }
+            all_rolls = next;
+        }
+        Some(all_rolls)
+    }
+
+    /// The lowest total the dice can make.
+    pub fn min_roll(&self) -> i32 {
+        self.distribution_map().keys().next().copied().unwrap_or(self.modifier)
+    }
+
+    /// The highest total the dice can make. Exploding dice have no true maximum, so
+    /// this is the highest total left in [`Dice::distribution_map`] once unlikely
+    /// explosion chains are cut off.
+    pub fn max_roll(&self) -> i32 {
+        self.distribution_map().keys().next_back().copied().unwrap_or(self.modifier)
+    }
+
+    /// The mean total over every possible roll.
+    pub fn expected_value(&self) -> f64 {
+        self.distribution_map()
+            .iter()
+            .map(|(&total, &probability)| f64::from(total) * probability)
+            .sum()
+    }
+
+    /// The fixed average printed in published stat blocks: the mean total rounded
+    /// down, so `2d6+3` averages 10 and `1d8+2` (a mean of 6.5) averages 6. Negative
+    /// means round down too, toward negative infinity.
+    pub fn stat_block_average(&self) -> i32 {
+        // A whole mean can come out a hair below itself in floating point
+        (self.expected_value() + 1e-9).floor() as i32
+    }
+
+    /// The variance of the total over every possible roll.
+    pub fn variance(&self) -> f64 {
+        let distribution = self.distribution_map();
+        let mean: f64 = distribution.iter().map(|(&total, &probability)| f64::from(total) * probability).sum();
+        distribution
+            .iter()
+            .map(|(&total, &probability)| (f64::from(total) - mean).powi(2) * probability)
+            .sum()
+    }
+
+    /// The mean, variance, mode, lowest and highest totals together, working out the
+    /// distribution once and making a single pass over it, rather than once for each.
+    pub fn distribution_stats(&self) -> DistributionStats {
+        let distribution = self.distribution_map();
+        let (mut sum, mut sum_of_squares) = (0.0, 0.0);
+        let mut mode = (self.modifier, f64::NEG_INFINITY);
+        for (&total, &probability) in &distribution {
+            let value = f64::from(total);
+            sum += value * probability;
+            sum_of_squares += value * value * probability;
+            if probability > mode.1 {
+                mode = (total, probability);
+            }
+        }
+        // The totals are in order, so the ends of the map are the lowest and highest
+        let min = distribution.keys().next().copied().unwrap_or(self.modifier);
+        let max = distribution.keys().next_back().copied().unwrap_or(self.modifier);
+        DistributionStats {
+            mean: sum,
+            // Rounding can leave a fixed total a hair below no variance at all
+            variance: (sum_of_squares - sum * sum).max(0.0),
+            mode: mode.0,
+            min,
+            max,
+        }
+    }
+
+    /// Whether every term is a plain pool of standard dice that are simply added, such
+    /// as `100d6+3` or `50d8 + 50d6`, so the total is a sum of independent uniform dice.
+    fn is_plain_pool(&self) -> bool {
+        std::iter::once(self).chain(&self.extra_terms).all(|term| {
+            term.success_target.is_none()
+                && term.reroll.is_empty()
+                && !term.explode
+                && term.custom_faces.is_none()
+                && term.keep.is_none()
+                && term.sides_roll.is_none()
+                && term.die_floor.is_none()
+                && term.reduce == Reduce::Sum
+                && !term.no_replacement
+        })
+    }
+
+    /// The exact mean and variance of the total from the closed form for a sum of
+    /// uniform dice (a die with `s` sides has mean `(s+1)/2` and variance `(s²-1)/12`),
+    /// or `None` unless the dice are a plain pool added together.
+    pub fn closed_form_moments(&self) -> Option<(f64, f64)> {
+        if !self.is_plain_pool() {
+            return None;
+        }
+        let mut mean = 0.0;
+        let mut variance = 0.0;
+        for term in std::iter::once(self).chain(&self.extra_terms) {
+            let (count, sides) = (f64::from(term.count), f64::from(term.sides));
+            mean += count * (sides + 1.0) / 2.0 + f64::from(term.modifier);
+            variance += count * (sides * sides - 1.0) / 12.0;
+        }
+        Some((mean, variance))
+    }
+
+    /// The probability of every possible total of a plain pool approximated by a normal
+    /// distribution with the same mean and variance, with a continuity correction: each
+    /// total takes the normal probability within half a point of it, and the lowest and
+    /// highest totals take the rest of their tails so the probabilities add up to 1.
+    ///
+    /// This takes time in proportion to the number of totals rather than to the number
+    /// of dice times that, which matters for huge pools like `250d6+250d6+250d6+250d6`.
+    /// Returns `None` unless the dice are a plain pool added together.
+    pub fn normal_approximation(&self) -> Option<BTreeMap<i32, f64>> {
+        let (mean, variance) = self.closed_form_moments()?;
+        let terms = || std::iter::once(self).chain(&self.extra_terms);
+        let modifier = terms().fold(0i32, |sum, term| sum.saturating_add(term.modifier));
+        let lowest = terms().fold(modifier, |sum, term| sum.saturating_add(i32::from(term.count)));
+        let highest = terms().fold(modifier, |sum, term| {
+            sum.saturating_add(i32::from(term.count) * i32::from(term.sides))
+        });
+        if variance == 0.0 {
+            return Some(BTreeMap::from([(lowest, 1.0)]));
+        }
+
+        let std_dev = variance.sqrt();
+        // Work from the tail on the near side of each total so its small chances stay accurate
+        let below = |edge: f64| normal_upper_tail((mean - edge) / std_dev);
+        let above = |edge: f64| normal_upper_tail((edge - mean) / std_dev);
+        let totals = (lowest..=highest)
+            .map(|total| {
+                let (low, high) = (f64::from(total) - 0.5, f64::from(total) + 0.5);
+                let probability = match (total == lowest, total == highest) {
+                    (true, _) => below(high),
+                    (_, true) => above(low),
+                    _ if f64::from(total) < mean => below(high) - below(low),
+                    _ => above(low) - above(high),
+                };
+                (total, probability.max(0.0))
+            })
+            .collect();
+        Some(totals)
+    }
+
+    /// The Shannon entropy of the total in bits, `-Σ p log2 p` over every possible
+    /// total: how unpredictable the roll is. A fair die with `n` equally likely totals
+    /// has `log2 n`, and totals bunched in the middle, as for `3d6`, have less than a
+    /// flat roll over the same range.
+    pub fn entropy(&self) -> f64 {
+        -self
+            .distribution_map()
+            .values()
+            .filter(|&&probability| probability > 0.0)
+            .map(|&probability| probability * probability.log2())
+            .sum::<f64>()
+    }
+
+    /// The cumulative distribution of the total, for asking many threshold questions
+    /// of the same dice without working the distribution out each time.
+    pub fn cdf(&self) -> Cdf {
+        Cdf::new(self)
+    }
+
+    /// A sampler that draws totals from the exact distribution, much faster than
+    /// rolling pools with keep, drop or rerolls again and again.
+    pub fn sampler(&self) -> DiceSampler {
+        DiceSampler::new(self)
+    }
+
+    /// The probability (0.0 to 1.0) that the total is exactly `total`, which is 0.0
+    /// for totals the dice cannot make.
+    pub fn probability_of(&self, total: i32) -> f64 {
+        self.distribution_map().get(&total).copied().unwrap_or(0.0)
+    }
+
+    /// A total raised to at least `min` and lowered to at most `max`, where either
+    /// bound may be left off. When `min` is above `max`, `max` wins.
+    pub fn clamp_total(total: i32, min: Option<i32>, max: Option<i32>) -> i32 {
+        let total = min.map_or(total, |min| total.max(min));
+        max.map_or(total, |max| total.min(max))
+    }
+
+    /// The distribution of the total clamped as [`Dice::clamp_total`] clamps it: the
+    /// chance of every total outside the bounds is added to the bound it is clamped to.
+    pub fn clamped_distribution(&self, min: Option<i32>, max: Option<i32>) -> BTreeMap<i32, f64> {
+        let mut clamped = BTreeMap::new();
+        for (total, probability) in self.distribution_map() {
+            *clamped.entry(Dice::clamp_total(total, min, max)).or_insert(0.0) += probability;
+        }
+        clamped
+    }
+
+    /// The probability of every possible total found the slow way, by walking every
+    /// equally likely sequence of faces as [`Dice::outcomes_by_total`] does. This is
+    /// kept to check [`Dice::distribution_map`] against, and returns `None` in the same
+    /// cases as that method.
+    pub fn enumerated_distribution(&self, limit: usize) -> Option<BTreeMap<i32, f64>> {
+        let outcomes = self.outcomes_by_total(limit)?;
+        let size: usize = outcomes.values().map(Vec::len).sum();
+        Some(
+            outcomes
+                .into_iter()
+                .map(|(total, sequences)| (total, sequences.len() as f64 / size as f64))
+                .collect(),
+        )
+    }
+
+    /// Every equally likely sequence of faces the dice can show with the total it
+    /// produces, in the order the faces count up (`[1, 1]`, `[1, 2]` and so on for
+    /// `2d6`), under the same conditions as [`Dice::outcomes_by_total`].
+    pub fn sample_space(&self, limit: usize) -> Option<Vec<(Vec<i32>, i32)>> {
+        let mut outcomes: Vec<(Vec<i32>, i32)> = self
+            .outcomes_by_total(limit)?
+            .into_iter()
+            .flat_map(|(total, sequences)| sequences.into_iter().map(move |sequence| (sequence, total)))
+            .collect();
+        outcomes.sort();
+        Some(outcomes)
+    }
+
+    /// Every equally likely sequence of faces the dice can show, grouped by the
+    /// total it produces, in ascending order of total (for `2d6`, 7 comes from
+    /// `[1, 6]`, `[2, 5]` and so on up to `[6, 1]`).
+    ///
+    /// Returns `None` for exploding dice, dice with rolled sides, dice drawn without
+    /// replacement, or when there are more than `limit` sequences.
+    pub fn outcomes_by_total(&self, limit: usize) -> Option<BTreeMap<i32, Vec<Vec<i32>>>> {
+        if self.explode || self.sides_roll.is_some() || self.no_replacement {
+            return None;
+        }
+        let faces: Vec<i32> = self.faces().collect();
+        let size = (0..self.count).try_fold(1usize, |size, _| size.checked_mul(faces.len()))?;
+        if size > limit {
+            return None;
+        }
+
+        // Extend every sequence so far by each face of the next die
+        let mut sequences = vec![Vec::new()];
+        for _ in 0..self.count {
+            sequences = sequences
+                .into_iter()
+                .flat_map(|sequence: Vec<i32>| {
+                    faces.iter().map(move |&face| {
+                        let mut next = sequence.clone();
+                        next.push(face);
+                        next
+                    })
+                })
+                .collect();
+        }
+
+        let mut by_total: BTreeMap<i32, Vec<Vec<i32>>> = BTreeMap::new();
+        for sequence in sequences {
+            let kept: Vec<i32> = sequence
+                .iter()
+                .zip(self.keep_mask(&sequence))
+                .filter_map(|(&face, kept)| kept.then_some(face))
+                .collect();
+            let total = self.kept_total(&kept);
+            by_total.entry(total).or_default().push(sequence);
+        }
+
+        // Every sequence of a mixed pool continues with every sequence of the next term
+        let mut size = size;
+        for term in &self.extra_terms {
+            let term_outcomes = term.outcomes_by_total(limit)?;
+            size = size.checked_mul(term_outcomes.values().map(Vec::len).sum())?;
+            if size > limit {
+                return None;
+            }
+            let mut next: BTreeMap<i32, Vec<Vec<i32>>> = BTreeMap::new();
+            for (total, sequences) in &by_total {
+                for (term_total, term_sequences) in &term_outcomes {
+                    let entry = next.entry(total + term_total).or_default();
+                    for sequence in sequences {
+                        for term_sequence in term_sequences {
+                            entry.push([sequence.as_slice(), term_sequence].concat());
+                        }
+                    }
+                }
+            }
+            by_total = next;
+        }
+        Some(by_total)
+    }
+
+    /// The number of outcomes behind each total when only some dice are kept, like
+    /// [`Dice::kept_distribution`] but counting rather than weighing outcomes.
+    fn kept_frequencies(&self, keep: Keep) -> Option<BTreeMap<i32, u64>> {
+        let mut states = BTreeMap::from([(Vec::new(), 1u64)]);
+        for _ in 0..self.count {
+            let mut next: BTreeMap<Vec<i32>, u64> = BTreeMap::new();
+            for (kept, &frequency) in &states {
+                for face in self.faces() {
+                    let entry = next.entry(Self::keep_best(kept, &[face], keep)).or_insert(0);
+                    *entry = entry.checked_add(frequency)?;
+                }
+            }
+            states = next;
+        }
+
+        let mut totals: BTreeMap<i32, u64> = BTreeMap::new();
+        for (kept, frequency) in states {
+            let entry = totals.entry(self.kept_total(&kept)).or_insert(0);
+            *entry = entry.checked_add(frequency)?;
+        }
+        Some(totals)
+    }
+
+    /// The probability of every best total out of `rolls` independent rolls, keyed by
+    /// total. The best is at most `t` only when every roll is, so the chance of a best
+    /// of `t` is `P(total <= t)^rolls - P(total < t)^rolls`. A single roll is the
+    /// ordinary distribution, and `rolls` of 0 counts as 1.
+    pub fn best_of_distribution(&self, rolls: u32) -> BTreeMap<i32, f64> {
+        let rolls = f64::from(rolls.max(1));
+        let mut below = 0.0;
+        self.distribution_map()
+            .into_iter()
+            .map(|(total, probability)| {
+                let at_most = below + probability;
+                let best = at_most.powf(rolls) - f64::powf(below, rolls);
+                below = at_most;
+                (total, best)
+            })
+            .collect()
+    }
+
+    /// Every possible best total out of `rolls` independent rolls paired with the number
+    /// of equally likely outcomes that produce it, counting outcomes as in
+    /// [`Dice::outcome_frequencies`].
+    ///
+    /// Returns `None` when the rolls have no finite set of outcomes or their number does
+    /// not fit in a `u64`.
+    pub fn best_of_frequencies(&self, rolls: u32) -> Option<(Vec<i32>, Vec<u64>)> {
+        let (totals, counts) = self.outcome_frequencies()?;
+        let rolls = rolls.max(1);
+        let mut below: u64 = 0;
+        let mut best_counts = Vec::with_capacity(counts.len());
+        for count in counts {
+            let at_most = below.checked_add(count)?;
+            best_counts.push(at_most.checked_pow(rolls)? - below.pow(rolls));
+            below = at_most;
+        }
+        Some((totals, best_counts))
+    }
+
+    /// Every possible total paired with its percentage chance, in ascending order of total.
+    pub fn roll_distribution(&self) -> (Vec<i32>, Vec<f64>) {        
+        self.distribution_map()
+            .into_iter()
+            .map(|(total, probability)| (total, probability * 100.0))
+            .unzip()
+    }
+
+    /// The probability mass the two distributions share: the sum over every total of
+    /// the smaller of the two probabilities. Identical distributions overlap by 1.0
+    /// and distributions with no totals in common overlap by 0.0.
+    pub fn overlap(&self, other: &Dice) -> f64 {
+        let theirs = other.distribution_map();
+        self.distribution_map()
+            .iter()
+            .filter_map(|(total, &probability)| {
+                theirs.get(total).map(|&other_probability| probability.min(other_probability))
+            })
+            .sum()
+    }
+
+    /// The probability that this roll's total is strictly greater than the other's.
+    /// Ties do not count, so `1d6` beats another `1d6` with probability 15/36 and
+    /// `a.chance_to_beat(&b) + b.chance_to_beat(&a)` is one minus the chance of a tie.
+    pub fn chance_to_beat(&self, other: &Dice) -> f64 {
+        self.difference_distribution(other)
+            .range(1..)
+            .map(|(_, probability)| probability)
+            .sum()
+    }
+
+    /// The probabilities that this roll's total is greater than, equal to and less
+    /// than the other's, which add up to one.
+    pub fn chances_against(&self, other: &Dice) -> (f64, f64, f64) {
+        let difference = self.difference_distribution(other);
+        let sum = |range| difference.range(range).map(|(_, probability)| probability).sum::<f64>();
+        (sum(1..=i32::MAX), sum(0..=0), sum(i32::MIN..=-1))
+    }
+
+    /// The distribution of this roll's total minus the other's.
+    fn difference_distribution(&self, other: &Dice) -> BTreeMap<i32, f64> {
+        let theirs: BTreeMap<i32, f64> = other
+            .distribution_map()
+            .into_iter()
+            .map(|(total, probability)| (-total, probability))
+            .collect();
+        convolve(&self.distribution_map(), &theirs)
+    }
+
+    /// Combine two dice with the same number of sides into a single pool,
+    /// summing their counts and modifiers (e.g. `2d6+1` and `3d6+2` make `5d6+3`).
+    /// Custom dice only merge with dice showing the same faces, floored dice only with
+    /// dice of the same floor, and dice that keep or drop some of their dice do not
+    /// merge at all. Dice whose success targets, rerolls, explosions or way of combining
+    /// the dice differ are not merged either, since the pool could only roll one of them.
+    pub fn merge(&self, other: &Dice) -> Result<Dice, DiceError> {
+        if self.keep.is_some() || other.keep.is_some() {
+            return Err(DiceError::KeptDiceMerge);
+        }
+        let mismatched = [
+            ("success targets", self.success_target != other.success_target),
+            ("success weights", self.success_weight != other.success_weight),
+            ("double successes", self.double_face != other.double_face),
+            ("rerolls", self.reroll != other.reroll),
+            (
+                "explosions",
+                self.explode != other.explode
+                    || (self.explode && (self.explode_face, self.explode_which) != (other.explode_face, other.explode_which)),
+            ),
+            ("reductions", self.reduce != other.reduce),
+        ];
+        if let Some(&(setting, _)) = mismatched.iter().find(|(_, differ)| *differ) {
+            return Err(DiceError::MismatchedMerge { setting });
+        }
+        if self.sides != other.sides || self.custom_faces != other.custom_faces || self.sides_roll != other.sides_roll
+            || self.die_floor != other.die_floor || self.no_replacement != other.no_replacement
+        {
+            return Err(DiceError::MismatchedSides {
+                left: self.sides,
+                right: other.sides,
+            });
+        }
+
+        let count = self.count.checked_add(other.count).ok_or(DiceError::TooManyDice {
+            count: u32::from(self.count) + u32::from(other.count),
+        })?;
+        let modifier = self
+            .modifier
+            .checked_add(other.modifier)
+            .ok_or(DiceError::ModifierOverflow)?;
+
+        Ok(Dice {
+            sides: self.sides,
+            count,
+            modifier,
+            success_target: self.success_target,
+            success_weight: self.success_weight,
+            double_face: self.double_face,
+            reroll: self.reroll.clone(),
+            explode: self.explode,
+            percentile: self.percentile,
+            custom_faces: self.custom_faces.clone(),
+            custom_name: self.custom_name.clone(),
+            extra_terms: [self.extra_terms.as_slice(), &other.extra_terms].concat(),
+            keep: None,
+            keep_tie: self.keep_tie,
+            keep_order: self.keep_order,
+            explode_face: self.explode_face,
+            explode_which: self.explode_which,
+            sides_roll: self.sides_roll.clone(),
+            die_floor: self.die_floor,
+            floor_scope: self.floor_scope,
+            reduce: self.reduce,
+            explosion_limit: self.explosion_limit,
+            reroll_limit: self.reroll_limit,
+            no_replacement: self.no_replacement,
+        })
+    }
+}
+
+/// Writes the dice back as notation that [`Dice::parse_with`] reads as the same dice,
+/// given the registry holding any custom dice. Settings without notation, such as the
+/// reduce mode or the explosion limit, are left out.
+impl fmt::Display for Dice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.count, self.die_type())?;
+        if let Some(target) = self.success_target {
+            write!(f, ">={}", target)?;
+            if self.success_weight != 1 {
+                write!(f, "*{}", self.success_weight)?;
+            }
+            if let Some(face) = self.double_face {
+                write!(f, "^{}", face)?;
+            }
+        }
+        // Braces keep a following negative modifier from reading as a range
+        if !self.reroll.is_empty() {
+            let faces: Vec<String> = self.reroll.iter().map(i32::to_string).collect();
+            write!(f, "r{{{}}}", faces.join(","))?;
+        }
+        if self.explode {
+            write!(f, "!")?;
+            if self.explode_face == ExplodeFace::Lowest {
+                write!(f, "l")?;
+            }
+        }
+        match self.keep {
+            Some(Keep::Highest(count)) => write!(f, "kh{}", count)?,
+            Some(Keep::Lowest(count)) => write!(f, "kl{}", count)?,
+            None => {}
+        }
+        if let Some(floor) = self.die_floor {
+            write!(f, "min{}", floor)?;
+        }
+        if self.modifier != 0 {
+            write!(f, "{:+}", self.modifier)?;
+        }
+        for term in &self.extra_terms {
+            write!(f, " + {}", term)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{RngCore, SeedableRng};
+    use std::collections::VecDeque;
+
+    /// A random number generator that makes dice show a predetermined sequence of faces.
+    struct ForcedRng {
+        values: VecDeque<u32>,
+    }
+
+    impl ForcedRng {
+        /// Force the given faces, in order, on dice with `sides` sides.
+        fn new(sides: u8, faces: &[u8]) -> Self {
+            let dice: Vec<(u8, u8)> = faces.iter().map(|&face| (sides, face)).collect();
+            Self::mixed(&dice)
+        }
+
+        /// Force the given `(sides, face)` pairs, in order, for pools of mixed dice.
+        fn mixed(dice: &[(u8, u8)]) -> Self {
+            // random_range maps a u32 onto 1..=sides by taking the high half of
+            // value * sides, so the smallest value for each face lands on it exactly
+            let values = dice
+                .iter()
+                .map(|&(sides, face)| ((u64::from(face - 1) << 32).div_ceil(u64::from(sides))) as u32)
+                .collect();
+            ForcedRng { values }
+        }
+    }
+
+    impl RngCore for ForcedRng {
+        fn next_u32(&mut self) -> u32 {
+            self.values.pop_front().expect("ran out of forced faces")
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            u64::from(self.next_u32())
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            rand::rand_core::impls::fill_bytes_via_next(self, dest);
+        }
+    }
+
+    #[test]
+    fn test_parse_simple_dice() {
+        let dice = Dice::parse("1d6").unwrap();
+        assert_eq!(dice.count, 1);
+        assert_eq!(dice.sides, 6);
+        assert_eq!(dice.modifier, 0);
+    }
+
+    #[test]
+    fn test_parse_multiple_dice() {
+        let dice = Dice::parse("3d8").unwrap();
+        assert_eq!(dice.count, 3);
+        assert_eq!(dice.sides, 8);
+        assert_eq!(dice.modifier, 0);
+    }
+
+    #[test]
+    fn test_parse_dice_with_positive_modifier() {
+        let dice = Dice::parse("2d10+5").unwrap();
+        assert_eq!(dice.count, 2);
+        assert_eq!(dice.sides, 10);
+        assert_eq!(dice.modifier, 5);
+    }
+
+    #[test]
+    fn test_parse_dice_with_negative_modifier() {
+        let dice = Dice::parse("1d20-3").unwrap();
+        assert_eq!(dice.count, 1);
+        assert_eq!(dice.sides, 20);
+        assert_eq!(dice.modifier, -3);
+    }
+
+    #[test]
+    fn test_parse_whitespace_handling() {
+        let dice = Dice::parse("  2D6+1  ").unwrap();
+        assert_eq!(dice.count, 2);
+        assert_eq!(dice.sides, 6);
+        assert_eq!(dice.modifier, 1);
+    }
+
+    #[test]
+    fn test_parse_invalid_format() {
+        assert!(Dice::parse("invalid").is_err());
+        assert!(Dice::parse("2x6").is_err());
+        assert!(Dice::parse("d6").is_err());
+        assert!(Dice::parse("2d").is_err());
+    }
+
+    #[test]
+    fn test_parse_invalid_numbers() {
+        assert!(Dice::parse("abc d6").is_err());
+        assert!(Dice::parse("2d abc").is_err());
+        assert!(Dice::parse("2d6+ abc").is_err());
+    }
+
+    #[test]
+    fn test_parse_zero_values() {
+        assert!(Dice::parse("0d6").is_err());
+        assert!(Dice::parse("2d0").is_err());
+    }
+
+    #[test]
+    fn test_parse_success_pool() {
+        let dice = Dice::parse("5d10>=8").unwrap();
+        assert_eq!(dice.count, 5);
+        assert_eq!(dice.sides, 10);
+        assert_eq!(dice.success_target, Some(8));
+        assert_eq!(Dice::parse("2d6").unwrap().success_target, None);
+        assert!(Dice::parse("5d10>=").is_err());
+        assert!(Dice::parse("5d10>8").is_err());
+
+        let mut dice = Dice::parse("5d10").unwrap();
+        dice.set_success_target(8);
+        assert_eq!(dice, Dice::parse("5d10>=8").unwrap());
+        // A term with a target of its own keeps it
+        let mut dice = Dice::parse("2d6>=5 + 3d10").unwrap();
+        dice.set_success_target(8);
+        assert_eq!((dice.success_target, dice.extra_terms[0].success_target), (Some(5), Some(8)));
+    }
+
+    #[test]
+    fn test_success_pool_roll_range() {
+        let dice = Dice::parse("5d10>=8").unwrap();
+        let mut rng = rand::rng();
+        for _ in 0..100 {
+            assert!((0..=5).contains(&dice.roll_with(&mut rng)));
+        }
+    }
+
+    #[test]
+    fn test_expected_successes() {
+        let dice = Dice::parse("5d10>=8").unwrap();
+        assert!((dice.expected_successes().unwrap() - 5.0 * 3.0 / 10.0).abs() < 1e-12);
+        assert_eq!(Dice::parse("3d6").unwrap().expected_successes(), None);
+
+        // The closed form agrees with the mean of the exact distribution
+        let (successes, percentages) = dice.roll_distribution();
+        let mean: f64 = successes
+            .iter()
+            .zip(percentages.iter())
+            .map(|(&total, &percentage)| f64::from(total) * percentage / 100.0)
+            .sum();
+        assert!((mean - 1.5).abs() < 1e-9);
+
+        // A rerolled 10 never shows, so it never explodes: each die is 2 successes in 9
+        let rerolled = Dice::parse("5d10>=8r10!").unwrap();
+        assert!((rerolled.expected_successes().unwrap() - 5.0 * 2.0 / 9.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_parse_reroll() {
+        let faces = |spec: &str| Dice::parse(spec).unwrap().reroll.into_iter().collect::<Vec<i32>>();
+        assert_eq!(faces("4d6r1"), vec![1]);
+        assert_eq!(faces("4d6r1-2"), vec![1, 2]);
+        assert_eq!(faces("4d6r{1,3}"), vec![1, 3]);
+        assert_eq!(faces("4d6r{1-2,6}"), vec![1, 2, 6]);
+
+        // A modifier can still follow a braced reroll or come before the reroll
+        assert_eq!(Dice::parse("4d6r{1}-2").unwrap().modifier, -2);
+        assert_eq!(Dice::parse("4d6-2r1").unwrap().modifier, -2);
+
+        assert!(Dice::parse("4d6r").is_err());
+        assert!(Dice::parse("4d6r{1,3").is_err());
+        assert!(Dice::parse("4d6r3-1").is_err());
+        assert!(Dice::parse("1d6r1-6").is_err());
+
+        // Ranges only list the faces the die can show
+        assert_eq!(faces("4d6r5-9"), vec![5, 6]);
+        assert_eq!(faces("1d%r95-200"), vec![95, 96, 97, 98, 99, 100]);
+    }
+
+    #[test]
+    fn test_roll_rerolls_range() {
+        let dice = Dice::parse("4d6r1-2").unwrap();
+        let mut rng = StdRng::seed_from_u64(209);
+        for _ in 0..200 {
+            // Every die shows 3 or more once the range is rerolled
+            assert!((12..=24).contains(&dice.roll_with(&mut rng)));
+        }
+
+        let (totals, _) = dice.roll_distribution();
+        assert_eq!(totals.first(), Some(&12));
+        assert_eq!(totals.last(), Some(&24));
+    }
+
+    #[test]
+    fn test_forced_rng() {
+        let dice = Dice::parse("3d6").unwrap();
+        assert_eq!(dice.roll_with(&mut ForcedRng::new(6, &[1, 6, 4])), 11);
+    }
+
+    #[test]
+    fn test_roll_detailed() {
+        let dice = Dice::parse("3d10>=8!+1").unwrap();
+        let result = dice.roll_detailed_with(&mut ForcedRng::new(10, &[10, 9, 3, 5]));
+        let faces: Vec<i32> = result.dice.iter().map(|die| die.face).collect();
+        assert_eq!(faces, vec![10, 9, 3, 5]);
+        let exploded: Vec<bool> = result.dice.iter().map(|die| die.from_explosion).collect();
+        assert_eq!(exploded, vec![false, true, false, false]);
+        let successes = result.dice.iter().filter(|die| die.success).count();
+        assert_eq!(successes, 2);
+        assert_eq!(result.modifier, 1);
+        assert_eq!(result.total, 3);
+    }
+
+    #[test]
+    fn test_roll_avoiding_previous_total() {
+        let dice = Dice::parse("1d6").unwrap();
+        assert_eq!(dice.roll_avoiding(Some(4), &mut ForcedRng::new(6, &[4, 4, 2])), 2);
+        assert_eq!(dice.roll_avoiding(None, &mut ForcedRng::new(6, &[4])), 4);
+
+        let mut rng = StdRng::seed_from_u64(297);
+        let mut previous = None;
+        for _ in 0..500 {
+            let total = dice.roll_avoiding(previous, &mut rng);
+            assert_ne!(Some(total), previous);
+            previous = Some(total);
+        }
+        // A die with one face cannot help repeating, but still stops rolling
+        assert_eq!(Dice::parse("1d1").unwrap().roll_avoiding(Some(1), &mut rng), 1);
+    }
+
+    #[test]
+    fn test_die_term_in_mixed_pool() {
+        let dice = Dice::parse("2d6 + 1d8 + 1d%").unwrap();
+        let result = dice.roll_detailed_with(&mut StdRng::seed_from_u64(304));
+        let terms: Vec<usize> = result.dice.iter().map(|die| die.term).collect();
+        assert_eq!(terms, vec![0, 0, 1, 2]);
+        let types: Vec<String> = std::iter::once(&dice).chain(&dice.extra_terms).map(Dice::die_type).collect();
+        assert_eq!(types, vec!["d6", "d8", "d%"]);
+        assert_eq!(Dice::parse("1d(1d6)").unwrap().die_type(), "d(1d6)");
+    }
+
+    #[test]
+    fn test_success_count_distribution_is_binomial() {
+        let counts = Dice::parse("5d10>=8").unwrap().success_count_distribution().unwrap();
+        assert_eq!(counts.len(), 6);
+        let choose = [1.0, 5.0, 10.0, 10.0, 5.0, 1.0];
+        for (successes, probability) in counts {
+            let k = successes as i32;
+            let binomial = choose[successes as usize] * 0.3_f64.powi(k) * 0.7_f64.powi(5 - k);
+            assert!((probability - binomial).abs() < 1e-12, "{} successes", successes);
+        }
+
+        // Weights and modifiers do not change how many dice succeed
+        let weighted = Dice::parse("5d10>=8*2+1").unwrap().success_count_distribution();
+        assert_eq!(weighted, Dice::parse("5d10>=8").unwrap().success_count_distribution());
+        let mixed = Dice::parse("2d6>=5 + 1d10>=8 + 1d4").unwrap().success_count_distribution().unwrap();
+        assert_eq!(mixed.keys().copied().collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+        assert_eq!(Dice::parse("3d6").unwrap().success_count_distribution(), None);
+    }
+
+    #[test]
+    fn test_roll_batch_into_reuses_buffer() {
+        let dice = Dice::parse("2d6+1").unwrap();
+        let mut rng = StdRng::seed_from_u64(294);
+        let mut buf = vec![-1; 3];
+        dice.roll_batch_into(&mut rng, 500, &mut buf);
+        assert_eq!(buf.len(), 500);
+        assert!(buf.iter().all(|total| (3..=13).contains(total)));
+
+        let (capacity, pointer) = (buf.capacity(), buf.as_ptr());
+        for count in [500, 20, 0] {
+            dice.roll_batch_into(&mut rng, count, &mut buf);
+            assert_eq!(buf.len(), count);
+            assert_eq!((buf.capacity(), buf.as_ptr()), (capacity, pointer));
+        }
+        dice.roll_batch_into(&mut ForcedRng::new(6, &[1, 6, 4, 4]), 2, &mut buf);
+        assert_eq!(buf, vec![8, 9]);
+    }
+
+    #[test]
+    fn test_probability_of_exact_total() {
+        let dice = Dice::parse("2d6").unwrap();
+        assert!((dice.probability_of(7) - 6.0 / 36.0).abs() < 1e-12);
+        assert_eq!(dice.probability_of(13), 0.0);
+        assert_eq!(dice.probability_of(-4), 0.0);
+    }
+
+    #[test]
+    fn test_roll_bounds_and_expected_value() {
+        let dice = Dice::parse("2d6+3").unwrap();
+        assert_eq!((dice.min_roll(), dice.max_roll()), (5, 15));
+        assert!((dice.expected_value() - 10.0).abs() < 1e-12);
+
+        let dice = Dice::parse("4d6kh3").unwrap();
+        assert_eq!((dice.min_roll(), dice.max_roll()), (3, 18));
+        assert_eq!(Dice::parse("3d10>=8-1").unwrap().min_roll(), -1);
+    }
+
+    #[test]
+    fn test_clamped_distribution() {
+        assert_eq!(Dice::clamp_total(1, Some(3), None), 3);
+        assert_eq!(Dice::clamp_total(5, Some(3), Some(4)), 4);
+        assert_eq!(Dice::clamp_total(5, None, None), 5);
+        assert_eq!(Dice::clamp_total(2, Some(6), Some(4)), 4);
+
+        let d6 = Dice::parse("1d6").unwrap();
+        let clamped = d6.clamped_distribution(Some(3), None);
+        assert_eq!(clamped.keys().copied().collect::<Vec<_>>(), vec![3, 4, 5, 6]);
+        // The chances of 1 and 2 both land on 3
+        assert!((clamped[&3] - 0.5).abs() < 1e-12);
+        assert!((clamped[&4] - 1.0 / 6.0).abs() < 1e-12);
+        assert!((clamped.values().sum::<f64>() - 1.0).abs() < 1e-12);
+
+        let both = Dice::parse("2d6").unwrap().clamped_distribution(Some(4), Some(10));
+        assert!((both[&4] - 6.0 / 36.0).abs() < 1e-12);
+        assert!((both[&10] - 6.0 / 36.0).abs() < 1e-12);
+        assert_eq!(both.len(), 7);
+    }
+
+    #[test]
+    fn test_chance_to_beat() {
+        // Summing P(2d6 > x) over x = 1..12 gives 216/36, so the chance is exactly a half
+        let two_d6 = Dice::parse("2d6").unwrap();
+        let d12 = Dice::parse("1d12").unwrap();
+        assert!((two_d6.chance_to_beat(&d12) - 0.5).abs() < 1e-12);
+
+        let d6 = Dice::parse("1d6").unwrap();
+        assert!((d6.chance_to_beat(&d6) - 15.0 / 36.0).abs() < 1e-12);
+        assert_eq!(Dice::parse("1d4").unwrap().chance_to_beat(&Dice::parse("1d4+4").unwrap()), 0.0);
+
+        let goblin = Dice::parse("1d8+1").unwrap();
+        let (win, tie, lose) = two_d6.chances_against(&goblin);
+        assert!((win + tie + lose - 1.0).abs() < 1e-12);
+        assert!((win - two_d6.chance_to_beat(&goblin)).abs() < 1e-12);
+        assert!((lose - goblin.chance_to_beat(&two_d6)).abs() < 1e-12);
+        // Each total from 2 to 9 of the d8 is matched by the 2d6 with the chance of that total
+        assert!((tie - 30.0 / 288.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_outcomes_by_total() {
+        let outcomes = Dice::parse("2d6").unwrap().outcomes_by_total(36).unwrap();
+        assert_eq!(outcomes[&7].len(), 6);
+        assert_eq!(outcomes[&7][0], vec![1, 6]);
+        assert_eq!(outcomes[&2], vec![vec![1, 1]]);
+        assert_eq!(outcomes.values().map(Vec::len).sum::<usize>(), 36);
+
+        assert!(Dice::parse("2d6").unwrap().outcomes_by_total(35).is_none());
+        assert!(Dice::parse("2d6!").unwrap().outcomes_by_total(1000).is_none());
+    }
+
+    #[test]
+    fn test_new_validates_count_and_sides() {
+        let dice = Dice::new(4, 8, -1).unwrap();
+        assert_eq!(dice, Dice::parse("4d8-1").unwrap());
+        assert_eq!((dice.min_roll(), dice.max_roll()), (3, 31));
+        assert_eq!(Dice::new(1, 20, 0).unwrap(), Dice::parse("1d20").unwrap());
+
+        assert_eq!(Dice::new(0, 6, 2), Err(DiceError::ZeroCount { spec: "0d6+2".to_string() }));
+        assert_eq!(Dice::new(3, 0, 0), Err(DiceError::ZeroSides { spec: "3d0".to_string() }));
+    }
+
+    #[test]
+    fn test_stat_block_average() {
+        // Averages as printed in published stat blocks
+        let average = |spec| Dice::parse(spec).unwrap().stat_block_average();
+        assert_eq!(average("2d6+3"), 10);
+        assert_eq!(average("1d6+2"), 5);
+        assert_eq!(average("2d8+4"), 13);
+        assert_eq!(average("2d10+8"), 19);
+        assert_eq!(average("8d6"), 28);
+        assert_eq!(average("7d10+21"), 59);
+        assert_eq!(average("19d12+133"), 256);
+        assert_eq!(average("1d4"), 2);
+        assert_eq!(average("1d4-3"), -1);
+    }
+
+    #[test]
+    fn test_with_count_and_sides() {
+        let dice = Dice::parse("4d6kh3r1!+2").unwrap();
+        let more = dice.with_count(6).unwrap();
+        assert_eq!(more.count, 6);
+        assert_eq!(more, Dice { count: 6, ..dice.clone() });
+        assert_eq!(more.to_string(), "6d6r{1}!kh3+2");
+        let larger = dice.with_sides(8).unwrap();
+        assert_eq!(larger, Dice { sides: 8, ..dice.clone() });
+        // The original is left as it was
+        assert_eq!(dice, Dice::parse("4d6kh3r1!+2").unwrap());
+
+        assert_eq!(dice.with_count(0), Err(DiceError::ZeroCount { spec: "0d6r{1}!kh3+2".to_string() }));
+        assert!(matches!(dice.with_count(2), Err(DiceError::InvalidKeep { .. })));
+        assert!(matches!(dice.with_sides(0), Err(DiceError::ZeroSides { .. })));
+        assert!(matches!(dice.with_sides(1), Err(DiceError::InvalidReroll { .. })));
+
+        // Other dice become plain dice with the new sides, and other terms stay
+        let mixed = Dice::parse("1d% + 2d8").unwrap().with_sides(12).unwrap();
+        assert_eq!(mixed.die_type(), "d12");
+        assert_eq!(mixed.extra_terms, Dice::parse("1d% + 2d8").unwrap().extra_terms);
+    }
+
+    #[test]
+    fn test_try_parse_all_collects_every_error() {
+        let specs = |list: &[&str]| list.iter().map(|spec| spec.to_string()).collect::<Vec<_>>();
+        let dice = Dice::try_parse_all(&specs(&["2d6", "1d20+3"])).unwrap();
+        assert_eq!(dice, vec![Dice::parse("2d6").unwrap(), Dice::parse("1d20+3").unwrap()]);
+
+        let failed = Dice::try_parse_all(&specs(&["0d6", "2d6", "2d", "1d20"])).unwrap_err();
+        assert_eq!(failed.len(), 2);
+        assert_eq!(failed[0].0, "0d6");
+        assert!(matches!(failed[0].1, DiceError::ZeroCount { .. }));
+        assert_eq!(failed[1].0, "2d");
+        assert!(Dice::try_parse_all(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_sample_space() {
+        let outcomes = Dice::parse("2d4kh1+1").unwrap().sample_space(16).unwrap();
+        assert_eq!(outcomes.len(), 16);
+        assert_eq!(outcomes[0], (vec![1, 1], 2));
+        assert_eq!(outcomes[1], (vec![1, 2], 3));
+        assert_eq!(outcomes[4], (vec![2, 1], 3));
+        assert_eq!(outcomes[15], (vec![4, 4], 5));
+        assert!(Dice::parse("2d4").unwrap().sample_space(15).is_none());
+    }
+
+    #[test]
+    fn test_mixed_pool_targets() {
+        let dice = Dice::parse("2d6>=5 + 1d10>=8").unwrap();
+        assert_eq!((dice.count, dice.sides, dice.success_target), (2, 6, Some(5)));
+        assert_eq!(dice.extra_terms.len(), 1);
+        assert_eq!(dice.extra_terms[0].success_target, Some(8));
+
+        // A 5 and an 8 are both successes, though the 8 only on the d10
+        let mut rng = ForcedRng::mixed(&[(6, 5), (6, 2), (10, 8)]);
+        let result = dice.roll_detailed_with(&mut rng);
+        let successes: Vec<bool> = result.dice.iter().map(|die| die.success).collect();
+        assert_eq!(successes, vec![true, false, true]);
+        assert_eq!(result.total, 2);
+
+        let expected = dice.expected_successes().unwrap();
+        assert!((expected - (2.0 / 6.0 * 2.0 + 3.0 / 10.0)).abs() < 1e-12);
+        let (totals, frequencies) = dice.outcome_frequencies().unwrap();
+        assert_eq!(totals, vec![0, 1, 2, 3]);
+        assert_eq!(frequencies.iter().sum::<u64>(), 360);
+    }
+
+    #[test]
+    fn test_mixed_pool_sums_terms() {
+        let dice = Dice::parse("2d6+1 + 1d8").unwrap();
+        assert_eq!((dice.count, dice.sides, dice.modifier), (2, 6, 1));
+        assert_eq!(dice.extra_terms[0].sides, 8);
+        let (totals, _) = dice.roll_distribution();
+        assert_eq!(totals, (4..=21).collect::<Vec<i32>>());
+        let result = dice.roll_detailed_with(&mut ForcedRng::mixed(&[(6, 3), (6, 4), (8, 8)]));
+        assert_eq!(result.total, 16);
+    }
+
+    #[test]
+    fn test_parse_keep_and_drop() {
+        assert_eq!(Dice::parse("4d6kh3").unwrap().keep, Some(Keep::Highest(3)));
+        assert_eq!(Dice::parse("2d20k").unwrap().keep, Some(Keep::Highest(1)));
+        assert_eq!(Dice::parse("2d20kl1+2").unwrap().keep, Some(Keep::Lowest(1)));
+        assert_eq!(Dice::parse("4d6dl1").unwrap().keep, Some(Keep::Highest(3)));
+        assert_eq!(Dice::parse("5d6dh2").unwrap().keep, Some(Keep::Lowest(3)));
+        assert_eq!(Dice::parse("4d6").unwrap().keep, None);
+        assert!(Dice::parse("4d6kh0").is_err());
+        assert!(Dice::parse("4d6kh5").is_err());
+        assert!(Dice::parse("3d6dh3").is_err());
+    }
+
+    #[test]
+    fn test_keep_never_leaves_zero_dice() {
+        for spec in ["3d6dh3", "3d6dl3", "3d6dl4", "4d6kh0", "4d6dh0", "2d6 + 3d6dh3", "1d(3d6dh3)"] {
+            assert!(
+                matches!(Dice::parse(spec), Err(DiceError::InvalidKeep { .. } | DiceError::InvalidSidesRoll { .. })),
+                "{}",
+                spec
+            );
+        }
+        assert_eq!(Dice::parse("3d6dh2").unwrap().keep, Some(Keep::Lowest(1)));
+
+        // A keep of 0 set by hand still keeps the best die
+        let mut dice = Dice::parse("3d6").unwrap();
+        dice.keep = Some(Keep::Highest(0));
+        let result = dice.roll_detailed_with(&mut ForcedRng::new(6, &[2, 5, 3]));
+        assert_eq!(result.total, 5);
+        assert_eq!(result.dice.iter().filter(|die| die.kept).count(), 1);
+        assert_eq!(dice.distribution_map(), Dice::parse("3d6kh1").unwrap().distribution_map());
+    }
+
+    #[test]
+    fn test_parse_strict_modifier_sign() {
+        let signed = |spec| {
+            Dice::parse_with_strictness(spec, &DieRegistry::new(), Strictness { modifier_sign: true, ..Default::default() })
+        };
+        for spec in ["2d6+3-1", "2d6+03", "2d6-3>=5", "4d6+1kh3", "1d20+2 + 1d4-1-1"] {
+            assert!(matches!(signed(spec), Err(DiceError::AmbiguousModifier { .. })), "{}", spec);
+            // Without the check the modifier is read as before
+            assert!(Dice::parse(spec).is_ok(), "{}", spec);
+        }
+        for spec in ["2d6+3", "2d6-1", "4d6kh3+1", "2d6+0", "1d20+2 + 1d4-1", "1d(1d4+1)+10", "2d6r1-2+3"] {
+            assert_eq!(signed(spec).unwrap(), Dice::parse(spec).unwrap(), "{}", spec);
+        }
+        // Malformed modifiers are errors either way
+        assert!(matches!(signed("2d6 3"), Err(DiceError::UnknownNotation { .. })));
+        assert!(matches!(signed("2d6++3"), Err(DiceError::InvalidNumber { .. })));
+    }
+
+    #[test]
+    fn test_parse_strict() {
+        let strict = |spec| Dice::parse_strict_with(spec, &DieRegistry::new());
+        assert!(matches!(strict("2d6k2"), Err(DiceError::AmbiguousNotation { .. })));
+        assert!(matches!(strict("1d(2d6k)"), Err(DiceError::AmbiguousNotation { .. })));
+        assert!(matches!(strict("2d6xyz"), Err(DiceError::UnknownNotation { .. })));
+        assert_eq!(strict("4d6dl1").unwrap(), Dice::parse("4d6dl1").unwrap());
+    }
+
+    #[test]
+    fn test_keep_distribution() {
+        let advantage = Dice::parse("2d20kh1").unwrap();
+        assert!((advantage.probability_of(20) - 39.0 / 400.0).abs() < 1e-12);
+        assert!((advantage.probability_of(1) - 1.0 / 400.0).abs() < 1e-12);
+
+        let (totals, frequencies) = Dice::parse("4d6kh3").unwrap().outcome_frequencies().unwrap();
+        assert_eq!((totals[0], *totals.last().unwrap()), (3, 18));
+        assert_eq!(frequencies.iter().sum::<u64>(), 1296);
+        let mean: f64 = totals
+            .iter()
+            .zip(&frequencies)
+            .map(|(&total, &frequency)| f64::from(total) * frequency as f64)
+            .sum::<f64>()
+            / 1296.0;
+        assert!((mean - 15869.0 / 1296.0).abs() < 1e-9);
+        assert_eq!(
+            Dice::parse("4d6dl1").unwrap().distribution_map(),
+            Dice::parse("4d6kh3").unwrap().distribution_map()
+        );
+    }
+
+    #[test]
+    fn test_keep_tie_policy() {
+        let mut dice = Dice::parse("3d20kh1").unwrap();
+        let kept = |dice: &Dice| {
+            let result = dice.roll_detailed_with(&mut ForcedRng::new(20, &[15, 4, 15]));
+            assert_eq!(result.total, 15);
+            result.dice.iter().map(|die| die.kept).collect::<Vec<bool>>()
+        };
+        assert_eq!(kept(&dice), vec![true, false, false]);
+        dice.set_keep_tie(KeepTie::Last);
+        assert_eq!(kept(&dice), vec![false, false, true]);
+    }
+
+    #[test]
+    fn test_floor_kept_dice_only() {
+        let mut dice = Dice::parse("4d6kh3min3").unwrap();
+        let faces = [1, 5, 1, 6];
+        let everywhere = dice.roll_detailed_with(&mut ForcedRng::new(6, &faces));
+        assert_eq!(everywhere.dice.iter().map(|die| die.face).collect::<Vec<i32>>(), vec![3, 5, 3, 6]);
+
+        // The kept 1 is raised to 3, while the dropped 1 keeps its natural face
+        dice.set_floor_scope(FloorScope::KeptDice);
+        let kept_only = dice.roll_detailed_with(&mut ForcedRng::new(6, &faces));
+        assert_eq!(kept_only.dice.iter().map(|die| die.face).collect::<Vec<i32>>(), vec![3, 5, 1, 6]);
+        assert_eq!(kept_only.dice.iter().map(|die| die.kept).collect::<Vec<bool>>(), vec![true, true, false, true]);
+        assert_eq!((kept_only.total, everywhere.total), (14, 14));
+
+        let mut pool = Dice::parse("3d6kl2min4>=5").unwrap();
+        pool.set_floor_scope(FloorScope::KeptDice);
+        let result = pool.roll_detailed_with(&mut ForcedRng::new(6, &[1, 2, 6]));
+        // The 1 and 2 are kept and raised to 4, which is still short of the target
+        assert_eq!(result.dice.iter().map(|die| die.face).collect::<Vec<i32>>(), vec![4, 4, 6]);
+        assert_eq!(result.total, 0);
+    }
+
+    #[test]
+    fn test_keep_order() {
+        let mut dice = Dice::parse("4d6!kh3").unwrap();
+        let faces = [6, 1, 5, 4, 3];
+        // After exploding, the 6, 5 and 4 are the highest of all five dice
+        assert_eq!(dice.roll_with(&mut ForcedRng::new(6, &faces)), 15);
+        let after = dice.distribution_map();
+
+        // Before exploding, the 6 is kept by its first face and brings its 1 along
+        dice.set_keep_order(KeepOrder::BeforeExplode);
+        let result = dice.roll_detailed_with(&mut ForcedRng::new(6, &faces));
+        assert_eq!(result.total, 16);
+        assert_eq!(result.dice.iter().map(|die| die.kept).collect::<Vec<bool>>(), vec![true, true, true, true, false]);
+
+        let before = dice.distribution_map();
+        assert_ne!(before, after);
+        let total_probability: f64 = before.values().sum();
+        assert!((total_probability - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_rolled_sides() {
+        let dice = Dice::parse("1d(1d6)").unwrap();
+        assert_eq!(dice.sides, 6);
+        let mut rng = StdRng::seed_from_u64(235);
+        for _ in 0..100 {
+            let result = dice.roll_detailed_with(&mut rng);
+            let sides = result.rolled_sides.unwrap();
+            assert!((1..=6).contains(&sides));
+            assert!((1..=i32::from(sides)).contains(&result.total));
+        }
+
+        // A 1 comes up on every die size, so its chance averages 1/s over s = 1..6
+        let harmonic: f64 = (1..=6).map(|sides| 1.0 / f64::from(sides)).sum();
+        assert!((dice.probability_of(1) - harmonic / 6.0).abs() < 1e-12);
+        let total_probability: f64 = dice.distribution_map().values().sum();
+        assert!((total_probability - 1.0).abs() < 1e-12);
+
+        let dice = Dice::parse("2d(1d4+1d4)+1 + 1d6").unwrap();
+        assert_eq!((dice.count, dice.sides, dice.modifier), (2, 8, 1));
+        assert_eq!(dice.extra_terms.len(), 1);
+    }
+
+    #[test]
+    fn test_best_of_distribution() {
+        // The best of two d6 is k when both are at most k but not both below it: (2k - 1)/36
+        let dice = Dice::parse("1d6").unwrap();
+        let best = dice.best_of_distribution(2);
+        assert_eq!(best.len(), 6);
+        for (&total, &probability) in &best {
+            assert!((probability - f64::from(2 * total - 1) / 36.0).abs() < 1e-12);
+        }
+        let (totals, counts) = dice.best_of_frequencies(2).unwrap();
+        assert_eq!(totals, vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(counts, vec![1, 3, 5, 7, 9, 11]);
+
+        for (best, single) in dice.best_of_distribution(1).values().zip(dice.distribution_map().values()) {
+            assert!((best - single).abs() < 1e-12);
+        }
+        let total_probability: f64 = Dice::parse("3d6").unwrap().best_of_distribution(3).values().sum();
+        assert!((total_probability - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_entropy() {
+        assert!((Dice::parse("1d6").unwrap().entropy() - 6f64.log2()).abs() < 1e-12);
+        assert_eq!(Dice::parse("1d1+3").unwrap().entropy(), 0.0);
+        // 3d6 makes 16 totals, but is less unpredictable than a flat roll over 16
+        let bell = Dice::parse("3d6").unwrap().entropy();
+        assert!(bell > 6f64.log2() && bell < 16f64.log2());
+    }
+
+    #[test]
+    fn test_weighted_success_pool() {
+        let dice = Dice::parse("2d6>=5 + 1d10>=8*2").unwrap();
+        assert_eq!(dice.success_weight, 1);
+        assert_eq!(dice.extra_terms[0].success_weight, 2);
+        // 5 and 2 on the d6s, 9 on the d10: one success plus a double success
+        let result = dice.roll_detailed_with(&mut ForcedRng::mixed(&[(6, 5), (6, 2), (10, 9)]));
+        assert_eq!(result.total, 3);
+        assert_eq!(result.dice.iter().filter(|die| die.success).count(), 2);
+        assert_eq!(dice.max_roll(), 4);
+        assert!((dice.expected_successes().unwrap() - (2.0 / 3.0 + 0.6)).abs() < 1e-12);
+
+        assert!(matches!(Dice::parse("2d6*2"), Err(DiceError::InvalidWeight { .. })));
+        assert!(matches!(Dice::parse("2d6>=5*0"), Err(DiceError::InvalidWeight { .. })));
+    }
+
+    #[test]
+    fn test_double_success_face() {
+        let dice = Dice::parse("1d10>=7^10").unwrap();
+        assert_eq!(dice.double_face, Some(10));
+        assert_eq!(dice.roll_with(&mut ForcedRng::new(10, &[10])), 2);
+        assert_eq!(dice.roll_with(&mut ForcedRng::new(10, &[7])), 1);
+        assert_eq!(dice.roll_with(&mut ForcedRng::new(10, &[6])), 0);
+
+        // Seven, nine and ten: four successes from three dice
+        let pool = Dice::parse("3d10>=7^10").unwrap();
+        assert_eq!(pool.roll_with(&mut ForcedRng::new(10, &[7, 9, 10])), 4);
+        assert!((pool.expected_successes().unwrap() - 3.0 * 0.5).abs() < 1e-12);
+        assert!((pool.probability_of(6) - 0.001).abs() < 1e-12);
+
+        assert!(matches!(Dice::parse("1d10^10"), Err(DiceError::InvalidDoubleFace { .. })));
+        assert!(matches!(Dice::parse("1d10>=7^6"), Err(DiceError::InvalidDoubleFace { .. })));
+        assert_eq!(
+            Dice::parse("1d10>=7^6").unwrap_err().to_string(),
+            "Invalid double success in '1d10>=7^6': the face counted twice must meet the success target"
+        );
+    }
+
+    #[test]
+    fn test_fudge_dice() {
+        let dice = Dice::parse("4dF+1").unwrap();
+        assert_eq!(dice.custom_faces, Some(vec![-1, 0, 1]));
+        assert_eq!((dice.min_roll(), dice.max_roll()), (-3, 5));
+        assert!((dice.probability_of(1) - 19.0 / 81.0).abs() < 1e-12);
+        assert_eq!(dice.die_type(), "df");
+        assert_eq!(Dice::parse("4df").unwrap().custom_faces, dice.custom_faces);
+
+        // Longer names are still custom dice, and a registered die of the name wins
+        assert!(matches!(Dice::parse("4dfoo"), Err(DiceError::UnknownDie { name, .. }) if name == "foo"));
+        let mut registry = DieRegistry::new();
+        registry.insert("f", vec![0, 0, 1]).unwrap();
+        assert_eq!(Dice::parse_with("4df", &registry).unwrap().max_roll(), 4);
+        assert_eq!(Dice::parse_with("4df", &registry).unwrap().min_roll(), 0);
+    }
+
+    #[test]
+    fn test_display_round_trips() {
+        let mut registry = DieRegistry::new();
+        registry.insert("fate", vec![-1, 0, 1]).unwrap();
+        let forms = [
+            "2d6", "1d20+5", "3d8-2", "5d10>=8", "2d10>=8*2", "10d10>=7^10", "4d6r1", "4d6r1-2", "4d6r{1,3}-2",
+            "6d10>=8!", "4d6kh3", "2d20kl1", "4d6dl1", "4d6dh1", "4d6min3", "1d%", "3d%+10", "4dfate", "4dfate+1",
+            "4dF", "4dF+2", "1d(1d6)", "2d(1d4+1d6)+1", "2d6>=5 + 1d10>=8", "1d20+2 + 1d4", "8d6r{1,2}!kh5min2+3",
+            "2d6!l", "6d10>=8!lkh3+1",
+        ];
+        for spec in forms {
+            let dice = Dice::parse_with(spec, &registry).unwrap();
+            let written = dice.to_string();
+            assert_eq!(Dice::parse_with(&written, &registry).unwrap(), dice, "{} -> {}", spec, written);
+        }
+        assert_eq!(Dice::parse("4d6dl1+2").unwrap().to_string(), "4d6kh3+2");
+        assert_eq!(Dice::parse("2d6>=5+1d10>=8").unwrap().to_string(), "2d6>=5 + 1d10>=8");
+    }
+
+    #[test]
+    fn test_reduce_modes() {
+        let reduced = |spec, reduce| {
+            let mut dice = Dice::parse(spec).unwrap();
+            dice.set_reduce(reduce);
+            dice
+        };
+        let product = reduced("2d6", Reduce::Product);
+        assert_eq!((product.min_roll(), product.max_roll()), (1, 36));
+        // 12 comes from 2x6, 3x4, 4x3 and 6x2
+        assert!((product.probability_of(12) - 4.0 / 36.0).abs() < 1e-12);
+        assert_eq!(product.outcome_frequencies().unwrap().0.len(), 18);
+        assert_eq!(product.roll_detailed_with(&mut ForcedRng::new(6, &[3, 5])).total, 15);
+
+        // The highest of two d20 matches keeping the highest one
+        assert_eq!(
+            reduced("2d20+1", Reduce::Max).distribution_map().keys().collect::<Vec<_>>(),
+            Dice::parse("2d20kh1+1").unwrap().distribution_map().keys().collect::<Vec<_>>()
+        );
+        assert_eq!(reduced("3d6", Reduce::Min).max_roll(), 6);
+        assert_eq!(reduced("1d4 + 2d6", Reduce::Product).max_roll(), 40);
+    }
+
+    #[test]
+    fn test_roll_overflow_is_an_error() {
+        let mut registry = DieRegistry::new();
+        registry.insert("huge", vec![i32::MAX]).unwrap();
+        let dice = Dice::parse_with("2dhuge", &registry).unwrap();
+        let mut rng = StdRng::seed_from_u64(250);
+        assert_eq!(dice.try_roll_with(&mut rng), Err(DiceRollError::Overflow));
+        assert_eq!(dice.roll(), Err(DiceRollError::Overflow));
+        // The infallible roll saturates instead of panicking
+        assert_eq!(dice.roll_with(&mut rng), i32::MAX);
+
+        let dice = Dice::parse_with("1dhuge-1 + 1d1", &registry).unwrap();
+        assert_eq!(dice.try_roll_with(&mut rng).unwrap().total, i32::MAX);
+        assert_eq!(Dice::parse("3d6").unwrap().roll().unwrap().dice.len(), 3);
+    }
+
+    #[test]
+    fn test_mechanics_respect_their_own_limits() {
+        let mut exploding = Dice::parse("2d6!").unwrap();
+        exploding.set_explosion_limit(2);
+        let result = exploding.roll_detailed_with(&mut ForcedRng::new(6, &[6; 6]));
+        assert_eq!(result.dice.len(), 6);
+        assert_eq!(result.total, 36);
+        assert_eq!(exploding.max_roll(), 36);
+
+        // Rerolls stop after the limit and keep the face, leaving the explosion limit alone
+        let mut rerolling = Dice::parse("1d6r1-4!").unwrap();
+        rerolling.set_reroll_limit(3);
+        assert_eq!(rerolling.explosion_limit, MAX_EXPLOSIONS);
+        let result = rerolling.roll_detailed_with(&mut ForcedRng::new(6, &[1, 2, 3, 4, 5]));
+        assert_eq!(result.total, 4);
+
+        let defaults = Dice::parse("1d6").unwrap();
+        assert_eq!((defaults.explosion_limit, defaults.reroll_limit), (MAX_EXPLOSIONS, MAX_REROLLS));
+    }
+
+    #[test]
+    fn test_no_replacement() {
+        let mut dice = Dice::parse("3d6").unwrap();
+        dice.set_no_replacement().unwrap();
+        let mut rng = StdRng::seed_from_u64(253);
+        for _ in 0..200 {
+            let mut faces: Vec<i32> = dice.roll_detailed_with(&mut rng).dice.iter().map(|die| die.face).collect();
+            faces.sort_unstable();
+            faces.dedup();
+            assert_eq!(faces.len(), 3);
+        }
+
+        // Each of the 20 sets of three distinct faces is equally likely
+        let distribution = dice.distribution_map();
+        assert_eq!(distribution.keys().next(), Some(&6));
+        assert_eq!(distribution.keys().last(), Some(&15));
+        assert!((distribution[&6] - 1.0 / 20.0).abs() < 1e-12);
+        assert!((distribution[&10] - 3.0 / 20.0).abs() < 1e-12);
+        assert!((dice.expected_value() - 10.5).abs() < 1e-9);
+        assert_eq!(dice.outcome_frequencies(), None);
+
+        // Keeping the highest 2 of 3 distinct faces never keeps a pair
+        let mut kept = Dice::parse("3d6kh2").unwrap();
+        kept.set_no_replacement().unwrap();
+        assert_eq!(kept.min_roll(), 5);
+        assert!((kept.probability_of(11) - 4.0 / 20.0).abs() < 1e-12);
+
+        assert!(matches!(
+            Dice::parse("7d6").unwrap().set_no_replacement(),
+            Err(DiceError::InvalidDraw { .. })
+        ));
+        assert!(Dice::parse("2d6!").unwrap().set_no_replacement().is_err());
+        assert!(Dice::parse("6d6").unwrap().set_no_replacement().is_ok());
+    }
+
+    #[test]
+    fn test_die_floor() {
+        let mut dice = Dice::parse("3d6").unwrap();
+        assert_eq!(dice.min_roll(), 3);
+        dice.set_die_floor(3).unwrap();
+        assert_eq!(dice.min_roll(), 9);
+        assert_eq!(dice.max_roll(), 18);
+        // A 1, 2 or 3 all count as 3, so the 3 comes up half the time
+        assert!((Dice::parse("1d6min3").unwrap().probability_of(3) - 0.5).abs() < 1e-12);
+        assert_eq!(dice.distribution_map(), Dice::parse("3d6min3").unwrap().distribution_map());
+        let result = dice.roll_detailed_with(&mut ForcedRng::new(6, &[1, 5, 2]));
+        assert_eq!(result.total, 11);
+
+        // Every term of a mixed pool is floored
+        let mut pool = Dice::parse("1d4 + 1d8").unwrap();
+        pool.set_die_floor(2).unwrap();
+        assert_eq!(pool.min_roll(), 4);
+
+        // Exploding dice raised to their highest face would never stop
+        assert!(Dice::parse("2d6!").unwrap().set_die_floor(6).is_err());
+        assert!(matches!(Dice::parse("2d6!min6"), Err(DiceError::InfiniteExplosion { .. })));
+    }
+
+    #[test]
+    fn test_rolled_sides_guards() {
+        assert!(matches!(Dice::parse("1d(1d6-1)"), Err(DiceError::InvalidSidesRoll { .. })));
+        assert!(matches!(Dice::parse("1d(1d6"), Err(DiceError::InvalidSidesRoll { .. })));
+        assert!(matches!(Dice::parse("1d(50d6)"), Err(DiceError::InvalidSidesRoll { .. })));
+        // Any die the roll can make must explode finitely, and a d1 explodes forever
+        assert!(matches!(Dice::parse("1d(1d6)!"), Err(DiceError::InfiniteExplosion { .. })));
+        assert!(Dice::parse("1d(1d6+1)!").is_ok());
+    }
+
+    #[test]
+    fn test_crit_rules() {
+        let dice = Dice::parse("2d6+3").unwrap();
+        let faces = [2, 5, 6, 1];
+        let result = dice.roll_crit_with(CritRule::DoubleDice, &mut ForcedRng::new(6, &faces));
+        assert_eq!(result.dice.len(), 4);
+        assert_eq!(result.total, 17);
+
+        let result = dice.roll_crit_with(CritRule::DoubleTotal, &mut ForcedRng::new(6, &faces));
+        assert_eq!(result.dice.len(), 2);
+        assert_eq!(result.total, 20);
+
+        let result = dice.roll_crit_with(CritRule::MaxPlusRoll, &mut ForcedRng::new(6, &faces));
+        assert_eq!(result.total, 22);
+
+        assert!((dice.crit_expected_value(CritRule::DoubleDice) - 17.0).abs() < 1e-9);
+        assert!((dice.crit_expected_value(CritRule::DoubleTotal) - 20.0).abs() < 1e-9);
+        assert!((dice.crit_expected_value(CritRule::MaxPlusRoll) - 22.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_pool_extremes() {
+        let dice = Dice::parse("4d6+2").unwrap();
+        let result = dice.roll_detailed_with(&mut ForcedRng::new(6, &[3, 5, 2, 4]));
+        assert_eq!(result.highest_face(), Some(5));
+        assert_eq!(result.lowest_face(), Some(2));
+        assert_eq!(result.spread(), Some(3));
+        assert_eq!(result.total, 16);
+
+        let result = dice.roll_detailed_with(&mut ForcedRng::new(6, &[4, 4, 4, 4]));
+        assert_eq!(result.spread(), Some(0));
+        let result = Dice::parse("3d10").unwrap().roll_detailed_with(&mut ForcedRng::new(10, &[10, 1, 7]));
+        assert_eq!(result.spread(), Some(9));
+    }
+
+    #[test]
+    fn test_raw_total_excludes_modifier() {
+        let dice = Dice::parse("2d6+100").unwrap();
+        let mut rng = StdRng::seed_from_u64(226);
+        for _ in 0..100 {
+            let result = dice.roll_detailed_with(&mut rng);
+            assert!((2..=12).contains(&result.raw_total()));
+            assert_eq!(result.raw_total() + 100, result.total);
+        }
+    }
+
+    #[test]
+    fn test_success_pool_face_sum() {
+        let dice = Dice::parse("5d6>=4").unwrap();
+        let result = dice.roll_detailed_with(&mut ForcedRng::new(6, &[6, 2, 4, 1, 5]));
+        assert_eq!(result.total, 3);
+        assert_eq!(result.face_sum(), 18);
+    }
+
+    #[test]
+    fn test_parse_explode() {
+        assert!(Dice::parse("6d10>=8!").unwrap().explode);
+        assert!(Dice::parse("2d6!+1").unwrap().explode);
+        assert!(!Dice::parse("2d6").unwrap().explode);
+        assert!(Dice::parse("1d1!").is_err());
+        assert!(Dice::parse("1d6r1-5!").is_err());
+    }
+
+    #[test]
+    fn test_exploding_sum() {
+        let dice = Dice::parse("2d6!").unwrap();
+        // The first die shows 6 and explodes into a 2
+        assert_eq!(dice.roll_with(&mut ForcedRng::new(6, &[6, 2, 3])), 11);
+    }
+
+    #[test]
+    fn test_pattern_probability() {
+        let dice = Dice::parse("3d6").unwrap();
+        let chance = |pattern| dice.pattern_probability(pattern).unwrap();
+        assert!((chance(FacePattern::AllMax) - (1.0f64 / 6.0).powi(3)).abs() < 1e-12);
+        assert!((chance(FacePattern::AllMin) - 1.0 / 216.0).abs() < 1e-12);
+        assert!((chance(FacePattern::AllSame) - 6.0 / 216.0).abs() < 1e-12);
+        assert!((chance(FacePattern::AllDifferent) - 120.0 / 216.0).abs() < 1e-12);
+        // 1-2-3 up to 4-5-6, each in 3! orders
+        assert!((chance(FacePattern::Straight) - 24.0 / 216.0).abs() < 1e-12);
+
+        // Rerolled faces never show, and the terms of a pool of like dice count together
+        let rerolled = Dice::parse("2d6r1 + 1d6r1").unwrap();
+        assert!((rerolled.pattern_probability(FacePattern::AllMin).unwrap() - 1.0 / 125.0).abs() < 1e-12);
+        assert_eq!(Dice::parse("7d6").unwrap().pattern_probability(FacePattern::AllDifferent), Some(0.0));
+        assert_eq!(Dice::parse("1d6 + 1d8").unwrap().pattern_probability(FacePattern::AllMax), None);
+
+        // Pools too large for a factorial in an f64 still give sensible chances
+        let large = Dice::parse("200d6").unwrap();
+        assert_eq!(large.pattern_probability(FacePattern::AllDifferent), Some(0.0));
+        assert_eq!(large.pattern_probability(FacePattern::Straight).map(f64::to_bits), Some(0.0f64.to_bits()));
+        let wide = Dice::parse("180d200").unwrap();
+        let different = wide.pattern_probability(FacePattern::AllDifferent).unwrap();
+        assert!(different.is_finite() && different > 0.0 && different < 1e-12, "{}", different);
+        let straight = wide.pattern_probability(FacePattern::Straight).unwrap();
+        assert!(straight.is_finite() && straight > 0.0 && straight < different, "{}", straight);
+    }
+
+    #[test]
+    fn test_explode_which() {
+        let mut dice = Dice::parse("3d6!").unwrap();
+        dice.set_explode_which(ExplodeWhich::Highest).unwrap();
+        // Only the first of the two 6s explodes, into a 6 and then a 2
+        let result = dice.roll_detailed_with(&mut ForcedRng::new(6, &[6, 3, 6, 6, 2]));
+        let faces: Vec<i32> = result.dice.iter().map(|die| die.face).collect();
+        assert_eq!(faces, vec![6, 6, 2, 3, 6]);
+        assert_eq!(result.total, 23);
+        // A pool without a 6 never explodes
+        assert_eq!(dice.roll_with(&mut ForcedRng::new(6, &[5, 3, 5])), 13);
+
+        // The lowest die explodes only when every die shows a 6
+        let mut lowest = Dice::parse("2d6!").unwrap();
+        lowest.set_explode_which(ExplodeWhich::Lowest).unwrap();
+        assert_eq!(lowest.roll_with(&mut ForcedRng::new(6, &[6, 5])), 11);
+        assert_eq!(lowest.roll_with(&mut ForcedRng::new(6, &[6, 6, 1])), 13);
+
+        for (which, spec) in [(ExplodeWhich::Highest, "3d4!"), (ExplodeWhich::Lowest, "3d4!"), (ExplodeWhich::Highest, "4d6>=5!")] {
+            let mut dice = Dice::parse(spec).unwrap();
+            dice.set_explode_which(which).unwrap();
+            let distribution = dice.distribution_map();
+            assert!((distribution.values().sum::<f64>() - 1.0).abs() < 1e-9);
+            let mut rng = StdRng::seed_from_u64(315);
+            let rolls = 100_000;
+            let mean = (0..rolls).map(|_| f64::from(dice.roll_with(&mut rng))).sum::<f64>() / f64::from(rolls);
+            assert!((mean - dice.expected_value()).abs() < 0.05, "{:?} {}: {} vs {}", which, spec, mean, dice.expected_value());
+        }
+        // At most one chain of explosions, so 3d4! with the highest exploding averages
+        // 7.5 plus the chance of any 4 times the 2.5 + 1/3 of a fresh exploding die
+        let mut highest = Dice::parse("3d4!").unwrap();
+        highest.set_explode_which(ExplodeWhich::Highest).unwrap();
+        let any_four = 1.0 - (3.0f64 / 4.0).powi(3);
+        assert!((highest.expected_value() - (7.5 + any_four * 10.0 / 3.0)).abs() < 1e-6);
+
+        let mut kept = Dice::parse("4d6!kh3").unwrap();
+        assert!(matches!(kept.set_explode_which(ExplodeWhich::Highest), Err(DiceError::InvalidKeep { .. })));
+        assert!(kept.set_explode_which(ExplodeWhich::All).is_ok());
+    }
+
+    #[test]
+    fn test_explode_low() {
+        let mut dice = Dice::parse("2d6!").unwrap();
+        dice.set_explode_face(ExplodeFace::Lowest).unwrap();
+        // The first die shows 1 and explodes into a 4, while the 6 does not explode
+        let result = dice.roll_detailed_with(&mut ForcedRng::new(6, &[1, 4, 6]));
+        assert_eq!(result.total, 11);
+        assert_eq!(result.dice.iter().map(|die| die.from_explosion).collect::<Vec<bool>>(), vec![false, true, false]);
+
+        let mut low = Dice::parse("1d6!").unwrap();
+        low.set_explode_face(ExplodeFace::Lowest).unwrap();
+        let distribution = low.distribution_map();
+        assert!(!distribution.contains_key(&1));
+        // A 3 is rolled outright or as a 1 exploding into a 2
+        assert!((distribution[&2] - 1.0 / 6.0).abs() < 1e-12);
+        assert!((distribution[&3] - 7.0 / 36.0).abs() < 1e-12);
+        let total_probability: f64 = distribution.values().sum();
+        assert!((total_probability - 1.0).abs() < 1e-9);
+
+        // A die that can only show its lowest face would explode forever
+        let mut only_low = Dice::parse("1d6r2-6!").unwrap();
+        assert!(matches!(only_low.set_explode_face(ExplodeFace::Lowest), Err(DiceError::InfiniteExplosion { .. })));
+        assert_eq!(only_low.explode_face, ExplodeFace::Highest);
+        assert!(matches!(Dice::parse("1d6r2-6!l"), Err(DiceError::InfiniteExplosion { .. })));
+
+        // Exploding low is written `!l`, so the dice read back the same
+        assert_eq!(dice.to_string(), "2d6!l");
+        assert_eq!(Dice::parse("2d6!l").unwrap(), dice);
+    }
+
+    #[test]
+    fn test_explosion_adds_success() {
+        let dice = Dice::parse("3d10>=8!").unwrap();
+        // The 10 is a success and explodes into a 9, which is another success
+        assert_eq!(dice.roll_with(&mut ForcedRng::new(10, &[10, 9, 3, 5])), 2);
+        // Without the explosion the same pool only has one success
+        let plain = Dice::parse("3d10>=8").unwrap();
+        assert_eq!(plain.roll_with(&mut ForcedRng::new(10, &[10, 3, 5])), 1);
+    }
+
+    #[test]
+    fn test_critical_successes() {
+        let dice = Dice::parse("5d10>=7").unwrap();
+        let result = dice.roll_detailed_with(&mut ForcedRng::new(10, &[10, 7, 3, 10, 9]));
+        assert_eq!(result.total, 4);
+        assert_eq!(result.criticals(), 2);
+        // Every critical is also a success
+        assert!(result.dice.iter().all(|die| die.success || !die.critical));
+
+        // A dropped die showing its highest face is not a critical
+        let kept = Dice::parse("3d6>=5kl2").unwrap();
+        let result = kept.roll_detailed_with(&mut ForcedRng::new(6, &[6, 5, 2]));
+        assert_eq!((result.total, result.criticals()), (1, 0));
+        // Without a target there are no successes to be critical
+        let result = Dice::parse("2d6").unwrap().roll_detailed_with(&mut ForcedRng::new(6, &[6, 6]));
+        assert_eq!(result.criticals(), 0);
+    }
+
+    #[test]
+    fn test_exploding_distribution() {
+        let distribution = Dice::parse("1d6!").unwrap().distribution_map();
+        assert!(!distribution.contains_key(&6));
+        assert!((distribution[&5] - 1.0 / 6.0).abs() < 1e-12);
+        assert!((distribution[&8] - 1.0 / 36.0).abs() < 1e-12);
+        let total_probability: f64 = distribution.values().sum();
+        assert!((total_probability - 1.0).abs() < 1e-9);
+
+        let pool = Dice::parse("6d10>=8!").unwrap();
+        let mean: f64 = pool
+            .distribution_map()
+            .iter()
+            .map(|(&successes, &probability)| f64::from(successes) * probability)
+            .sum();
+        assert!((mean - pool.expected_successes().unwrap()).abs() < 1e-6);
+        assert!((pool.expected_successes().unwrap() - 6.0 * 0.3 / 0.9).abs() < 1e-12);
+        assert!(pool.outcome_frequencies().is_none());
+    }
+
+    #[test]
+    fn test_percentile_value() {
+        assert_eq!(percentile_value(0, 0), 100);
+        assert_eq!(percentile_value(0, 1), 1);
+        assert_eq!(percentile_value(0, 7), 7);
+        assert_eq!(percentile_value(9, 0), 90);
+        assert_eq!(percentile_value(9, 9), 99);
+    }
+
+    #[test]
+    fn test_percentile_dice() {
+        let dice = Dice::parse("1d%+5").unwrap();
+        assert!(dice.percentile);
+        assert_eq!((dice.count, dice.sides, dice.modifier), (1, 100, 5));
+        assert!(Dice::parse("d%").is_err());
+
+        let mut rng = StdRng::seed_from_u64(212);
+        for _ in 0..500 {
+            assert!((6..=105).contains(&dice.roll_with(&mut rng)));
+        }
+        // A tens die of 00 with a units die of 0 reads as 100
+        let dice = Dice::parse("1d%").unwrap();
+        assert_eq!(dice.roll_with(&mut ForcedRng::new(10, &[1, 1])), 100);
+        assert_eq!(dice.roll_with(&mut ForcedRng::new(10, &[10, 10])), 99);
+    }
+
+    #[test]
+    fn test_enumerated_distribution_matches() {
+        for spec in ["2d6", "3d4+1", "4d6kh3", "3d6r1", "5d10>=8", "2d6 + 1d8>=5", "2d20kl1-2", "3d6min2"] {
+            let dice = Dice::parse(spec).unwrap();
+            let enumerated = dice.enumerated_distribution(100_000).unwrap();
+            let distribution = dice.distribution_map();
+            assert_eq!(enumerated.keys().collect::<Vec<_>>(), distribution.keys().collect::<Vec<_>>(), "{}", spec);
+            for (total, probability) in enumerated {
+                assert!((probability - distribution[&total]).abs() < 1e-9, "{} at {}", spec, total);
+            }
+        }
+        assert_eq!(Dice::parse("2d6!").unwrap().enumerated_distribution(100_000), None);
+        assert_eq!(Dice::parse("10d10").unwrap().enumerated_distribution(100_000), None);
+    }
+
+    #[test]
+    fn test_outcome_frequencies() {
+        let (totals, frequencies) = Dice::parse("2d6").unwrap().outcome_frequencies().unwrap();
+        assert_eq!(totals, (2..=12).collect::<Vec<i32>>());
+        assert_eq!(frequencies, vec![1, 2, 3, 4, 5, 6, 5, 4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn test_probability_mass() {
+        let mut tally: BTreeMap<i32, u64> = BTreeMap::new();
+        for first in 1..=6 {
+            for second in 1..=6 {
+                *tally.entry(first + second).or_insert(0) += 1;
+            }
+        }
+        let mass = Dice::parse("2d6").unwrap().probability_mass().unwrap();
+        assert_eq!(mass, tally);
+        assert_eq!(mass.values().sum::<u64>(), 36);
+        assert_eq!(Dice::parse("2d6!").unwrap().probability_mass(), None);
+    }
+
+    #[test]
+    fn test_check_sides() {
+        let dice = Dice::parse("2d6 + 1d20").unwrap();
+        assert_eq!(dice.check_sides(4, 20), Ok(()));
+        assert_eq!(
+            dice.check_sides(4, 12),
+            Err(DiceError::SidesOutOfRange { sides: 20, min: 4, max: 12 })
+        );
+        assert_eq!(
+            Dice::parse("1d(1d6)").unwrap().check_sides(2, 100),
+            Err(DiceError::SidesOutOfRange { sides: 1, min: 2, max: 100 })
+        );
+        assert_eq!(dice.check_sides(20, 6), Err(DiceError::InvalidSidesRange { min: 20, max: 6 }));
+    }
+
+    #[test]
+    fn test_critical_chance() {
+        let chance = |spec: &str, crit_on| Dice::parse(spec).unwrap().critical_chance(crit_on);
+        assert!((chance("1d20", 20) - 0.05).abs() < 1e-12);
+        assert!((chance("1d20+7", 19) - 0.1).abs() < 1e-12);
+        assert!((chance("2d20kh1", 20) - (1.0 - 0.95 * 0.95)).abs() < 1e-12);
+        assert!((chance("2d20kl1", 20) - 0.05 * 0.05).abs() < 1e-12);
+        // Keeping the lowest two of three leaves a 6 in unless two dice roll below it
+        let one_six = 3.0 * (5.0 / 6.0) * (1.0 / 6.0) * (1.0 / 6.0) + (1.0 / 6.0_f64).powi(3);
+        assert!((chance("3d6kl2", 6) - one_six).abs() < 1e-12);
+        assert_eq!(chance("1d6", 7), 0.0);
+    }
+
+    #[test]
+    fn test_variance() {
+        assert!((Dice::parse("1d20").unwrap().variance() - 399.0 / 12.0).abs() < 1e-9);
+        assert!((Dice::parse("2d6+5").unwrap().variance() - 35.0 / 6.0).abs() < 1e-9);
+        assert_eq!(Dice::parse("3d1").unwrap().variance(), 0.0);
+    }
+
+    #[test]
+    fn test_repeated_terms_are_worked_out_once() {
+        let worked_out = |spec: &str| {
+            let before = TERM_DISTRIBUTIONS.with(|count| count.get());
+            let distribution = Dice::parse(spec).unwrap().distribution_map();
+            (TERM_DISTRIBUTIONS.with(|count| count.get()) - before, distribution)
+        };
+        let (count, repeated) = worked_out("4d6kh3 + 4d6kh3+1 + 1d8 + 4d6kh3-2");
+        assert_eq!(count, 2);
+        let (_, single) = worked_out("4d6kh3");
+        let (_, d8) = worked_out("1d8");
+        let expected = convolve(&convolve(&convolve(&single, &single), &d8), &single);
+        assert_eq!(repeated.len(), expected.len());
+        // The modifiers of the repeated pool come to -1
+        for ((total, probability), (expected_total, expected_probability)) in repeated.iter().zip(&expected) {
+            assert_eq!(total + 1, *expected_total);
+            assert!((probability - expected_probability).abs() < 1e-12);
+        }
+        assert_eq!(worked_out("3d6 + 3d8 + 2d6").0, 3);
+    }
+
+    #[test]
+    fn test_distribution_stats() {
+        for spec in ["3d6", "4d6kh3+2", "2d20kl1", "3d1", "5d10>=8", "1d6! + 1d4"] {
+            let dice = Dice::parse(spec).unwrap();
+            let stats = dice.distribution_stats();
+            assert!((stats.mean - dice.expected_value()).abs() < 1e-9, "{}", spec);
+            assert!((stats.variance - dice.variance()).abs() < 1e-9, "{}", spec);
+            assert_eq!((stats.min, stats.max), (dice.min_roll(), dice.max_roll()), "{}", spec);
+        }
+
+        let stats = Dice::parse("3d6").unwrap().distribution_stats();
+        assert!((stats.mean - 10.5).abs() < 1e-9);
+        assert!((stats.variance - 8.75).abs() < 1e-9);
+        // 10 and 11 are equally likely
+        assert_eq!((stats.mode, stats.min, stats.max), (10, 3, 18));
+        assert_eq!(Dice::parse("2d20kh1").unwrap().distribution_stats().mode, 20);
+    }
+
+    #[test]
+    fn test_closed_form_moments() {
+        let dice = Dice::parse("3d6+2 + 2d4").unwrap();
+        let distribution = dice.distribution_map();
+        let mean: f64 = distribution.iter().map(|(&total, &probability)| f64::from(total) * probability).sum();
+        let variance: f64 = distribution
+            .iter()
+            .map(|(&total, &probability)| (f64::from(total) - mean).powi(2) * probability)
+            .sum();
+        let (closed_mean, closed_variance) = dice.closed_form_moments().unwrap();
+        assert!((closed_mean - mean).abs() < 1e-9);
+        assert!((closed_variance - variance).abs() < 1e-9);
+        assert_eq!(Dice::parse("4d6kh3").unwrap().closed_form_moments(), None);
+        assert_eq!(Dice::parse("2d6!").unwrap().normal_approximation(), None);
+    }
+
+    #[test]
+    fn test_normal_approximation() {
+        let dice = Dice::parse("200d6+5").unwrap();
+        let (mean, variance) = dice.closed_form_moments().unwrap();
+        assert_eq!((mean, variance), (705.0, 200.0 * 35.0 / 12.0));
+
+        let approximation = dice.normal_approximation().unwrap();
+        assert_eq!(approximation.keys().next(), Some(&205));
+        assert_eq!(approximation.keys().next_back(), Some(&1205));
+        let total_probability: f64 = approximation.values().sum();
+        assert!((total_probability - 1.0).abs() < 1e-9);
+        let approximate_mean: f64 = approximation.iter().map(|(&total, &probability)| f64::from(total) * probability).sum();
+        let approximate_variance: f64 = approximation
+            .iter()
+            .map(|(&total, &probability)| (f64::from(total) - mean).powi(2) * probability)
+            .sum();
+        assert!((approximate_mean - mean).abs() < 1e-6);
+        // The continuity correction spreads each total over a whole point, adding 1/12
+        assert!((approximate_variance - variance - 1.0 / 12.0).abs() < 0.01);
+
+        // Close to the exact distribution near the middle, where the totals are most likely
+        let exact = dice.distribution_map();
+        for total in [650, 705, 760] {
+            assert!((approximation[&total] - exact[&total]).abs() < 1e-4, "{}", total);
+        }
+        assert_eq!(Dice::parse("3d1+2").unwrap().normal_approximation(), Some(BTreeMap::from([(5, 1.0)])));
+    }
+
+    #[test]
+    fn test_overlap() {
+        let d6 = Dice::parse("1d6").unwrap();
+        assert!((d6.overlap(&d6) - 1.0).abs() < 1e-12);
+        // Shifting by 3 leaves 4, 5 and 6 in common
+        assert!((d6.overlap(&Dice::parse("1d6+3").unwrap()) - 0.5).abs() < 1e-12);
+        assert_eq!(d6.overlap(&Dice::parse("1d6+6").unwrap()), 0.0);
+
+        // 2d6 and 2d6+1 share min(P(n), P(n-1)) at every total from 3 to 12
+        let two_d6 = Dice::parse("2d6").unwrap();
+        let shifted = Dice::parse("2d6+1").unwrap();
+        assert!((two_d6.overlap(&shifted) - 30.0 / 36.0).abs() < 1e-12);
+        assert!((shifted.overlap(&two_d6) - 30.0 / 36.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_with_modifier() {
+        let dice = Dice::parse("2d6+1 + 1d4").unwrap();
+        let buffed = dice.with_modifier(3);
+        assert_eq!(dice.modifier, 1);
+        assert_eq!(dice, Dice::parse("2d6+1 + 1d4").unwrap());
+        assert_eq!(buffed.modifier, 4);
+        assert_eq!(buffed.extra_terms, dice.extra_terms);
+        assert_eq!((buffed.min_roll(), buffed.max_roll()), (7, 20));
+        assert_eq!(dice.with_modifier(-5).modifier, -4);
+        assert_eq!(dice.with_modifier(i32::MAX).modifier, i32::MAX);
+    }
+
+    #[test]
+    fn test_parse_never_panics() {
+        // Pieces of notation, so random specifications get past the first checks
+        let pieces = [
+            "0", "1", "2", "9", "20", "100", "255", "256", "99999999999", "d", "D", "d%", "+", "-", ">=", "*", "^",
+            "r", "r{", "}", ",", "!", "!l", "k", "kh", "kl", "dh", "dl", "min", "(", ")", " ", "1d6", "4d6", "d(1d6)",
+            "fate", "x", "é", "✓", "r1-2000000000", "2000000000",
+        ];
+        let mut registry = DieRegistry::new();
+        registry.insert("fate", vec![-1, 0, 1]).unwrap();
+        let mut rng = StdRng::seed_from_u64(282);
+        for _ in 0..20_000 {
+            let length = rng.random_range(0..12);
+            let spec: String = (0..length).map(|_| pieces[rng.random_range(0..pieces.len())]).collect();
+            let parsed = std::panic::catch_unwind(|| {
+                let _ = Dice::parse_with(&spec, &registry);
+                let _ = Dice::parse_strict_with(&spec, &registry);
+            });
+            assert!(parsed.is_ok(), "parsing '{}' panicked", spec);
+        }
+
+        // A large sides roll is rejected without working out its whole distribution
+        assert!(matches!(Dice::parse("1d(255d%dh)"), Err(DiceError::InvalidSidesRoll { .. })));
+
+        // A huge reroll range is rejected without listing every face in it
+        let start = std::time::Instant::now();
+        assert!(matches!(Dice::parse("1d6r1-2000000000"), Err(DiceError::InvalidReroll { .. })));
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_scale() {
+        let dice = Dice::parse("2d6+1 + 1d4").unwrap();
+        let doubled = dice.scale(2.0).unwrap();
+        assert_eq!(doubled, Dice::parse("4d6+2 + 2d4").unwrap());
+        assert!((doubled.expected_value() - 2.0 * dice.expected_value()).abs() < 1e-9);
+        let halved = Dice::parse("4d6+2").unwrap().scale(0.5).unwrap();
+        assert!((halved.expected_value() - 0.5 * 16.0).abs() < 1e-9);
+
+        // Keep notation keeps the same share of the dice
+        assert_eq!(Dice::parse("4d6kh3").unwrap().scale(2.0).unwrap(), Dice::parse("8d6kh6").unwrap());
+        assert_eq!(Dice::parse("2d20kh1").unwrap().scale(1.4).unwrap(), Dice::parse("3d20kh1").unwrap());
+
+        assert!(matches!(dice.scale(0.0), Err(DiceError::InvalidScale { .. })));
+        assert!(matches!(dice.scale(f64::NAN), Err(DiceError::InvalidScale { .. })));
+        assert!(matches!(dice.scale(0.1), Err(DiceError::InvalidScale { .. })));
+        assert!(matches!(dice.scale(200.0), Err(DiceError::InvalidScale { .. })));
+    }
+
+    #[test]
+    fn test_merge_same_sides() {
+        let pool = Dice::parse("2d6+1").unwrap().merge(&Dice::parse("3d6+2").unwrap()).unwrap();
+        assert_eq!(pool, Dice::parse("5d6+3").unwrap());
+    }
+
+    #[test]
+    fn test_merge_mismatched_sides() {
+        let err = Dice::parse("2d6").unwrap().merge(&Dice::parse("1d8").unwrap()).unwrap_err();
+        assert_eq!(err, DiceError::MismatchedSides { left: 6, right: 8 });
+    }
+
+    #[test]
+    fn test_merge_mismatched_settings() {
+        let merge = |left: &str, right: &str| Dice::parse(left).unwrap().merge(&Dice::parse(right).unwrap());
+        let mismatched = |setting| Err(DiceError::MismatchedMerge { setting });
+        assert_eq!(merge("3d10>=8", "2d10>=7"), mismatched("success targets"));
+        assert_eq!(merge("3d10>=8*2", "2d10>=8"), mismatched("success weights"));
+        assert_eq!(merge("3d10>=7^10", "2d10>=7"), mismatched("double successes"));
+        assert_eq!(merge("2d6r1", "2d6"), mismatched("rerolls"));
+        assert_eq!(merge("2d6", "2d6!"), mismatched("explosions"));
+
+        // Matching settings carry over to the merged pool
+        let weighted = merge("3d10>=8*2", "2d10>=8*2").unwrap();
+        assert_eq!(weighted, Dice::parse("5d10>=8*2").unwrap());
+    }
+
+    #[test]
+    fn test_merge_too_many_dice() {
+        let big = Dice::parse("200d6").unwrap();
+        assert_eq!(big.merge(&big).unwrap_err(), DiceError::TooManyDice { count: 400 });
+    }
+}
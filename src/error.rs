@@ -0,0 +1,207 @@
+use std::fmt;
+
+/// Errors produced while parsing or combining dice.
+///
+/// New variants are added as the notation grows, so the enum is `#[non_exhaustive]`
+/// and code outside this crate must include a wildcard arm when matching on it:
+///
+/// ```
+/// use droll::{Dice, DiceError};
+///
+/// let message = match Dice::parse("0d6") {
+///     Ok(_) => "valid",
+///     Err(DiceError::ZeroCount { .. }) => "no dice",
+///     Err(_) => "other error",
+/// };
+/// assert_eq!(message, "no dice");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DiceError {
+    /// The specification is not in `NdS` form.
+    InvalidFormat { spec: String },
+    /// A numeric part of the specification (such as the count, sides or modifier) failed to parse.
+    InvalidNumber {
+        spec: String,
+        field: &'static str,
+        value: String,
+    },
+    /// The specification has notation after the dice that is not recognized.
+    UnknownNotation { spec: String, notation: String },
+    /// The reroll notation is malformed or would reroll every face.
+    InvalidReroll { spec: String, reason: &'static str },
+    /// The keep or drop notation would keep no dice or more dice than are rolled.
+    InvalidKeep { spec: String, reason: &'static str },
+    /// Exploding dice that can only show the face they explode on would never stop rolling.
+    InfiniteExplosion { spec: String },
+    /// A success weight (`*W`) is not valid for the dice.
+    InvalidWeight { spec: String, reason: &'static str },
+    /// A face counting as two successes (`^F`) is not valid for the dice.
+    InvalidDoubleFace { spec: String, reason: &'static str },
+    /// Strict parsing rejected notation that is easy to get wrong.
+    AmbiguousNotation { spec: String, reason: &'static str },
+    /// Strict parsing rejected a modifier that is not a single signed number after the rest of the notation.
+    AmbiguousModifier { spec: String, reason: &'static str },
+    /// A die floor cannot be applied to the dice.
+    InvalidFloor { floor: i32, reason: &'static str },
+    /// The dice cannot be drawn without replacement.
+    InvalidDraw { reason: &'static str },
+    /// The specification names a custom die that is not registered.
+    UnknownDie { spec: String, name: String },
+    /// A custom die has an invalid name or set of faces.
+    InvalidCustomDie { name: String, reason: &'static str },
+    /// The roll deciding the sides of dice like `1d(1d6)` is malformed or can make an invalid die.
+    InvalidSidesRoll { spec: String, reason: &'static str },
+    /// The specification asks for zero dice.
+    ZeroCount { spec: String },
+    /// The specification asks for dice with zero sides.
+    ZeroSides { spec: String },
+    /// Two dice with different numbers of sides cannot be merged into one pool.
+    MismatchedSides { left: u8, right: u8 },
+    /// Dice that keep or drop some of their dice cannot be merged into one pool.
+    KeptDiceMerge,
+    /// Dice that count, reroll, explode or combine their dice differently cannot be merged
+    /// into one pool without changing what one of them rolls.
+    MismatchedMerge { setting: &'static str },
+    /// Combining dice would exceed the maximum of 255 dice.
+    TooManyDice { count: u32 },
+    /// Combining modifiers would overflow an `i32`.
+    ModifierOverflow,
+    /// A die has fewer or more sides than the range allowed by [`crate::Dice::check_sides`].
+    SidesOutOfRange { sides: u8, min: u8, max: u8 },
+    /// The range given to [`crate::Dice::check_sides`] has its minimum above its maximum.
+    InvalidSidesRange { min: u8, max: u8 },
+    /// The dice cannot be scaled by the factor given to [`crate::Dice::scale`].
+    InvalidScale { reason: &'static str },
+    /// The faces of the dice cannot be scripted with [`crate::ScriptedRng`].
+    InvalidScript { spec: String, reason: &'static str },
+}
+
+impl fmt::Display for DiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiceError::InvalidFormat { spec } => write!(
+                f,
+                "Invalid dice specification '{}': must be in format 'NdS' (e.g., '2d6')",
+                spec
+            ),
+            DiceError::InvalidNumber { spec, field, value } => write!(
+                f,
+                "Invalid {} in '{}': '{}' is not a valid number",
+                field, spec, value
+            ),
+            DiceError::UnknownNotation { spec, notation } => write!(
+                f,
+                "Invalid dice specification '{}': unrecognized notation '{}'",
+                spec, notation
+            ),
+            DiceError::InvalidReroll { spec, reason } => {
+                write!(f, "Invalid reroll in '{}': {}", spec, reason)
+            }
+            DiceError::InvalidKeep { spec, reason } => {
+                write!(f, "Invalid keep or drop in '{}': {}", spec, reason)
+            }
+            DiceError::InvalidWeight { spec, reason } => {
+                write!(f, "Invalid success weight in '{}': {}", spec, reason)
+            }
+            DiceError::InvalidDoubleFace { spec, reason } => {
+                write!(f, "Invalid double success in '{}': {}", spec, reason)
+            }
+            DiceError::AmbiguousNotation { spec, reason } => {
+                write!(f, "Ambiguous notation in '{}': {}", spec, reason)
+            }
+            DiceError::AmbiguousModifier { spec, reason } => {
+                write!(f, "Ambiguous modifier in '{}': {}", spec, reason)
+            }
+            DiceError::InvalidFloor { floor, reason } => write!(f, "Invalid die floor {}: {}", floor, reason),
+            DiceError::InvalidDraw { reason } => write!(f, "Cannot draw without replacement: {}", reason),
+            DiceError::InfiniteExplosion { spec } => write!(
+                f,
+                "Invalid explosion in '{}': the dice can only show the face they explode on",
+                spec
+            ),
+            DiceError::UnknownDie { spec, name } => write!(
+                f,
+                "Invalid dice specification '{}': unknown custom die '{}'",
+                spec, name
+            ),
+            DiceError::InvalidCustomDie { name, reason } => {
+                write!(f, "Invalid custom die '{}': {}", name, reason)
+            }
+            DiceError::InvalidSidesRoll { spec, reason } => {
+                write!(f, "Invalid sides roll in '{}': {}", spec, reason)
+            }
+            DiceError::ZeroCount { spec } => {
+                write!(f, "Invalid count in '{}': cannot use 0 dice", spec)
+            }
+            DiceError::ZeroSides { spec } => {
+                write!(f, "Invalid sides in '{}': cannot use 0 sides", spec)
+            }
+            DiceError::MismatchedSides { left, right } => write!(
+                f,
+                "Cannot merge d{} with d{}: dice must have the same number of sides",
+                left, right
+            ),
+            DiceError::KeptDiceMerge => write!(
+                f,
+                "Cannot merge dice that keep or drop some of their dice"
+            ),
+            DiceError::MismatchedMerge { setting } => write!(
+                f,
+                "Cannot merge dice with different {}: the merged pool would change what one of them rolls",
+                setting
+            ),
+            DiceError::TooManyDice { count } => write!(
+                f,
+                "Cannot combine {} dice: at most {} dice are supported",
+                count,
+                u8::MAX
+            ),
+            DiceError::ModifierOverflow => write!(f, "Combined modifier is too large"),
+            DiceError::SidesOutOfRange { sides, min, max } => write!(
+                f,
+                "Invalid sides d{}: dice must have between {} and {} sides",
+                sides, min, max
+            ),
+            DiceError::InvalidSidesRange { min, max } => write!(
+                f,
+                "Invalid sides range: the minimum of {} sides is above the maximum of {}",
+                min, max
+            ),
+            DiceError::InvalidScale { reason } => write!(f, "Cannot scale dice: {}", reason),
+            DiceError::InvalidScript { spec, reason } => {
+                write!(f, "Cannot script the faces of '{}': {}", spec, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DiceError {}
+
+/// Errors produced while rolling dice that parsed successfully, kept apart from
+/// [`DiceError`] so that a bad specification and a failed roll can be told apart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DiceRollError {
+    /// The total of the roll does not fit in an `i32`, as with pools of custom dice
+    /// showing huge faces or products of many dice.
+    Overflow,
+    /// A [`crate::ScriptedRng`] ran out of faces before the roll was done.
+    ScriptExhausted,
+    /// A [`crate::ScriptedRng`] gave a face the die being rolled cannot show.
+    ScriptedFace { face: i32 },
+}
+
+impl fmt::Display for DiceRollError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiceRollError::Overflow => write!(f, "The total of the roll is too large"),
+            DiceRollError::ScriptExhausted => write!(f, "The scripted faces ran out before the roll was done"),
+            DiceRollError::ScriptedFace { face } => {
+                write!(f, "The scripted face {} is not a face of the die rolled", face)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DiceRollError {}
@@ -0,0 +1,140 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A single roll recorded in the history file, stored as one JSON object per line.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Seconds since the Unix epoch when the roll was made
+    pub timestamp: u64,
+    pub spec: String,
+    pub total: i32,
+}
+
+/// Seconds since the Unix epoch, or 0 if the clock is set before it.
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+impl HistoryEntry {
+    pub fn now(spec: &str, total: i32) -> Self {
+        HistoryEntry {
+            timestamp: unix_timestamp(),
+            spec: spec.to_string(),
+            total,
+        }
+    }
+}
+
+/// Aggregate statistics over every roll in a history file.
+#[derive(Debug, PartialEq)]
+pub struct HistorySummary {
+    pub total_rolls: usize,
+    pub average: f64,
+    /// The most frequently rolled specification and how often it was rolled
+    pub most_rolled: Option<(String, usize)>,
+}
+
+/// Append entries to the history file, creating it if it does not exist yet.
+pub fn append_entries(path: &Path, entries: &[HistoryEntry]) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    for entry in entries {
+        let line = serde_json::to_string(entry)?;
+        writeln!(file, "{}", line)?;
+    }
+    Ok(())
+}
+
+/// Read every entry from the history file, skipping blank lines.
+pub fn read_entries(path: &Path) -> io::Result<Vec<HistoryEntry>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut entries = Vec::new();
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: {}", line_number + 1, err),
+            )
+        })?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Summarize a set of history entries. Ties for the most rolled specification
+/// go to the one that sorts first.
+pub fn summarize(entries: &[HistoryEntry]) -> HistorySummary {
+    let mut spec_counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for entry in entries {
+        *spec_counts.entry(entry.spec.as_str()).or_insert(0) += 1;
+    }
+
+    let mut most_rolled: Option<(String, usize)> = None;
+    for (spec, count) in spec_counts {
+        if most_rolled.as_ref().is_none_or(|(_, best)| count > *best) {
+            most_rolled = Some((spec.to_string(), count));
+        }
+    }
+
+    let sum: i64 = entries.iter().map(|entry| i64::from(entry.total)).sum();
+    let average = if entries.is_empty() {
+        0.0
+    } else {
+        sum as f64 / entries.len() as f64
+    };
+
+    HistorySummary {
+        total_rolls: entries.len(),
+        average,
+        most_rolled,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_and_summarize_history() {
+        let path = std::env::temp_dir().join(format!("droll-history-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        append_entries(
+            &path,
+            &[HistoryEntry::now("1d20", 12), HistoryEntry::now("2d6", 7)],
+        )
+        .unwrap();
+        // A later session appends to the same file
+        append_entries(
+            &path,
+            &[HistoryEntry::now("1d20", 5), HistoryEntry::now("1d20", 20)],
+        )
+        .unwrap();
+
+        let entries = read_entries(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[1].spec, "2d6");
+
+        let summary = summarize(&entries);
+        assert_eq!(summary.total_rolls, 4);
+        assert!((summary.average - 11.0).abs() < 1e-9);
+        assert_eq!(summary.most_rolled, Some(("1d20".to_string(), 3)));
+    }
+
+    #[test]
+    fn test_summarize_empty_history() {
+        let summary = summarize(&[]);
+        assert_eq!(summary.total_rolls, 0);
+        assert_eq!(summary.most_rolled, None);
+    }
+}
@@ -0,0 +1,19 @@
+//! Dice parsing, rolling and probability distributions behind the `droll` command line tool.
+
+mod cdf;
+mod dice;
+mod error;
+mod registry;
+#[cfg(any(test, feature = "test-rng"))]
+mod rng_override;
+mod sampler;
+mod scripted;
+
+pub use cdf::Cdf;
+pub use dice::{CritRule, Dice, DieRoll, DistributionStats, ExplodeFace, ExplodeWhich, FacePattern, FloorScope, Keep, KeepOrder, KeepTie, MAX_EXPLOSIONS, MAX_REROLLS, Reduce, RollResult, Strictness, percentile_value};
+pub use error::{DiceError, DiceRollError};
+pub use registry::DieRegistry;
+#[cfg(any(test, feature = "test-rng"))]
+pub use rng_override::{clear_rng_override, set_rng_override};
+pub use sampler::DiceSampler;
+pub use scripted::ScriptedRng;
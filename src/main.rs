@@ -1,252 +1,5191 @@
-use clap::Parser;
-use rand::Rng;
-use std::collections::BTreeMap;
+mod bands;
+mod batch;
+mod config;
+mod history;
+mod optimize;
+mod output;
+mod plot;
+mod repl;
+mod rng_state;
+mod template;
+
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
+use bands::Bands;
+use config::Config;
+use droll::{CritRule, Dice, DiceError, DieRegistry, ExplodeFace, ExplodeWhich, FacePattern, FloorScope, Keep, KeepOrder, KeepTie, Reduce, RollResult, ScriptedRng, Strictness};
+use history::HistoryEntry;
+use optimize::Goal;
+use output::{DistributionRecord, OutputFormat, ParsedSpec, RollRecord, RollSummary, TotalRecord};
+use rand::rngs::StdRng;
+use rand_chacha::ChaCha12Rng;
+use rand::{Rng, SeedableRng};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use repl::Pity;
+use template::Template;
+use unicode_width::UnicodeWidthStr;
 
 /// Roll the specified dice and report the total, individual roles, and percentage chance of the result.
 #[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
-    /// Dice specifications (e.g., 1d6, 2d4+3)
-    #[arg(required = true, help = "Dice specifications (e.g., 1d6, 2d4+3)")]
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Running `droll <specs>` without a subcommand is the same as `droll roll <specs>`
+    #[command(flatten)]
+    roll: RollArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Roll the dice (the default when no subcommand is given)
+    Roll(Box<RollArgs>),
+    /// Show the probability distribution and statistics without rolling
+    Stats(Box<StatsArgs>),
+    /// Check that dice specifications are valid without rolling
+    Check(CheckArgs),
+    /// Summarize the rolls stored in a history file
+    History(HistoryArgs),
+    /// Plot the distributions of two specifications as overlaid curves in an SVG file
+    Plot(PlotArgs),
+}
+
+/// Options shared by every subcommand that reads dice specifications.
+#[derive(Args, Default)]
+struct SpecArgs {
+    /// Read a bare number N as N dice with this many sides (e.g. '3' becomes '3d6')
+    #[arg(long, value_name = "SIDES", conflicts_with = "bare_range", help = "Read a bare number N as N dice with SIDES sides (e.g. '3' becomes '3d6' with --default-die 6)")]
+    default_die: Option<u8>,
+
+    /// Read a bare number N as a flat roll from 1 to N (e.g. '20' becomes '1d20')
+    #[arg(long, help = "Read a bare number N as a flat roll from 1 to N (e.g. '20' becomes '1d20')")]
+    bare_range: bool,
+
+    /// Read custom dice from this config file instead of the default location
+    #[arg(long, value_name = "PATH", help = "Read custom dice from this config file (default: $XDG_CONFIG_HOME/droll/config.toml)")]
+    config: Option<PathBuf>,
+
+    /// Raise any die showing less than this up to it, in every specification
+    #[arg(long, value_name = "N", allow_negative_numbers = true, help = "Raise any single die showing less than N up to N, in every specification (like the 'min' notation, e.g. '4d6min3')")]
+    die_floor: Option<i32>,
+
+    /// Raise only the kept dice to the die floor, leaving dropped dice as rolled
+    #[arg(long, help = "Apply a die floor (e.g. '4d6kh3min3' or --die-floor) to the kept dice only, so dropped dice show their natural faces")]
+    floor_kept_only: bool,
+
+    /// The most extra dice a single exploding die can add
+    #[arg(long, value_name = "N", visible_alias = "explode-compounding-limit", help = "The most extra dice a single exploding die can add to a roll (default: 100)")]
+    explode_limit: Option<u32>,
+
+    /// Explode dice on their lowest face instead of their highest
+    #[arg(long, help = "Make exploding dice ('!') roll again on their lowest face instead of their highest, for roll-under systems")]
+    explode_low: bool,
+
+    /// Count the dice meeting this target instead of adding them, like '>=N' notation
+    #[arg(long, value_name = "N", allow_negative_numbers = true, help = "Count the dice showing N or more instead of adding them, as if every specification without a success target ended in '>=N' (e.g. '--count-successes 8 5d10' rolls 5d10>=8)")]
+    count_successes: Option<i32>,
+
+    /// Which dice of an exploding pool may explode
+    #[arg(long, value_enum, value_name = "WHICH", default_value_t = ExplodeWhichPolicy::All, help = "Which dice of an exploding pool ('!') may explode: all of them (the default), or only the die showing the highest or the lowest face, the first of them on a tie")]
+    explode_which: ExplodeWhichPolicy,
+
+    /// Draw the dice of each term like cards, so no face shows on more than one die
+    #[arg(long, help = "Draw the dice of each term without replacement, so no face shows on more than one die (e.g. '3d6' always shows three different faces)")]
+    no_replacement: bool,
+
+    /// The most times a single die is rerolled before it keeps its face
+    #[arg(long, value_name = "N", help = "The most times a single die is rerolled by 'r' notation before it keeps whatever it shows (default: 100)")]
+    reroll_limit: Option<u32>,
+
+    /// Combine the dice of each term by adding, multiplying, or taking the highest or lowest
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = ReduceMode::Sum, help = "How the kept dice of each term are combined before the modifier is added: sum, product, max or min")]
+    reduce: ReduceMode,
+
+    /// Reject dice with fewer sides than this
+    #[arg(long, value_name = "SIDES", help = "Reject any specification with a die of fewer than SIDES sides")]
+    min_sides: Option<u8>,
+
+    /// Reject dice with more sides than this
+    #[arg(long, value_name = "SIDES", help = "Reject any specification with a die of more than SIDES sides")]
+    max_sides: Option<u8>,
+
+    /// Roll advantage on a d20 (2d20kh1) with a third die, as Elven Accuracy allows
+    #[arg(long, help = "Upgrade advantage on a d20 (2d20kh1) to Elven Accuracy's three dice (3d20kh1); other dice are unchanged")]
+    elven_accuracy: bool,
+
+    /// Multiply the number of dice and the modifier of every specification by this factor
+    #[arg(long, value_name = "FACTOR", help = "Multiply the number of dice and the modifier of every term by FACTOR, rounding each to the nearest whole number (e.g. '2d6+1' becomes '4d6+2' with --scale 2), for quick balancing")]
+    scale: Option<f64>,
+
+    /// Reject notation that is easy to get wrong instead of warning about it
+    #[arg(long, visible_alias = "dice-notation-strict", help = "Reject ambiguous notation (a bare 'k', keep or drop without a number, keeping every die, repeated notation) instead of warning about it")]
+    strict: bool,
+
+    /// Reject modifiers that are not a single signed number at the end of a term
+    #[arg(long, help = "Reject modifiers that are not written as one explicit +N or -N after the rest of each term (e.g. '2d6+3-1', '2d6+03' or '2d6+3>=5'), for tools that want predictable parsing")]
+    strict_modifier_sign: bool,
+
+    /// The custom dice from the config file, filled in by `load_config_or_exit`
+    #[arg(skip)]
+    registry: DieRegistry,
+
+    /// The named rolls from the config file, filled in by `load_config_or_exit`
+    #[arg(skip)]
+    aliases: BTreeMap<String, String>,
+}
+
+/// When `--approx-threshold` switches to the normal approximation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum ApproxThreshold {
+    /// For pools with more possible totals than this
+    Totals(usize),
+    /// Never, always working out the exact distribution
+    #[default]
+    Off,
+}
+
+impl ApproxThreshold {
+    fn parse(text: &str) -> Result<Self, String> {
+        if text == "off" {
+            return Ok(ApproxThreshold::Off);
+        }
+        text.parse()
+            .map(ApproxThreshold::Totals)
+            .map_err(|_| format!("Invalid threshold '{}': give a number of totals or 'off'", text))
+    }
+}
+
+/// Options controlling how the distribution histogram is displayed.
+#[derive(Args, Default)]
+struct HistogramArgs {
+    /// Show each probability as a reduced fraction alongside the percentage
+    #[arg(long, help = "Show each probability in the histogram as a reduced fraction alongside the percentage")]
+    fraction: bool,
+
+    /// List the combinations of faces that produce each total
+    #[arg(long, help = "List the combinations of faces that produce each total (small pools only)")]
+    explain_distribution: bool,
+
+    /// Hide histogram rows less likely than this percentage
+    #[arg(long, value_name = "PCT", help = "Hide histogram rows whose probability is below PCT percent (statistics still use every outcome)")]
+    histogram_threshold: Option<f64>,
+
+    /// Approximate plain pools with more possible totals than this by a normal distribution
+    #[arg(long, value_name = "TOTALS", value_parser = ApproxThreshold::parse, default_value = "10000", help = "Use a normal approximation (with continuity correction) for plain pools of added dice with more than TOTALS possible totals, instead of the exact distribution; 'off' always works out the exact distribution")]
+    approx_threshold: ApproxThreshold,
+
+    /// Print each histogram row as just the total and its percentage, without bars
+    #[arg(long, help = "Print each histogram row as just 'TOTAL: PCT%', without bars or padding, for copying into a document")]
+    histogram_percent_only: bool,
+}
+
+/// Probability questions about the total that can be asked of any dice specification.
+#[derive(Args, Default)]
+struct QueryArgs {
+    /// Print the probability that the total is exactly N
+    #[arg(long, value_name = "N", allow_negative_numbers = true, help = "Print the probability that the total is exactly N (0% when the dice cannot make N)")]
+    exactly: Option<i32>,
+
+    /// Print the probability that the total is at least N, for each N given
+    #[arg(long, value_name = "N", allow_negative_numbers = true, value_delimiter = ',', help = "Print the probability that the total is at least N; repeat or separate with commas to ask several (e.g. --at-least 10,15,20)")]
+    at_least: Vec<i32>,
+
+    /// Print the Shannon entropy of the total in bits
+    #[arg(long, help = "Print the Shannon entropy of the total in bits, a measure of how unpredictable the roll is")]
+    entropy: bool,
+
+    /// Print the distribution as a one-line sparkline
+    #[arg(long, help = "Print the distribution as a single line of block characters, one per possible total and scaled so the most likely total is a full block")]
+    sparkline: bool,
+
+    /// Print the chance of each number of successes in a success pool
+    #[arg(long, help = "Print a histogram of the number of dice meeting the success target (0, 1, ... successes) for success pools, counting every success once")]
+    success_histogram: bool,
+
+    /// Print the probability that the dice show a pattern of faces, for each pattern given
+    #[arg(long, value_enum, value_name = "PATTERN", value_delimiter = ',', help = "Print the probability that the faces the dice first show make a pattern: all-max, all-min, all-same, all-different or straight (a run of consecutive faces); repeat or separate with commas to ask several")]
+    pattern: Vec<PatternQuery>,
+
+    /// Print the chances that the total beats, ties and loses to another specification's
+    #[arg(long, value_name = "SPEC", help = "Print the chances that the total is greater than, equal to and less than the total of SPEC, parsed with the same options as the other specifications (e.g. '--probability-of-spec 1d8+1' for the odds of beating a goblin's roll)")]
+    probability_of_spec: Option<String>,
+}
+
+/// The specification compared against by `--probability-of-spec`, parsed like the
+/// others, or exit with an error.
+fn opponent_or_exit(query: &QueryArgs, options: &SpecArgs) -> Option<Dice> {
+    let spec = query.probability_of_spec.as_ref()?;
+    Some(parse_specs_or_exit(std::slice::from_ref(spec), options).remove(0))
+}
+
+/// A pattern of faces asked about with `--pattern`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum PatternQuery {
+    /// Every die shows its highest face
+    AllMax,
+    /// Every die shows its lowest face
+    AllMin,
+    /// Every die shows the same face
+    AllSame,
+    /// No two dice show the same face
+    AllDifferent,
+    /// The dice show a run of consecutive faces
+    Straight,
+}
+
+impl From<PatternQuery> for FacePattern {
+    fn from(query: PatternQuery) -> Self {
+        match query {
+            PatternQuery::AllMax => FacePattern::AllMax,
+            PatternQuery::AllMin => FacePattern::AllMin,
+            PatternQuery::AllSame => FacePattern::AllSame,
+            PatternQuery::AllDifferent => FacePattern::AllDifferent,
+            PatternQuery::Straight => FacePattern::Straight,
+        }
+    }
+}
+
+/// The answers to the probability questions asked in the options, with the dice of
+/// `--probability-of-spec` already parsed as `opponent`.
+fn query_lines(dice: &Dice, options: &QueryArgs, opponent: Option<&Dice>) -> Vec<String> {
+    let mut lines = Vec::new();
+    if let Some(total) = options.exactly {
+        lines.push(format!("P(total = {}) = {:.1}%", total, dice.probability_of(total) * 100.0));
+    }
+    if !options.at_least.is_empty() {
+        // Work the distribution out once for every threshold
+        let cdf = dice.cdf();
+        for &total in &options.at_least {
+            lines.push(format!("P(total >= {}) = {:.1}%", total, cdf.at_least(total) * 100.0));
+        }
+    }
+    if options.entropy {
+        lines.push(format!("Entropy: {:.3} bits", dice.entropy()));
+    }
+    if options.sparkline {
+        lines.push(format!("Sparkline: {}", sparkline(dice)));
+    }
+    if options.success_histogram {
+        lines.extend(success_histogram_lines(dice));
+    }
+    if let Some(other) = opponent {
+        let (win, tie, lose) = dice.chances_against(other);
+        lines.push(format!("P({} > {}) = {:.1}%", dice, other, win * 100.0));
+        lines.push(format!("P({} = {}) = {:.1}%", dice, other, tie * 100.0));
+        lines.push(format!("P({} < {}) = {:.1}%", dice, other, lose * 100.0));
+    }
+    for &pattern in &options.pattern {
+        let name = pattern.to_possible_value().map_or(String::new(), |value| value.get_name().to_string());
+        lines.push(match dice.pattern_probability(pattern.into()) {
+            Some(probability) => format!("P({}) = {:.1}%", name, probability * 100.0),
+            None => format!("P({}): the dice must all be alike, with fixed sides and drawn with replacement", name),
+        });
+    }
+    lines
+}
+
+/// A histogram of the number of successes in a success pool, one row per count.
+fn success_histogram_lines(dice: &Dice) -> Vec<String> {
+    let Some(counts) = dice.success_count_distribution() else {
+        return vec!["Success count histogram: the dice have no success target".to_string()];
+    };
+    let mut lines = vec!["Success count histogram:".to_string()];
+    for (successes, probability) in counts {
+        let percentage = probability * 100.0;
+        let bars = "|".repeat(((percentage / 2.0).round() as usize).max(1));
+        lines.push(format!("{:3}: {} ({:.1}%)", successes, bars, percentage));
+    }
+    lines
+}
+
+/// The block characters of a sparkline, from the least to the most likely total.
+const SPARK_LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// The distribution as one block character per possible total, in order of total, each
+/// as tall as its probability is next to that of the most likely total.
+fn sparkline(dice: &Dice) -> String {
+    let distribution = dice.distribution_map();
+    let highest = distribution.values().copied().fold(0.0, f64::max);
+    distribution
+        .values()
+        .map(|probability| SPARK_LEVELS[(probability / highest * 7.0).round() as usize])
+        .collect()
+}
+
+/// The most combinations of faces `--explain-distribution` will list.
+const MAX_EXPLAINED_OUTCOMES: usize = 1296;
+
+/// The most combinations of faces `--export-sample-space` writes, one row each.
+const MAX_SAMPLE_SPACE_ROWS: usize = 100_000;
+
+/// The most rolls `--simulate` makes in all before asking to go ahead.
+const CONFIRM_ABOVE_ROLLS: u64 = 100_000_000;
+
+/// When to show the per-die breakdown of success pools.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SymbolMode {
+    Auto,
+    Always,
+    Never,
+}
+
+impl SymbolMode {
+    fn enabled(self) -> bool {
+        match self {
+            SymbolMode::Auto => io::stdout().is_terminal(),
+            SymbolMode::Always => true,
+            SymbolMode::Never => false,
+        }
+    }
+}
+
+/// When to colour the output with ANSI escapes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum ColorMode {
+    /// Colour only when writing to a terminal
+    Auto,
+    /// Always colour, even when writing to a file or pipe
+    Always,
+    /// Never colour
+    Never,
+}
+
+impl ColorMode {
+    fn enabled(self) -> bool {
+        match self {
+            ColorMode::Auto => io::stdout().is_terminal(),
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+        }
+    }
+}
+
+/// Which of several equal dice keep notation marks as kept.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum KeepTiePolicy {
+    /// Keep the die rolled first
+    First,
+    /// Keep the die rolled last
+    Last,
+}
+
+impl From<KeepTiePolicy> for KeepTie {
+    fn from(policy: KeepTiePolicy) -> Self {
+        match policy {
+            KeepTiePolicy::First => KeepTie::First,
+            KeepTiePolicy::Last => KeepTie::Last,
+        }
+    }
+}
+
+/// When keep notation chooses its dice in a pool that also explodes.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum KeepOrderPolicy {
+    /// Keep by the face each die first shows, taking its explosions along with it
+    BeforeExplode,
+    /// Let every die, including those added by explosions, compete for a kept place
+    AfterExplode,
+}
+
+impl From<KeepOrderPolicy> for KeepOrder {
+    fn from(policy: KeepOrderPolicy) -> Self {
+        match policy {
+            KeepOrderPolicy::BeforeExplode => KeepOrder::BeforeExplode,
+            KeepOrderPolicy::AfterExplode => KeepOrder::AfterExplode,
+        }
+    }
+}
+
+/// Which dice of an exploding pool may explode.
+#[derive(Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum ExplodeWhichPolicy {
+    /// Every die
+    #[default]
+    All,
+    /// Only the die showing the highest face
+    Highest,
+    /// Only the die showing the lowest face
+    Lowest,
+}
+
+impl From<ExplodeWhichPolicy> for ExplodeWhich {
+    fn from(policy: ExplodeWhichPolicy) -> Self {
+        match policy {
+            ExplodeWhichPolicy::All => ExplodeWhich::All,
+            ExplodeWhichPolicy::Highest => ExplodeWhich::Highest,
+            ExplodeWhichPolicy::Lowest => ExplodeWhich::Lowest,
+        }
+    }
+}
+
+/// The order `--sort-results` prints the output of each specification in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum SortOrder {
+    /// Lowest total first
+    Asc,
+    /// Highest total first
+    Desc,
+}
+
+/// How a critical hit changes the damage dice.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum CritDamage {
+    /// Roll twice as many dice, adding the modifier once
+    DoubleDice,
+    /// Double the whole total, modifier included
+    DoubleTotal,
+    /// Add the dice's maximum, without the modifier, to a normal roll
+    MaxPlusRoll,
+}
+
+impl From<CritDamage> for CritRule {
+    fn from(rule: CritDamage) -> Self {
+        match rule {
+            CritDamage::DoubleDice => CritRule::DoubleDice,
+            CritDamage::DoubleTotal => CritRule::DoubleTotal,
+            CritDamage::MaxPlusRoll => CritRule::MaxPlusRoll,
+        }
+    }
+}
+
+/// How the dice of each term are combined into its result.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+enum ReduceMode {
+    /// Add the dice
+    #[default]
+    Sum,
+    /// Multiply the dice
+    Product,
+    /// Take the highest die
+    Max,
+    /// Take the lowest die
+    Min,
+}
+
+impl From<ReduceMode> for Reduce {
+    fn from(mode: ReduceMode) -> Self {
+        match mode {
+            ReduceMode::Sum => Reduce::Sum,
+            ReduceMode::Product => Reduce::Product,
+            ReduceMode::Max => Reduce::Max,
+            ReduceMode::Min => Reduce::Min,
+        }
+    }
+}
+
+/// A fixed result to report instead of rolling, for testing tools at the extremes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum ForceMode {
+    /// The highest possible total
+    Max,
+    /// The lowest possible total
+    Min,
+    /// The expected total, rounded to the nearest whole number
+    Average,
+    /// The expected total, rounded down as in published stat blocks, for `--take-average`
+    #[value(skip)]
+    StatBlock,
+}
+
+/// The total `--force-roll` reports for the dice instead of rolling them.
+fn forced_total(dice: &Dice, mode: ForceMode) -> i32 {
+    match mode {
+        ForceMode::Max => dice.max_roll(),
+        ForceMode::Min => dice.min_roll(),
+        ForceMode::Average => dice.expected_value().round() as i32,
+        ForceMode::StatBlock => dice.stat_block_average(),
+    }
+}
+
+/// Every member of the `N#` group of a specification showing the forced total, as
+/// results without dice, so that they are reported like any other roll.
+fn forced_group(spec: &str, dice: &Dice, mode: ForceMode) -> Vec<GroupRoll> {
+    let size = split_group(split_label(spec).1).map_or(1, |(size, _)| size);
+    let result = RollResult {
+        dice: Vec::new(),
+        modifier: 0,
+        total: forced_total(dice, mode),
+        rolled_sides: None,
+    };
+    (0..size)
+        .map(|_| GroupRoll {
+            result: result.clone(),
+            rerolled_from: Vec::new(),
+            safety_net: None,
+            floored_from: None,
+        })
+        .collect()
+}
+
+/// The fixed result to report instead of rolling, if any: `--force-roll`, or the
+/// stat-block average for `--take-average`.
+fn force_mode(args: &RollArgs) -> Option<ForceMode> {
+    args.force_roll.or(args.take_average.then_some(ForceMode::StatBlock))
+}
+
+/// How a total scaled by `--percent` is rounded to a whole number.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+enum PercentRounding {
+    /// Round down (the default)
+    #[default]
+    Floor,
+    /// Round up
+    Ceil,
+    /// Round to the nearest whole number, halves away from zero
+    Nearest,
+}
+
+/// The total scaled by `(1 + percent/100)` and rounded, saturating at the limits of
+/// an `i32`. The total already includes the flat modifier, so the percentage applies last.
+fn percent_total(total: i32, percent: i32, rounding: PercentRounding) -> i32 {
+    let scaled = i64::from(total) * (100 + i64::from(percent));
+    let (quotient, remainder) = (scaled.div_euclid(100), scaled.rem_euclid(100));
+    let rounded = match rounding {
+        PercentRounding::Floor => quotient,
+        PercentRounding::Ceil if remainder > 0 => quotient + 1,
+        PercentRounding::Ceil => quotient,
+        // A negative total rounds half away from zero by rounding its halves down
+        PercentRounding::Nearest if remainder > 50 || (remainder == 50 && scaled > 0) => quotient + 1,
+        PercentRounding::Nearest => quotient,
+    };
+    rounded.clamp(i64::from(i32::MIN), i64::from(i32::MAX)) as i32
+}
+
+/// Damage resistance or vulnerability, applied to a total after the flat modifier,
+/// critical damage and any `--percent`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DamageAdjustment {
+    Resist,
+    Vulnerable,
+}
+
+impl DamageAdjustment {
+    fn from_args(args: &RollArgs) -> Option<Self> {
+        match (args.resist, args.vulnerable) {
+            (true, _) => Some(DamageAdjustment::Resist),
+            (_, true) => Some(DamageAdjustment::Vulnerable),
+            _ => None,
+        }
+    }
+
+    /// The adjusted total: halved and rounded down, so that 15 becomes 7 and -7 becomes
+    /// -4, or doubled, saturating at the limits of an `i32`.
+    fn apply(self, total: i32) -> i32 {
+        match self {
+            DamageAdjustment::Resist => total.div_euclid(2),
+            DamageAdjustment::Vulnerable => total.saturating_mul(2),
+        }
+    }
+
+    /// The note giving the total before the adjustment, e.g. ` (halved from 15)`.
+    fn note(self, before: i32) -> String {
+        match self {
+            DamageAdjustment::Resist => format!(" (halved from {})", before),
+            DamageAdjustment::Vulnerable => format!(" (doubled from {})", before),
+        }
+    }
+}
+
+/// The colours a DC check is shown in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum ColorScheme {
+    /// Green for a success, yellow for a success that spends luck and red for a failure
+    Default,
+    /// Bold blue, bright yellow and underlined orange, which differ in brightness as well as hue
+    Colorblind,
+    /// Bold, underlined and reversed text, with no hues at all
+    Mono,
+}
+
+impl ColorScheme {
+    /// The ANSI SGR codes for a success, a success that spends luck, and a failure.
+    fn codes(self) -> [&'static str; 3] {
+        match self {
+            ColorScheme::Default => ["32", "33", "31"],
+            ColorScheme::Colorblind => ["1;34", "93", "4;38;5;208"],
+            ColorScheme::Mono => ["1", "4", "7"],
+        }
+    }
+}
+
+/// Wrap `text` in the ANSI escape for the SGR `code`, resetting afterwards.
+fn paint(text: &str, code: &str) -> String {
+    format!("\x1b[{}m{}\x1b[0m", code, text)
+}
+
+/// Check the `--total-range-cuts`: each is a percentage, which clap has already
+/// checked, and the low cut cannot come after the high one.
+fn check_range_cuts(cuts: &[u8]) -> Result<(), String> {
+    match cuts {
+        &[low, high] if low > high => Err(format!("--total-range-cuts {} {}: the low cut is above the high cut", low, high)),
+        _ => Ok(()),
+    }
+}
+
+/// The colour of `total` for `--color-total-by-range`: the failure colour of `scheme`
+/// below `low` percent of the way from `min` to `max`, the success colour from `high`
+/// percent, and the luck colour between. Dice that can only make one total are high.
+fn range_color(total: i32, (min, max): (i32, i32), (low, high): (u8, u8), scheme: ColorScheme) -> &'static str {
+    let position = if max > min {
+        (f64::from(total) - f64::from(min)) / (f64::from(max) - f64::from(min)) * 100.0
+    } else {
+        100.0
+    };
+    let [success, luck, failure] = scheme.codes();
+    if position >= f64::from(high) {
+        success
+    } else if position < f64::from(low) {
+        failure
+    } else {
+        luck
+    }
+}
+
+/// The mark shown after each successful die in the per-die breakdown.
+const SUCCESS_SYMBOL: &str = "✓";
+
+#[derive(Args)]
+#[command(group(ArgGroup::new("crit_source").args(["crit_damage", "target_ac"]).multiple(true)))]
+struct RollArgs {
+    /// Dice specifications (e.g., 1d6, 2d4+3, 5d10>=8)
+    #[arg(required_unless_present_any = ["file", "bench", "export_config", "plot_compare", "opposed", "ability_scores", "interactive", "examples"], help = "Dice specifications (e.g., 1d6, 2d4+3, 5d10>=8)")]
     dice: Vec<String>,
-    
+
+    #[command(flatten)]
+    spec: SpecArgs,
+
+    /// Read further dice specifications from a file, one per line ('-' for stdin)
+    #[arg(long, value_name = "PATH", help = "Read dice specifications from a file, one per line ('-' reads from stdin)")]
+    file: Option<PathBuf>,
+
+    /// Skip invalid lines in the --file batch instead of stopping at the first one
+    #[arg(long, requires = "file", help = "Skip invalid lines in the --file batch with a warning and report a summary at the end")]
+    retry_on_error: bool,
+
     /// Show the roll distribution histogram
     #[arg(short = 'd', long = "histogram", help = "Display the probability distribution histogram")]
     show_histogram: bool,
+
+    #[command(flatten)]
+    histogram: HistogramArgs,
+
+    #[command(flatten)]
+    query: QueryArgs,
+
+    /// Show each die of a success pool, marking the successes
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = SymbolMode::Never, help = "Show each die of a success pool with successes marked (auto only when writing to a terminal)")]
+    symbols: SymbolMode,
+
+    /// Colour the result of each DC check
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ColorMode::Never, requires = "dc", help = "Colour the result of each --dc check in the colours of --color-scheme")]
+    color: ColorMode,
+
+    /// The colours used by --color and --color-total-by-range
+    #[arg(long, value_enum, value_name = "SCHEME", default_value_t = ColorScheme::Default, help = "The colours used by --color and --color-total-by-range: default (green/yellow/red), colorblind (blue/yellow/orange that also differ in brightness) or mono (bold/underline/reverse)")]
+    color_scheme: ColorScheme,
+
+    /// Colour each total by where it falls between the lowest and highest the dice can roll
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ColorMode::Never, help = "Colour each total by where it falls between the lowest and highest total of its dice: low rolls in the failure colour of --color-scheme, middling ones in the luck colour and high ones in the success colour")]
+    color_total_by_range: ColorMode,
+
+    /// Where the low and high ranges of --color-total-by-range end, as percentages
+    #[arg(long, num_args = 2, value_names = ["LOW", "HIGH"], default_values_t = [33, 67], value_parser = clap::value_parser!(u8).range(0..=100), help = "The percentages of the way from the lowest to the highest total below which a total is low and from which it is high, for --color-total-by-range")]
+    total_range_cuts: Vec<u8>,
+
+    /// Print only the highest single die of each roll instead of the total
+    ///
+    /// This differs from keep-highest notation: `4d6kh1+2` adds the modifier and
+    /// scores the kept die against any success target, while this prints the bare
+    /// face, with exploded dice counting as dice of the pool.
+    #[arg(long, conflicts_with_all = ["pool_min", "symbols"], help = "Print only the highest single die of each roll instead of the total (the modifier is not added)")]
+    pool_max: bool,
+
+    /// Print only the lowest single die of each roll instead of the total
+    #[arg(long, conflicts_with = "symbols", help = "Print only the lowest single die of each roll instead of the total (the modifier is not added)")]
+    pool_min: bool,
+
+    /// Also print the sum of the dice for success pools
+    #[arg(long, help = "Print the sum of the dice alongside the number of successes for success pools")]
+    with_sum: bool,
+
+    /// Also print the number of critical successes for success pools
+    #[arg(long, help = "Print the number of critical successes (dice showing their highest face, which also count as successes) alongside the successes for success pools")]
+    with_criticals: bool,
+
+    /// Also print how many rerolls 'r' notation made
+    #[arg(long, visible_alias = "retry-count", help = "Print how many times 'r' notation rerolled a die in each roll, for dice with rerolls")]
+    with_rerolls: bool,
+
+    /// Also print the spread of each pool, its highest die minus its lowest
+    #[arg(long, help = "Print the spread of each roll alongside the total: the highest single die minus the lowest, with exploded dice counting as dice of the pool")]
+    spread: bool,
+
+    /// Also print the Fate ladder adjective for rolls of Fate dice
+    #[arg(long, help = "Print the adjective of the Fate ladder alongside the total of Fate dice (4dF, or custom dice with the faces -1, 0 and 1), e.g. '4 (Great)'; other dice are printed as usual, and at least one specification must roll Fate dice")]
+    fate_ladder: bool,
+
+    /// Also list every die rolled with its type, e.g. [d6:4, d6:5, d8:7]
+    #[arg(long, help = "List every die rolled after the total, each with the type of die it is, e.g. '[d6:4, d6:5, d8:7]', to tell the dice of a mixed pool apart; dice dropped by keep notation are in parentheses, e.g. '[d20:12, (d20:12)]'")]
+    show_die_type: bool,
+
+    /// Print the total of the dice alone, leaving out the modifier
+    #[arg(long, help = "Print the total of the dice before the modifier is applied (e.g. 2-12 for 2d6+100)")]
+    raw_total: bool,
+
+    /// Check each total against a difficulty class, succeeding on DC or higher
+    #[arg(long, value_name = "DC", help = "Report whether each total meets or beats the difficulty class DC")]
+    dc: Option<i32>,
+
+    /// Luck points that can be added to a failed --dc check to make it succeed
+    #[arg(long, value_name = "POINTS", requires = "dc", help = "Spend up to POINTS luck to lift a failed --dc check to the DC, using only as many as needed")]
+    luck: Option<u32>,
+
+    /// Lower the --dc by this much after every failed check, until one succeeds
+    #[arg(long, value_name = "STEP", value_parser = clap::value_parser!(i32).range(1..), requires = "dc", help = "Treat the rolls as repeated attempts at one task: each failed --dc check lowers the DC by STEP for the next roll, and a success sets it back to --dc")]
+    escalating_dc: Option<i32>,
+
+    /// Print the label of the band each total falls in, e.g. '1-5:miss,6-15:hit,16-20:crit'
+    #[arg(long, value_name = "BANDS", value_parser = Bands::parse, help = "Print the label of the band each total falls in, given as LOW-HIGH:LABEL entries (e.g. '1-5:miss,6-15:hit,16-20:crit')")]
+    bands: Option<Bands>,
+
+    /// Print the effect each total has, from a TOML table of ranges such as '"1-3" = "minor"'
+    #[arg(long, value_name = "PATH", value_parser = Bands::load_effect_table, help = "Print the effect of each total, looked up in a TOML file mapping ranges of totals to effects (e.g. '\"1-3\" = \"minor\"')")]
+    effect_table: Option<Bands>,
+
+    /// Which of several equal dice keep notation marks as kept
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = KeepTiePolicy::First, help = "Which of several equal dice keep notation (e.g. 2d20kh1) marks as kept: the first or last rolled, as shown by --show-die-type and the kept flags of the structured formats")]
+    keep_tie: KeepTiePolicy,
+
+    /// Whether keep notation chooses among exploding dice before or after they explode
+    #[arg(long, value_enum, value_name = "ORDER", default_value_t = KeepOrderPolicy::AfterExplode, help = "Whether keep notation on exploding dice (e.g. 4d6!kh3) chooses by each die's first face, keeping its explosions with it (before-explode), or among every die rolled (after-explode, the default)")]
+    keep_order: KeepOrderPolicy,
+
+    /// Roll the dice as critical hit damage under this rule
+    #[arg(long, value_enum, value_name = "RULE", help = "Roll the dice as critical hit damage: double-dice, double-total or max-plus-roll (with --attack, only when the attack crits)")]
+    crit_damage: Option<CritDamage>,
+
+    /// Roll this attack first and only apply --crit-damage when its first die shows its highest face
+    #[arg(long, value_name = "SPEC", help = "Roll an attack first (e.g. 1d20+5); a natural maximum on its first die is a critical hit for --crit-damage (needs --crit-damage or --target-ac)")]
+    attack: Option<String>,
+
+    /// Check the --attack against this armour class and only roll damage when it hits
+    #[arg(long, value_name = "AC", allow_negative_numbers = true, requires = "attack", help = "Check the --attack against armour class AC and roll the damage only when it hits: a natural maximum is a critical hit, rolled under --crit-damage (double-dice unless given), a natural minimum always misses, and otherwise the attack hits when its total is at least AC")]
+    target_ac: Option<i32>,
+
+    /// Never let critical hit damage roll below its average
+    #[arg(long, requires = "crit_source", help = "Raise critical hit damage that rolls below its average (under --crit-damage, or double-dice for a critical hit against --target-ac, rounded down) up to the average, as a house rule")]
+    crit_floor_average: bool,
+
+    /// Roll each specification this many times and keep the highest total
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..=1000), conflicts_with = "explain_distribution", help = "Roll each specification N times and keep the highest total; the histogram shows the distribution of the best total")]
+    best_of: Option<u32>,
+
+    /// Reroll the whole pool once when its total is below this, keeping the second result
+    #[arg(long, value_name = "N", allow_negative_numbers = true, conflicts_with = "best_of", help = "Reroll the whole expression once when its total is below N, keeping the second result and reporting both")]
+    reroll_pool_below: Option<i32>,
+
+    /// The most times --reroll-pool-below rerolls the whole pool
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_POOL_REROLLS, requires = "reroll_pool_below", help = "The most times --reroll-pool-below rerolls a pool that stays too low, keeping the last result")]
+    pool_reroll_limit: u32,
+
+    /// Reroll a success pool once when it comes up with no successes, keeping the second result
+    #[arg(long, conflicts_with_all = ["best_of", "reroll_pool_below"], help = "Reroll the whole success pool once when it comes up with no successes, keeping the second result and reporting both")]
+    mulligan: bool,
+
+    /// Roll a second time when the first total is below this, keeping the higher
+    #[arg(long, value_name = "N", allow_negative_numbers = true, conflicts_with_all = ["best_of", "reroll_pool_below", "mulligan"], help = "Roll each specification a second time when the first total is below N and keep the higher of the two (a safety net for 1d20), reporting whether the net triggered")]
+    safety_net: Option<i32>,
+
+    /// Report the maximum, minimum or rounded average total instead of rolling (for testing)
+    ///
+    /// Named `--force-roll` because `--force` already means overwriting with `--export-config`.
+    #[arg(long, value_enum, value_name = "MODE", help = "Report the max, min or rounded average total instead of rolling, for testing tools at the extremes")]
+    force_roll: Option<ForceMode>,
+
+    /// Report the fixed average damage of a stat block instead of rolling
+    #[arg(long, conflicts_with = "force_roll", help = "Report the fixed average a stat block lists instead of rolling: the mean total rounded down (e.g. 2d6+3 gives 10, 1d8+2 gives 6)")]
+    take_average: bool,
+
+    /// Scale each total by a percentage after the flat modifier, e.g. 50 for +50% damage
+    #[arg(long, value_name = "N", allow_negative_numbers = true, help = "Multiply each total by (1 + N/100) after the flat modifier is added (e.g. '2d6+4 --percent 50' for +50% damage)")]
+    percent: Option<i32>,
+
+    /// How a total scaled by --percent is rounded
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = PercentRounding::Floor, requires = "percent", help = "How a total scaled by --percent is rounded: floor, ceil or nearest")]
+    percent_rounding: PercentRounding,
+
+    /// Halve each total, rounding down, for damage against a resistant target
+    #[arg(long, conflicts_with = "vulnerable", help = "Halve each total after everything else, rounding down (toward negative infinity), for damage against a resistant target; the total before halving is reported too")]
+    resist: bool,
+
+    /// Double each total, for damage against a vulnerable target
+    #[arg(long, help = "Double each total after everything else, for damage against a vulnerable target; the total before doubling is reported too")]
+    vulnerable: bool,
+
+    /// Print the output of each specification in order of its total
+    #[arg(long, value_enum, value_name = "ORDER", help = "Print the output of each specification in order of its total once every specification is rolled: asc or desc (a group like 3#1d20 is placed by its best roll; text output only)")]
+    sort_results: Option<SortOrder>,
+
+    /// Add up the rolls of each label and print the subtotals and a grand total
+    #[arg(long, help = "Once every specification is rolled, add up the totals of the rolls sharing a label (e.g. 'fire: 2d6' and 'fire: 1d4') and print each label's subtotal and the grand total; unlabeled rolls are counted together (text output, or standard error for the structured formats)")]
+    group_by_label: bool,
+
+    /// Print a blank line between the output of each specification
+    #[arg(long, visible_alias = "spacing", help = "Print a blank line between the output blocks of each specification (a group like 3#1d20 stays one block)")]
+    newline_separated: bool,
+
+    /// How to write the rolls: text, a JSON array, or JSON lines as each roll completes
+    #[arg(long, value_enum, help = "Output format: text, a single JSON array (json), one JSON object per line as each roll completes (jsonl), the same with the time and seed of each roll for audit logs (ndjson-with-meta), or a CSV table (csv); toml and yaml need the toml-output and yaml-output features [default: text, or going by the extension of --output]")]
+    format: Option<OutputFormat>,
+
+    /// Write the rolls to this file instead of standard output
+    #[arg(long, value_name = "PATH", help = "Write the rolls to PATH instead of standard output, in the format its extension names (.json, .jsonl, .csv, .toml, .yaml) unless --format is given; any other extension is text")]
+    output: Option<PathBuf>,
+
+    /// Keep showing the rolls as usual and log them to --output in this format
+    #[arg(long, value_enum, value_name = "FORMAT", requires = "output", help = "Show the rolls on standard output as usual (in --format, text by default) and log them to the --output file in FORMAT, so a readable display can sit beside a structured log (e.g. '--output rolls.json --roll-log-format json')")]
+    roll_log_format: Option<OutputFormat>,
+
+    /// Write only the total of each roll as JSON
+    #[arg(long, conflicts_with = "template", help = "Write each roll as just {\"total\": N}, in a single compact JSON array or, with --format jsonl, one per line")]
+    total_only_json: bool,
+
+    /// Write a header row at the top of CSV output (the default)
+    #[arg(long, overrides_with = "no_csv_header", help = "Write a header row at the top of CSV output (the default)")]
+    csv_header: bool,
+
+    /// Leave the header row out of CSV output
+    #[arg(long, overrides_with = "csv_header", help = "Leave the header row out of CSV output, for appending the rows to an existing table")]
+    no_csv_header: bool,
+
+    /// Wrap the JSON rolls in an object with a summary across every specification
+    #[arg(long, conflicts_with_all = ["template", "total_only_json"], help = "Write the rolls as one JSON object, {\"rolls\": [...], \"summary\": {...}}, whose summary gives the grand total of every roll, the number of specifications and the lowest and highest totals")]
+    summary_json: bool,
+
+    /// Write each roll as this template instead of the usual text line
+    #[arg(long, value_name = "FORMAT", value_parser = Template::parse, conflicts_with = "format", help = "Write each roll by filling in a template with {spec}, {label}, {total}, {rolls} and {modifier} placeholders (e.g. '{spec}: {total} [{rolls}]'); write {{ and }} for literal braces")]
+    template: Option<Template>,
+
+    /// Seed the random number generator for reproducible rolls
+    #[arg(long, help = "Seed the random number generator for reproducible rolls")]
+    seed: Option<u64>,
+
+    /// Roll each specification with every seed in a range and report the seeds that give a total
+    #[arg(long, value_name = "START-END", value_parser = SeedRange::parse, conflicts_with_all = ["seed", "seed_show", "interactive", "simulate"], help = "Roll each specification once with every seed from START to END and report which seeds give the total asked for by --sweep-for, so 'droll --seed SEED SPEC' can repeat it")]
+    seed_sweep: Option<SeedRange>,
+
+    /// The total --seed-sweep looks for
+    #[arg(long, value_name = "GOAL", value_parser = SweepGoal::parse, default_value = "max", requires = "seed_sweep", allow_negative_numbers = true, help = "What --seed-sweep looks for: max or min for the highest or lowest total rolled in the range, or a total N")]
+    sweep_for: SweepGoal,
+
+    /// Roll with these faces, in order, instead of random ones, for scripted demos
+    #[arg(long, value_name = "FACES", value_delimiter = ',', allow_negative_numbers = true, conflicts_with_all = ["seed", "seed_show", "seed_sweep", "interactive", "simulate"], help = "Make the dice show these faces in order instead of random ones (e.g. '--script-results 6,2,4'), rerolls and explosions included, for scripted demos and tests; running out of faces is an error")]
+    script_results: Option<Vec<i32>>,
+
+    /// Roll with a fresh random seed and print it, so the roll can be repeated with --seed
+    #[arg(long, conflicts_with = "seed", help = "Roll with a freshly generated seed and print it to standard error, so an interesting roll can be repeated with --seed")]
+    seed_show: bool,
+
+    /// Save the random number generator's state after rolling, to carry on from later
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["seed_sweep", "script_results"], help = "After rolling, write the random number generator's state to PATH, so a later run with --load-rng-state PATH carries on the exact same sequence")]
+    dump_rng_state: Option<PathBuf>,
+
+    /// Carry on from a random number generator state saved by --dump-rng-state
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["seed", "seed_show", "seed_sweep", "script_results"], help = "Roll with the random number generator state saved to PATH by --dump-rng-state, continuing its sequence where it left off (PATH may also be given to --dump-rng-state to keep a resumable session)")]
+    load_rng_state: Option<PathBuf>,
+
+    /// Roll initiative for each 'name:modifier' entry instead of rolling dice
+    #[arg(long, help = "Roll 1d20+modifier for each 'name:modifier' entry and print the initiative order")]
+    initiative: bool,
+
+    /// Roll a single d20 for --initiative that every combatant shares
+    #[arg(long, requires = "initiative", help = "With --initiative, roll a single d20 that the whole side shares and add each combatant's modifier to it, rather than rolling for each one")]
+    group_initiative: bool,
+
+    /// Roll a full set of six ability scores instead of rolling dice
+    #[arg(long, conflicts_with = "initiative", help = "Roll six ability scores as 4d6 dropping the lowest die and print them with their modifiers and sum")]
+    ability_scores: bool,
+
+    /// Roll hit points for LEVELS levels with the specification as the hit die
+    #[arg(long, value_name = "LEVELS", value_parser = clap::value_parser!(u32).range(1..), conflicts_with_all = ["initiative", "ability_scores"], help = "Roll hit points for LEVELS levels of a character, with the single specification as the hit die plus the constitution modifier (e.g. '1d10+2'): level 1 takes the most the die can give, every later level is rolled, and each level gives at least 1")]
+    hit_points: Option<u32>,
+
+    /// Take the average of the hit die for every level after the first
+    #[arg(long, requires = "hit_points", help = "With --hit-points, take the fixed average of the hit die (rounded up) for every level after the first rather than rolling")]
+    average_hp: bool,
+
+    /// Print a gallery of example invocations with what each one does
+    #[arg(long, help = "Print a gallery of example invocations, each with what it rolls and what it is for, as a tour of the notation")]
+    examples: bool,
+
+    /// Keep rolling every INTERVAL_MS milliseconds until interrupted
+    #[arg(long = "loop", value_name = "INTERVAL_MS", help = "Keep rolling every INTERVAL_MS milliseconds until interrupted with Ctrl-C")]
+    loop_interval: Option<u64>,
+
+    /// Write the probability distribution to a NumPy .npy file
+    #[arg(long, value_name = "PATH", help = "Write the probability distribution of a single dice specification to a NumPy .npy file")]
+    export_pmf: Option<String>,
+
+    /// Write every combination of faces and its total to a CSV file
+    #[arg(long, value_name = "PATH", help = "Write every equally likely combination of faces of a single dice specification, one row each with its total, to a CSV file (pools of at most 100000 combinations without explosions)")]
+    export_sample_space: Option<String>,
+
+    /// Roll each specification this many times and report the observed distribution and statistics
+    #[arg(long, value_name = "ROLLS", value_parser = clap::value_parser!(u64).range(1..), help = "Roll each specification ROLLS times and print the observed distribution with its count, mean, standard deviation, min and max")]
+    simulate: Option<u64>,
+
+    /// Print only the summary statistics of a simulation
+    #[arg(long, requires = "simulate", help = "With --simulate, print only the count, mean, standard deviation, min and max of each specification")]
+    summary_stats_only: bool,
+
+    /// Go ahead with a huge simulation without asking first
+    #[arg(long, short = 'y', help = "Go ahead without asking when --simulate would make more than 100000000 rolls in all; without a terminal to ask on, such a simulation needs --yes")]
+    yes: bool,
+
+    /// Mark the exact chance of each total next to the observed bars of a simulation
+    #[arg(long, requires = "simulate", conflicts_with = "summary_stats_only", help = "With --simulate, mark the exact chance of each total with a '+' beside its observed bar and print both percentages, so the simulation can be checked against the distribution")]
+    histogram_baseline: bool,
+
+    /// Roll the specifications in order and stop at the first whose total meets the condition
+    #[arg(long, value_name = "CONDITION", value_parser = Condition::parse, conflicts_with_all = ["simulate", "interactive", "initiative"], help = "Roll the specifications in order, stopping at the first whose total meets CONDITION (e.g. '>=15', '<3', '=20', '!=1') and reporting it, or that none did")]
+    first_match: Option<Condition>,
+
+    /// Pick one specification at random in proportion to its weight and roll it
+    #[arg(long, conflicts_with_all = ["simulate", "interactive", "initiative", "first_match"], help = "Treat each specification as WEIGHT:SPEC (e.g. '3:1d6' '1:2d10+5') and roll just one, chosen at random in proportion to the weights, as with a loot table")]
+    weighted: bool,
+
+    /// Roll each success pool again and again until its successes add up to N
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..), conflicts_with_all = ["simulate", "interactive", "initiative", "first_match"], help = "An extended test: roll each success pool repeatedly, adding up the successes, and report how many rolls it took to reach N")]
+    until_successes: Option<u32>,
+
+    /// The most rolls an extended test makes before giving up
+    #[arg(long, value_name = "ROLLS", default_value_t = DEFAULT_EXTENDED_TEST_ROLLS, value_parser = clap::value_parser!(u32).range(1..), requires = "until_successes", help = "The most rolls --until-successes makes of each pool before giving up")]
+    max_rolls: u32,
+
+    /// Print each parsed specification as JSON instead of rolling it
+    #[arg(long, conflicts_with_all = ["interactive", "simulate", "initiative"], help = "Parse each specification and print its structure (count, sides, modifier and notation of every term) as a line of JSON, without rolling")]
+    parse_only: bool,
+
+    /// Print the whole distribution of each specification as JSON instead of rolling it
+    #[arg(long, conflicts_with_all = ["interactive", "simulate", "initiative", "parse_only"], help = "Print the distribution of each specification as a line of JSON with aligned arrays of values, probabilities and cumulative probabilities, without rolling")]
+    distribution_json: bool,
+
+    /// Plot the distributions of two specifications as overlaid curves in an SVG file
+    #[arg(long, num_args = 3, value_names = ["SPEC1", "SPEC2", "PATH"], help = "Write the distributions of SPEC1 and SPEC2 as overlaid curves to an SVG file at PATH")]
+    plot_compare: Option<Vec<String>>,
+
+    /// Roll an attacker's success pool against a defender's and report the net hits
+    #[arg(long, num_args = 2, value_names = ["ATTACKER", "DEFENDER"], help = "Roll two success pools against each other (e.g. --opposed 6d6>=5 4d6>=5) and report the net hits (attacker successes minus defender successes) and the winner")]
+    opposed: Option<Vec<String>>,
+
+    /// Append every roll to this JSON lines history file
+    #[arg(long, value_name = "PATH", help = "Append every roll to a JSON lines history file at PATH")]
+    history: Option<PathBuf>,
+
+    /// Time ROLLS rolls of a fixed specification and report the throughput (maintainer diagnostic)
+    #[arg(long, hide = true, value_name = "ROLLS", num_args = 0..=1, default_missing_value = "1000000")]
+    bench: Option<u64>,
+
+    /// Write each specification back as notation and check it parses to the same dice (maintainer diagnostic)
+    #[arg(long, hide = true)]
+    check_roundtrip: bool,
+
+    /// Work out each distribution by enumerating every roll and check it against the fast method (maintainer diagnostic)
+    #[arg(long, hide = true)]
+    benchmark_distribution: bool,
+
+    /// Read and roll specifications one line at a time, with line editing and history
+    #[arg(long, short = 'i', help = "Read and roll specifications one line at a time, with line editing and history recalled with the up arrow ('bank SPEC' stores the dice rolled, 'spend N' uses N of them, 'undo' takes back the last bank or spend, 'quit' or Ctrl-D stops)")]
+    interactive: bool,
+
+    /// The prompt shown before each line in interactive mode
+    #[arg(long, requires = "interactive", default_value = repl::DEFAULT_PROMPT, help = "The prompt shown before each line with --interactive")]
+    prompt: String,
+
+    /// Keep the interactive history in this file instead of the default location
+    #[arg(long, value_name = "PATH", requires = "interactive", help = "Keep the --interactive history in PATH (default: $XDG_STATE_HOME/droll/history)")]
+    repl_history: Option<PathBuf>,
+
+    /// Guarantee a roll of at least THRESHOLD after WINDOW lower rolls in a row
+    #[arg(long, num_args = 2, value_names = ["THRESHOLD", "WINDOW"], allow_negative_numbers = true, requires = "interactive", help = "With --interactive, keep count of rolls in a row below THRESHOLD and, after WINDOW of them, draw the next roll only from totals of at least THRESHOLD (or the highest total, if the dice cannot reach it)")]
+    pity: Option<Vec<i32>>,
+
+    /// Never roll the same total for a specification twice in a row
+    #[arg(long, conflicts_with = "pity", help = "With --loop or --interactive, roll a specification again whenever its total matches the one it rolled just before (dice that can only make one total, like 1d1, are an error)")]
+    no_repeat: bool,
+
+    /// Play press your luck with SPEC in interactive mode
+    #[arg(long, value_name = "SPEC", requires = "interactive", help = "With --interactive, play press your luck with SPEC: 'roll' adds a roll of it to a running total, a roll meeting --bust loses the running total, and 'bank' alone locks it in")]
+    press_your_luck: Option<String>,
+
+    /// The rolls that bust in press your luck
+    #[arg(long, value_name = "CONDITION", value_parser = Condition::parse, default_value = "=1", requires = "press_your_luck", help = "The totals that bust with --press-your-luck, losing the running total (e.g. '=1', '<=2')")]
+    bust: Condition,
+
+    /// Allow this many rerolls of the last roll in interactive mode
+    #[arg(long, value_name = "N", requires = "interactive", help = "With --interactive, allow N uses of 'reroll', which rolls the last specification again and reports the rerolls left (e.g. '--reroll-budget 3' for three rerolls per rest)")]
+    reroll_budget: Option<u32>,
+
+    /// Write a commented template config file to the --config path or the default location
+    #[arg(long, help = "Write a commented template config file to the --config path or the default location, then exit")]
+    export_config: bool,
+
+    /// Replace an existing config file with --export-config
+    #[arg(long, requires = "export_config", help = "Overwrite an existing config file with --export-config")]
+    force: bool,
 }
 
-#[derive(Debug)]
-struct Dice {
-    sides: u8,
-    count: u8,
-    modifier: i32,
-}
+#[derive(Args)]
+struct StatsArgs {
+    /// Dice specifications (e.g., 1d6, 2d4+3, 5d10>=8)
+    #[arg(required = true, help = "Dice specifications (e.g., 1d6, 2d4+3, 5d10>=8)")]
+    dice: Vec<String>,
+
+    #[command(flatten)]
+    spec: SpecArgs,
+
+    #[command(flatten)]
+    histogram: HistogramArgs,
+
+    #[command(flatten)]
+    query: QueryArgs,
+
+    /// Write the probability distribution to a NumPy .npy file
+    #[arg(long, value_name = "PATH", help = "Write the probability distribution of a single dice specification to a NumPy .npy file")]
+    export_pmf: Option<String>,
+
+    /// Write every combination of faces and its total to a CSV file
+    #[arg(long, value_name = "PATH", help = "Write every equally likely combination of faces of a single dice specification, one row each with its total, to a CSV file (pools of at most 100000 combinations without explosions)")]
+    export_sample_space: Option<String>,
+
+    /// Print the whole distribution of each specification as JSON instead of the histogram
+    #[arg(long, help = "Print the distribution of each specification as a line of JSON with aligned arrays of values, probabilities and cumulative probabilities, instead of the histogram")]
+    distribution_json: bool,
+
+    /// Print one table comparing the statistics of every specification
+    #[arg(long, value_name = "TARGET", allow_negative_numbers = true, conflicts_with = "distribution_json", help = "Print one table comparing every specification, a row each with its mean, variance, min, max and chance of a total of at least TARGET, instead of the histograms")]
+    compare_table: Option<i32>,
+
+    /// Print how much more or less likely each total is with the first of two specifications
+    #[arg(long, conflicts_with_all = ["distribution_json", "compare_table"], help = "Given two specifications, print for every total either can make the chance with the first minus the chance with the second, to show where one is more likely than the other")]
+    distribution_diff: bool,
+
+    /// Suggest the combination of the specifications that does best within a budget of dice
+    #[arg(long, value_name = "DICE", value_parser = clap::value_parser!(u32).range(1..=1000), conflicts_with_all = ["distribution_json", "compare_table"], help = "Treat the specifications as candidates, each usable any number of times and costing the number of dice it rolls, and suggest the combination rolling at most DICE dice that does best for --optimize-for")]
+    optimize: Option<u32>,
+
+    /// What --optimize looks for in a combination
+    #[arg(long, value_enum, value_name = "GOAL", default_value_t = Goal::Mean, requires = "optimize", help = "What --optimize looks for: mean (the highest expected total) or variance (the steadiest total among combinations spending the most of the budget)")]
+    optimize_for: Goal,
+
+    /// Print the chance of at least one critical over this many attacks
+    #[arg(long, value_name = "ATTACKS", value_parser = clap::value_parser!(u32).range(1..), help = "Print the chance of at least one critical over ATTACKS independent attacks with each specification, where an attack crits when a kept die of its first term shows a natural --crit-on or higher (e.g. '--crit-chance 10 2d20kh1' for ten attacks at advantage)")]
+    crit_chance: Option<u32>,
+
+    /// The lowest natural face that is a critical for --crit-chance
+    #[arg(long, value_name = "FACE", allow_negative_numbers = true, requires = "crit_chance", help = "The lowest natural face of a die that counts as a critical for --crit-chance (default: the highest face of the die)")]
+    crit_on: Option<i32>,
+}
+
+#[derive(Args)]
+struct CheckArgs {
+    /// Dice specifications (e.g., 1d6, 2d4+3, 5d10>=8)
+    #[arg(required = true, help = "Dice specifications (e.g., 1d6, 2d4+3, 5d10>=8)")]
+    dice: Vec<String>,
+
+    #[command(flatten)]
+    spec: SpecArgs,
+}
+
+#[derive(Args)]
+struct PlotArgs {
+    /// The two dice specifications to compare (e.g., 2d6 1d12)
+    #[arg(num_args = 2, required = true, value_names = ["SPEC1", "SPEC2"], help = "The two dice specifications to compare (e.g., 2d6 1d12)")]
+    dice: Vec<String>,
+
+    /// Write the SVG to this file
+    #[arg(long, value_name = "PATH", required = true, help = "Write the SVG plot to PATH")]
+    output: String,
+
+    #[command(flatten)]
+    spec: SpecArgs,
+}
+
+#[derive(Args)]
+struct HistoryArgs {
+    /// The JSON lines history file written by rolling with --history
+    #[arg(long, value_name = "PATH", required = true, help = "The JSON lines history file to summarize, as written by rolling with --history PATH")]
+    history: PathBuf,
+}
+
+/// Rewrite a bare number specification into 'NdS' notation according to the
+/// `--default-die` and `--bare-range` flags, leaving any other specification untouched.
+fn resolve_bare_number(spec: &str, default_die: Option<u8>, bare_range: bool) -> Result<String, String> {
+    let trimmed = spec.trim();
+    if trimmed.is_empty() || !trimmed.chars().all(|c| c.is_ascii_digit()) {
+        return Ok(spec.to_string());
+    }
+
+    match (default_die, bare_range) {
+        (Some(sides), _) => Ok(format!("{}d{}", trimmed, sides)),
+        (None, true) => Ok(format!("1d{}", trimmed)),
+        (None, false) => Err(format!(
+            "'{}' is a bare number: use --default-die <SIDES> to roll it as {}dSIDES, or --bare-range to roll 1d{}",
+            trimmed, trimmed, trimmed
+        )),
+    }
+}
+
+/// Write a probability distribution as a NumPy `.npy` (format version 1.0) array.
+///
+/// The array has shape `(n, 2)` with little-endian `f64` elements in row-major order:
+/// column 0 holds each possible total and column 1 its probability (0.0 to 1.0),
+/// so `numpy.load(path)` returns the distribution directly.
+fn write_pmf_npy<W: Write>(writer: &mut W, totals: &[i32], percentages: &[f64]) -> io::Result<()> {
+    let mut header = format!(
+        "{{'descr': '<f8', 'fortran_order': False, 'shape': ({}, 2), }}",
+        totals.len()
+    );
+    // The magic string, version and header length take 10 bytes, and the whole
+    // preamble must be padded with spaces to a multiple of 64 ending in a newline
+    let unpadded = 10 + header.len() + 1;
+    header.push_str(&" ".repeat((64 - unpadded % 64) % 64));
+    header.push('\n');
+
+    writer.write_all(b"\x93NUMPY")?;
+    writer.write_all(&[1, 0])?;
+    writer.write_all(&(header.len() as u16).to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    for (total, percentage) in totals.iter().zip(percentages.iter()) {
+        writer.write_all(&f64::from(*total).to_le_bytes())?;
+        writer.write_all(&(percentage / 100.0).to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// The specification rolled by `--bench`: enough dice to exercise the per-die path.
+const BENCH_SPEC: &str = "10d6+3";
+
+/// Timing from a `--bench` run.
+#[derive(Debug)]
+struct BenchReport {
+    rolls: u64,
+    elapsed: Duration,
+}
+
+impl BenchReport {
+    fn rolls_per_second(&self) -> f64 {
+        self.rolls as f64 / self.elapsed.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+}
+
+/// Roll the dice `rolls` times and time how long it takes.
+fn run_bench<R: Rng + ?Sized>(dice: &Dice, rolls: u64, rng: &mut R) -> BenchReport {
+    let start = Instant::now();
+    let mut checksum: i64 = 0;
+    for _ in 0..rolls {
+        checksum += i64::from(dice.roll_with(rng));
+    }
+    // Keep the optimizer from discarding the rolls
+    std::hint::black_box(checksum);
+    BenchReport {
+        rolls,
+        elapsed: start.elapsed(),
+    }
+}
+
+/// Parse a specification, write it back as notation and parse that again, describing
+/// the round trip or why the notation did not survive it.
+fn roundtrip_report(spec: &str, options: &SpecArgs) -> Result<String, String> {
+    let dice = parse_spec(spec, options).map_err(|err| format!("{}: {}", spec, err))?;
+    let written = dice.to_string();
+    match parse_spec(&written, options) {
+        Ok(reparsed) if reparsed == dice => Ok(format!("{} -> {}: ok", spec, written)),
+        Ok(reparsed) => Err(format!("{} -> {}: parses as different dice ({:?})", spec, written, reparsed)),
+        Err(err) => Err(format!("{} -> {}: does not parse: {}", spec, written, err)),
+    }
+}
+
+/// The most sequences of faces `--benchmark-distribution` will enumerate for one specification.
+const MAX_BENCHMARKED_OUTCOMES: usize = 1_000_000;
+
+/// How far apart the enumerated and fast probabilities of a total may be before they disagree.
+const DISTRIBUTION_TOLERANCE: f64 = 1e-9;
+
+/// Work out the distribution of a specification both by enumerating every sequence of
+/// faces and by the fast method, describing how long each took, or every total where
+/// they disagree.
+fn distribution_benchmark_report(spec: &str, options: &SpecArgs) -> Result<String, String> {
+    let dice = parse_spec(spec, options).map_err(|err| format!("{}: {}", spec, err))?;
+    let start = Instant::now();
+    let Some(enumerated) = dice.enumerated_distribution(MAX_BENCHMARKED_OUTCOMES) else {
+        return Ok(format!(
+            "{}: skipped (only pools of at most {} outcomes without explosions can be enumerated)",
+            spec, MAX_BENCHMARKED_OUTCOMES
+        ));
+    };
+    let enumeration_time = start.elapsed();
+    let start = Instant::now();
+    let distribution = dice.distribution_map();
+    let fast_time = start.elapsed();
+
+    let totals: BTreeSet<i32> = enumerated.keys().chain(distribution.keys()).copied().collect();
+    let discrepancies: Vec<String> = totals
+        .into_iter()
+        .filter_map(|total| {
+            let slow = enumerated.get(&total).copied().unwrap_or(0.0);
+            let fast = distribution.get(&total).copied().unwrap_or(0.0);
+            ((slow - fast).abs() > DISTRIBUTION_TOLERANCE)
+                .then(|| format!("{} (enumerated {:.12}, fast {:.12})", total, slow, fast))
+        })
+        .collect();
+    if !discrepancies.is_empty() {
+        return Err(format!("{}: distributions differ at {}", spec, discrepancies.join(", ")));
+    }
+    Ok(format!(
+        "{}: {} totals match (enumerated in {:.3}ms, fast in {:.3}ms)",
+        spec,
+        distribution.len(),
+        enumeration_time.as_secs_f64() * 1000.0,
+        fast_time.as_secs_f64() * 1000.0
+    ))
+}
+
+/// How a `Condition` compares a total with its number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    AtLeast,
+    AtMost,
+    NotEqual,
+    Above,
+    Below,
+    Equal,
+}
+
+impl Comparison {
+    /// The operators as written, two-character ones first so that `>=` is not read as `>`.
+    const OPERATORS: [(&'static str, Comparison); 7] = [
+        ("==", Comparison::Equal),
+        (">=", Comparison::AtLeast),
+        ("<=", Comparison::AtMost),
+        ("!=", Comparison::NotEqual),
+        (">", Comparison::Above),
+        ("<", Comparison::Below),
+        ("=", Comparison::Equal),
+    ];
+
+    fn symbol(self) -> &'static str {
+        match self {
+            Comparison::AtLeast => ">=",
+            Comparison::AtMost => "<=",
+            Comparison::NotEqual => "!=",
+            Comparison::Above => ">",
+            Comparison::Below => "<",
+            Comparison::Equal => "=",
+        }
+    }
+}
+
+/// A test of a total against a number, such as `>=15`, written for `--first-match`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Condition {
+    comparison: Comparison,
+    value: i32,
+}
+
+impl Condition {
+    fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        let (comparison, rest) = Comparison::OPERATORS
+            .into_iter()
+            .find_map(|(operator, comparison)| text.strip_prefix(operator).map(|rest| (comparison, rest)))
+            .ok_or_else(|| format!("Invalid condition '{}': must start with >=, <=, >, <, = or !=", text))?;
+        let value = rest
+            .trim()
+            .parse::<i32>()
+            .map_err(|_| format!("Invalid condition '{}': '{}' is not a valid number", text, rest.trim()))?;
+        Ok(Condition { comparison, value })
+    }
+
+    /// Whether `total` meets the condition.
+    fn matches(self, total: i32) -> bool {
+        match self.comparison {
+            Comparison::AtLeast => total >= self.value,
+            Comparison::AtMost => total <= self.value,
+            Comparison::NotEqual => total != self.value,
+            Comparison::Above => total > self.value,
+            Comparison::Below => total < self.value,
+            Comparison::Equal => total == self.value,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.comparison.symbol(), self.value)
+    }
+}
+
+/// Roll the specifications in order with `roll` until one meets the condition, listing
+/// each total rolled and then the first match, or that nothing matched.
+fn first_match_lines(specs: &[String], dice_vec: &[Dice], condition: Condition, mut roll: impl FnMut(&Dice) -> i32) -> Vec<String> {
+    let mut lines = Vec::new();
+    for (spec, dice) in specs.iter().zip(dice_vec) {
+        let total = roll(dice);
+        lines.push(format!("{}: {}", spec, total));
+        if condition.matches(total) {
+            lines.push(format!("First match: {} rolled {} ({})", spec, total, condition));
+            return lines;
+        }
+    }
+    lines.push(format!("No specification rolled {}", condition));
+    lines
+}
+
+/// The weight of each `WEIGHT:SPEC` specification given with `--weighted`. Every
+/// weight must be a whole number of at least 1.
+fn parse_weights(specs: &[String]) -> Result<Vec<u32>, String> {
+    specs
+        .iter()
+        .map(|spec| match split_label(spec).0 {
+            Some(weight) => match weight.parse::<u32>() {
+                Ok(weight) if weight > 0 => Ok(weight),
+                _ => Err(format!("Invalid weight '{}' in '{}': must be a whole number of at least 1", weight, spec)),
+            },
+            None => Err(format!("Missing weight in '{}': give it as WEIGHT:SPEC (e.g. '3:1d6')", spec)),
+        })
+        .collect()
+}
+
+/// Choose an index at random, each in proportion to its weight.
+fn weighted_pick<R: Rng + ?Sized>(weights: &[u32], rng: &mut R) -> usize {
+    let mut draw = rng.random_range(0..weights.iter().map(|&weight| u64::from(weight)).sum::<u64>());
+    for (index, &weight) in weights.iter().enumerate() {
+        if draw < u64::from(weight) {
+            return index;
+        }
+        draw -= u64::from(weight);
+    }
+    unreachable!("the draw is below the sum of the weights")
+}
+
+/// How many rolls `--until-successes` makes unless `--max-rolls` says otherwise.
+const DEFAULT_EXTENDED_TEST_ROLLS: u32 = 100;
+
+/// Run an extended test of a success pool, adding up the successes of each total from
+/// `roll` until they reach `target` or `max_rolls` rolls have been made, listing every
+/// roll and then how many it took.
+fn extended_test_lines(spec: &str, target: u32, max_rolls: u32, mut roll: impl FnMut() -> i32) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut accumulated: i64 = 0;
+    for rolls in 1..=max_rolls {
+        let successes = roll();
+        accumulated += i64::from(successes);
+        lines.push(format!("{} roll {}: {} (total {})", spec, rolls, successes, accumulated));
+        if accumulated >= i64::from(target) {
+            let noun = if rolls == 1 { "roll" } else { "rolls" };
+            lines.push(format!("{} reached {} successes in {} {}", spec, target, rolls, noun));
+            return lines;
+        }
+    }
+    lines.push(format!(
+        "{} did not reach {} successes in {} rolls ({} so far)",
+        spec, target, max_rolls, accumulated
+    ));
+    lines
+}
+
+/// The most seeds `--seed-sweep` will try for each specification.
+const MAX_SWEPT_SEEDS: u64 = 1_000_000;
+
+/// The most seeds listed for each specification by `--seed-sweep`.
+const MAX_LISTED_SEEDS: usize = 10;
+
+/// An inclusive range of seeds for `--seed-sweep`, written `START-END`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct SeedRange {
+    start: u64,
+    end: u64,
+}
+
+impl SeedRange {
+    fn parse(text: &str) -> Result<Self, String> {
+        let (start, end) = text
+            .split_once('-')
+            .ok_or_else(|| format!("Invalid seed range '{}': must be in format 'START-END' (e.g., '1-1000')", text))?;
+        let number = |part: &str| {
+            part.trim()
+                .parse::<u64>()
+                .map_err(|_| format!("Invalid seed range '{}': '{}' is not a valid seed", text, part.trim()))
+        };
+        let (start, end) = (number(start)?, number(end)?);
+        if start > end {
+            return Err(format!("Invalid seed range '{}': the start is after the end", text));
+        }
+        if end - start >= MAX_SWEPT_SEEDS {
+            return Err(format!("Invalid seed range '{}': at most {} seeds can be swept", text, MAX_SWEPT_SEEDS));
+        }
+        Ok(SeedRange { start, end })
+    }
+}
+
+/// The total `--seed-sweep` looks for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SweepGoal {
+    /// The highest total rolled with any seed in the range
+    Max,
+    /// The lowest total rolled with any seed in the range
+    Min,
+    Total(i32),
+}
+
+impl SweepGoal {
+    fn parse(text: &str) -> Result<Self, String> {
+        match text.trim() {
+            "max" => Ok(SweepGoal::Max),
+            "min" => Ok(SweepGoal::Min),
+            other => other
+                .parse()
+                .map(SweepGoal::Total)
+                .map_err(|_| format!("Invalid sweep goal '{}': must be max, min or a total", text)),
+        }
+    }
+}
+
+impl fmt::Display for SweepGoal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SweepGoal::Max => write!(f, "max"),
+            SweepGoal::Min => write!(f, "min"),
+            SweepGoal::Total(total) => write!(f, "{}", total),
+        }
+    }
+}
+
+/// Roll the dice once with every seed in the range, as `--seed SEED` would roll them,
+/// returning the total that meets the goal and every seed that rolls it, in order.
+/// `None` when no seed rolls a total asked for exactly.
+fn sweep_seeds(dice: &Dice, range: SeedRange, goal: SweepGoal) -> Option<(i32, Vec<u64>)> {
+    let beats = |total: i32, best: i32| match goal {
+        SweepGoal::Max => total > best,
+        SweepGoal::Min => total < best,
+        SweepGoal::Total(_) => false,
+    };
+    let mut found: Option<(i32, Vec<u64>)> = None;
+    for seed in range.start..=range.end {
+        let total = dice.roll_detailed_with(&mut StdRng::seed_from_u64(seed)).total;
+        if let SweepGoal::Total(target) = goal
+            && total != target
+        {
+            continue;
+        }
+        match &mut found {
+            Some((best, seeds)) if total == *best => seeds.push(seed),
+            Some((best, _)) if !beats(total, *best) => {}
+            _ => found = Some((total, vec![seed])),
+        }
+    }
+    found
+}
+
+/// Describe the result of a seed sweep, e.g. `2d6: highest total 12 with seeds 17, 203`.
+fn seed_sweep_line(spec: &str, dice: &Dice, range: SeedRange, goal: SweepGoal) -> String {
+    // Every seed rolls some total, so only an exact total can go unfound
+    let Some((total, seeds)) = sweep_seeds(dice, range, goal) else {
+        return format!("{}: no seed from {} to {} rolls {}", spec, range.start, range.end, goal);
+    };
+    let described = match goal {
+        SweepGoal::Max => "highest total",
+        SweepGoal::Min => "lowest total",
+        SweepGoal::Total(_) => "total",
+    };
+    let mut listed: Vec<String> = seeds.iter().take(MAX_LISTED_SEEDS).map(u64::to_string).collect();
+    if seeds.len() > MAX_LISTED_SEEDS {
+        listed.push(format!("and {} more", seeds.len() - MAX_LISTED_SEEDS));
+    }
+    let noun = if seeds.len() == 1 { "seed" } else { "seeds" };
+    format!("{}: {} {} with {} {}", spec, described, total, noun, listed.join(", "))
+}
+
+/// A single participant in an initiative roll.
+#[derive(Debug)]
+struct Combatant {
+    name: String,
+    modifier: i32,
+}
+
+impl Combatant {
+    fn parse(entry: &str) -> Result<Self, String> {
+        let entry = entry.trim();
+
+        // Split the name from the modifier on the last ':'
+        let (name, modifier_str) = entry.rsplit_once(':').ok_or_else(|| {
+            format!(
+                "Invalid initiative entry '{}': must be in format 'name:modifier' (e.g., 'goblin:+2')",
+                entry
+            )
+        })?;
+
+        if name.is_empty() {
+            return Err(format!("Invalid initiative entry '{}': name cannot be empty", entry));
+        }
+
+        let modifier = modifier_str.trim_start_matches('+').parse::<i32>().map_err(|_| {
+            format!(
+                "Invalid modifier in '{}': '{}' is not a valid number",
+                entry, modifier_str
+            )
+        })?;
+
+        Ok(Combatant {
+            name: name.to_string(),
+            modifier,
+        })
+    }
+}
+
+/// The initiative result for a single combatant.
+#[derive(Debug)]
+struct InitiativeRoll {
+    name: String,
+    modifier: i32,
+    result: i32,
+}
+
+/// Sort initiative rolls descending by result, breaking ties by the higher
+/// modifier and then by the order the combatants were given in.
+fn order_initiative(rolls: &mut [InitiativeRoll]) {
+    // sort_by is stable, so input order is preserved for complete ties
+    rolls.sort_by(|a, b| {
+        b.result
+            .cmp(&a.result)
+            .then_with(|| b.modifier.cmp(&a.modifier))
+    });
+}
+
+/// Roll 1d20+modifier for every combatant and return them in initiative order.
+fn roll_initiative<R: Rng + ?Sized>(combatants: &[Combatant], rng: &mut R) -> Vec<InitiativeRoll> {
+    let d20 = Dice::parse("1d20").expect("1d20 is a valid dice specification");
+    initiative_order(combatants, || d20.roll_with(rng))
+}
+
+/// Roll a single d20 for the whole group and add each combatant's modifier to it,
+/// returning the shared roll and the group in initiative order.
+fn roll_group_initiative<R: Rng + ?Sized>(combatants: &[Combatant], rng: &mut R) -> (i32, Vec<InitiativeRoll>) {
+    let base = Dice::parse("1d20").expect("1d20 is a valid dice specification").roll_with(rng);
+    (base, initiative_order(combatants, || base))
+}
+
+/// Add each combatant's modifier to the d20 drawn for them, saturating at the limits of an
+/// `i32`, and sort them into initiative order.
+fn initiative_order(combatants: &[Combatant], mut d20: impl FnMut() -> i32) -> Vec<InitiativeRoll> {
+    let mut order: Vec<InitiativeRoll> = combatants
+        .iter()
+        .map(|combatant| InitiativeRoll {
+            name: combatant.name.clone(),
+            modifier: combatant.modifier,
+            result: d20().saturating_add(combatant.modifier),
+        })
+        .collect();
+
+    order_initiative(&mut order);
+    order
+}
+
+/// Running statistics accumulated over a stream of rolls.
+#[derive(Debug, Default)]
+struct RunningStats {
+    count: u64,
+    sum: i64,
+    sum_of_squares: f64,
+    min: Option<i32>,
+    max: Option<i32>,
+}
+
+impl RunningStats {
+    fn record(&mut self, total: i32) {
+        self.count += 1;
+        self.sum += i64::from(total);
+        self.sum_of_squares += f64::from(total) * f64::from(total);
+        self.min = Some(self.min.map_or(total, |min| min.min(total)));
+        self.max = Some(self.max.map_or(total, |max| max.max(total)));
+    }
+
+    fn mean(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        self.sum as f64 / self.count as f64
+    }
+
+    /// The population standard deviation of the totals recorded so far.
+    fn std_dev(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let mean = self.mean();
+        (self.sum_of_squares / self.count as f64 - mean * mean).max(0.0).sqrt()
+    }
+}
+
+/// Roll a specification `rolls` times and describe what came up: a histogram of the
+/// observed totals followed by a summary line, or only the summary when `summary_only`.
+/// With `baseline`, the histogram also marks the exact chance of every possible total.
+fn simulation_lines<R: Rng + ?Sized>(
+    spec: &str,
+    dice: &Dice,
+    rolls: u64,
+    summary_only: bool,
+    baseline: bool,
+    rng: &mut R,
+) -> Vec<String> {
+    let mut stats = RunningStats::default();
+    let mut observed: BTreeMap<i32, u64> = BTreeMap::new();
+    for _ in 0..rolls {
+        let total = dice.roll_with(rng);
+        stats.record(total);
+        *observed.entry(total).or_insert(0) += 1;
+    }
+
+    let mut lines = Vec::new();
+    if baseline {
+        lines.push(format!("Observed distribution of {} rolls of {} ('+' marks the expected chance):", rolls, spec));
+        lines.extend(baseline_rows(&observed, rolls, &dice.distribution_map()));
+    } else if !summary_only {
+        lines.push(format!("Observed distribution of {} rolls of {}:", rolls, spec));
+        for (total, count) in &observed {
+            let percentage = *count as f64 / rolls as f64 * 100.0;
+            let bars = "|".repeat(((percentage / 2.0).round() as usize).max(1));
+            lines.push(format!("{:3}: {} ({:.1}%)", total, bars, percentage));
+        }
+    }
+    lines.push(format!(
+        "{}: count {}, mean {:.2}, std dev {:.2}, min {}, max {}",
+        spec,
+        stats.count,
+        stats.mean(),
+        stats.std_dev(),
+        stats.min.map_or("-".to_string(), |min| min.to_string()),
+        stats.max.map_or("-".to_string(), |max| max.to_string()),
+    ));
+    lines
+}
+
+/// The rows of a simulated histogram for `--histogram-baseline`: for every total that
+/// came up or could have, the observed bar with a `+` at the length the exact chance
+/// would give it, e.g. `  7: |||||||+ (16.5% observed, 16.7% expected)`.
+fn baseline_rows(observed: &BTreeMap<i32, u64>, rolls: u64, expected: &BTreeMap<i32, f64>) -> Vec<String> {
+    let totals: BTreeSet<i32> = observed.keys().chain(expected.keys()).copied().collect();
+    totals
+        .into_iter()
+        .map(|total| {
+            let observed = observed.get(&total).map_or(0.0, |&count| count as f64 / rolls as f64 * 100.0);
+            let expected = expected.get(&total).map_or(0.0, |&probability| probability * 100.0);
+            // Every total that came up or could come up gets at least a cell
+            let bars = if observed > 0.0 { ((observed / 2.0).round() as usize).max(1) } else { 0 };
+            let marker = (expected > 0.0).then(|| ((expected / 2.0).round() as usize).max(1));
+            let cells: String = (1..=bars.max(marker.unwrap_or(0)))
+                .map(|cell| if Some(cell) == marker { '+' } else if cell <= bars { '|' } else { ' ' })
+                .collect();
+            format!("{:3}: {} ({:.1}% observed, {:.1}% expected)", total, cells, observed, expected)
+        })
+        .collect()
+}
+
+/// Roll every dice specification once per interval for as long as `keep_going` returns true,
+/// printing each round of results and returning the statistics gathered for each specification.
+/// With `no_repeat`, no specification rolls the same total two rounds in a row.
+fn run_loop<R: Rng + ?Sized>(
+    dice_vec: &[Dice],
+    interval: Duration,
+    no_repeat: bool,
+    rng: &mut R,
+    mut keep_going: impl FnMut() -> bool,
+) -> Vec<RunningStats> {
+    let mut stats: Vec<RunningStats> = dice_vec.iter().map(|_| RunningStats::default()).collect();
+    let mut previous: Vec<Option<i32>> = vec![None; dice_vec.len()];
+
+    while keep_going() {
+        let totals: Vec<String> = dice_vec
+            .iter()
+            .zip(stats.iter_mut())
+            .zip(previous.iter_mut())
+            .map(|((dice, spec_stats), previous)| {
+                let total = if no_repeat { dice.roll_avoiding(*previous, rng) } else { dice.roll_with(rng) };
+                *previous = Some(total);
+                spec_stats.record(total);
+                total.to_string()
+            })
+            .collect();
+        println!("{}", totals.join(" "));
+        thread::sleep(interval);
+    }
+    stats
+}
+
+/// Parse a dice specification, applying the bare number options and named rolls.
+/// Ambiguous notation is an error with `--strict` and a warning without it, and an
+/// ambiguous modifier is an error with `--strict-modifier-sign`.
+fn parse_spec(spec: &str, options: &SpecArgs) -> Result<Dice, String> {
+    let (_, spec) = split_group(split_label(spec).1)?;
+    let spec = options.aliases.get(spec.trim()).map_or(spec, String::as_str);
+    let resolved = resolve_bare_number(spec, options.default_die, options.bare_range)?;
+    let strictness = Strictness {
+        notation: true,
+        modifier_sign: options.strict_modifier_sign,
+    };
+    let parsed = match Dice::parse_with_strictness(&resolved, &options.registry, strictness) {
+        Err(err @ DiceError::AmbiguousNotation { .. }) if !options.strict => {
+            eprintln!("Warning: {}", err);
+            Dice::parse_with_strictness(&resolved, &options.registry, Strictness { notation: false, ..strictness })
+        }
+        parsed => parsed,
+    };
+    let mut dice = parsed.map_err(|err| err.to_string())?;
+    if options.min_sides.is_some() || options.max_sides.is_some() {
+        dice.check_sides(options.min_sides.unwrap_or(1), options.max_sides.unwrap_or(u8::MAX))
+            .map_err(|err| err.to_string())?;
+    }
+    dice.set_reduce(options.reduce.into());
+    if let Some(target) = options.count_successes {
+        dice.set_success_target(target);
+    }
+    if let Some(limit) = options.explode_limit {
+        dice.set_explosion_limit(limit);
+    }
+    if let Some(limit) = options.reroll_limit {
+        dice.set_reroll_limit(limit);
+    }
+    if options.explode_low {
+        dice.set_explode_face(ExplodeFace::Lowest).map_err(|err| err.to_string())?;
+    }
+    dice.set_explode_which(options.explode_which.into()).map_err(|err| err.to_string())?;
+    if let Some(floor) = options.die_floor {
+        dice.set_die_floor(floor).map_err(|err| err.to_string())?;
+    }
+    if options.floor_kept_only {
+        dice.set_floor_scope(FloorScope::KeptDice);
+    }
+    if options.no_replacement {
+        dice.set_no_replacement().map_err(|err| err.to_string())?;
+    }
+    if options.elven_accuracy {
+        apply_elven_accuracy(&mut dice);
+    }
+    if let Some(factor) = options.scale {
+        dice = dice.scale(factor).map_err(|err| err.to_string())?;
+    }
+    Ok(dice)
+}
+
+/// Upgrade every term rolled with advantage on a d20 (`2d20kh1`) to the three dice
+/// of Elven Accuracy (`3d20kh1`), leaving every other term alone.
+fn apply_elven_accuracy(dice: &mut Dice) {
+    let upgrade = |term: &mut Dice| {
+        let advantage = term.count == 2
+            && term.sides == 20
+            && term.keep == Some(Keep::Highest(1))
+            && term.custom_faces.is_none()
+            && term.sides_roll.is_none();
+        if advantage {
+            term.count = 3;
+        }
+    };
+    upgrade(dice);
+    dice.extra_terms.iter_mut().for_each(upgrade);
+}
+
+/// Load the custom dice and named rolls from the config file into the options, exiting
+/// if the file is invalid. A missing file at the default location just means no config.
+fn load_config_or_exit(options: &mut SpecArgs) {
+    let path = match &options.config {
+        Some(path) => path.clone(),
+        None => match config::default_path() {
+            Some(path) if path.exists() => path,
+            _ => return,
+        },
+    };
+    let loaded = Config::load(&path).and_then(|config| {
+        let registry = config.registry().map_err(|err| err.to_string())?;
+        Ok((registry, config.aliases))
+    });
+    match loaded {
+        Ok((registry, aliases)) => {
+            options.registry = registry;
+            options.aliases = aliases;
+        }
+        Err(err) => {
+            eprintln!("Error reading config file '{}': {}", path.display(), err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Write the template config file to `path` or the default location, exiting on failure.
+fn export_config_or_exit(path: Option<&Path>, force: bool) {
+    let Some(path) = path.map(Path::to_path_buf).or_else(config::default_path) else {
+        eprintln!("Error: no default config location, pass --config <PATH>");
+        std::process::exit(1);
+    };
+    if let Err(err) = config::write_template(&path, force) {
+        eprintln!("Error writing config file '{}': {}", path.display(), err);
+        std::process::exit(1);
+    }
+    println!("Config template written to {}", path.display());
+}
+
+/// Parse a specification typed in interactive mode, where each line rolls once, so
+/// that a group like `3#1d6` is an error rather than a single roll.
+fn parse_interactive_spec(spec: &str, options: &SpecArgs) -> Result<Dice, String> {
+    if split_group(split_label(spec).1)?.0 > 1 {
+        return Err(format!(
+            "Cannot roll the group '{}' in interactive mode: enter the specification once for each roll",
+            spec
+        ));
+    }
+    parse_spec(spec, options)
+}
+
+/// The largest group allowed by the `N#` prefix.
+const MAX_GROUP_SIZE: u32 = 1000;
+
+/// Split a group roll like `5#1d20+7` into how many times to roll and the
+/// specification to roll. Specifications without a `N#` prefix roll once.
+fn split_group(spec: &str) -> Result<(u32, &str), String> {
+    let Some((size_str, rest)) = spec.split_once('#') else {
+        return Ok((1, spec));
+    };
+    let size = size_str.trim().parse::<u32>().map_err(|_| {
+        format!(
+            "Invalid group size in '{}': '{}' is not a valid number",
+            spec, size_str
+        )
+    })?;
+    if size == 0 || size > MAX_GROUP_SIZE {
+        return Err(format!(
+            "Invalid group size in '{}': must be between 1 and {}",
+            spec, MAX_GROUP_SIZE
+        ));
+    }
+    Ok((size, rest))
+}
+
+/// Split a labeled roll like `fireball: 8d6` into its label and the specification
+/// to roll, which may still have a group prefix. Unlabeled specifications have no label.
+fn split_label(spec: &str) -> (Option<&str>, &str) {
+    match spec.split_once(':') {
+        Some((label, rest)) if !label.trim().is_empty() => (Some(label.trim()), rest.trim_start()),
+        _ => (None, spec),
+    }
+}
+
+/// The structured output record for a roll, labeled when the specification is.
+fn roll_record(spec: &str, result: &RollResult, distribution: Option<&BTreeMap<i32, f64>>) -> RollRecord {
+    RollRecord::new(spec, result, distribution).with_label(split_label(spec).0)
+}
+
+/// One line of JSON per specification describing how it parsed, for `--parse-only`.
+fn parsed_spec_lines(specs: &[String], dice: &[Dice]) -> Vec<String> {
+    specs
+        .iter()
+        .zip(dice)
+        .map(|(spec, dice)| {
+            let record = ParsedSpec::new(spec, split_label(spec).0, dice);
+            serde_json::to_string(&record).expect("a parsed specification always serializes")
+        })
+        .collect()
+}
+
+/// One line of JSON per specification holding its whole distribution, for `--distribution-json`.
+fn distribution_json_lines(specs: &[String], dice: &[Dice]) -> Vec<String> {
+    specs
+        .iter()
+        .zip(dice)
+        .map(|(spec, dice)| {
+            let record = DistributionRecord::new(spec, dice);
+            serde_json::to_string(&record).expect("a distribution always serializes")
+        })
+        .collect()
+}
+
+/// How many times `--reroll-pool-below` rerolls a pool unless `--pool-reroll-limit` says otherwise.
+const DEFAULT_POOL_REROLLS: u32 = 1;
+
+/// How each member of a group is rolled, from the roll flags.
+#[derive(Clone, Copy)]
+struct RollMode {
+    /// Roll critical hit damage under this rule
+    crit: Option<CritRule>,
+    /// Keep the highest total of this many rolls
+    best_of: Option<u32>,
+    /// Reroll the whole pool when its total is below this
+    reroll_pool_below: Option<i32>,
+    /// The most times the whole pool is rerolled for being too low
+    pool_reroll_limit: u32,
+    /// Reroll the whole pool once when none of its dice are successes
+    mulligan: bool,
+    /// Roll a second time when the total is below this, keeping the higher
+    safety_net: Option<i32>,
+    /// Raise critical hit damage below its average up to the average
+    crit_floor_average: bool,
+}
+
+impl Default for RollMode {
+    fn default() -> Self {
+        RollMode {
+            crit: None,
+            best_of: None,
+            reroll_pool_below: None,
+            pool_reroll_limit: DEFAULT_POOL_REROLLS,
+            mulligan: false,
+            safety_net: None,
+            crit_floor_average: false,
+        }
+    }
+}
+
+/// A roll of one member of a group, with the totals it replaced if the whole pool was rerolled.
+struct GroupRoll {
+    result: RollResult,
+    rerolled_from: Vec<i32>,
+    /// The first and second totals when the safety net triggered
+    safety_net: Option<(i32, i32)>,
+    /// The critical damage rolled when `--crit-floor-average` raised it to the average
+    floored_from: Option<i32>,
+}
+
+/// Roll a specification once, or once per member of its `N#` group. With `best_of`,
+/// each member keeps the highest total of that many rolls, the first of them on a
+/// tie; with `reroll_pool_below`, a member whose total is too low is rolled again,
+/// up to `pool_reroll_limit` times, and keeps the last result, whatever it is; with
+/// `mulligan`, a member with no successes is rolled again once and keeps the second result;
+/// with `safety_net`, a member whose total is too low is rolled again once and keeps the higher.
+fn roll_group<R: Rng + ?Sized>(spec: &str, dice: &Dice, mode: RollMode, rng: &mut R) -> Vec<GroupRoll> {
+    let size = split_group(split_label(spec).1).map_or(1, |(size, _)| size);
+    // Each roll with the critical damage it rolled, when --crit-floor-average raised it
+    let mut roll = || match mode.crit {
+        Some(rule) => {
+            let mut result = dice.roll_crit_with(rule, rng);
+            let mut floored_from = None;
+            if mode.crit_floor_average {
+                // The average rounded down, as a stat block gives it
+                let average = (dice.crit_expected_value(rule) + 1e-9).floor() as i32;
+                let raised = Dice::clamp_total(result.total, Some(average), None);
+                if raised != result.total {
+                    floored_from = Some(result.total);
+                    result.total = raised;
+                }
+            }
+            (result, floored_from)
+        }
+        None => (dice.roll_detailed_with(rng), None),
+    };
+    (0..size)
+        .map(|_| {
+            let mut best = roll();
+            for _ in 1..mode.best_of.unwrap_or(1) {
+                let rolled = roll();
+                if rolled.0.total > best.0.total {
+                    best = rolled;
+                }
+            }
+            let mut rerolled_from = Vec::new();
+            if let Some(below) = mode.reroll_pool_below {
+                while best.0.total < below && rerolled_from.len() < mode.pool_reroll_limit as usize {
+                    rerolled_from.push(best.0.total);
+                    best = roll();
+                }
+            }
+            if mode.mulligan && !best.0.dice.iter().any(|die| die.kept && die.success) {
+                rerolled_from.push(best.0.total);
+                best = roll();
+            }
+            let mut safety_net = None;
+            if mode.safety_net.is_some_and(|below| best.0.total < below) {
+                let second = roll();
+                safety_net = Some((best.0.total, second.0.total));
+                if second.0.total > best.0.total {
+                    best = second;
+                }
+            }
+            let (result, floored_from) = best;
+            GroupRoll {
+                result,
+                rerolled_from,
+                safety_net,
+                floored_from,
+            }
+        })
+        .collect()
+}
+
+/// The subtotal of every label in the order the labels were first rolled, then the
+/// grand total, for `--group-by-label`, e.g. `fire: 17`. Unlabeled rolls are counted
+/// together.
+fn label_total_lines(entries: &[HistoryEntry]) -> Vec<String> {
+    let mut subtotals: Vec<(&str, i64)> = Vec::new();
+    for entry in entries {
+        let label = split_label(&entry.spec).0.unwrap_or("unlabeled");
+        match subtotals.iter_mut().find(|(seen, _)| *seen == label) {
+            Some((_, subtotal)) => *subtotal += i64::from(entry.total),
+            None => subtotals.push((label, i64::from(entry.total))),
+        }
+    }
+    let mut lines = vec!["Totals by label:".to_string()];
+    lines.extend(subtotals.iter().map(|(label, subtotal)| format!("  {}: {}", label, subtotal)));
+    lines.push(format!("Grand total: {}", subtotals.iter().map(|(_, subtotal)| subtotal).sum::<i64>()));
+    lines
+}
+
+/// Whether the safety net triggered, with the two totals it chose between if it did,
+/// e.g. ` (safety net: 4 and 15)`.
+fn safety_net_note(totals: Option<(i32, i32)>) -> String {
+    match totals {
+        Some((first, second)) => format!(" (safety net: {} and {})", first, second),
+        None => " (no safety net)".to_string(),
+    }
+}
+
+/// Roll an attack, describing it and whether the first die shows its highest face.
+fn roll_attack<R: Rng + ?Sized>(dice: &Dice, rng: &mut R) -> (String, bool) {
+    let result = dice.roll_detailed_with(rng);
+    let natural = result.dice.first().map_or(0, |die| die.face);
+    let critical = natural == dice.highest_face();
+    let line = if critical {
+        format!("Attack: {} (natural {}, critical hit)", result.total, natural)
+    } else {
+        format!("Attack: {} (natural {})", result.total, natural)
+    };
+    (line, critical)
+}
+
+/// How an attack against an armour class turned out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AttackHit {
+    Miss,
+    Hit,
+    Critical,
+}
+
+/// Roll an attack against armour class `ac`, describing it and how it turned out. A
+/// natural maximum on the first die is a critical hit and a natural minimum always
+/// misses; otherwise the attack hits when its total is at least the AC.
+fn roll_attack_against<R: Rng + ?Sized>(dice: &Dice, ac: i32, rng: &mut R) -> (String, AttackHit) {
+    let result = dice.roll_detailed_with(rng);
+    let natural = result.dice.first().map_or(0, |die| die.face);
+    let (hit, outcome) = if natural == dice.highest_face() {
+        (AttackHit::Critical, "critical hit")
+    } else if natural == dice.lowest_face() || result.total < ac {
+        (AttackHit::Miss, "miss")
+    } else {
+        (AttackHit::Hit, "hit")
+    };
+    let line = format!("Attack: {} (natural {}) against AC {}: {}", result.total, natural, ac, outcome);
+    (line, hit)
+}
+
+/// Parse every dice specification, reporting each invalid one and exiting if there were any.
+fn parse_specs_or_exit(specs: &[String], options: &SpecArgs) -> Vec<Dice> {
+    let mut dice_vec = Vec::new();
+    let mut valid = true;
+
+    for spec in specs {
+        match parse_spec(spec, options) {
+            Ok(dice) => dice_vec.push(dice),
+            Err(err) => {
+                eprintln!("Error parsing dice specification '{}': {}", spec, err);
+                valid = false;
+            }
+        }
+    }
+    if !valid {
+        std::process::exit(1);
+    }
+    dice_vec
+}
+
+/// Reduce `numerator/denominator` to lowest terms (e.g. 6/36 becomes "1/6").
+fn reduced_fraction(numerator: u64, denominator: u64) -> String {
+    fn gcd(a: u64, b: u64) -> u64 {
+        if b == 0 { a } else { gcd(b, a % b) }
+    }
+    let divisor = gcd(numerator, denominator).max(1);
+    format!("{}/{}", numerator / divisor, denominator / divisor)
+}
+
+/// Build the lines of the probability distribution histogram and statistics for a dice
+/// specification, or for the best total of `best_of` rolls of it.
+fn histogram_lines(dice: &Dice, options: &HistogramArgs, best_of: Option<u32>) -> Vec<String> {
+    let approximation = match options.approx_threshold {
+        ApproxThreshold::Totals(threshold) => {
+            dice.normal_approximation().filter(|totals| best_of.is_none() && totals.len() > threshold)
+        }
+        ApproxThreshold::Off => None,
+    };
+    let (unique_totals, percentages): (Vec<i32>, Vec<f64>) = match best_of {
+        Some(rolls) => dice
+            .best_of_distribution(rolls)
+            .into_iter()
+            .map(|(total, probability)| (total, probability * 100.0))
+            .unzip(),
+        None => match &approximation {
+            Some(totals) => totals.iter().map(|(&total, &probability)| (total, probability * 100.0)).unzip(),
+            None => dice.roll_distribution(),
+        },
+    };
+    // Exact fractions are only available when the outcomes can be counted
+    let frequencies = match (options.fraction, best_of) {
+        (false, _) => None,
+        (true, Some(rolls)) => dice.best_of_frequencies(rolls),
+        (true, None) if approximation.is_some() => None,
+        (true, None) => dice.outcome_frequencies(),
+    };
+
+    let mut lines = vec![match best_of {
+        Some(rolls) => format!("Best of {} distribution histogram:", rolls),
+        None => "Roll distribution histogram:".to_string(),
+    }];
+    let mut hidden = 0;
+    for (index, (total, percentage)) in unique_totals.iter().zip(percentages.iter()).enumerate() {
+        if options.histogram_threshold.is_some_and(|threshold| *percentage < threshold) {
+            hidden += 1;
+            continue;
+        }
+        if options.histogram_percent_only {
+            lines.push(match &frequencies {
+                Some((_, counts)) => format!(
+                    "{}: {:.1}% ({})",
+                    total,
+                    percentage,
+                    reduced_fraction(counts[index], counts.iter().sum())
+                ),
+                None => format!("{}: {:.1}%", total, percentage),
+            });
+            continue;
+        }
+        // Convert percentage back to approximate frequency for visual bars
+        // Using a scale where 1% ≈ 1 bar for reasonable display
+        let bar_count = (*percentage / 2.0).round() as usize; // Scale down for better display
+        let bars = "|".repeat(bar_count.max(1)); // Ensure at least 1 bar for non-zero percentages
+        match &frequencies {
+            Some((_, counts)) => {
+                let total_outcomes: u64 = counts.iter().sum();
+                lines.push(format!(
+                    "{:3}: {} ({:.1}%, {})",
+                    total,
+                    bars,
+                    percentage,
+                    reduced_fraction(counts[index], total_outcomes)
+                ));
+            }
+            None => lines.push(format!("{:3}: {} ({:.1}%)", total, bars, percentage)),
+        }
+    }
+    if hidden > 0 {
+        let noun = if hidden == 1 { "outcome" } else { "outcomes" };
+        lines.push(format!(
+            "({} {} below {}% hidden)",
+            hidden,
+            noun,
+            options.histogram_threshold.unwrap_or_default()
+        ));
+    }
+    if let Some(totals) = &approximation
+        && let Some((mean, variance)) = dice.closed_form_moments()
+    {
+        lines.push(format!(
+            "Normal approximation over {} totals (mean {:.2}, standard deviation {:.2})",
+            totals.len(),
+            mean,
+            variance.sqrt()
+        ));
+    }
+    if let Some(expected) = dice.expected_successes().filter(|_| best_of.is_none()) {
+        lines.push(format!("Expected successes: {:.2}", expected));
+    }
+    if options.explain_distribution {
+        lines.extend(explanation_lines(dice));
+    }
+    lines
+}
+
+/// List the combinations of faces behind each total, e.g. `  7 = (1,6) (2,5) ...`.
+fn explanation_lines(dice: &Dice) -> Vec<String> {
+    let Some(outcomes) = dice.outcomes_by_total(MAX_EXPLAINED_OUTCOMES) else {
+        return vec![format!(
+            "Combinations not listed: only pools of at most {} outcomes without explosions are explained",
+            MAX_EXPLAINED_OUTCOMES
+        )];
+    };
+    let mut lines = vec!["Combinations per total:".to_string()];
+    for (total, sequences) in outcomes {
+        let combinations: Vec<String> = sequences
+            .iter()
+            .map(|faces| {
+                let faces: Vec<String> = faces.iter().map(i32::to_string).collect();
+                format!("({})", faces.join(","))
+            })
+            .collect();
+        lines.push(format!("{:3} = {}", total, combinations.join(" ")));
+    }
+    lines
+}
+
+/// Pad `text` with spaces to `width` columns as displayed in a terminal, so wide
+/// characters and symbols line up where `{:width$}` would count them as one column.
+fn pad_to_width(text: &str, width: usize) -> String {
+    let padding = width.saturating_sub(text.width());
+    format!("{}{}", text, " ".repeat(padding))
+}
+
+/// A table with a row for each specification giving its mean, variance, lowest and
+/// highest totals and the chance of a total of at least `target`, for choosing
+/// between them at a glance.
+fn compare_table_lines(specs: &[String], dice_vec: &[Dice], target: i32) -> Vec<String> {
+    let chance_header = format!("P(>={})", target);
+    let width = specs.iter().map(|spec| spec.width()).max().unwrap_or(0).max("Spec".len());
+    let mut lines = vec![format!(
+        "{} {:>8} {:>9} {:>5} {:>5} {:>9}",
+        pad_to_width("Spec", width),
+        "Mean",
+        "Variance",
+        "Min",
+        "Max",
+        chance_header
+    )];
+    for (spec, dice) in specs.iter().zip(dice_vec) {
+        let stats = dice.distribution_stats();
+        lines.push(format!(
+            "{} {:>8.2} {:>9.2} {:>5} {:>5} {:>8.1}%",
+            pad_to_width(spec, width),
+            stats.mean,
+            stats.variance,
+            stats.min,
+            stats.max,
+            dice.cdf().at_least(target) * 100.0
+        ));
+    }
+    lines
+}
+
+/// The chance of each total with `first` minus its chance with `second`, over every
+/// total either can make. A total only one of them can make has no chance with the other.
+fn distribution_diff(first: &Dice, second: &Dice) -> BTreeMap<i32, f64> {
+    let mut diff = first.distribution_map();
+    for (total, probability) in second.distribution_map() {
+        *diff.entry(total).or_insert(0.0) -= probability;
+    }
+    diff
+}
+
+/// List the difference in the chance of each total, e.g. `  7:  +8.33% ++++++++`, with a
+/// mark for every percentage point: `+` where the first specification is more likely.
+fn distribution_diff_lines(specs: &[String], first: &Dice, second: &Dice) -> Vec<String> {
+    let mut lines = vec![format!("P({}) - P({}):", specs[0], specs[1])];
+    for (total, difference) in distribution_diff(first, second) {
+        let percentage = difference * 100.0;
+        let mark = if percentage < 0.0 { "-" } else { "+" };
+        let marks = mark.repeat(percentage.abs().round() as usize);
+        lines.push(format!("{:3}: {:+7.2}% {}", total, percentage, marks).trim_end().to_string());
+    }
+    lines
+}
+
+/// How many ability scores make up a character's full set.
+const ABILITY_SCORE_COUNT: usize = 6;
+
+/// Roll a full set of ability scores, each the highest three of 4d6.
+fn roll_ability_scores<R: Rng + ?Sized>(rng: &mut R) -> Vec<i32> {
+    let dice = Dice::parse("4d6dl1").expect("4d6dl1 is a valid dice specification");
+    (0..ABILITY_SCORE_COUNT).map(|_| dice.roll_with(rng)).collect()
+}
+
+/// The modifier an ability score gives: half the amount above 10, rounded down.
+fn ability_modifier(score: i32) -> i32 {
+    (score - 10).div_euclid(2)
+}
+
+/// The hit points gained at each of `levels` levels with `hit_die` (including the
+/// constitution modifier): the most the die can give at level 1, and after that a roll,
+/// or the average rounded up with `average`. Every level gives at least 1.
+fn roll_hit_points<R: Rng + ?Sized>(hit_die: &Dice, levels: u32, average: bool, rng: &mut R) -> Vec<i32> {
+    let later = |rng: &mut R| {
+        if average {
+            (hit_die.expected_value() - 1e-9).ceil() as i32
+        } else {
+            hit_die.roll_with(rng)
+        }
+    };
+    std::iter::once(hit_die.max_roll())
+        .chain((1..levels).map(|_| later(rng)))
+        .map(|gained| Dice::clamp_total(gained, Some(1), None))
+        .collect()
+}
+
+/// List the hit points gained at each level, e.g. `Level  2:   7`, then their total.
+fn hit_point_lines(levels: &[i32]) -> Vec<String> {
+    let mut lines = vec!["Hit points:".to_string()];
+    for (level, gained) in levels.iter().enumerate() {
+        lines.push(format!("Level {:2}: {:3}", level + 1, gained));
+    }
+    lines.push(format!("Total: {}", levels.iter().sum::<i32>()));
+    lines
+}
+
+/// The gallery printed by `--examples`: the arguments of each invocation, with what it does.
+const EXAMPLES: &[(&[&str], &str)] = &[
+    (&["1d20+5"], "Roll a d20 and add 5, for an attack or a skill check"),
+    (&["4d6dl1"], "Roll four six-sided dice and drop the lowest, for ability scores"),
+    (&["2d20kh1+3"], "Roll two d20s and keep the higher, for advantage"),
+    (&["2d20kl1"], "Roll two d20s and keep the lower, for disadvantage"),
+    (&["8d6"], "Roll eight six-sided dice and add them up, for a fireball"),
+    (&["1d8+1d6+3"], "Roll a d8 and a d6 together and add 3, for a mixed damage roll"),
+    (&["6d10>=8"], "Count the dice showing 8 or more, for a success pool"),
+    (&["5d10>=8!"], "Count successes, rolling another die for every 10"),
+    (&["3d6!"], "Roll again and add whenever a die shows a 6"),
+    (&["--reroll-limit", "1", "2d6r{1,2}"], "Reroll each 1 or 2 once, for great weapon fighting"),
+    (&["4d6min3"], "Raise any die showing less than 3 up to 3"),
+    (&["1d%"], "Roll percentile dice, a tens d10 and a units d10, from 1 to 100"),
+    (&["1d(1d6)"], "Roll a d6 to decide how many sides the die has, then roll it"),
+    (&["attack: 1d20+7", "damage: 2d6+4"], "Roll several specifications at once, each with a label"),
+    (&["--dc", "15", "1d20+4"], "Check the roll against a difficulty class of 15"),
+    (&["--take-average", "2d6+3"], "Give the fixed average a stat block lists instead of rolling"),
+    (&["--simulate", "10000", "2d6"], "Roll 10000 times and show how often each total came up"),
+    (&["stats", "3d6"], "Show the exact chance of every total without rolling"),
+];
+
+/// An argument as it would be typed into a shell, quoted when it holds characters the
+/// shell would otherwise read itself.
+fn shell_quote(arg: &str) -> String {
+    if arg.contains(|c: char| !c.is_ascii_alphanumeric() && !"+-_.,:=/%".contains(c)) {
+        format!("'{}'", arg)
+    } else {
+        arg.to_string()
+    }
+}
+
+/// The example gallery, one invocation per line with what it does lined up beside it.
+fn example_lines() -> Vec<String> {
+    let invocations: Vec<String> = EXAMPLES
+        .iter()
+        .map(|(args, _)| {
+            let args: Vec<String> = args.iter().map(|arg| shell_quote(arg)).collect();
+            format!("droll {}", args.join(" "))
+        })
+        .collect();
+    let width = invocations.iter().map(String::len).max().unwrap_or(0);
+    invocations
+        .iter()
+        .zip(EXAMPLES)
+        .map(|(invocation, (_, explanation))| format!("{:width$}  {}", invocation, explanation))
+        .collect()
+}
+
+/// List each ability score with its modifier, e.g. ` 15 (+2)`, then their sum.
+fn ability_score_lines(scores: &[i32]) -> Vec<String> {
+    let mut lines = vec!["Ability scores:".to_string()];
+    for &score in scores {
+        lines.push(format!("{:3} ({:+})", score, ability_modifier(score)));
+    }
+    let modifiers: i32 = scores.iter().map(|&score| ability_modifier(score)).sum();
+    lines.push(format!("Sum: {} (modifiers {:+})", scores.iter().sum::<i32>(), modifiers));
+    lines
+}
+
+/// Number the initiative order with the names padded into a column, e.g. ` 1. Goblin: 17 (1d20+2)`.
+fn initiative_lines(rolls: &[InitiativeRoll]) -> Vec<String> {
+    let name_width = rolls.iter().map(|roll| roll.name.width() + 1).max().unwrap_or(0);
+    rolls
+        .iter()
+        .enumerate()
+        .map(|(position, roll)| {
+            format!(
+                "{:2}. {} {:3} (1d20{:+})",
+                position + 1,
+                pad_to_width(&format!("{}:", roll.name), name_width),
+                roll.result,
+                roll.modifier
+            )
+        })
+        .collect()
+}
+
+/// The luck points needed to lift `total` to `dc`: none when the check already
+/// succeeds, and `None` when even all of the luck would not be enough, so no
+/// points are wasted on a check that fails anyway.
+fn luck_needed(total: i32, dc: i32, luck: u32) -> Option<u32> {
+    let shortfall = u32::try_from(i64::from(dc) - i64::from(total)).unwrap_or(0);
+    (shortfall <= luck).then_some(shortfall)
+}
+
+/// The signed margin of `total` over `dc` before any luck is spent, e.g. `+4 over DC`
+/// or `-3 under DC`. Meeting the DC exactly is a success, so it is `+0 over DC`.
+fn dc_margin(total: i32, dc: i32) -> String {
+    let margin = i64::from(total) - i64::from(dc);
+    if margin >= 0 {
+        format!("+{} over DC", margin)
+    } else {
+        format!("{} under DC", margin)
+    }
+}
+
+/// The DC for the attempt after checking `total` against `dc` with `--escalating-dc`:
+/// a failure lowers it by `step`, while a success, with or without luck, ends the
+/// task and sets it back to `base`.
+fn next_dc(base: i32, dc: i32, total: i32, luck: u32, step: i32) -> i32 {
+    match luck_needed(total, dc, luck) {
+        Some(_) => base,
+        None => dc.saturating_sub(step),
+    }
+}
+
+/// Describe a check of `total` against `dc` with its margin, e.g.
+/// `vs DC 15: success (1 luck spent), -1 under DC`, with the outcome in the colours
+/// of `scheme` if there is one.
+fn dc_check(total: i32, dc: i32, luck: u32, scheme: Option<ColorScheme>) -> String {
+    let (outcome, index) = match luck_needed(total, dc, luck) {
+        Some(0) => ("success".to_string(), 0),
+        Some(spent) => (format!("success ({} luck spent)", spent), 1),
+        None => ("failure".to_string(), 2),
+    };
+    match scheme {
+        Some(scheme) => format!("vs DC {}: {}, {}", dc, paint(&outcome, scheme.codes()[index]), dc_margin(total, dc)),
+        None => format!("vs DC {}: {}, {}", dc, outcome, dc_margin(total, dc)),
+    }
+}
+
+/// The chance of at least one critical over `attacks` independent attacks, each a
+/// critical with the chance from [`Dice::critical_chance`], e.g.
+/// `Chance of at least one critical in 10 attacks: 40.1% (5.0% per attack)`.
+fn crit_chance_line(dice: &Dice, attacks: u32, crit_on: i32) -> String {
+    let per_attack = dice.critical_chance(crit_on);
+    let at_least_one = 1.0 - (1.0 - per_attack).powf(f64::from(attacks));
+    let noun = if attacks == 1 { "attack" } else { "attacks" };
+    format!(
+        "Chance of at least one critical in {} {}: {:.1}% ({:.1}% per attack)",
+        attacks,
+        noun,
+        at_least_one * 100.0,
+        per_attack * 100.0
+    )
+}
+
+/// Report an opposed roll of two success pools, e.g.
+/// `Attacker 3 successes vs defender 1 success: 2 net hits, attacker wins`.
+fn opposed_line(attacker_hits: i32, defender_hits: i32) -> String {
+    let successes = |hits: i32| format!("{} {}", hits, if hits == 1 { "success" } else { "successes" });
+    let net_hits = attacker_hits.saturating_sub(defender_hits);
+    let outcome = match net_hits.cmp(&0) {
+        std::cmp::Ordering::Greater => "attacker wins",
+        std::cmp::Ordering::Less => "defender wins",
+        std::cmp::Ordering::Equal => "tie",
+    };
+    let noun = if net_hits.abs() == 1 { "net hit" } else { "net hits" };
+    format!(
+        "Attacker {} vs defender {}: {} {}, {}",
+        successes(attacker_hits),
+        successes(defender_hits),
+        net_hits,
+        noun,
+        outcome
+    )
+}
+
+/// Show every die of a success pool with the successes marked, e.g. `[8✓ 3 10✓] = 2 successes`.
+fn success_breakdown(result: &RollResult) -> String {
+    let faces: Vec<String> = result
+        .dice
+        .iter()
+        .map(|die| {
+            if die.success {
+                format!("{}{}", die.face, SUCCESS_SYMBOL)
+            } else {
+                die.face.to_string()
+            }
+        })
+        .collect();
+    let noun = if result.total == 1 { "success" } else { "successes" };
+    format!("[{}] = {} {}", faces.join(" "), result.total, noun)
+}
+
+/// Replace every `@PATH` argument with the whitespace separated arguments in the file
+/// at PATH, so that more specifications can be rolled than fit on a command line.
+/// Unlike `--file`, the file holds arguments, so it can carry options too. Arguments
+/// that are not valid UTF-8, like some paths, are passed on untouched for clap.
+fn expand_argfiles(args: impl IntoIterator<Item = OsString>) -> Result<Vec<OsString>, String> {
+    let mut expanded = Vec::new();
+    for (index, arg) in args.into_iter().enumerate() {
+        match arg.to_str().and_then(|arg| arg.strip_prefix('@')) {
+            // The program name is never an argument file
+            Some(path) if index > 0 => {
+                let text = fs::read_to_string(path).map_err(|err| format!("Cannot read argument file '{}': {}", path, err))?;
+                expanded.extend(text.split_whitespace().map(OsString::from));
+            }
+            _ => expanded.push(arg),
+        }
+    }
+    Ok(expanded)
+}
+
+/// Read the specifications in a batch file (or stdin for '-'), exiting if it cannot be read.
+fn read_batch_or_exit(path: &Path) -> Vec<batch::BatchLine> {
+    let lines = if path == Path::new("-") {
+        batch::read_spec_lines(io::stdin().lock())
+    } else {
+        File::open(path).and_then(|file| batch::read_spec_lines(BufReader::new(file)))
+    };
+    match lines {
+        Ok(lines) => lines,
+        Err(err) => {
+            eprintln!("Error reading batch file '{}': {}", path.display(), err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Print the probability distribution histogram and statistics for a dice specification.
+fn print_histogram(dice: &Dice, options: &HistogramArgs, best_of: Option<u32>) {
+    for line in histogram_lines(dice, options, best_of) {
+        println!("{}", line);
+    }
+}
+
+/// Write the distribution of the only dice specification to a `.npy` file, exiting on failure.
+fn export_pmf_or_exit(path: &str, dice_vec: &[Dice]) {
+    if dice_vec.len() != 1 {
+        eprintln!("Error: --export-pmf requires exactly one dice specification");
+        std::process::exit(1);
+    }
+    let (unique_totals, percentages) = dice_vec[0].roll_distribution();
+    let result = File::create(path).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        write_pmf_npy(&mut writer, &unique_totals, &percentages)?;
+        writer.flush()
+    });
+    if let Err(err) = result {
+        eprintln!("Error writing distribution to '{}': {}", path, err);
+        std::process::exit(1);
+    }
+    println!("Distribution written to {}", path);
+}
+
+/// Write a sample space as CSV: a `die1,die2,...,total` header, then a row for each
+/// combination of faces with the total it produces.
+fn write_sample_space_csv<W: Write>(writer: &mut W, outcomes: &[(Vec<i32>, i32)]) -> io::Result<()> {
+    let dice = outcomes.first().map_or(0, |(faces, _)| faces.len());
+    let mut header: Vec<String> = (1..=dice).map(|die| format!("die{}", die)).collect();
+    header.push("total".to_string());
+    writeln!(writer, "{}", header.join(","))?;
+    for (faces, total) in outcomes {
+        let row: Vec<String> = faces.iter().chain(std::iter::once(total)).map(i32::to_string).collect();
+        writeln!(writer, "{}", row.join(","))?;
+    }
+    Ok(())
+}
+
+/// Write the sample space of the only dice specification to a CSV file at `path`,
+/// exiting on any error.
+fn export_sample_space_or_exit(path: &str, dice_vec: &[Dice]) {
+    if dice_vec.len() != 1 {
+        eprintln!("Error: --export-sample-space requires exactly one dice specification");
+        std::process::exit(1);
+    }
+    let Some(outcomes) = dice_vec[0].sample_space(MAX_SAMPLE_SPACE_ROWS) else {
+        eprintln!(
+            "Error: --export-sample-space only writes pools of at most {} combinations without explosions",
+            MAX_SAMPLE_SPACE_ROWS
+        );
+        std::process::exit(1);
+    };
+    let result = File::create(path).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        write_sample_space_csv(&mut writer, &outcomes)?;
+        writer.flush()
+    });
+    if let Err(err) = result {
+        eprintln!("Error writing sample space to '{}': {}", path, err);
+        std::process::exit(1);
+    }
+    println!("Sample space written to {}", path);
+}
+
+/// The random number generator for a roll, with the seed to print for `--seed-show`.
+/// This is the generator `StdRng` wraps, so seeds roll the same as elsewhere, but its
+/// state can be saved for `--dump-rng-state`.
+fn roll_rng(args: &RollArgs) -> (ChaCha12Rng, Option<u64>) {
+    if let Some(path) = &args.load_rng_state {
+        return match rng_state::load(path) {
+            Ok(rng) => (rng, None),
+            Err(err) => {
+                eprintln!("Error reading random number generator state '{}': {}", path.display(), err);
+                std::process::exit(1);
+            }
+        };
+    }
+    match args.seed {
+        Some(seed) => (ChaCha12Rng::seed_from_u64(seed), None),
+        None if args.seed_show => {
+            let seed = rand::rng().random();
+            (ChaCha12Rng::seed_from_u64(seed), Some(seed))
+        }
+        None => (ChaCha12Rng::from_os_rng(), None),
+    }
+}
+
+fn run_roll(args: &RollArgs) {
+    if let Err(err) = check_range_cuts(&args.total_range_cuts) {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    }
+    let (mut rng, shown_seed) = roll_rng(args);
+    roll_all(args, &mut rng, shown_seed);
+    if let Some(path) = &args.dump_rng_state
+        && let Err(err) = rng_state::save(&rng, path)
+    {
+        eprintln!("Error writing random number generator state '{}': {}", path.display(), err);
+        std::process::exit(1);
+    }
+}
+
+/// Everything `run_roll` does with the random number generator, which is left where
+/// the rolls stopped for `--dump-rng-state`.
+fn roll_all(args: &RollArgs, rng: &mut ChaCha12Rng, shown_seed: Option<u64>) {
+    // The seed the rolls are made with, when there is one to repeat them by
+    let seed = args.seed.or(shown_seed);
+    if let Some(seed) = shown_seed {
+        // Standard error keeps the structured formats parseable
+        eprintln!("Seed: {}", seed);
+    }
+
+    // --plot-compare always takes exactly three values
+    if let Some([first, second, path]) = args.plot_compare.as_deref() {
+        write_plot_or_exit(&[first.clone(), second.clone()], path, &args.spec);
+        return;
+    }
+
+    // --opposed always takes exactly two values
+    if let Some([attacker, defender]) = args.opposed.as_deref() {
+        let specs = [attacker.clone(), defender.clone()];
+        let pools = parse_specs_or_exit(&specs, &args.spec);
+        if let Some(spec) = specs.iter().zip(&pools).find_map(|(spec, pool)| pool.success_target.is_none().then_some(spec)) {
+            eprintln!("Error: --opposed needs two success pools, but '{}' has no success target", spec);
+            std::process::exit(1);
+        }
+        let (attacker_hits, defender_hits) = match force_mode(args) {
+            Some(mode) => (forced_total(&pools[0], mode), forced_total(&pools[1], mode)),
+            None => (pools[0].roll_with(rng), pools[1].roll_with(rng)),
+        };
+        println!("{}", opposed_line(attacker_hits, defender_hits));
+        return;
+    }
+
+    if let Some(rolls) = args.bench {
+        let dice = Dice::parse(BENCH_SPEC).expect("the bench specification is valid");
+        let report = run_bench(&dice, rolls, rng);
+        println!(
+            "Rolled {} x {} in {:.3}s ({:.0} rolls/s)",
+            report.rolls,
+            BENCH_SPEC,
+            report.elapsed.as_secs_f64(),
+            report.rolls_per_second()
+        );
+        return;
+    }
+
+    if args.check_roundtrip {
+        let mut drifted = false;
+        for spec in &args.dice {
+            match roundtrip_report(spec, &args.spec) {
+                Ok(line) => println!("{}", line),
+                Err(line) => {
+                    eprintln!("Error: {}", line);
+                    drifted = true;
+                }
+            }
+        }
+        if drifted {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if args.benchmark_distribution {
+        let mut differed = false;
+        for spec in &args.dice {
+            match distribution_benchmark_report(spec, &args.spec) {
+                Ok(line) => println!("{}", line),
+                Err(line) => {
+                    eprintln!("Error: {}", line);
+                    differed = true;
+                }
+            }
+        }
+        if differed {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if args.no_repeat && !args.interactive && args.loop_interval.is_none() {
+        eprintln!("Error: --no-repeat needs --loop or --interactive, where a specification is rolled more than once");
+        std::process::exit(1);
+    }
+    if args.interactive {
+        let history_path = args.repl_history.clone().or_else(repl::default_history_path);
+        let parse = |spec: &str| parse_interactive_spec(spec, &args.spec);
+        let pity = match args.pity.as_deref() {
+            Some(&[threshold, window]) => match u32::try_from(window) {
+                Ok(window) if window > 0 => Some(Pity::new(threshold, window)),
+                _ => {
+                    eprintln!("Error: --pity window must be at least 1, got {}", window);
+                    std::process::exit(1);
+                }
+            },
+            _ => None,
+        };
+        let press_your_luck = args.press_your_luck.as_deref().map(|spec| match parse_interactive_spec(spec, &args.spec) {
+            Ok(dice) => repl::PressYourLuck::new(dice, args.bust),
+            Err(err) => {
+                eprintln!("Error parsing dice specification '{}': {}", spec, err);
+                std::process::exit(1);
+            }
+        });
+        let session = repl::Session {
+            pity,
+            no_repeat: args.no_repeat,
+            press_your_luck,
+            rerolls: args.reroll_budget,
+            ..Default::default()
+        };
+        if let Err(err) = repl::run(&args.prompt, history_path.as_deref(), parse, session, rng) {
+            eprintln!("Error in interactive mode: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if args.initiative {
+        let mut combatants = Vec::new();
+        for entry in &args.dice {
+            match Combatant::parse(entry) {
+                Ok(combatant) => combatants.push(combatant),
+                Err(err) => {
+                    eprintln!("Error parsing initiative entry '{}': {}", entry, err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        let order = if args.group_initiative {
+            let (base, order) = roll_group_initiative(&combatants, rng);
+            println!("Initiative order (shared roll {}):", base);
+            order
+        } else {
+            println!("Initiative order:");
+            roll_initiative(&combatants, rng)
+        };
+        for line in initiative_lines(&order) {
+            println!("{}", line);
+        }
+        return;
+    }
+
+    if args.examples {
+        for line in example_lines() {
+            println!("{}", line);
+        }
+        return;
+    }
+
+    if args.ability_scores {
+        for line in ability_score_lines(&roll_ability_scores(rng)) {
+            println!("{}", line);
+        }
+        return;
+    }
+
+    if let Some(levels) = args.hit_points {
+        let [hit_die] = parse_specs_or_exit(&args.dice, &args.spec).try_into().unwrap_or_else(|_| {
+            eprintln!("Error: --hit-points takes a single hit die, e.g. '1d10+2'");
+            std::process::exit(1);
+        });
+        for line in hit_point_lines(&roll_hit_points(&hit_die, levels, args.average_hp, rng)) {
+            println!("{}", line);
+        }
+        return;
+    }
+
+    let mut specs = args.dice.clone();
+    let mut dice_vec = parse_specs_or_exit(&args.dice, &args.spec);
+    let mut batch_failures = None;
+    if let Some(path) = &args.file {
+        let lines = read_batch_or_exit(path);
+        if args.retry_on_error {
+            let report = batch::parse_batch(&lines, |spec| parse_spec(spec, &args.spec));
+            for (line, err) in &report.failed {
+                eprintln!("Warning: skipping line {} '{}': {}", line.line_number, line.spec, err);
+            }
+            batch_failures = Some(report.failed.len());
+            for (spec, dice) in report.parsed {
+                specs.push(spec);
+                dice_vec.push(dice);
+            }
+        } else {
+            let batch_specs: Vec<String> = lines.into_iter().map(|line| line.spec).collect();
+            dice_vec.extend(parse_specs_or_exit(&batch_specs, &args.spec));
+            specs.extend(batch_specs);
+        }
+    }
+    if args.fate_ladder && !dice_vec.iter().any(is_fate_dice) {
+        eprintln!("Error: --fate-ladder needs Fate dice (e.g. 4dF), but none of the specifications roll them");
+        std::process::exit(1);
+    }
+    if args.parse_only {
+        for line in parsed_spec_lines(&specs, &dice_vec) {
+            println!("{}", line);
+        }
+        return;
+    }
+    for dice in &mut dice_vec {
+        dice.set_keep_tie(args.keep_tie.into());
+        dice.set_keep_order(args.keep_order.into());
+    }
+    if args.distribution_json {
+        for line in distribution_json_lines(&specs, &dice_vec) {
+            println!("{}", line);
+        }
+        return;
+    }
+    if let Some(bands) = &args.bands {
+        for (low, high) in bands.gaps() {
+            eprintln!("Warning: no band covers totals {} to {}", low, high);
+        }
+    }
+    if let Some(effects) = &args.effect_table {
+        for (low, high) in effects.gaps() {
+            eprintln!("Warning: the effect table has no effect for totals {} to {}", low, high);
+        }
+    }
+
+    if let Some(rolls) = args.simulate {
+        let all_rolls = rolls.saturating_mul(specs.len() as u64);
+        if all_rolls > CONFIRM_ABOVE_ROLLS {
+            let what = format!("a simulation of {} rolls in all", all_rolls);
+            let terminal = io::stdin().is_terminal() && io::stderr().is_terminal();
+            if let Err(err) = confirm(&what, args.yes, terminal, &mut io::stdin().lock(), &mut io::stderr()) {
+                eprintln!("Error: {}", err);
+                std::process::exit(1);
+            }
+        }
+        for (spec, dice) in specs.iter().zip(&dice_vec) {
+            for line in simulation_lines(spec, dice, rolls, args.summary_stats_only, args.histogram_baseline, rng) {
+                println!("{}", line);
+            }
+        }
+        return;
+    }
+
+    if let Some(condition) = args.first_match {
+        let lines = first_match_lines(&specs, &dice_vec, condition, |dice| match force_mode(args) {
+            Some(mode) => forced_total(dice, mode),
+            None => dice.roll_with(rng),
+        });
+        for line in lines {
+            println!("{}", line);
+        }
+        return;
+    }
+
+    if args.weighted {
+        let weights = parse_weights(&specs).unwrap_or_else(|err| {
+            eprintln!("Error: {}", err);
+            std::process::exit(1);
+        });
+        let chosen = weighted_pick(&weights, rng);
+        let total = match force_mode(args) {
+            Some(mode) => forced_total(&dice_vec[chosen], mode),
+            None => dice_vec[chosen].roll_with(rng),
+        };
+        let spec = split_label(&specs[chosen]).1;
+        let sum: u64 = weights.iter().map(|&weight| u64::from(weight)).sum();
+        println!("{} (weight {} of {}): {}", spec, weights[chosen], sum, total);
+        return;
+    }
+
+    if let Some(faces) = &args.script_results {
+        let mut script = ScriptedRng::new(faces.iter().copied());
+        for (spec, dice) in specs.iter().zip(&dice_vec) {
+            if let Err(err) = script.set_die(dice) {
+                eprintln!("Error: {}", err);
+                std::process::exit(1);
+            }
+            let result = dice.roll_detailed_with(&mut script);
+            if let Err(err) = script.check() {
+                eprintln!("Error rolling '{}': {}", spec, err);
+                std::process::exit(1);
+            }
+            let label_prefix = split_label(spec).0.map_or(String::new(), |label| format!("{}: ", label));
+            println!("{}{}", label_prefix, roll_line(args, dice, &result, args.dc));
+        }
+        if script.remaining() > 0 {
+            eprintln!("Warning: {} scripted faces were not rolled", script.remaining());
+        }
+        return;
+    }
+
+    if let Some(range) = args.seed_sweep {
+        for (spec, dice) in specs.iter().zip(&dice_vec) {
+            println!("{}", seed_sweep_line(spec, dice, range, args.sweep_for));
+        }
+        return;
+    }
+
+    if let Some(target) = args.until_successes {
+        if let Some(spec) = specs.iter().zip(&dice_vec).find_map(|(spec, dice)| dice.success_target.is_none().then_some(spec)) {
+            eprintln!("Error: --until-successes needs success pools, but '{}' has no success target", spec);
+            std::process::exit(1);
+        }
+        for (spec, dice) in specs.iter().zip(&dice_vec) {
+            let lines = extended_test_lines(spec, target, args.max_rolls, || match force_mode(args) {
+                Some(mode) => forced_total(dice, mode),
+                None => dice.roll_with(rng),
+            });
+            for line in lines {
+                println!("{}", line);
+            }
+        }
+        return;
+    }
+
+    // A template is the whole of each output line, so nothing else is written around it
+    let format = output_format(args);
+    if args.total_only_json && !matches!(format, OutputFormat::Json | OutputFormat::Jsonl) {
+        // Only an explicit --format can be anything else
+        let name = format.to_possible_value().map_or(String::new(), |value| value.get_name().to_string());
+        eprintln!("Error: --total-only-json writes JSON, so it cannot be used with --format {}", name);
+        std::process::exit(1);
+    }
+    if args.summary_json && format != OutputFormat::Json {
+        let name = format.to_possible_value().map_or(String::new(), |value| value.get_name().to_string());
+        eprintln!("Error: --summary-json writes a single JSON object, so it cannot be used with --format {}", name);
+        std::process::exit(1);
+    }
+    if !format.is_structured() && args.template.is_none() {
+        println!("Dice to roll: {:?}", dice_vec);
+    }
+
+    if let Some(path) = &args.export_pmf {
+        export_pmf_or_exit(path, &dice_vec);
+    }
+    if let Some(path) = &args.export_sample_space {
+        export_sample_space_or_exit(path, &dice_vec);
+    }
+
+    if let Some(interval_ms) = args.loop_interval {
+        // Stop cleanly on Ctrl-C so the final statistics still get printed
+        let running = Arc::new(AtomicBool::new(true));
+        let handler_flag = Arc::clone(&running);
+        if let Err(err) = ctrlc::set_handler(move || handler_flag.store(false, Ordering::SeqCst)) {
+            eprintln!("Error installing Ctrl-C handler: {}", err);
+            std::process::exit(1);
+        }
+
+        if args.no_repeat
+            && let Some((spec, dice)) = specs.iter().zip(&dice_vec).find(|(_, dice)| dice.min_roll() == dice.max_roll())
+        {
+            eprintln!("Error: --no-repeat cannot avoid repeating '{}', which always rolls {}", spec, dice.min_roll());
+            std::process::exit(1);
+        }
+        let stats = run_loop(&dice_vec, Duration::from_millis(interval_ms), args.no_repeat, rng, || {
+            running.load(Ordering::SeqCst)
+        });
+
+        println!("Final statistics:");
+        for (spec, spec_stats) in specs.iter().zip(stats.iter()) {
+            println!(
+                "{}: {} rolls, mean {:.2}, min {}, max {}",
+                spec,
+                spec_stats.count,
+                spec_stats.mean(),
+                spec_stats.min.map_or("-".to_string(), |min| min.to_string()),
+                spec_stats.max.map_or("-".to_string(), |max| max.to_string()),
+            );
+        }
+        return;
+    }
+    // Without an attack to decide it, every roll is critical damage
+    let mut crit = args.crit_damage.map(CritRule::from);
+    if let Some(attack) = &args.attack {
+        if crit.is_none() && args.target_ac.is_none() {
+            eprintln!("Error: --attack needs --crit-damage or --target-ac to decide what it does to the damage");
+            std::process::exit(1);
+        }
+        let attack_dice = parse_specs_or_exit(std::slice::from_ref(attack), &args.spec).remove(0);
+        let (line, hit) = match args.target_ac {
+            Some(ac) => roll_attack_against(&attack_dice, ac, rng),
+            None => match roll_attack(&attack_dice, rng) {
+                (line, true) => (line, AttackHit::Critical),
+                (line, false) => (line, AttackHit::Hit),
+            },
+        };
+        if format.is_structured() {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+        match hit {
+            // A miss does no damage, so there is nothing to roll
+            AttackHit::Miss => return,
+            AttackHit::Hit => crit = None,
+            AttackHit::Critical => crit = crit.or(Some(CritRule::DoubleDice)),
+        }
+    }
+
+    if args.mulligan
+        && let Some(spec) = specs.iter().zip(&dice_vec).find_map(|(spec, dice)| dice.success_target.is_none().then_some(spec))
+    {
+        eprintln!("Error: --mulligan needs success pools, but '{}' has no success target", spec);
+        std::process::exit(1);
+    }
+    let mode = RollMode {
+        crit,
+        best_of: args.best_of,
+        reroll_pool_below: args.reroll_pool_below,
+        pool_reroll_limit: args.pool_reroll_limit,
+        mulligan: args.mulligan,
+        safety_net: args.safety_net,
+        crit_floor_average: args.crit_floor_average,
+    };
+    let opponent = opponent_or_exit(&args.query, &args.spec);
+    let mut out = output_writer_or_exit(args.output.as_deref().filter(|_| args.roll_log_format.is_none()));
+    let mut log_records = Vec::new();
+    let adjustment = DamageAdjustment::from_args(args);
+    // The DC of the current attempt, which --escalating-dc lowers after each failure
+    let mut dc = args.dc;
+    let mut history_entries = Vec::new();
+    let mut records = Vec::new();
+    // The text output of each specification, with the total it is sorted by
+    let mut blocks: Vec<(i32, Vec<String>)> = Vec::new();
+    for (spec, dice) in specs.iter().zip(dice_vec) {
+        let mut block = Vec::new();
+        let mut block_total = i32::MIN;
+        // The structured formats carry the distribution in each record instead of a histogram
+        let distribution = (args.show_histogram && format.is_structured())
+            .then(|| dice.best_of_distribution(args.best_of.unwrap_or(1)));
+        let label_prefix = split_label(spec).0.map_or(String::new(), |label| format!("{}: ", label));
+        let rolls = match force_mode(args) {
+            Some(force) => forced_group(spec, &dice, force),
+            None => roll_group(spec, &dice, mode, rng),
+        };
+        for GroupRoll { mut result, rerolled_from, safety_net, floored_from } in rolls {
+            if let Some(percent) = args.percent {
+                result.total = percent_total(result.total, percent, args.percent_rounding);
+            }
+            let before = result.total;
+            if let Some(adjustment) = adjustment {
+                result.total = adjustment.apply(result.total);
+            }
+            match format {
+                OutputFormat::Text => match &args.template {
+                    Some(template) => block.push(template.render(spec, split_label(spec).0, &result)),
+                    None => {
+                        let mut line = format!("{}{}", label_prefix, roll_line(args, &dice, &result, dc));
+                        if let (Some(base), Some(current), Some(step)) = (args.dc, dc, args.escalating_dc) {
+                            dc = Some(next_dc(base, current, result.total, args.luck.unwrap_or(0), step));
+                        }
+                        if !rerolled_from.is_empty() {
+                            let totals: Vec<String> = rerolled_from.iter().map(i32::to_string).collect();
+                            line.push_str(&format!(" (rerolled from {})", totals.join(", ")));
+                        }
+                        if args.safety_net.is_some() {
+                            line.push_str(&safety_net_note(safety_net));
+                        }
+                        if let Some(rolled) = floored_from {
+                            line.push_str(&format!(" (raised from {} to the average)", rolled));
+                        }
+                        if let Some(adjustment) = adjustment {
+                            line.push_str(&adjustment.note(before));
+                        }
+                        block.push(line);
+                    }
+                },
+                OutputFormat::Jsonl | OutputFormat::NdjsonWithMeta => {
+                    let record = roll_record(spec, &result, distribution.as_ref()).with_rerolled_from(&rerolled_from).with_floored_from(floored_from);
+                    if format == OutputFormat::NdjsonWithMeta {
+                        write_output_or_exit(output::write_meta_line(&mut out, &record, seed));
+                    } else if args.total_only_json {
+                        write_output_or_exit(output::write_json_line(&mut out, &TotalRecord::from(&record)));
+                    } else {
+                        write_output_or_exit(output::write_json_line(&mut out, &record));
+                    }
+                }
+                _ => records.push(roll_record(spec, &result, distribution.as_ref()).with_rerolled_from(&rerolled_from).with_floored_from(floored_from)),
+            }
+            if args.roll_log_format.is_some() {
+                log_records.push(roll_record(spec, &result, None).with_rerolled_from(&rerolled_from).with_floored_from(floored_from));
+            }
+            history_entries.push(HistoryEntry::now(spec, result.total));
+            // A group is placed by its best roll
+            block_total = block_total.max(result.total);
+        }
+
+        if !format.is_structured() {
+            if args.show_histogram {
+                block.extend(histogram_lines(&dice, &args.histogram, args.best_of));
+            }
+            block.extend(query_lines(&dice, &args.query, opponent.as_ref()));
+            blocks.push((block_total, block));
+        }
+    }
+    if let Some(order) = args.sort_results {
+        sort_blocks(&mut blocks, order);
+    }
+    for (index, (_, block)) in blocks.iter().enumerate() {
+        if needs_separator(args, index) {
+            write_output_or_exit(writeln!(out));
+        }
+        for line in block {
+            write_output_or_exit(writeln!(out, "{}", line));
+        }
+    }
+    if args.group_by_label {
+        for line in label_total_lines(&history_entries) {
+            // Keep the structured output parseable
+            if format.is_structured() {
+                eprintln!("{}", line);
+            } else {
+                write_output_or_exit(writeln!(out, "{}", line));
+            }
+        }
+    }
+    // Text and JSON lines have already been written as each roll completed
+    if !matches!(format, OutputFormat::Text | OutputFormat::Jsonl | OutputFormat::NdjsonWithMeta) {
+        write_output_or_exit(write_records(&mut out, format, &records, args, specs.len(), seed));
+    }
+    write_output_or_exit(out.flush());
+    if let (Some(log_format), Some(path)) = (args.roll_log_format, &args.output) {
+        let mut log = output_writer_or_exit(Some(path));
+        write_output_or_exit(write_records(&mut log, log_format, &log_records, args, specs.len(), seed));
+        write_output_or_exit(log.flush());
+    }
+
+    if let Some(path) = &args.history
+        && let Err(err) = history::append_entries(path, &history_entries)
+    {
+        eprintln!("Error writing history file '{}': {}", path.display(), err);
+        std::process::exit(1);
+    }
+
+    if let Some(failed) = batch_failures {
+        let summary = format!("Batch summary: {} rolled, {} skipped", history_entries.len(), failed);
+        // Keep the structured output parseable
+        if format.is_structured() {
+            eprintln!("{}", summary);
+        } else {
+            println!("{}", summary);
+        }
+    }
+}
+
+/// Write every record in the given format, as the JSON or CSV output or as the log of
+/// `--roll-log-format`. Text is a `spec: total` line per roll, and `seed` is the seed
+/// the rolls were made with, for `ndjson-with-meta`.
+fn write_records<W: Write>(
+    out: &mut W,
+    format: OutputFormat,
+    records: &[RollRecord],
+    args: &RollArgs,
+    specs: usize,
+    seed: Option<u64>,
+) -> io::Result<()> {
+    match format {
+        OutputFormat::Text => records
+            .iter()
+            .try_for_each(|record| writeln!(out, "{}: {}", record.spec, record.total)),
+        OutputFormat::Jsonl if args.total_only_json => records
+            .iter()
+            .try_for_each(|record| output::write_json_line(out, &TotalRecord::from(record))),
+        OutputFormat::Jsonl => records.iter().try_for_each(|record| output::write_json_line(out, record)),
+        OutputFormat::NdjsonWithMeta => records.iter().try_for_each(|record| output::write_meta_line(out, record, seed)),
+        OutputFormat::Json if args.total_only_json => output::write_totals_json(out, records),
+        OutputFormat::Json if args.summary_json => output::write_json_with_summary(out, records, &RollSummary::new(records, specs)),
+        OutputFormat::Json => output::write_json_array(out, records),
+        OutputFormat::Csv => output::write_csv(out, records, !args.no_csv_header),
+        #[cfg(feature = "toml-output")]
+        OutputFormat::Toml => output::write_toml(out, records),
+        #[cfg(feature = "yaml-output")]
+        OutputFormat::Yaml => output::write_yaml(out, records),
+    }
+}
+
+/// Put the output blocks of the specifications in order of their totals, leaving
+/// blocks with equal totals in the order they were given.
+fn sort_blocks(blocks: &mut [(i32, Vec<String>)], order: SortOrder) {
+    match order {
+        SortOrder::Asc => blocks.sort_by_key(|&(total, _)| total),
+        SortOrder::Desc => blocks.sort_by_key(|&(total, _)| std::cmp::Reverse(total)),
+    }
+}
+
+/// Whether a blank line goes before the output of the specification at `index`, which
+/// is only between the blocks of text output when `--newline-separated` is set.
+fn needs_separator(args: &RollArgs, index: usize) -> bool {
+    args.newline_separated && index > 0 && !output_format(args).is_structured()
+}
+
+/// The format the rolls are written in: `--format` when given, otherwise whatever the
+/// extension of `--output` names, unless `--output` is a log in its own format. A
+/// template is always text, and `--total-only-json` is JSON unless the file is JSON lines.
+fn output_format(args: &RollArgs) -> OutputFormat {
+    let format = match (args.format, &args.output) {
+        (Some(format), _) => return format,
+        (None, Some(path)) if args.template.is_none() && args.roll_log_format.is_none() => OutputFormat::from_extension(path),
+        _ => OutputFormat::Text,
+    };
+    if (args.total_only_json && format != OutputFormat::Jsonl) || args.summary_json {
+        OutputFormat::Json
+    } else {
+        format
+    }
+}
+
+/// Where the rolls are written: the `--output` file, created or truncated, or
+/// standard output. Exits with an error if the file cannot be created.
+fn output_writer_or_exit(path: Option<&Path>) -> Box<dyn Write> {
+    let Some(path) = path else {
+        return Box::new(io::stdout().lock());
+    };
+    match File::create(path) {
+        Ok(file) => Box::new(BufWriter::new(file)),
+        Err(err) => {
+            eprintln!("Error creating output file '{}': {}", path.display(), err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Every die of a roll with the type of die it is, e.g. `[d6:4, d6:5, d8:7]`, with
+/// dice dropped by keep notation in parentheses.
+fn typed_faces(dice: &Dice, result: &RollResult) -> String {
+    let types: Vec<String> = std::iter::once(dice).chain(&dice.extra_terms).map(Dice::die_type).collect();
+    let faces: Vec<String> = result
+        .dice
+        .iter()
+        .map(|die| {
+            let face = format!("{}:{}", types[die.term], die.face);
+            if die.kept { face } else { format!("({})", face) }
+        })
+        .collect();
+    format!("[{}]", faces.join(", "))
+}
+
+/// The adjectives of the Fate ladder from Terrible (-2) up to Legendary (+8).
+const FATE_LADDER: [&str; 11] = [
+    "Terrible", "Poor", "Mediocre", "Average", "Fair", "Good", "Great", "Superb", "Fantastic", "Epic", "Legendary",
+];
+
+/// The rung of the Fate ladder for a total, e.g. `Great` for +4. Totals off either end
+/// count from the last rung, e.g. `Legendary+2` for +10 and `Terrible-1` for -3.
+fn fate_ladder(total: i32) -> String {
+    const LOWEST: i32 = -2;
+    let highest = LOWEST + FATE_LADDER.len() as i32 - 1;
+    match total {
+        total if total < LOWEST => format!("{}{}", FATE_LADDER[0], total - LOWEST),
+        total if total > highest => format!("{}+{}", FATE_LADDER[FATE_LADDER.len() - 1], total - highest),
+        total => FATE_LADDER[(total - LOWEST) as usize].to_string(),
+    }
+}
+
+/// Whether every term rolls Fate dice, whose faces are -1, 0 and 1.
+fn is_fate_dice(dice: &Dice) -> bool {
+    std::iter::once(dice).chain(&dice.extra_terms).all(|term| {
+        term.custom_faces.as_ref().is_some_and(|faces| {
+            let faces: BTreeSet<i32> = faces.iter().copied().collect();
+            faces == BTreeSet::from([-1, 0, 1])
+        })
+    })
+}
+
+/// The text output for a single roll: its total, or whichever report the flags ask for,
+/// with a check against `dc` when there is one.
+fn roll_line(args: &RollArgs, dice: &Dice, result: &RollResult, dc: Option<i32>) -> String {
+    if args.pool_max || args.pool_min {
+        let extreme = if args.pool_max { result.highest_face() } else { result.lowest_face() };
+        return extreme.map_or("-".to_string(), |face| face.to_string());
+    }
+    let mut line = if args.symbols.enabled() && dice.success_target.is_some() {
+        success_breakdown(result)
+    } else {
+        let text = if args.raw_total { result.raw_total().to_string() } else { result.total.to_string() };
+        match (args.color_total_by_range.enabled(), args.total_range_cuts.as_slice()) {
+            (true, &[low, high]) => {
+                let color = range_color(result.total, (dice.min_roll(), dice.max_roll()), (low, high), args.color_scheme);
+                paint(&text, color)
+            }
+            _ => text,
+        }
+    };
+    if args.with_sum && dice.success_target.is_some() {
+        line.push_str(&format!(" (sum {})", result.face_sum()));
+    }
+    if args.with_criticals && dice.success_target.is_some() {
+        let criticals = result.criticals();
+        let noun = if criticals == 1 { "critical" } else { "criticals" };
+        line.push_str(&format!(" ({} {})", criticals, noun));
+    }
+    if args.with_rerolls && std::iter::once(dice).chain(&dice.extra_terms).any(|term| !term.reroll.is_empty()) {
+        let rerolls = result.rerolls();
+        let noun = if rerolls == 1 { "reroll" } else { "rerolls" };
+        line.push_str(&format!(" ({} {})", rerolls, noun));
+    }
+    if args.spread
+        && let Some(spread) = result.spread()
+    {
+        line.push_str(&format!(" (spread {})", spread));
+    }
+    if args.fate_ladder && is_fate_dice(dice) {
+        line.push_str(&format!(" ({})", fate_ladder(result.total)));
+    }
+    if args.show_die_type {
+        line.push_str(&format!(" {}", typed_faces(dice, result)));
+    }
+    if let Some(dc) = dc {
+        let scheme = args.color.enabled().then_some(args.color_scheme);
+        line.push_str(&format!(" {}", dc_check(result.total, dc, args.luck.unwrap_or(0), scheme)));
+    }
+    if let Some(bands) = &args.bands {
+        line.push_str(&format!(" ({})", bands.label_for(result.total).unwrap_or("no band")));
+    }
+    if let Some(effects) = &args.effect_table {
+        line.push_str(&format!(" -> {}", effects.label_for(result.total).unwrap_or("no effect")));
+    }
+    if let Some(sides) = result.rolled_sides {
+        line.push_str(&format!(" (rolled d{})", sides));
+    }
+    line
+}
+
+/// Ask on `prompt` whether to go ahead with `what`, reading the answer from `input`. With
+/// `assume_yes` it goes ahead without asking, and without a `terminal` to ask on it
+/// refuses. The error says why it did not go ahead.
+fn confirm<R: BufRead, W: Write>(what: &str, assume_yes: bool, terminal: bool, input: &mut R, prompt: &mut W) -> Result<(), String> {
+    if assume_yes {
+        return Ok(());
+    }
+    if !terminal {
+        return Err(format!("refusing {} without --yes, as there is no terminal to confirm it on", what));
+    }
+    write!(prompt, "Go ahead with {}? [y/N] ", what).and_then(|()| prompt.flush()).map_err(|err| err.to_string())?;
+    let mut answer = String::new();
+    input.read_line(&mut answer).map_err(|err| err.to_string())?;
+    match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => Ok(()),
+        _ => Err("aborted".to_string()),
+    }
+}
+
+/// Exit with an error if writing the structured output failed.
+fn write_output_or_exit(result: io::Result<()>) {
+    if let Err(err) = result {
+        eprintln!("Error writing output: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn run_stats(args: &StatsArgs) {
+    let dice_vec = parse_specs_or_exit(&args.dice, &args.spec);
+
+    if let Some(path) = &args.export_pmf {
+        export_pmf_or_exit(path, &dice_vec);
+    }
+    if let Some(path) = &args.export_sample_space {
+        export_sample_space_or_exit(path, &dice_vec);
+    }
+
+    if args.distribution_json {
+        for line in distribution_json_lines(&args.dice, &dice_vec) {
+            println!("{}", line);
+        }
+        return;
+    }
+
+    if let Some(budget) = args.optimize {
+        match optimize::optimize(&dice_vec, budget, args.optimize_for) {
+            Some(plan) => println!("Best within {} dice: {}", budget, plan.describe(&args.dice)),
+            None => {
+                eprintln!("Error: every candidate rolls more than {} dice", budget);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(target) = args.compare_table {
+        for line in compare_table_lines(&args.dice, &dice_vec, target) {
+            println!("{}", line);
+        }
+        return;
+    }
+
+    if args.distribution_diff {
+        let [first, second] = dice_vec.as_slice() else {
+            eprintln!("Error: --distribution-diff compares exactly two specifications, got {}", dice_vec.len());
+            std::process::exit(1);
+        };
+        for line in distribution_diff_lines(&args.dice, first, second) {
+            println!("{}", line);
+        }
+        return;
+    }
+
+    let opponent = opponent_or_exit(&args.query, &args.spec);
+    for (spec, dice) in args.dice.iter().zip(dice_vec.iter()) {
+        println!("{}", spec);
+        if let Some(attacks) = args.crit_chance {
+            println!("{}", crit_chance_line(dice, attacks, args.crit_on.unwrap_or_else(|| dice.highest_face())));
+            continue;
+        }
+        print_histogram(dice, &args.histogram, None);
+        for line in query_lines(dice, &args.query, opponent.as_ref()) {
+            println!("{}", line);
+        }
+    }
+}
+
+/// Plot the two specifications of the `plot` subcommand.
+fn run_plot(args: &PlotArgs) {
+    write_plot_or_exit(&args.dice, &args.output, &args.spec);
+}
+
+/// Write the distributions of `specs` as overlaid curves to an SVG file at `path`, or
+/// exit with an error.
+fn write_plot_or_exit(specs: &[String], path: &str, options: &SpecArgs) {
+    let dice = parse_specs_or_exit(specs, options);
+    let series: Vec<(&str, BTreeMap<i32, f64>)> =
+        specs.iter().zip(&dice).map(|(spec, dice)| (spec.as_str(), dice.distribution_map())).collect();
+    if let Err(err) = std::fs::write(path, plot::comparison_svg(&series)) {
+        eprintln!("Error writing plot to '{}': {}", path, err);
+        std::process::exit(1);
+    }
+    println!("Comparison plot written to {}", path);
+}
+
+/// Summarize every roll in the history file: how many there are, their average total
+/// and the specification rolled most often.
+fn run_history(args: &HistoryArgs) {
+    let entries = match history::read_entries(&args.history) {
+        Ok(entries) => entries,
+        Err(err) => {
+            eprintln!("Error reading history file '{}': {}", args.history.display(), err);
+            std::process::exit(1);
+        }
+    };
+    let summary = history::summarize(&entries);
+    println!("Total rolls: {}", summary.total_rolls);
+    println!("Average total: {:.2}", summary.average);
+    if let Some((spec, count)) = summary.most_rolled {
+        println!("Most rolled: {} ({} rolls)", spec, count);
+    }
+}
+
+/// Report whether each dice specification is valid, returning true when all of them are.
+fn run_check(args: &CheckArgs) -> bool {
+    let mut all_valid = true;
+    for spec in &args.dice {
+        match parse_spec(spec, &args.spec) {
+            Ok(_) => println!("{}: ok", spec),
+            Err(err) => {
+                println!("{}: {}", spec, err);
+                all_valid = false;
+            }
+        }
+    }
+    all_valid
+}
+
+fn main() {
+    let mut cli = match expand_argfiles(std::env::args_os()) {
+        Ok(args) => Cli::parse_from(args),
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    // Writing the template comes before loading, so --force can replace an invalid config
+    let roll_args = match &cli.command {
+        None => Some(&cli.roll),
+        Some(Command::Roll(args)) => Some(args.as_ref()),
+        _ => None,
+    };
+    if let Some(args) = roll_args
+        && args.export_config
+    {
+        export_config_or_exit(args.spec.config.as_deref(), args.force);
+        return;
+    }
+
+    match &mut cli.command {
+        None => load_config_or_exit(&mut cli.roll.spec),
+        Some(Command::Roll(args)) => load_config_or_exit(&mut args.spec),
+        Some(Command::Stats(args)) => load_config_or_exit(&mut args.spec),
+        Some(Command::Check(args)) => load_config_or_exit(&mut args.spec),
+        Some(Command::Plot(args)) => load_config_or_exit(&mut args.spec),
+        Some(Command::History(_)) => {}
+    }
+
+    match &cli.command {
+        None => run_roll(&cli.roll),
+        Some(Command::Roll(args)) => run_roll(args),
+        Some(Command::Stats(args)) => run_stats(args),
+        Some(Command::Check(args)) => {
+            if !run_check(args) {
+                std::process::exit(1);
+            }
+        }
+        Some(Command::History(args)) => run_history(args),
+        Some(Command::Plot(args)) => run_plot(args),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_combatant() {
+        let combatant = Combatant::parse("goblin:+2").unwrap();
+        assert_eq!(combatant.name, "goblin");
+        assert_eq!(combatant.modifier, 2);
+        assert_eq!(Combatant::parse("orc:-1").unwrap().modifier, -1);
+        assert!(Combatant::parse("goblin").is_err());
+        assert!(Combatant::parse(":3").is_err());
+        assert!(Combatant::parse("goblin:abc").is_err());
+    }
+
+    #[test]
+    fn test_initiative_order() {
+        let party: Vec<Combatant> = ["fighter:+1", "rogue:+4", "wizard:+1", "cleric:+0"]
+            .iter()
+            .map(|entry| Combatant::parse(entry).unwrap())
+            .collect();
+
+        let order = roll_initiative(&party, &mut StdRng::seed_from_u64(42));
+        assert_eq!(order.len(), 4);
+        for pair in order.windows(2) {
+            assert!(pair[0].result > pair[1].result
+                || (pair[0].result == pair[1].result && pair[0].modifier >= pair[1].modifier));
+        }
+
+        // The same seed always produces the same order
+        let again = roll_initiative(&party, &mut StdRng::seed_from_u64(42));
+        let names: Vec<&str> = order.iter().map(|roll| roll.name.as_str()).collect();
+        let names_again: Vec<&str> = again.iter().map(|roll| roll.name.as_str()).collect();
+        assert_eq!(names, names_again);
+
+        // Extreme modifiers saturate instead of overflowing
+        let extremes: Vec<Combatant> = ["Orc:+2147483647", "Imp:-2147483648"]
+            .iter()
+            .map(|entry| Combatant::parse(entry).unwrap())
+            .collect();
+        let order = initiative_order(&extremes, || 20);
+        assert_eq!((order[0].result, order[1].result), (i32::MAX, -2147483628));
+        let order = initiative_order(&extremes, || -5);
+        assert_eq!(order[1].result, i32::MIN);
+    }
+
+    #[test]
+    fn test_group_initiative_shares_one_roll() {
+        let party: Vec<Combatant> = ["fighter:+1", "rogue:+4", "wizard:-1", "cleric:+0"]
+            .iter()
+            .map(|entry| Combatant::parse(entry).unwrap())
+            .collect();
+
+        for seed in 0..20 {
+            let (base, order) = roll_group_initiative(&party, &mut StdRng::seed_from_u64(seed));
+            assert!((1..=20).contains(&base));
+            for roll in &order {
+                assert_eq!(roll.result, base + roll.modifier, "{}", roll.name);
+            }
+            let names: Vec<&str> = order.iter().map(|roll| roll.name.as_str()).collect();
+            assert_eq!(names, vec!["rogue", "fighter", "cleric", "wizard"]);
+        }
+
+        assert!(Cli::try_parse_from(["droll", "--group-initiative", "a:+1"]).is_err());
+        let cli = Cli::try_parse_from(["droll", "--initiative", "--group-initiative", "a:+1"]).unwrap();
+        assert!(cli.roll.group_initiative);
+    }
+
+    #[test]
+    fn test_running_stats() {
+        let mut stats = RunningStats::default();
+        for total in [3, 9, 6] {
+            stats.record(total);
+        }
+        assert_eq!(stats.count, 3);
+        assert_eq!(stats.min, Some(3));
+        assert_eq!(stats.max, Some(9));
+        assert!((stats.mean() - 6.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_bounded_loop_accumulates_rolls() {
+        let dice_vec = vec![Dice::parse("1d6").unwrap(), Dice::parse("2d4+1").unwrap()];
+        let mut rounds = 0;
+        let stats = run_loop(&dice_vec, Duration::ZERO, false, &mut StdRng::seed_from_u64(7), || {
+            rounds += 1;
+            rounds <= 25
+        });
+
+        assert_eq!(stats.len(), 2);
+        assert!(stats.iter().all(|spec_stats| spec_stats.count == 25));
+        assert!(stats[0].min.unwrap() >= 1 && stats[0].max.unwrap() <= 6);
+        assert!(stats[1].min.unwrap() >= 3 && stats[1].max.unwrap() <= 9);
+    }
+
+    /// Read back the (n, 2) float64 array written by `write_pmf_npy`.
+    fn read_pmf_npy(bytes: &[u8]) -> Vec<(f64, f64)> {
+        assert_eq!(&bytes[..6], b"\x93NUMPY");
+        assert_eq!(&bytes[6..8], &[1, 0]);
+        let header_len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        let header = std::str::from_utf8(&bytes[10..10 + header_len]).unwrap();
+        assert!(header.contains("'descr': '<f8'"));
+        assert!(header.ends_with('\n'));
+        assert_eq!((10 + header_len) % 64, 0);
+
+        let values: Vec<f64> = bytes[10 + header_len..]
+            .chunks_exact(8)
+            .map(|chunk| f64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        assert!(header.contains(&format!("'shape': ({}, 2)", values.len() / 2)));
+        values.chunks_exact(2).map(|row| (row[0], row[1])).collect()
+    }
+
+    #[test]
+    fn test_pmf_npy_round_trip() {
+        let dice = Dice::parse("2d6").unwrap();
+        let (unique_totals, percentages) = dice.roll_distribution();
+        let mut bytes = Vec::new();
+        write_pmf_npy(&mut bytes, &unique_totals, &percentages).unwrap();
+
+        let rows = read_pmf_npy(&bytes);
+        assert_eq!(rows.len(), 11);
+        for (row, (total, outcomes)) in [(0, (2.0, 1.0)), (5, (7.0, 6.0)), (10, (12.0, 1.0))] {
+            assert_eq!(rows[row].0, total);
+            assert!((rows[row].1 - outcomes / 36.0).abs() < 1e-12);
+        }
+        let total_probability: f64 = rows.iter().map(|(_, probability)| probability).sum();
+        assert!((total_probability - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_sample_space_csv_rows() {
+        let outcomes = Dice::parse("3d4+1").unwrap().sample_space(MAX_SAMPLE_SPACE_ROWS).unwrap();
+        let mut bytes = Vec::new();
+        write_sample_space_csv(&mut bytes, &outcomes).unwrap();
+        let text = String::from_utf8(bytes).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        // A header, then one row for each of the 4^3 combinations
+        assert_eq!(lines.len(), 1 + 4usize.pow(3));
+        assert_eq!(lines[0], "die1,die2,die3,total");
+        assert_eq!(lines[1], "1,1,1,4");
+        assert_eq!(lines[2], "1,1,2,5");
+        assert_eq!(lines[64], "4,4,4,13");
+    }
+
+    #[test]
+    fn test_bare_number_as_default_die_count() {
+        let resolved = resolve_bare_number("3", Some(6), false).unwrap();
+        let dice = Dice::parse(&resolved).unwrap();
+        assert_eq!((dice.count, dice.sides, dice.modifier), (3, 6, 0));
+    }
+
+    #[test]
+    fn test_bare_number_as_range() {
+        let resolved = resolve_bare_number(" 20 ", None, true).unwrap();
+        let dice = Dice::parse(&resolved).unwrap();
+        assert_eq!((dice.count, dice.sides, dice.modifier), (1, 20, 0));
+    }
+
+    #[test]
+    fn test_bare_number_requires_a_flag() {
+        assert!(resolve_bare_number("20", None, false).is_err());
+        // Full specifications pass through unchanged regardless of the flags
+        assert_eq!(resolve_bare_number("2d8+1", Some(6), false).unwrap(), "2d8+1");
+        assert_eq!(resolve_bare_number("2d8+1", None, true).unwrap(), "2d8+1");
+    }
+
+    #[test]
+    fn test_bare_specs_default_to_roll() {
+        let cli = Cli::try_parse_from(["droll", "-d", "2d6+1"]).unwrap();
+        assert!(cli.command.is_none());
+        assert_eq!(cli.roll.dice, vec!["2d6+1"]);
+        assert!(cli.roll.show_histogram);
+    }
+
+    #[test]
+    fn test_roll_subcommand() {
+        let cli = Cli::try_parse_from(["droll", "roll", "--seed", "5", "1d20", "2d4"]).unwrap();
+        match cli.command {
+            Some(Command::Roll(args)) => {
+                assert_eq!(args.dice, vec!["1d20", "2d4"]);
+                assert_eq!(args.seed, Some(5));
+            }
+            _ => panic!("expected the roll subcommand"),
+        }
+    }
+
+    #[test]
+    fn test_examples_are_valid() {
+        for (args, _) in EXAMPLES {
+            let cli = Cli::try_parse_from(std::iter::once(&"droll").chain(args.iter()))
+                .unwrap_or_else(|err| panic!("{:?}: {}", args, err));
+            let (specs, options) = match &cli.command {
+                Some(Command::Stats(stats)) => (&stats.dice, &stats.spec),
+                _ => (&cli.roll.dice, &cli.roll.spec),
+            };
+            assert!(!specs.is_empty(), "{:?}", args);
+            for spec in specs {
+                assert!(parse_spec(spec, options).is_ok(), "{:?}: {}", args, spec);
+            }
+        }
+
+        let lines = example_lines();
+        assert_eq!(lines.len(), EXAMPLES.len());
+        assert!(lines[1].starts_with("droll 4d6dl1 "));
+        assert!(lines[1].ends_with("  Roll four six-sided dice and drop the lowest, for ability scores"));
+        assert!(lines[6].starts_with("droll '6d10>=8' "), "{}", lines[6]);
+        assert!(lines[13].starts_with("droll 'attack: 1d20+7' 'damage: 2d6+4' "), "{}", lines[13]);
+        assert!(Cli::try_parse_from(["droll", "--examples"]).is_ok());
+    }
+
+    #[test]
+    fn test_stats_subcommand() {
+        let cli = Cli::try_parse_from(["droll", "stats", "--default-die", "6", "3"]).unwrap();
+        match cli.command {
+            Some(Command::Stats(args)) => {
+                assert_eq!(args.dice, vec!["3"]);
+                assert_eq!(args.spec.default_die, Some(6));
+            }
+            _ => panic!("expected the stats subcommand"),
+        }
+        assert!(Cli::try_parse_from(["droll", "stats"]).is_err());
+    }
+
+    #[test]
+    fn test_check_subcommand() {
+        let cli = Cli::try_parse_from(["droll", "check", "2d6", "1d20+3"]).unwrap();
+        match &cli.command {
+            Some(Command::Check(args)) => assert!(run_check(args)),
+            _ => panic!("expected the check subcommand"),
+        }
+
+        let cli = Cli::try_parse_from(["droll", "check", "2d6", "2x6"]).unwrap();
+        match &cli.command {
+            Some(Command::Check(args)) => assert!(!run_check(args)),
+            _ => panic!("expected the check subcommand"),
+        }
+    }
+
+    #[test]
+    fn test_plot_subcommand() {
+        let path = std::env::temp_dir().join(format!("droll-plot-{}.svg", std::process::id()));
+        let path = path.to_str().unwrap();
+        let cli = Cli::try_parse_from(["droll", "plot", "2d6", "1d12", "--output", path]).unwrap();
+        match &cli.command {
+            Some(Command::Plot(args)) => {
+                assert_eq!(args.dice, vec!["2d6", "1d12"]);
+                run_plot(args);
+            }
+            _ => panic!("expected the plot subcommand"),
+        }
+        let svg = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert!(svg.contains("<title>2d6 vs 1d12</title>"));
+        assert!(Cli::try_parse_from(["droll", "plot", "2d6", "1d12"]).is_err());
+        assert!(Cli::try_parse_from(["droll", "plot", "2d6", "--output", "plot.svg"]).is_err());
+    }
+
+    #[test]
+    fn test_history_subcommand() {
+        let cli = Cli::try_parse_from(["droll", "history", "--history", "rolls.jsonl"]).unwrap();
+        match &cli.command {
+            Some(Command::History(args)) => assert_eq!(args.history, PathBuf::from("rolls.jsonl")),
+            _ => panic!("expected the history subcommand"),
+        }
+        assert!(Cli::try_parse_from(["droll", "history"]).is_err());
+        assert!(Cli::try_parse_from(["droll", "--history-stats", "--history", "rolls.jsonl"]).is_err());
+    }
+
+    #[test]
+    fn test_reduced_fraction() {
+        assert_eq!(reduced_fraction(6, 36), "1/6");
+        assert_eq!(reduced_fraction(5, 36), "5/36");
+        assert_eq!(reduced_fraction(0, 36), "0/1");
+    }
+
+    #[test]
+    fn test_histogram_fraction() {
+        let dice = Dice::parse("2d6").unwrap();
+        let lines = histogram_lines(&dice, &HistogramArgs { fraction: true, ..Default::default() }, None);
+        let seven = lines.iter().find(|line| line.starts_with("  7:")).unwrap();
+        assert!(seven.ends_with("(16.7%, 1/6)"));
+
+        let lines = histogram_lines(&dice, &HistogramArgs::default(), None);
+        let seven = lines.iter().find(|line| line.starts_with("  7:")).unwrap();
+        assert!(seven.ends_with("(16.7%)"));
+    }
+
+    #[test]
+    fn test_histogram_percent_only() {
+        let dice = Dice::parse("2d6").unwrap();
+        let cli = Cli::try_parse_from(["droll", "2d6", "--histogram-percent-only"]).unwrap();
+        let lines = histogram_lines(&dice, &cli.roll.histogram, None);
+        assert_eq!(lines.len(), 12);
+        assert_eq!(lines[1], "2: 2.8%");
+        assert_eq!(lines[6], "7: 16.7%");
+        assert!(!lines.iter().any(|line| line.contains('|')));
+
+        let options = HistogramArgs { histogram_percent_only: true, fraction: true, ..Default::default() };
+        assert_eq!(histogram_lines(&dice, &options, None)[6], "7: 16.7% (1/6)");
+    }
+
+    #[test]
+    fn test_best_of_rolls_and_histogram() {
+        let dice = Dice::parse("1d6").unwrap();
+        let options = HistogramArgs { fraction: true, ..Default::default() };
+        let lines = histogram_lines(&dice, &options, Some(2));
+        assert_eq!(lines[0], "Best of 2 distribution histogram:");
+        assert!(lines[1].ends_with("(2.8%, 1/36)"));
+        assert!(lines[6].ends_with("(30.6%, 11/36)"));
+
+        // The best of many rolls of a d6 is almost always a 6
+        let mode = RollMode { best_of: Some(50), ..Default::default() };
+        let results = roll_group("20#1d6", &dice, mode, &mut StdRng::seed_from_u64(241));
+        assert!(results.iter().all(|roll| roll.result.total == 6));
+    }
+
+    #[test]
+    fn test_attack_crits_on_natural_maximum() {
+        let dice = Dice::parse("1d20+5").unwrap();
+        let mut rng = StdRng::seed_from_u64(237);
+        let mut crits = 0;
+        for _ in 0..200 {
+            let (line, critical) = roll_attack(&dice, &mut rng);
+            assert_eq!(critical, line.contains("natural 20,"));
+            crits += usize::from(critical);
+        }
+        assert!(crits > 0 && crits < 40);
+
+        // double-dice on 2d6 rolls 4 dice for every member of a group
+        let damage = Dice::parse("2d6").unwrap();
+        let mode = RollMode { crit: Some(CritRule::DoubleDice), ..Default::default() };
+        let results = roll_group("3#2d6", &damage, mode, &mut rng);
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|roll| roll.result.dice.len() == 4));
+    }
+
+    #[test]
+    fn test_crit_floor_average() {
+        let damage = Dice::parse("2d6+3").unwrap();
+        let floored = RollMode { crit: Some(CritRule::DoubleDice), crit_floor_average: true, ..Default::default() };
+        let plain = RollMode { crit: Some(CritRule::DoubleDice), ..Default::default() };
+        let mut raised = 0;
+        for seed in 0..50 {
+            let rolled = roll_group("2d6+3", &damage, plain, &mut StdRng::seed_from_u64(seed))[0].result.total;
+            let floored = roll_group("2d6+3", &damage, floored, &mut StdRng::seed_from_u64(seed)).remove(0);
+            // 4d6+3 averages 17
+            assert_eq!(floored.result.total, rolled.max(17));
+            assert_eq!(floored.floored_from, (rolled < 17).then_some(rolled));
+            raised += usize::from(rolled < 17);
+        }
+        assert!(raised > 0);
+
+        let cli = Cli::try_parse_from(["droll", "--crit-damage", "double-total", "--crit-floor-average", "1d8+2"]).unwrap();
+        assert!(cli.roll.crit_floor_average);
+        let cli = Cli::try_parse_from(["droll", "--attack", "1d20+5", "--target-ac", "15", "--crit-floor-average", "1d8+2"]).unwrap();
+        assert!(cli.roll.crit_floor_average);
+        assert!(Cli::try_parse_from(["droll", "--crit-floor-average", "1d8+2"]).is_err());
+    }
+
+    #[test]
+    fn test_attack_against_armour_class() {
+        let dice = Dice::parse("1d20+5").unwrap();
+        for seed in 0..100 {
+            let (line, hit) = roll_attack_against(&dice, 15, &mut StdRng::seed_from_u64(seed));
+            let total: i32 = line["Attack: ".len()..].split(' ').next().unwrap().parse().unwrap();
+            let natural = total - 5;
+            let expected = match natural {
+                20 => AttackHit::Critical,
+                1 => AttackHit::Miss,
+                _ if total >= 15 => AttackHit::Hit,
+                _ => AttackHit::Miss,
+            };
+            assert_eq!(hit, expected, "{}", line);
+        }
+
+        // A seeded hit writes its damage, and a seeded miss rolls none
+        let seed_for = |wanted: AttackHit| {
+            (0..).find(|&seed| roll_attack_against(&dice, 15, &mut StdRng::seed_from_u64(seed)).1 == wanted).unwrap()
+        };
+        for (wanted, name) in [(AttackHit::Hit, "hit"), (AttackHit::Miss, "miss")] {
+            let path = std::env::temp_dir().join(format!("droll-attack-{}-{}.txt", name, std::process::id()));
+            let seed = seed_for(wanted).to_string();
+            let args = ["droll", "--seed", &seed, "--attack", "1d20+5", "--target-ac", "15", "--output", path.to_str().unwrap(), "1d8+3"];
+            run_roll(&Cli::try_parse_from(args).unwrap().roll);
+            let written = fs::read_to_string(&path).ok();
+            if wanted == AttackHit::Hit {
+                let damage: i32 = written.unwrap().trim().parse().unwrap();
+                assert!((4..=11).contains(&damage));
+                fs::remove_file(&path).unwrap();
+            } else {
+                assert_eq!(written, None);
+            }
+        }
+
+        assert!(Cli::try_parse_from(["droll", "--target-ac", "15", "1d8"]).is_err());
+    }
+
+    #[test]
+    fn test_forced_totals() {
+        let dice = Dice::parse("2d6+3").unwrap();
+        assert_eq!(forced_total(&dice, ForceMode::Max), 15);
+        assert_eq!(forced_total(&dice, ForceMode::Min), 5);
+        assert_eq!(forced_total(&dice, ForceMode::Average), 10);
+        assert_eq!(forced_total(&Dice::parse("1d4").unwrap(), ForceMode::Average), 3);
+
+        let cli = Cli::try_parse_from(["droll", "--force-roll", "max", "2d6+3"]).unwrap();
+        assert_eq!(cli.roll.force_roll, Some(ForceMode::Max));
+
+        // --take-average rounds down where --force-roll average rounds to nearest
+        let cli = Cli::try_parse_from(["droll", "--take-average", "1d8+2"]).unwrap();
+        let mode = force_mode(&cli.roll).unwrap();
+        assert_eq!(forced_total(&Dice::parse("1d8+2").unwrap(), mode), 6);
+        assert_eq!(forced_total(&Dice::parse("1d8+2").unwrap(), ForceMode::Average), 7);
+        assert!(Cli::try_parse_from(["droll", "--take-average", "--force-roll", "max", "1d8"]).is_err());
+        // The stat-block average is only reached through --take-average
+        assert!(Cli::try_parse_from(["droll", "--force-roll", "stat-block", "1d8"]).is_err());
+
+        // Forced totals are reported like rolls, so the structured formats carry them
+        let group = forced_group("hits: 2#2d6+3", &dice, ForceMode::Max);
+        assert_eq!(group.len(), 2);
+        let record = roll_record("hits: 2#2d6+3", &group[1].result, None);
+        assert_eq!((record.total, record.label.as_deref()), (15, Some("hits")));
+    }
+
+    #[test]
+    fn test_pattern_query() {
+        let cli = Cli::try_parse_from(["droll", "stats", "--pattern", "all-max,straight", "--pattern", "all-same", "3d6"]).unwrap();
+        let Some(Command::Stats(args)) = cli.command else { panic!("expected the stats command") };
+        assert_eq!(args.query.pattern, vec![PatternQuery::AllMax, PatternQuery::Straight, PatternQuery::AllSame]);
+        let lines = query_lines(&Dice::parse("3d6").unwrap(), &args.query, None);
+        assert_eq!(lines, vec!["P(all-max) = 0.5%", "P(straight) = 11.1%", "P(all-same) = 2.8%"]);
+        let mixed = query_lines(&Dice::parse("1d6 + 1d8").unwrap(), &args.query, None);
+        assert!(mixed[0].starts_with("P(all-max): the dice must all be alike"), "{}", mixed[0]);
+    }
+
+    #[test]
+    fn test_probability_of_spec_query() {
+        let cli = Cli::try_parse_from(["droll", "stats", "--probability-of-spec", "1d8+1", "2d6"]).unwrap();
+        let Some(Command::Stats(args)) = cli.command else { panic!("expected the stats command") };
+        let opponent = parse_spec(args.query.probability_of_spec.as_ref().unwrap(), &args.spec).unwrap();
+        let lines = query_lines(&Dice::parse("2d6").unwrap(), &args.query, Some(&opponent));
+        assert_eq!(lines, vec!["P(2d6 > 1d8+1) = 61.1%", "P(2d6 = 1d8+1) = 10.4%", "P(2d6 < 1d8+1) = 28.5%"]);
+        let percentages: f64 = lines
+            .iter()
+            .map(|line| line.rsplit(' ').next().unwrap().trim_end_matches('%').parse::<f64>().unwrap())
+            .sum();
+        assert!((percentages - 100.0).abs() < 0.15);
+        // The opponent is parsed with the same options as the other specifications
+        let cli = Cli::try_parse_from(["droll", "stats", "--max-sides", "6", "--probability-of-spec", "1d8", "2d6"]).unwrap();
+        let Some(Command::Stats(args)) = cli.command else { panic!("expected the stats command") };
+        assert!(parse_spec(args.query.probability_of_spec.as_ref().unwrap(), &args.spec).is_err());
+    }
+
+    #[test]
+    fn test_exactly_query() {
+        let dice = Dice::parse("2d6").unwrap();
+        let query = |total| query_lines(&dice, &QueryArgs { exactly: Some(total), ..Default::default() }, None);
+        assert_eq!(query(7), vec!["P(total = 7) = 16.7%"]);
+        assert_eq!(query(13), vec!["P(total = 13) = 0.0%"]);
+        assert!(query_lines(&dice, &QueryArgs::default(), None).is_empty());
+
+        let entropy = QueryArgs { entropy: true, ..Default::default() };
+        assert_eq!(query_lines(&Dice::parse("1d8").unwrap(), &entropy, None), vec!["Entropy: 3.000 bits"]);
+
+        // One character per distinct total, tallest at the most likely
+        let spark = QueryArgs { sparkline: true, ..Default::default() };
+        assert_eq!(query_lines(&dice, &spark, None), vec!["Sparkline: ▂▃▄▆▇█▇▆▄▃▂"]);
+        assert_eq!(sparkline(&Dice::parse("1d4").unwrap()), "████");
+        let pool = QueryArgs { success_histogram: true, ..Default::default() };
+        let lines = query_lines(&Dice::parse("5d10>=8").unwrap(), &pool, None);
+        assert_eq!(lines.len(), 7);
+        assert_eq!(lines[1], "  0: |||||||| (16.8%)");
+        assert_eq!(lines[6], "  5: | (0.2%)");
+        assert_eq!(query_lines(&dice, &pool, None), vec!["Success count histogram: the dice have no success target"]);
+        let line = sparkline(&Dice::parse("4d6kh3").unwrap());
+        assert_eq!(line.chars().count(), Dice::parse("4d6kh3").unwrap().distribution_map().len());
+
+        let cli = Cli::try_parse_from(["droll", "2d6", "--at-least", "7,12", "--at-least", "2"]).unwrap();
+        assert_eq!(
+            query_lines(&dice, &cli.roll.query, None),
+            vec!["P(total >= 7) = 58.3%", "P(total >= 12) = 2.8%", "P(total >= 2) = 100.0%"]
+        );
+    }
+
+    #[test]
+    fn test_histogram_normal_approximation() {
+        let dice = Dice::parse("40d6").unwrap();
+        let exact = histogram_lines(&dice, &HistogramArgs::default(), None);
+        assert!(!exact.iter().any(|line| line.starts_with("Normal approximation")));
+
+        let cli = Cli::try_parse_from(["droll", "40d6", "--approx-threshold", "100", "--fraction"]).unwrap();
+        let lines = histogram_lines(&dice, &cli.roll.histogram, None);
+        assert_eq!(lines.len(), exact.len() + 1);
+        assert!(lines.contains(&"Normal approximation over 201 totals (mean 140.00, standard deviation 10.80)".to_string()));
+        assert!(!lines.iter().any(|line| line.contains('/')));
+        // Pools with keep notation have no closed form, so stay exact
+        let options = HistogramArgs { approx_threshold: ApproxThreshold::Totals(0), ..Default::default() };
+        let kept = histogram_lines(&Dice::parse("2d6kh1").unwrap(), &options, None);
+        assert!(!kept.iter().any(|line| line.starts_with("Normal approximation")));
+
+        // The approximation is on by default and can be turned off
+        let default = Cli::try_parse_from(["droll", "200d100"]).unwrap();
+        assert_eq!(default.roll.histogram.approx_threshold, ApproxThreshold::Totals(10_000));
+        let off = Cli::try_parse_from(["droll", "200d100", "--approx-threshold", "off"]).unwrap();
+        assert_eq!(off.roll.histogram.approx_threshold, ApproxThreshold::Off);
+        assert!(Cli::try_parse_from(["droll", "1d6", "--approx-threshold", "never"]).is_err());
+    }
+
+    #[test]
+    fn test_histogram_threshold_hides_tails() {
+        let dice = Dice::parse("3d6").unwrap();
+        let options = HistogramArgs {
+            histogram_threshold: Some(1.0),
+            ..Default::default()
+        };
+        let lines = histogram_lines(&dice, &options, None);
+        // 3 and 18 are each 1/216 (0.46%) likely, while 4 and 17 are 3/216 (1.39%)
+        for hidden in ["  3:", " 18:"] {
+            assert!(!lines.iter().any(|line| line.starts_with(hidden)), "{}", hidden);
+        }
+        assert!(lines.iter().any(|line| line.starts_with("  4:")));
+        assert!(lines.contains(&"(2 outcomes below 1% hidden)".to_string()));
+        assert_eq!(histogram_lines(&dice, &HistogramArgs::default(), None).len(), 17);
+    }
+
+    #[test]
+    fn test_explain_distribution() {
+        let lines = explanation_lines(&Dice::parse("2d6").unwrap());
+        let seven = lines.iter().find(|line| line.starts_with("  7 =")).unwrap();
+        assert_eq!(seven, "  7 = (1,6) (2,5) (3,4) (4,3) (5,2) (6,1)");
+
+        let lines = explanation_lines(&Dice::parse("5d6").unwrap());
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("Combinations not listed"));
+    }
 
-impl Dice {
-    fn parse(spec: &str) -> Result<Self, String> {
-        // Trim whitespace
-        let spec = spec.trim().to_lowercase();
+    #[test]
+    fn test_luck_spends_only_what_is_needed() {
+        assert_eq!(luck_needed(14, 15, 3), Some(1));
+        assert_eq!(luck_needed(12, 15, 3), Some(3));
+        assert_eq!(luck_needed(11, 15, 3), None);
+        assert_eq!(luck_needed(18, 15, 3), Some(0));
+        assert_eq!(dc_check(14, 15, 3, None), "vs DC 15: success (1 luck spent), -1 under DC");
+        assert_eq!(dc_check(15, 15, 0, None), "vs DC 15: success, +0 over DC");
+        assert_eq!(dc_check(11, 15, 3, None), "vs DC 15: failure, -4 under DC");
+    }
 
-        // Split the count and side values by "d"
-        let parts: Vec<&str> = spec.split('d').collect();
-        
-        // If we have more than 2 parts after the split the format is invalid
-        if parts.len() != 2 {
-            return Err(format!(
-                "Invalid dice specification '{}': must be in format 'NdS' (e.g., '2d6')",
-                spec
-            ));
+    #[test]
+    fn test_escalating_dc_lowers_after_failures() {
+        let mut dc = 15;
+        let mut checked = Vec::new();
+        for total in [10, 11, 12, 12] {
+            checked.push(dc);
+            dc = next_dc(15, dc, total, 0, 2);
         }
+        // Each failure lowers the DC by a step, and the success on 12 against DC 11 sets it back
+        assert_eq!(checked, vec![15, 13, 11, 15]);
+        assert_eq!(dc, 13);
+        // A check saved by luck is a success too
+        assert_eq!(next_dc(15, 13, 12, 1, 2), 15);
 
-        // Store the first part as the count of the number of dice to roll
-        let count = parts[0].parse::<u8>().map_err(|_| {
-            format!(
-                "Invalid count in '{}': '{}' is not a valid number",
-                spec, parts[0]
-            )
-        })?;
+        assert!(Cli::try_parse_from(["droll", "--escalating-dc", "2", "1d20"]).is_err());
+        assert!(Cli::try_parse_from(["droll", "--dc", "15", "--escalating-dc", "0", "1d20"]).is_err());
+    }
 
-        // If the count is 0 the format is invalid
-        if count == 0 {
-            return Err(format!("Invalid count in '{}': cannot use 0 dice", spec));
-        }
-
-        // Parse sides and modifier from the second part
-        let (sides, modifier) = if parts[1].contains('+') {
-            let mut split = parts[1].split('+');
-            let sides_str = split.next().unwrap();
-            let modifier_str = split.next().unwrap_or("0");
-
-            let sides = sides_str.parse::<u8>().map_err(|_| {
-                format!(
-                    "Invalid sides in '{}': '{}' is not a valid number",
-                    spec, sides_str
-                )
-            })?;
-
-            let modifier = modifier_str.parse::<i32>().map_err(|_| {
-                format!(
-                    "Invalid modifier in '{}': '{}' is not a valid number",
-                    spec, modifier_str
-                )
-            })?;
-
-            (sides, modifier)
-        } else if parts[1].contains('-') {
-            let mut split = parts[1].split('-');
-            let sides_str = split.next().unwrap();
-            let modifier_str = split.next().unwrap_or("0");
-
-            let sides = sides_str.parse::<u8>().map_err(|_| {
-                format!(
-                    "Invalid sides in '{}': '{}' is not a valid number",
-                    spec, sides_str
-                )
-            })?;
-
-            let modifier = modifier_str.parse::<i32>().map_err(|_| {
-                format!(
-                    "Invalid modifier in '{}': '{}' is not a valid number",
-                    spec, modifier_str
-                )
-            })?;
-
-            (sides, -modifier) // Make the modifier negative
-        } else {
-            let sides = parts[1].parse::<u8>().map_err(|_| {
-                format!(
-                    "Invalid sides in '{}': '{}' is not a valid number",
-                    spec, parts[1]
-                )
-            })?;
-            (sides, 0)
+    #[test]
+    fn test_dc_margin_is_signed() {
+        assert_eq!(dc_margin(19, 15), "+4 over DC");
+        assert_eq!(dc_margin(12, 15), "-3 under DC");
+        assert_eq!(dc_margin(15, 15), "+0 over DC");
+    }
+
+    #[test]
+    fn test_script_results_roll_given_faces() {
+        let cli = Cli::try_parse_from(["droll", "--script-results", "100,37,-6", "2d%", "1d6+1"]).unwrap();
+        assert_eq!(cli.roll.script_results, Some(vec![100, 37, -6]));
+        let dice = parse_specs_or_exit(&cli.roll.dice, &cli.roll.spec);
+        let roll_all = |faces: [i32; 3]| {
+            let mut script = ScriptedRng::new(faces);
+            let totals: Vec<i32> = dice
+                .iter()
+                .map(|dice| {
+                    script.set_die(dice).unwrap();
+                    dice.roll_with(&mut script)
+                })
+                .collect();
+            (totals, script.check())
         };
+        assert_eq!(roll_all([100, 37, 6]), (vec![137, 7], Ok(())));
+        // -6 is not a face of a d6
+        assert_eq!(roll_all([100, 37, -6]).1, Err(droll::DiceRollError::ScriptedFace { face: -6 }));
+        assert!(Cli::try_parse_from(["droll", "--script-results", "6", "--seed", "1", "1d6"]).is_err());
+    }
 
-        if sides == 0 {
-            return Err(format!("Invalid sides in '{}': cannot use 0 sides", spec));
-        }
+    #[test]
+    fn test_seed_sweep_finds_target() {
+        let dice = Dice::parse("2d6").unwrap();
+        let range = SeedRange::parse("0-50").unwrap();
+        let roll = |seed| dice.roll_detailed_with(&mut StdRng::seed_from_u64(seed)).total;
+        let (total, seeds) = sweep_seeds(&dice, range, SweepGoal::Total(7)).unwrap();
+        assert_eq!(total, 7);
+        assert!(!seeds.is_empty());
+        // Every seed that rolls a 7 is found, and no other
+        assert!((0..=50).all(|seed| seeds.contains(&seed) == (roll(seed) == 7)));
 
-        Ok(Dice {
-            sides,
-            count,
-            modifier,
-        })
+        let (highest, seeds) = sweep_seeds(&dice, range, SweepGoal::Max).unwrap();
+        assert_eq!(highest, (0..=50).map(roll).max().unwrap());
+        assert!(seeds.iter().all(|&seed| roll(seed) == highest));
+        let line = seed_sweep_line("2d6", &dice, range, SweepGoal::Min);
+        assert!(line.starts_with(&format!("2d6: lowest total {} with seed", (0..=50).map(roll).min().unwrap())), "{}", line);
+        assert_eq!(seed_sweep_line("2d6", &dice, range, SweepGoal::Total(13)), "2d6: no seed from 0 to 50 rolls 13");
+
+        // A seed found by the sweep rolls the same total when passed to --seed
+        let cli = Cli::try_parse_from(["droll", "--seed", &seeds[0].to_string(), "2d6"]).unwrap();
+        let rolled = roll_group("2d6", &dice, RollMode::default(), &mut roll_rng(&cli.roll).0);
+        assert_eq!(rolled[0].result.total, highest);
+
+        assert_eq!(SweepGoal::parse("-3"), Ok(SweepGoal::Total(-3)));
+        assert!(SeedRange::parse("9-3").is_err());
+        assert!(SeedRange::parse("0-1000000").is_err());
+        assert!(Cli::try_parse_from(["droll", "--seed-sweep", "1-10", "--seed", "4", "2d6"]).is_err());
     }
 
-    fn roll(&self) -> i32 {
-        let mut total:i32 = 0;
-        for _ in 0..self.count {
-            let roll:i32 = rand::rng().random_range(1..=self.sides).into();
-            total += roll;
+    #[test]
+    fn test_extended_test_counts_rolls_to_target() {
+        let dice = Dice::parse("5d10>=8").unwrap();
+        let mut rng = StdRng::seed_from_u64(301);
+        let lines = extended_test_lines("5d10>=8", 6, 100, || dice.roll_with(&mut rng));
+        // The seed gives 2, 1, 2 and 1 successes, reaching 6 on the fourth roll
+        assert_eq!(
+            lines,
+            vec![
+                "5d10>=8 roll 1: 2 (total 2)",
+                "5d10>=8 roll 2: 1 (total 3)",
+                "5d10>=8 roll 3: 2 (total 5)",
+                "5d10>=8 roll 4: 1 (total 6)",
+                "5d10>=8 reached 6 successes in 4 rolls",
+            ]
+        );
+
+        // The cap stops a test that cannot finish
+        let lines = extended_test_lines("1d6>=6", 50, 3, || 1);
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[3], "1d6>=6 did not reach 50 successes in 3 rolls (3 so far)");
+
+        let cli = Cli::try_parse_from(["droll", "--until-successes", "10", "8d10>=8"]).unwrap();
+        assert_eq!(cli.roll.max_rolls, DEFAULT_EXTENDED_TEST_ROLLS);
+        assert!(Cli::try_parse_from(["droll", "--max-rolls", "5", "8d10>=8"]).is_err());
+    }
+
+    #[test]
+    fn test_weighted_pick_follows_weights() {
+        let specs: Vec<String> = ["3:1d6", "1: 2d10+5", "6:1d4"].iter().map(|spec| spec.to_string()).collect();
+        let weights = parse_weights(&specs).unwrap();
+        assert_eq!(weights, vec![3, 1, 6]);
+        let mut rng = StdRng::seed_from_u64(311);
+        let mut counts = [0u32; 3];
+        for _ in 0..10_000 {
+            counts[weighted_pick(&weights, &mut rng)] += 1;
         }
-        total += self.modifier;
-        total
+        for (count, weight) in counts.iter().zip(&weights) {
+            let share = f64::from(*count) / 10_000.0;
+            assert!((share - f64::from(*weight) / 10.0).abs() < 0.02, "{:?}", counts);
+        }
+
+        let bad = |spec: &str| parse_weights(&[spec.to_string()]);
+        assert!(bad("1d6").unwrap_err().starts_with("Missing weight"));
+        assert!(bad("0:1d6").is_err());
+        assert!(bad("-2:1d6").is_err());
+        assert!(bad("gold:1d6").is_err());
+        let cli = Cli::try_parse_from(["droll", "--weighted", "3:1d6", "1:2d10+5"]).unwrap();
+        assert!(cli.roll.weighted);
     }
 
-    fn roll_distribution(&self) -> (Vec<i32>, Vec<f64>) {        
-        // Store all possible rolls
-        let mut all_rolls = BTreeMap::new();
-        
-        // Generate all possible combinations for multiple dice
-        fn generate_combinations(count: u8, sides: u8, current_sum: i32, rolls_map: &mut BTreeMap<i32, usize>) {
-            if count == 0 {
-                *rolls_map.entry(current_sum).or_insert(0) += 1;
-                return;
-            }
-            
-            for roll in 1..=sides {
-                generate_combinations(count - 1, sides, current_sum + roll as i32, rolls_map);
-            }
+    #[test]
+    fn test_first_match_stops_at_first_meeting_condition() {
+        let specs: Vec<String> = ["1d4", "1d6", "1d20+10", "1d100"].iter().map(|spec| spec.to_string()).collect();
+        let dice_vec: Vec<Dice> = specs.iter().map(|spec| Dice::parse(spec).unwrap()).collect();
+        // A d4 and a d6 can never reach 11, and 1d20+10 always does
+        let mut rng = StdRng::seed_from_u64(286);
+        let mut rolled = 0;
+        let lines = first_match_lines(&specs, &dice_vec, Condition::parse(">10").unwrap(), |dice| {
+            rolled += 1;
+            dice.roll_with(&mut rng)
+        });
+        assert_eq!(rolled, 3);
+        assert_eq!(lines.len(), 4);
+        assert!(lines[2].starts_with("1d20+10: "));
+        assert!(lines[3].starts_with("First match: 1d20+10 rolled "), "{}", lines[3]);
+        assert!(lines[3].ends_with(" (>10)"));
+
+        let never = first_match_lines(&specs, &dice_vec, Condition::parse("<0").unwrap(), |dice| dice.roll_with(&mut rng));
+        assert_eq!(never.len(), 5);
+        assert_eq!(never[4], "No specification rolled <0");
+
+        assert_eq!(Condition::parse("== 20"), Ok(Condition { comparison: Comparison::Equal, value: 20 }));
+        let not_three = Condition::parse("!= 3").unwrap();
+        assert_eq!(not_three.to_string(), "!=3");
+        assert!(not_three.matches(4) && !not_three.matches(3));
+        assert!(Condition::parse("20").is_err());
+        assert!(Condition::parse(">=x").is_err());
+    }
+
+    #[test]
+    fn test_crit_chance_over_attacks() {
+        let dice = Dice::parse("1d20+5").unwrap();
+        // 1 - 0.95^10 = 40.13%
+        assert_eq!(crit_chance_line(&dice, 10, 20), "Chance of at least one critical in 10 attacks: 40.1% (5.0% per attack)");
+        assert_eq!(crit_chance_line(&dice, 1, 19), "Chance of at least one critical in 1 attack: 10.0% (10.0% per attack)");
+        // Advantage crits on 9.75% of attacks, so 1 - 0.9025^10 over ten of them
+        let advantage = Dice::parse("2d20kh1").unwrap();
+        assert_eq!(crit_chance_line(&advantage, 10, 20), "Chance of at least one critical in 10 attacks: 64.2% (9.8% per attack)");
+
+        let cli = Cli::try_parse_from(["droll", "stats", "--crit-chance", "10", "--crit-on", "19", "2d20kh1"]).unwrap();
+        let Some(Command::Stats(args)) = cli.command else { panic!("expected the stats command") };
+        assert_eq!((args.crit_chance, args.crit_on), (Some(10), Some(19)));
+    }
+
+    #[test]
+    fn test_opposed_net_hits() {
+        let attacker = Dice::parse("6d6>=5").unwrap();
+        let defender = Dice::parse("4d6>=5").unwrap();
+        let hits = |mode| opposed_line(forced_total(&attacker, mode), forced_total(&defender, ForceMode::Average));
+        // The defender averages 4/3 successes, which rounds to 1
+        assert_eq!(hits(ForceMode::Max), "Attacker 6 successes vs defender 1 success: 5 net hits, attacker wins");
+        assert_eq!(hits(ForceMode::Min), "Attacker 0 successes vs defender 1 success: -1 net hit, defender wins");
+        assert_eq!(opposed_line(2, 2), "Attacker 2 successes vs defender 2 successes: 0 net hits, tie");
+
+        let cli = Cli::try_parse_from(["droll", "--opposed", "6d6>=5", "4d6>=5"]).unwrap();
+        assert_eq!(cli.roll.opposed, Some(vec!["6d6>=5".to_string(), "4d6>=5".to_string()]));
+    }
+
+    #[test]
+    fn test_argfile_expansion() {
+        let path = std::env::temp_dir().join(format!("droll-argfile-{}.txt", std::process::id()));
+        fs::write(&path, "1d6 2d8+1\n\t3d4  --with-sum\n").unwrap();
+        let args = ["droll".into(), "1d20".into(), format!("@{}", path.display()).into(), "4d6kh3".into()];
+        let expanded = expand_argfiles(args).unwrap();
+        assert_eq!(expanded, ["droll", "1d20", "1d6", "2d8+1", "3d4", "--with-sum", "4d6kh3"]);
+        let cli = Cli::try_parse_from(expanded).unwrap();
+        assert_eq!(cli.roll.dice, ["1d20", "1d6", "2d8+1", "3d4", "4d6kh3"]);
+
+        fs::remove_file(&path).unwrap();
+        let err = expand_argfiles(["droll".into(), format!("@{}", path.display()).into()]).unwrap_err();
+        assert!(err.starts_with("Cannot read argument file"), "{}", err);
+
+        // An argument that is not UTF-8 is passed on rather than read or rejected
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStringExt;
+            let log = OsString::from_vec(b"x\xff.log".to_vec());
+            let args = ["droll".into(), "--output".into(), log.clone(), "1d6".into()];
+            let expanded = expand_argfiles(args).unwrap();
+            assert_eq!(expanded[2], log);
+            assert!(Cli::try_parse_from(expanded).is_ok());
         }
-        
-        generate_combinations(self.count, self.sides, self.modifier, &mut all_rolls);
-        
-        // Calculate total outcomes and convert frequencies to percentages
-        let total_outcomes: usize = all_rolls.values().sum();
-        let (unique_totals, frequencies): (Vec<i32>, Vec<usize>) = all_rolls.into_iter().unzip();
-        let percentages: Vec<f64> = frequencies
+    }
+
+    #[test]
+    fn test_compare_table_row_per_spec() {
+        let specs = vec!["1d20".to_string(), "2d10".to_string(), "3d6+1".to_string()];
+        let dice_vec: Vec<Dice> = specs.iter().map(|spec| Dice::parse(spec).unwrap()).collect();
+        let lines = compare_table_lines(&specs, &dice_vec, 11);
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "Spec      Mean  Variance   Min   Max   P(>=11)");
+        // 1d20 has variance (20^2 - 1) / 12, and 2d10 twice (10^2 - 1) / 12
+        assert_eq!(lines[1], "1d20     10.50     33.25     1    20     50.0%");
+        assert_eq!(lines[2], "2d10     11.00     16.50     2    20     55.0%");
+        assert_eq!(lines[3], "3d6+1    11.50      8.75     4    19     62.5%");
+
+        let cli = Cli::try_parse_from(["droll", "stats", "--compare-table", "11", "1d20", "2d10"]).unwrap();
+        let Some(Command::Stats(args)) = cli.command else { panic!("expected the stats command") };
+        assert_eq!(args.compare_table, Some(11));
+    }
+
+    #[test]
+    fn test_distribution_diff() {
+        let (two_d6, d12) = (Dice::parse("2d6").unwrap(), Dice::parse("1d12").unwrap());
+        let diff = distribution_diff(&two_d6, &d12);
+        assert_eq!(diff.keys().copied().collect::<Vec<_>>(), (1..=12).collect::<Vec<_>>());
+        assert!(diff.values().sum::<f64>().abs() < 1e-12);
+        // 2d6 is more likely in the middle and 1d12 at the ends, with ties at 4 and 10
+        let signs: Vec<i32> = diff
+            .values()
+            .map(|&difference| if difference.abs() < 1e-12 { 0 } else { difference.signum() as i32 })
+            .collect();
+        assert_eq!(signs, vec![-1, -1, -1, 0, 1, 1, 1, 1, 1, 0, -1, -1]);
+        assert!((diff[&7] - (6.0 - 3.0) / 36.0).abs() < 1e-12);
+
+        let specs = vec!["2d6".to_string(), "1d12".to_string()];
+        let lines = distribution_diff_lines(&specs, &two_d6, &d12);
+        assert_eq!(lines.len(), 13);
+        assert_eq!(lines[0], "P(2d6) - P(1d12):");
+        assert_eq!(lines[1], "  1:   -8.33% --------");
+        assert_eq!(lines[4], "  4:   +0.00%");
+        assert_eq!(lines[7], "  7:   +8.33% ++++++++");
+
+        let cli = Cli::try_parse_from(["droll", "stats", "--distribution-diff", "2d6", "1d12"]).unwrap();
+        let Some(Command::Stats(args)) = cli.command else { panic!("expected the stats command") };
+        assert!(args.distribution_diff);
+    }
+
+    #[test]
+    fn test_sort_results_orders_by_total() {
+        let specs = ["1d8", "1d20", "1d4", "a: 1d4"];
+        let unsorted: Vec<(i32, Vec<String>)> = specs
             .iter()
-            .map(|&freq| (freq as f64 / total_outcomes as f64) * 100.0)
+            .map(|spec| {
+                let total = forced_total(&Dice::parse(split_label(spec).1).unwrap(), ForceMode::Max);
+                (total, vec![format!("{} {}", spec, total)])
+            })
             .collect();
-        
-        (unique_totals, percentages)
+        let lines = |order| {
+            let mut blocks = unsorted.clone();
+            sort_blocks(&mut blocks, order);
+            blocks.into_iter().flat_map(|(_, block)| block).collect::<Vec<_>>()
+        };
+        // Equal totals keep the order they were given in
+        assert_eq!(lines(SortOrder::Asc), ["1d4 4", "a: 1d4 4", "1d8 8", "1d20 20"]);
+        assert_eq!(lines(SortOrder::Desc), ["1d20 20", "1d8 8", "1d4 4", "a: 1d4 4"]);
+
+        let cli = Cli::try_parse_from(["droll", "--sort-results", "desc", "1d8", "1d20"]).unwrap();
+        assert_eq!(cli.roll.sort_results, Some(SortOrder::Desc));
     }
-}
 
-fn main() {
-    let args = Cli::parse();
-    let mut dice_vec = Vec::new();
+    #[test]
+    fn test_color_schemes() {
+        let check = |total, scheme| dc_check(total, 15, 3, Some(scheme));
+        assert_eq!(check(16, ColorScheme::Default), "vs DC 15: \x1b[32msuccess\x1b[0m, +1 over DC");
+        assert_eq!(check(11, ColorScheme::Default), "vs DC 15: \x1b[31mfailure\x1b[0m, -4 under DC");
+        for total in [16, 14, 11] {
+            assert_ne!(check(total, ColorScheme::Colorblind), check(total, ColorScheme::Default));
+        }
+        // Every scheme tells the three outcomes apart
+        for scheme in [ColorScheme::Default, ColorScheme::Colorblind, ColorScheme::Mono] {
+            let codes = scheme.codes();
+            assert!(codes[0] != codes[1] && codes[1] != codes[2] && codes[0] != codes[2]);
+        }
 
-    for spec in &args.dice {
-        match Dice::parse(spec) {
-            Ok(dice) => dice_vec.push(dice),
-            Err(err) => {
-                eprintln!("Error parsing dice specification '{}': {}", spec, err);
-                std::process::exit(1);
-            }
+        let cli = Cli::try_parse_from(["droll", "1d20", "--dc", "15", "--color", "always", "--color-scheme", "colorblind"]).unwrap();
+        assert_eq!(cli.roll.color_scheme, ColorScheme::Colorblind);
+        assert_eq!(cli.roll.color, ColorMode::Always);
+        assert_eq!(Cli::try_parse_from(["droll", "1d20", "--dc", "15"]).unwrap().roll.color, ColorMode::Never);
+        assert!(Cli::try_parse_from(["droll", "1d20", "--color", "always"]).is_err());
+    }
+
+    #[test]
+    fn test_color_total_by_range() {
+        let cuts = (33, 67);
+        // 19 is 90% of the way from 2 to 20
+        assert_eq!(range_color(19, (2, 20), cuts, ColorScheme::Default), "32");
+        assert_eq!(range_color(11, (2, 20), cuts, ColorScheme::Default), "33");
+        assert_eq!(range_color(3, (2, 20), cuts, ColorScheme::Default), "31");
+        assert_eq!(range_color(4, (4, 4), cuts, ColorScheme::Mono), "1");
+
+        let cli = Cli::try_parse_from(["droll", "--color-total-by-range", "always", "1d20+1"]).unwrap();
+        assert_eq!(cli.roll.total_range_cuts, vec![33, 67]);
+        let dice = parse_spec("1d20+1", &cli.roll.spec).unwrap();
+        let mut rng = StdRng::seed_from_u64(306);
+        let result = std::iter::repeat_with(|| dice.roll_detailed_with(&mut rng)).find(|result| result.total >= 20).unwrap();
+        assert_eq!(roll_line(&cli.roll, &dice, &result, None), format!("\x1b[32m{}\x1b[0m", result.total));
+        // Off by default
+        let cli = Cli::try_parse_from(["droll", "1d20+1"]).unwrap();
+        assert_eq!(roll_line(&cli.roll, &dice, &result, None), result.total.to_string());
+        assert!(Cli::try_parse_from(["droll", "--total-range-cuts", "50", "101", "1d20"]).is_err());
+        assert!(check_range_cuts(&[50, 50]).is_ok());
+        let cli = Cli::try_parse_from(["droll", "--total-range-cuts", "70", "30", "1d20"]).unwrap();
+        assert_eq!(
+            check_range_cuts(&cli.roll.total_range_cuts),
+            Err("--total-range-cuts 70 30: the low cut is above the high cut".to_string())
+        );
+    }
+
+    #[test]
+    fn test_success_breakdown_marks_successes() {
+        let dice = Dice::parse("8d10>=7").unwrap();
+        let mut rng = StdRng::seed_from_u64(215);
+        for _ in 0..20 {
+            let result = dice.roll_detailed_with(&mut rng);
+            let line = success_breakdown(&result);
+            assert_eq!(line.matches(SUCCESS_SYMBOL).count() as i32, result.total);
+            let noun = if result.total == 1 { "success" } else { "successes" };
+            assert!(line.ends_with(&format!("= {} {}", result.total, noun)));
         }
     }
-    println!("Dice to roll: {:?}", dice_vec);
-    for dice in dice_vec {
-        let total = dice.roll();
-        println!("{}", total);
-        
-        if args.show_histogram {
-            let (unique_totals, percentages) = dice.roll_distribution();
-            println!("Roll distribution histogram:");
-            for (total, percentage) in unique_totals.iter().zip(percentages.iter()) {
-                // Convert percentage back to approximate frequency for visual bars
-                // Using a scale where 1% ≈ 1 bar for reasonable display
-                let bar_count = (*percentage / 2.0).round() as usize; // Scale down for better display
-                let bars = "|".repeat(bar_count.max(1)); // Ensure at least 1 bar for non-zero percentages
-                println!("{:3}: {} ({:.1}%)", total, bars, percentage);
-            }
+
+    #[test]
+    fn test_simulation_summary_only() {
+        let dice = Dice::parse("2d6").unwrap();
+        let lines = simulation_lines("2d6", &dice, 10_000, true, false, &mut StdRng::seed_from_u64(248));
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("2d6: count 10000, mean 7."), "{}", lines[0]);
+        assert!(lines[0].ends_with("min 2, max 12"));
+
+        // Without the flag the observed histogram comes first, one row per total
+        let lines = simulation_lines("2d6", &dice, 10_000, false, false, &mut StdRng::seed_from_u64(248));
+        assert_eq!(lines.len(), 1 + 11 + 1);
+        assert_eq!(lines[0], "Observed distribution of 10000 rolls of 2d6:");
+
+        let mut stats = RunningStats::default();
+        for total in [2, 4, 4, 4, 5, 5, 7, 9] {
+            stats.record(total);
         }
+        assert_eq!(stats.std_dev(), 2.0);
     }
 
-}
+    #[test]
+    fn test_histogram_baseline_tracks_expected() {
+        let dice = Dice::parse("2d6").unwrap();
+        let lines = simulation_lines("2d6", &dice, 200_000, false, true, &mut StdRng::seed_from_u64(296));
+        assert_eq!(lines.len(), 1 + 11 + 1);
+        for row in &lines[1..12] {
+            let (_, percentages) = row.split_once(" (").unwrap();
+            let numbers: Vec<f64> = percentages
+                .split(['%', ','])
+                .filter_map(|part| part.trim().parse().ok())
+                .collect();
+            assert!((numbers[0] - numbers[1]).abs() < 0.5, "{}", row);
+        }
+        assert!(lines[6].starts_with("  7: |||||||+ (") && lines[6].ends_with("16.7% expected)"), "{}", lines[6]);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        // Totals that never came up still show where they were expected
+        let observed = BTreeMap::from([(1, 1)]);
+        let rows = baseline_rows(&observed, 1, &Dice::parse("1d4").unwrap().distribution_map());
+        assert_eq!(rows[0], "  1: ||||||||||||+||||||||||||||||||||||||||||||||||||| (100.0% observed, 25.0% expected)");
+        assert_eq!(rows[1], "  2:             + (0.0% observed, 25.0% expected)");
+    }
 
     #[test]
-    fn test_parse_simple_dice() {
-        let dice = Dice::parse("1d6").unwrap();
-        assert_eq!(dice.count, 1);
-        assert_eq!(dice.sides, 6);
-        assert_eq!(dice.modifier, 0);
+    fn test_bench_reports_throughput() {
+        let dice = Dice::parse(BENCH_SPEC).unwrap();
+        let report = run_bench(&dice, 10_000, &mut StdRng::seed_from_u64(216));
+        assert_eq!(report.rolls, 10_000);
+        assert!(report.rolls_per_second() > 0.0);
+
+        let cli = Cli::try_parse_from(["droll", "--bench"]).unwrap();
+        assert_eq!(cli.roll.bench, Some(1_000_000));
+    }
+
+    #[test]
+    fn test_newline_separated_blocks() {
+        let cli = Cli::try_parse_from(["droll", "--newline-separated", "1d20", "3#1d6", "2d8"]).unwrap();
+        let separators = (0..cli.roll.dice.len()).filter(|&index| needs_separator(&cli.roll, index)).count();
+        assert_eq!(separators, 2);
+
+        let cli = Cli::try_parse_from(["droll", "--spacing", "--format", "json", "1d20", "2d8"]).unwrap();
+        assert!((0..2).all(|index| !needs_separator(&cli.roll, index)));
+    }
+
+    #[test]
+    fn test_output_format_from_extension() {
+        let format = |args: &[&str]| output_format(&Cli::try_parse_from(args).unwrap().roll);
+        assert_eq!(format(&["droll", "--output", "results.json", "1d20"]), OutputFormat::Json);
+        assert_eq!(format(&["droll", "--output", "results.csv", "1d20"]), OutputFormat::Csv);
+        assert_eq!(format(&["droll", "--output", "results.log", "1d20"]), OutputFormat::Text);
+        assert_eq!(format(&["droll", "1d20"]), OutputFormat::Text);
+        // --format wins over the extension, and a template is always text
+        assert_eq!(format(&["droll", "--output", "results.json", "--format", "jsonl", "1d20"]), OutputFormat::Jsonl);
+        assert_eq!(format(&["droll", "--output", "results.json", "--template", "{total}", "1d20"]), OutputFormat::Text);
+        // Asking for only the totals is asking for JSON
+        assert_eq!(format(&["droll", "--total-only-json", "1d20"]), OutputFormat::Json);
+        assert_eq!(format(&["droll", "--total-only-json", "--output", "totals.jsonl", "1d20"]), OutputFormat::Jsonl);
+        assert_eq!(format(&["droll", "--total-only-json", "--format", "csv", "1d20"]), OutputFormat::Csv);
+        assert_eq!(format(&["droll", "--summary-json", "--output", "rolls.jsonl", "1d20"]), OutputFormat::Json);
+
+        let path = std::env::temp_dir().join(format!("droll-output-{}.csv", std::process::id()));
+        let cli = Cli::try_parse_from(["droll", "--seed", "292", "--output", path.to_str().unwrap(), "2d6", "1d20"]).unwrap();
+        run_roll(&cli.roll);
+        let written = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "spec,label,rolls,modifier,total");
+        assert!(lines[1].starts_with("2d6,,") && lines[2].starts_with("1d20,,"), "{}", written);
+    }
+
+    #[test]
+    fn test_ndjson_with_meta_output() {
+        let path = std::env::temp_dir().join(format!("droll-meta-{}.ndjson", std::process::id()));
+        let args = ["droll", "--seed", "326", "--format", "ndjson-with-meta", "--output", path.to_str().unwrap(), "2d6", "fire: 1d20"];
+        let cli = Cli::try_parse_from(args).unwrap();
+        assert_eq!(output_format(&cli.roll), OutputFormat::NdjsonWithMeta);
+        run_roll(&cli.roll);
+        let written = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let lines: Vec<serde_json::Value> = written.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        for line in &lines {
+            assert!(line["timestamp"].as_u64().is_some_and(|timestamp| timestamp > 0), "{}", line);
+            assert_eq!(line["seed"], 326);
+        }
+        assert_eq!(lines[1]["label"], "fire");
+    }
+
+    #[test]
+    fn test_csv_header_toggle() {
+        let records = [RollRecord::new("2d6", &Dice::parse("2d6").unwrap().roll_detailed_with(&mut StdRng::seed_from_u64(330)), None)];
+        let csv = |args: &[&str]| {
+            let cli = Cli::try_parse_from(args).unwrap();
+            let mut buffer = Vec::new();
+            write_records(&mut buffer, OutputFormat::Csv, &records, &cli.roll, 1, None).unwrap();
+            String::from_utf8(buffer).unwrap()
+        };
+        let with_header = csv(&["droll", "--format", "csv", "2d6"]);
+        assert!(with_header.starts_with("spec,label,rolls,modifier,total\n2d6,"), "{}", with_header);
+        assert_eq!(csv(&["droll", "--format", "csv", "--csv-header", "2d6"]), with_header);
+        let without = csv(&["droll", "--format", "csv", "--no-csv-header", "2d6"]);
+        assert_eq!(without.lines().collect::<Vec<_>>(), with_header.lines().skip(1).collect::<Vec<_>>());
+        // The last of the two flags wins
+        assert_eq!(csv(&["droll", "--no-csv-header", "--csv-header", "2d6"]), with_header);
+    }
+
+    #[test]
+    fn test_roll_log_format_separate_from_display() {
+        let path = std::env::temp_dir().join(format!("droll-roll-log-{}.txt", std::process::id()));
+        let args = ["droll", "--seed", "316", "--output", path.to_str().unwrap(), "--roll-log-format", "json", "2d6", "fire: 1d20"];
+        let cli = Cli::try_parse_from(args).unwrap();
+        // The display stays text while the log is JSON, whatever the extension
+        assert_eq!(output_format(&cli.roll), OutputFormat::Text);
+        run_roll(&cli.roll);
+        let logged: Vec<RollRecord> = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(logged.len(), 2);
+        assert_eq!(logged[1].label.as_deref(), Some("fire"));
+
+        // The same rolls written as the text display and as a CSV log
+        let mut text = Vec::new();
+        write_records(&mut text, OutputFormat::Text, &logged, &cli.roll, 2, None).unwrap();
+        let expected = format!("2d6: {}\nfire: 1d20: {}\n", logged[0].total, logged[1].total);
+        assert_eq!(String::from_utf8(text).unwrap(), expected);
+        let mut csv = Vec::new();
+        write_records(&mut csv, OutputFormat::Csv, &logged, &cli.roll, 2, None).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap().lines().count(), 3);
+
+        assert!(Cli::try_parse_from(["droll", "--roll-log-format", "json", "1d6"]).is_err());
+    }
+
+    #[test]
+    fn test_shown_seed_reproduces_roll() {
+        let cli = Cli::try_parse_from(["droll", "--seed-show", "10d20"]).unwrap();
+        let dice = parse_specs_or_exit(&cli.roll.dice, &cli.roll.spec);
+        let (mut rng, seed) = roll_rng(&cli.roll);
+        let seed = seed.unwrap();
+        let first = dice[0].roll_detailed_with(&mut rng);
+
+        let seed_arg = seed.to_string();
+        let cli = Cli::try_parse_from(["droll", "--seed", &seed_arg, "10d20"]).unwrap();
+        let (mut rng, shown) = roll_rng(&cli.roll);
+        assert_eq!(shown, None);
+        assert_eq!(dice[0].roll_detailed_with(&mut rng), first);
+
+        assert!(Cli::try_parse_from(["droll", "--seed-show", "--seed", "1", "1d6"]).is_err());
+    }
+
+    #[test]
+    fn test_percent_after_flat_modifier() {
+        let cli = Cli::try_parse_from(["droll", "2d6+4", "--percent", "50", "--force-roll", "average"]).unwrap();
+        assert_eq!(cli.roll.percent_rounding, PercentRounding::Floor);
+        let dice = parse_specs_or_exit(&cli.roll.dice, &cli.roll.spec);
+        // The flat modifier comes first: (7 + 4) * 1.5 = 16.5
+        let total = forced_total(&dice[0], ForceMode::Average);
+        assert_eq!(total, 11);
+        assert_eq!(percent_total(total, 50, PercentRounding::Floor), 16);
+        assert_eq!(percent_total(total, 50, PercentRounding::Ceil), 17);
+        assert_eq!(percent_total(total, 50, PercentRounding::Nearest), 17);
+        assert_eq!(percent_total(16, 50, PercentRounding::Floor), 24);
+        assert_eq!(percent_total(10, -25, PercentRounding::Nearest), 8);
+        assert_eq!(percent_total(-3, 50, PercentRounding::Floor), -5);
+        assert_eq!(percent_total(-3, 50, PercentRounding::Nearest), -5);
+        assert_eq!(percent_total(i32::MAX, 100, PercentRounding::Floor), i32::MAX);
+        assert!(Cli::try_parse_from(["droll", "2d6", "--percent-rounding", "ceil"]).is_err());
+    }
+
+    #[test]
+    fn test_resist_halves_and_vulnerable_doubles() {
+        let cli = Cli::try_parse_from(["droll", "2d6+3", "--resist", "--force-roll", "max"]).unwrap();
+        let dice = parse_specs_or_exit(&cli.roll.dice, &cli.roll.spec);
+        let total = forced_total(&dice[0], ForceMode::Max);
+        assert_eq!(total, 15);
+        let resist = DamageAdjustment::from_args(&cli.roll).unwrap();
+        assert_eq!(resist, DamageAdjustment::Resist);
+        // 15 halves to 7.5, which rounds down
+        assert_eq!(resist.apply(total), 7);
+        assert_eq!(resist.note(total), " (halved from 15)");
+        assert_eq!(resist.apply(-7), -4);
+
+        let vulnerable = DamageAdjustment::Vulnerable;
+        assert_eq!(vulnerable.apply(15), 30);
+        assert_eq!(vulnerable.apply(i32::MAX), i32::MAX);
+        assert_eq!(vulnerable.note(15), " (doubled from 15)");
+        assert!(Cli::try_parse_from(["droll", "2d6", "--resist", "--vulnerable"]).is_err());
+        assert_eq!(DamageAdjustment::from_args(&Cli::try_parse_from(["droll", "2d6"]).unwrap().roll), None);
+    }
+
+    #[test]
+    fn test_distribution_benchmark_report() {
+        let options = SpecArgs::default();
+        for spec in ["2d6", "4d6dl1", "3d8r1+2", "6d6>=5", "2d4 + 1d6>=4", "2d20kh1"] {
+            let line = distribution_benchmark_report(spec, &options).unwrap();
+            assert!(line.starts_with(&format!("{}: ", spec)) && line.contains("totals match"), "{}", line);
+        }
+        assert!(distribution_benchmark_report("2d6!", &options).unwrap().contains("skipped"));
+        assert!(distribution_benchmark_report("2x6", &options).is_err());
+    }
+
+    #[test]
+    fn test_roundtrip_report() {
+        let options = SpecArgs::default();
+        assert_eq!(roundtrip_report("4d6dl1", &options), Ok("4d6dl1 -> 4d6kh3: ok".to_string()));
+        assert_eq!(roundtrip_report("x: 3#2d8-1r1", &options), Ok("x: 3#2d8-1r1 -> 2d8r{1}-1: ok".to_string()));
+        assert!(roundtrip_report("2x6", &options).is_err());
+    }
+
+    #[test]
+    fn test_parse_only_structure() {
+        let cli = Cli::try_parse_from(["droll", "--parse-only", "hit: 4d6kh3+2d10>=8r1!+1"]).unwrap();
+        assert!(cli.roll.parse_only);
+        let dice = parse_specs_or_exit(&cli.roll.dice, &cli.roll.spec);
+        let lines = parsed_spec_lines(&cli.roll.dice, &dice);
+        assert_eq!(lines.len(), 1);
+
+        let json: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(json["label"], "hit");
+        let terms = json["terms"].as_array().unwrap();
+        assert_eq!(terms.len(), 2);
+        assert_eq!(terms[0]["count"], 4);
+        assert_eq!(terms[0]["sides"], 6);
+        assert_eq!(terms[0]["keep"], "kh3");
+        assert_eq!(terms[0]["explode"], false);
+        assert!(terms[0].get("success_target").is_none());
+        assert_eq!(terms[1]["count"], 2);
+        assert_eq!(terms[1]["sides"], 10);
+        assert_eq!(terms[1]["modifier"], 1);
+        assert_eq!(terms[1]["success_target"], 8);
+        assert_eq!(terms[1]["reroll"], serde_json::json!([1]));
+        assert_eq!(terms[1]["explode"], true);
+        assert_eq!(terms[1]["reduce"], "sum");
+    }
+
+    #[test]
+    fn test_strict_notation() {
+        let strict = SpecArgs { strict: true, ..Default::default() };
+        assert!(parse_spec("2d6xyz", &strict).is_err());
+        assert!(parse_spec("2d6xyz", &SpecArgs::default()).is_err());
+        for spec in ["2d6k2", "4d6k3", "4d6dl", "2d20!!", "5d10>=8>=7", "4d6kh3kl1"] {
+            let err = parse_spec(spec, &strict).unwrap_err();
+            assert!(err.starts_with("Ambiguous notation"), "{}: {}", spec, err);
+            // Without --strict the roll still goes ahead
+            assert!(parse_spec(spec, &SpecArgs::default()).is_ok(), "{}", spec);
+        }
+        assert!(parse_spec("4d6kh3 + 2d20kl1!", &strict).is_ok());
+
+        let cli = Cli::try_parse_from(["droll", "--strict-modifier-sign", "2d6+3-1"]).unwrap();
+        let err = parse_spec("2d6+3-1", &cli.roll.spec).unwrap_err();
+        assert!(err.starts_with("Ambiguous modifier"), "{}", err);
+        // Ambiguous notation is still only a warning, while the modifier is checked
+        assert!(parse_spec("4d6k3+1", &cli.roll.spec).is_ok());
+        assert!(parse_spec("4d6k3+01", &cli.roll.spec).is_err());
+    }
+
+    #[test]
+    fn test_elven_accuracy() {
+        let cli = Cli::try_parse_from(["droll", "--elven-accuracy", "2d20kh1+5"]).unwrap();
+        let dice = parse_spec("2d20kh1+5", &cli.roll.spec).unwrap();
+        assert_eq!(dice, parse_spec("3d20kh1+5", &SpecArgs::default()).unwrap());
+        // Keeping the highest of three makes a 20 come up 1 - (19/20)^3 of the time
+        assert!((dice.probability_of(25) - (1.0 - 0.95f64.powi(3))).abs() < 1e-12);
+        let mut rng = StdRng::seed_from_u64(267);
+        for _ in 0..50 {
+            let result = dice.roll_detailed_with(&mut rng);
+            assert_eq!(result.dice.len(), 3);
+            assert_eq!(result.total - 5, result.highest_face().unwrap());
+        }
+
+        // Disadvantage and other dice are left alone
+        for spec in ["2d20kl1", "2d20", "2d12kh1", "4d6kh3"] {
+            assert_eq!(parse_spec(spec, &cli.roll.spec).unwrap(), Dice::parse(spec).unwrap(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn test_scale_option() {
+        let cli = Cli::try_parse_from(["droll", "--scale", "1.5", "2d8+2"]).unwrap();
+        let dice = parse_spec("2d8+2", &cli.roll.spec).unwrap();
+        assert_eq!(dice, Dice::parse("3d8+3").unwrap());
+        let cli = Cli::try_parse_from(["droll", "--scale", "0.1", "2d8+2"]).unwrap();
+        assert!(parse_spec("2d8+2", &cli.roll.spec).unwrap_err().starts_with("Cannot scale dice"));
+    }
+
+    #[test]
+    fn test_floor_kept_only_option() {
+        let cli = Cli::try_parse_from(["droll", "--floor-kept-only", "--die-floor", "2", "4d6kh3 + 2d4"]).unwrap();
+        let dice = parse_spec("4d6kh3 + 2d4", &cli.roll.spec).unwrap();
+        assert_eq!(dice.floor_scope, FloorScope::KeptDice);
+        assert_eq!(dice.extra_terms[0].floor_scope, FloorScope::KeptDice);
+        assert_eq!(dice.min_roll(), 6 + 4);
+    }
+
+    #[test]
+    fn test_explode_low_option() {
+        let cli = Cli::try_parse_from(["droll", "--explode-low", "3d6!"]).unwrap();
+        let dice = parse_spec("3d6!", &cli.roll.spec).unwrap();
+        assert_eq!(dice.explode_face, ExplodeFace::Lowest);
+        assert!(!dice.distribution_map().contains_key(&3));
+        let err = parse_spec("1d6r2-6!", &cli.roll.spec).unwrap_err();
+        assert!(err.starts_with("Invalid explosion"), "{}", err);
+    }
+
+    #[test]
+    fn test_explode_which_option() {
+        let cli = Cli::try_parse_from(["droll", "--explode-which", "highest", "3d6!"]).unwrap();
+        let dice = parse_spec("3d6!", &cli.roll.spec).unwrap();
+        assert_eq!(dice.explode_which, ExplodeWhich::Highest);
+        assert_eq!(parse_spec("3d6!", &SpecArgs::default()).unwrap().explode_which, ExplodeWhich::All);
+        let err = parse_spec("4d6!kh3", &cli.roll.spec).unwrap_err();
+        assert!(err.starts_with("Invalid keep or drop"), "{}", err);
+    }
+
+    #[test]
+    fn test_count_successes_option() {
+        let cli = Cli::try_parse_from(["droll", "5d10", "--count-successes", "8"]).unwrap();
+        let dice = parse_spec("5d10", &cli.roll.spec).unwrap();
+        assert_eq!(dice, parse_spec("5d10>=8", &SpecArgs::default()).unwrap());
+        let mut rng = StdRng::seed_from_u64(332);
+        for _ in 0..50 {
+            let result = dice.roll_detailed_with(&mut rng);
+            let eights = result.dice.iter().filter(|die| die.face >= 8).count();
+            assert_eq!(result.total, eights as i32);
+        }
+    }
+
+    #[test]
+    fn test_sides_range() {
+        let cli = Cli::try_parse_from(["droll", "--min-sides", "4", "--max-sides", "20", "1d6"]).unwrap();
+        assert!(parse_spec("3d8+1d20", &cli.roll.spec).is_ok());
+        assert_eq!(
+            parse_spec("1d100", &cli.roll.spec).unwrap_err(),
+            "Invalid sides d100: dice must have between 4 and 20 sides"
+        );
+        assert!(parse_spec("2d2", &cli.roll.spec).is_err());
+        assert!(parse_spec("1d100", &SpecArgs::default()).is_ok());
+        let cli = Cli::try_parse_from(["droll", "--min-sides", "20", "--max-sides", "6", "1d6"]).unwrap();
+        assert_eq!(
+            parse_spec("1d6", &cli.roll.spec).unwrap_err(),
+            "Invalid sides range: the minimum of 20 sides is above the maximum of 6"
+        );
+    }
+
+    #[test]
+    fn test_reduce_product() {
+        let cli = Cli::try_parse_from(["droll", "--reduce", "product", "2d6"]).unwrap();
+        let dice = parse_spec("2d6", &cli.roll.spec).unwrap();
+        assert_eq!((dice.min_roll(), dice.max_roll()), (1, 36));
+        let mut rng = StdRng::seed_from_u64(249);
+        for _ in 0..200 {
+            let result = dice.roll_detailed_with(&mut rng);
+            assert_eq!(result.total, result.dice[0].face * result.dice[1].face);
+        }
+    }
+
+    #[test]
+    fn test_split_group() {
+        assert_eq!(split_group("5#1d20+7").unwrap(), (5, "1d20+7"));
+        assert_eq!(split_group("1d20+7").unwrap(), (1, "1d20+7"));
+        assert!(split_group("0#1d6").is_err());
+        assert!(split_group("x#1d6").is_err());
+        assert!(split_group("1001#1d6").is_err());
+    }
+
+    #[test]
+    fn test_fate_ladder() {
+        assert_eq!(fate_ladder(4), "Great");
+        assert_eq!(fate_ladder(3), "Good");
+        assert_eq!(fate_ladder(0), "Mediocre");
+        assert_eq!(fate_ladder(-2), "Terrible");
+        assert_eq!(fate_ladder(8), "Legendary");
+        assert_eq!(fate_ladder(10), "Legendary+2");
+        assert_eq!(fate_ladder(-3), "Terrible-1");
+
+        let mut registry = DieRegistry::new();
+        registry.insert("fate", vec![-1, 0, 1]).unwrap();
+        assert!(is_fate_dice(&Dice::parse_with("4dfate+2", &registry).unwrap()));
+        assert!(!is_fate_dice(&Dice::parse("4d6").unwrap()));
+        let dice = Dice::parse("4dF+2").unwrap();
+        assert!(is_fate_dice(&dice));
+        let cli = Cli::try_parse_from(["droll", "--fate-ladder", "4dF+2"]).unwrap();
+        // Two pluses and two blanks on top of the +2 make +4
+        let mut rng = ScriptedRng::new([1, 1, 0, 0]);
+        rng.set_die(&dice).unwrap();
+        let result = dice.roll_detailed_with(&mut rng);
+        assert_eq!(result.total, 4);
+        assert_eq!(roll_line(&cli.roll, &dice, &result, None), "4 (Great)");
+        let d6 = Dice::parse("1d6").unwrap();
+        let result = d6.roll_detailed_with(&mut StdRng::seed_from_u64(338));
+        assert_eq!(roll_line(&cli.roll, &d6, &result, None), result.total.to_string());
+    }
+
+    #[test]
+    fn test_group_by_label() {
+        let cli = Cli::try_parse_from(["droll", "--group-by-label", "fire: 2d6", "slashing: 1d8", "fire: 1d4"]).unwrap();
+        assert!(cli.roll.group_by_label);
+        let entries = [
+            HistoryEntry::now("fire: 2d6", 9),
+            HistoryEntry::now("slashing: 1d8", 5),
+            HistoryEntry::now("fire: 1d4", 3),
+            HistoryEntry::now("1d6", 2),
+            HistoryEntry::now("slashing:2#1d8", 7),
+        ];
+        assert_eq!(
+            label_total_lines(&entries),
+            vec!["Totals by label:", "  fire: 12", "  slashing: 12", "  unlabeled: 2", "Grand total: 26"]
+        );
+    }
+
+    #[test]
+    fn test_labeled_spec_json() {
+        assert_eq!(split_label("fireball: 8d6"), (Some("fireball"), "8d6"));
+        assert_eq!(split_label("attack:3#1d20+5"), (Some("attack"), "3#1d20+5"));
+        assert_eq!(split_label("1d20"), (None, "1d20"));
+
+        let options = SpecArgs::default();
+        let spec = "fireball: 2#8d6";
+        let dice = parse_spec(spec, &options).unwrap();
+        let results = roll_group(spec, &dice, RollMode::default(), &mut StdRng::seed_from_u64(240));
+        assert_eq!(results.len(), 2);
+        let json = serde_json::to_value(roll_record(spec, &results[0].result, None)).unwrap();
+        assert_eq!(json["label"], "fireball");
+        assert_eq!(json["total"], results[0].result.total);
+
+        // Unlabeled rolls leave the field out
+        let json = serde_json::to_value(roll_record("8d6", &results[0].result, None)).unwrap();
+        assert!(json.get("label").is_none());
+    }
+
+    #[test]
+    fn test_interactive_rejects_groups() {
+        let options = SpecArgs::default();
+        assert_eq!(
+            parse_interactive_spec("3#1d6", &options).unwrap_err(),
+            "Cannot roll the group '3#1d6' in interactive mode: enter the specification once for each roll"
+        );
+        assert!(parse_interactive_spec("fire: 2#8d6", &options).is_err());
+        assert_eq!(parse_interactive_spec("1#1d6", &options), parse_spec("1d6", &options));
+        assert_eq!(parse_interactive_spec("2d6+1", &options), parse_spec("2d6+1", &options));
+    }
+
+    #[test]
+    fn test_group_rolls_independently() {
+        let options = SpecArgs::default();
+        let mut rng = StdRng::seed_from_u64(217);
+        let specs = ["3#1d6", "1d20", "2#2d4+1"];
+        let counts: Vec<usize> = specs
+            .iter()
+            .map(|spec| {
+                let dice = parse_spec(spec, &options).unwrap();
+                roll_group(spec, &dice, RollMode::default(), &mut rng).len()
+            })
+            .collect();
+        assert_eq!(counts, vec![3, 1, 2]);
+
+        // Eight 1d6 rolls from one seed are not all the same value
+        let dice = parse_spec("8#1d6", &options).unwrap();
+        let totals: Vec<i32> = roll_group("8#1d6", &dice, RollMode::default(), &mut rng)
+            .iter()
+            .map(|roll| roll.result.total)
+            .collect();
+        assert!(totals.iter().all(|total| (1..=6).contains(total)));
+        assert!(totals.iter().any(|&total| total != totals[0]));
+    }
+
+    #[test]
+    fn test_reroll_pool_below() {
+        let dice = Dice::parse("2d6").unwrap();
+        let mode = RollMode { reroll_pool_below: Some(13), ..Default::default() };
+        // Every 2d6 total is below 13, so each roll is rerolled exactly once
+        let rolls = roll_group("2d6", &dice, mode, &mut StdRng::seed_from_u64(243));
+        let mut rng = StdRng::seed_from_u64(243);
+        let first = dice.roll_detailed_with(&mut rng);
+        let second = dice.roll_detailed_with(&mut rng);
+        assert_eq!(rolls.len(), 1);
+        assert_eq!(rolls[0].rerolled_from, vec![first.total]);
+        assert_eq!(rolls[0].result, second);
+
+        // A total that is high enough stands
+        let mode = RollMode { reroll_pool_below: Some(2), ..Default::default() };
+        let rolls = roll_group("5#2d6", &dice, mode, &mut StdRng::seed_from_u64(243));
+        assert!(rolls.iter().all(|roll| roll.rerolled_from.is_empty()));
+    }
+
+    /// Roll `spec` once under `mode` with the dice showing `faces` in order.
+    fn scripted_roll(spec: &str, mode: RollMode, faces: &[i32]) -> GroupRoll {
+        let dice = Dice::parse(spec).unwrap();
+        let mut rng = ScriptedRng::new(faces.iter().copied());
+        rng.set_die(&dice).unwrap();
+        roll_group(spec, &dice, mode, &mut rng).remove(0)
+    }
+
+    #[test]
+    fn test_mulligan_rerolls_failed_pool_once() {
+        let mode = RollMode { mulligan: true, ..Default::default() };
+        // A success stands
+        let roll = scripted_roll("1d6>=6", mode, &[6]);
+        assert!(roll.rerolled_from.is_empty());
+        assert_eq!(roll.result.total, 1);
+        // A failure is rolled again
+        let roll = scripted_roll("1d6>=6", mode, &[2, 6]);
+        assert_eq!(roll.rerolled_from, vec![0]);
+        assert_eq!(roll.result.total, 1);
+        // Only once, even when the second roll fails as well
+        let roll = scripted_roll("1d6>=6", mode, &[2, 3]);
+        assert_eq!(roll.rerolled_from, vec![0]);
+        assert_eq!(roll.result.total, 0);
+    }
+
+    #[test]
+    fn test_safety_net_rolls_again_below_threshold() {
+        let mode = RollMode { safety_net: Some(10), ..Default::default() };
+        let roll = scripted_roll("1d20", mode, &[10]);
+        assert_eq!((roll.safety_net, roll.result.total), (None, 10));
+        // The higher of the two rolls is kept
+        let roll = scripted_roll("1d20", mode, &[4, 15]);
+        assert_eq!((roll.safety_net, roll.result.total), (Some((4, 15)), 15));
+        let roll = scripted_roll("1d20", mode, &[7, 3]);
+        assert_eq!((roll.safety_net, roll.result.total), (Some((7, 3)), 7));
+        assert_eq!(safety_net_note(Some((4, 15))), " (safety net: 4 and 15)");
+        assert_eq!(safety_net_note(None), " (no safety net)");
+    }
+
+    #[test]
+    fn test_limit_flags() {
+        use droll::{MAX_EXPLOSIONS, MAX_REROLLS};
+
+        // The pool reroll limit bounds how often a pool that stays too low is rerolled
+        let dice = Dice::parse("2d6").unwrap();
+        let mode = RollMode { reroll_pool_below: Some(13), pool_reroll_limit: 4, ..Default::default() };
+        let rolls = roll_group("3#2d6", &dice, mode, &mut StdRng::seed_from_u64(252));
+        assert!(rolls.iter().all(|roll| roll.rerolled_from.len() == 4));
+
+        let cli = Cli::try_parse_from(["droll", "--explode-limit", "3", "--reroll-limit", "7", "4d6!r1"]).unwrap();
+        let dice = parse_spec("4d6!r1", &cli.roll.spec).unwrap();
+        assert_eq!((dice.explosion_limit, dice.reroll_limit), (3, 7));
+        assert_eq!(cli.roll.pool_reroll_limit, DEFAULT_POOL_REROLLS);
+        let dice = parse_spec("4d6!r1", &SpecArgs::default()).unwrap();
+        assert_eq!((dice.explosion_limit, dice.reroll_limit), (MAX_EXPLOSIONS, MAX_REROLLS));
+    }
+
+    #[test]
+    fn test_show_die_type_in_mixed_pool() {
+        let cli = Cli::try_parse_from(["droll", "--show-die-type", "2d6 + 1d8"]).unwrap();
+        let dice = parse_spec("2d6 + 1d8", &cli.roll.spec).unwrap();
+        let result = dice.roll_detailed_with(&mut StdRng::seed_from_u64(304));
+        let faces: Vec<i32> = result.dice.iter().map(|die| die.face).collect();
+        assert_eq!(
+            roll_line(&cli.roll, &dice, &result, None),
+            format!("{} [d6:{}, d6:{}, d8:{}]", result.total, faces[0], faces[1], faces[2])
+        );
+    }
+
+    #[test]
+    fn test_keep_tie_shows_which_die_is_kept() {
+        let shown = |policy: &str| {
+            let cli = Cli::try_parse_from(["droll", "--show-die-type", "--keep-tie", policy, "2d20kh1"]).unwrap();
+            let mut dice = parse_spec("2d20kh1", &cli.roll.spec).unwrap();
+            dice.set_keep_tie(cli.roll.keep_tie.into());
+            let mut rng = ScriptedRng::new([12, 12]);
+            rng.set_die(&dice).unwrap();
+            let result = dice.roll_detailed_with(&mut rng);
+            let kept = serde_json::to_value(roll_record("2d20kh1", &result, None)).unwrap()["kept"].clone();
+            (roll_line(&cli.roll, &dice, &result, None), kept)
+        };
+        assert_eq!(shown("first"), ("12 [d20:12, (d20:12)]".to_string(), serde_json::json!([true, false])));
+        assert_eq!(shown("last"), ("12 [(d20:12), d20:12]".to_string(), serde_json::json!([false, true])));
+
+        // Records of pools without dropped dice mark every die kept
+        let result = Dice::parse("2d6").unwrap().roll_detailed_with(&mut StdRng::seed_from_u64(230));
+        assert_eq!(serde_json::to_value(roll_record("2d6", &result, None)).unwrap()["kept"], serde_json::json!([true, true]));
+    }
+
+    #[test]
+    fn test_with_rerolls() {
+        let cli = Cli::try_parse_from(["droll", "--retry-count", "1d2r1"]).unwrap();
+        let mut dice = Dice::parse("1d2r1").unwrap();
+        dice.set_reroll_limit(3);
+        let mut rng = StdRng::seed_from_u64(288);
+        let mut rerolled = false;
+        for _ in 0..50 {
+            let result = dice.roll_detailed_with(&mut rng);
+            let rerolls = result.dice[0].rerolls;
+            // A 1 is only left once the limit of rerolls is used up
+            assert!(rerolls <= 3 && (result.total == 2 || rerolls == 3));
+            rerolled |= rerolls > 0;
+            let noun = if rerolls == 1 { "reroll" } else { "rerolls" };
+            assert_eq!(roll_line(&cli.roll, &dice, &result, None), format!("{} ({} {})", result.total, rerolls, noun));
+        }
+        assert!(rerolled);
+
+        // Dice without reroll notation report nothing
+        let plain = Dice::parse("2d6").unwrap();
+        let result = plain.roll_detailed_with(&mut rng);
+        assert_eq!(result.rerolls(), 0);
+        assert_eq!(roll_line(&cli.roll, &plain, &result, None), result.total.to_string());
     }
 
     #[test]
-    fn test_parse_multiple_dice() {
-        let dice = Dice::parse("3d8").unwrap();
-        assert_eq!(dice.count, 3);
-        assert_eq!(dice.sides, 8);
-        assert_eq!(dice.modifier, 0);
+    fn test_spread() {
+        let cli = Cli::try_parse_from(["droll", "--spread", "4d6+1"]).unwrap();
+        let dice = Dice::parse("4d6+1").unwrap();
+        let mut rng = StdRng::seed_from_u64(321);
+        for _ in 0..20 {
+            let result = dice.roll_detailed_with(&mut rng);
+            let faces: Vec<i32> = result.dice.iter().map(|die| die.face).collect();
+            let spread = faces.iter().max().unwrap() - faces.iter().min().unwrap();
+            assert_eq!(roll_line(&cli.roll, &dice, &result, None), format!("{} (spread {})", result.total, spread));
+        }
     }
 
     #[test]
-    fn test_parse_dice_with_positive_modifier() {
-        let dice = Dice::parse("2d10+5").unwrap();
-        assert_eq!(dice.count, 2);
-        assert_eq!(dice.sides, 10);
-        assert_eq!(dice.modifier, 5);
+    fn test_with_criticals() {
+        let cli = Cli::try_parse_from(["droll", "--with-criticals", "8d6>=5"]).unwrap();
+        let dice = Dice::parse("8d6>=5").unwrap();
+        let mut rng = StdRng::seed_from_u64(273);
+        for _ in 0..20 {
+            let result = dice.roll_detailed_with(&mut rng);
+            let sixes = result.dice.iter().filter(|die| die.face == 6).count();
+            let noun = if sixes == 1 { "critical" } else { "criticals" };
+            assert_eq!(roll_line(&cli.roll, &dice, &result, None), format!("{} ({} {})", result.total, sixes, noun));
+        }
+        // Plain dice have no successes, so no criticals are reported
+        let plain = Dice::parse("2d6").unwrap();
+        let result = plain.roll_detailed_with(&mut rng);
+        assert_eq!(roll_line(&cli.roll, &plain, &result, None), result.total.to_string());
     }
 
     #[test]
-    fn test_parse_dice_with_negative_modifier() {
-        let dice = Dice::parse("1d20-3").unwrap();
-        assert_eq!(dice.count, 1);
-        assert_eq!(dice.sides, 20);
-        assert_eq!(dice.modifier, -3);
+    fn test_ability_scores() {
+        let mut rng = StdRng::seed_from_u64(271);
+        for _ in 0..50 {
+            let scores = roll_ability_scores(&mut rng);
+            assert_eq!(scores.len(), 6);
+            assert!(scores.iter().all(|score| (3..=18).contains(score)), "{:?}", scores);
+        }
+
+        assert_eq!((ability_modifier(3), ability_modifier(10), ability_modifier(11), ability_modifier(18)), (-4, 0, 0, 4));
+        let lines = ability_score_lines(&[15, 14, 13, 12, 10, 8]);
+        assert_eq!(lines.len(), 8);
+        assert_eq!(lines[1], " 15 (+2)");
+        assert_eq!(lines[6], "  8 (-1)");
+        assert_eq!(lines[7], "Sum: 72 (modifiers +5)");
+
+        let cli = Cli::try_parse_from(["droll", "--ability-scores"]).unwrap();
+        assert!(cli.roll.ability_scores);
     }
 
     #[test]
-    fn test_parse_whitespace_handling() {
-        let dice = Dice::parse("  2D6+1  ").unwrap();
-        assert_eq!(dice.count, 2);
-        assert_eq!(dice.sides, 6);
-        assert_eq!(dice.modifier, 1);
+    fn test_confirm_huge_operation() {
+        let what = "a simulation of 1000000000 rolls in all";
+        let mut prompt = Vec::new();
+        // Without a terminal it refuses unless told to go ahead, and never waits for an answer
+        let err = confirm(what, false, false, &mut "y\n".as_bytes(), &mut prompt).unwrap_err();
+        assert_eq!(err, format!("refusing {} without --yes, as there is no terminal to confirm it on", what));
+        assert!(prompt.is_empty());
+        assert!(confirm(what, true, false, &mut "".as_bytes(), &mut prompt).is_ok());
+
+        assert!(confirm(what, false, true, &mut "Yes\n".as_bytes(), &mut prompt).is_ok());
+        assert_eq!(String::from_utf8(prompt).unwrap(), format!("Go ahead with {}? [y/N] ", what));
+        for answer in ["n\n", "\n", ""] {
+            assert_eq!(confirm(what, false, true, &mut answer.as_bytes(), &mut Vec::new()), Err("aborted".to_string()));
+        }
+
+        let cli = Cli::try_parse_from(["droll", "--simulate", "1000000000", "-y", "1d6"]).unwrap();
+        assert!(cli.roll.yes);
     }
 
     #[test]
-    fn test_parse_invalid_format() {
-        assert!(Dice::parse("invalid").is_err());
-        assert!(Dice::parse("2x6").is_err());
-        assert!(Dice::parse("d6").is_err());
-        assert!(Dice::parse("2d").is_err());
+    fn test_hit_points() {
+        let hit_die = Dice::parse("1d10+2").unwrap();
+        let mut rng = StdRng::seed_from_u64(322);
+        for _ in 0..20 {
+            let levels = roll_hit_points(&hit_die, 5, false, &mut rng);
+            assert_eq!(levels.len(), 5);
+            assert_eq!(levels[0], 12);
+            assert!(levels[1..].iter().all(|gained| (3..=12).contains(gained)), "{:?}", levels);
+        }
+        assert_eq!(roll_hit_points(&hit_die, 3, true, &mut rng), vec![12, 8, 8]);
+        // A poor constitution still gives a hit point a level
+        assert_eq!(roll_hit_points(&Dice::parse("1d6-3").unwrap(), 3, true, &mut rng), vec![3, 1, 1]);
+
+        let lines = hit_point_lines(&[12, 8, 5]);
+        assert_eq!(lines, vec!["Hit points:", "Level  1:  12", "Level  2:   8", "Level  3:   5", "Total: 25"]);
+
+        let cli = Cli::try_parse_from(["droll", "--hit-points", "4", "--average-hp", "1d8+1"]).unwrap();
+        assert_eq!((cli.roll.hit_points, cli.roll.average_hp), (Some(4), true));
+        assert!(Cli::try_parse_from(["droll", "--average-hp", "1d8"]).is_err());
+        assert!(Cli::try_parse_from(["droll", "--hit-points", "0", "1d8"]).is_err());
     }
 
     #[test]
-    fn test_parse_invalid_numbers() {
-        assert!(Dice::parse("abc d6").is_err());
-        assert!(Dice::parse("2d abc").is_err());
-        assert!(Dice::parse("2d6+ abc").is_err());
+    fn test_initiative_columns_align_with_wide_names() {
+        let roll = |name: &str, result: i32| InitiativeRoll {
+            name: name.to_string(),
+            modifier: 1,
+            result,
+        };
+        let lines = initiative_lines(&[roll("Ann", 17), roll("竜王", 9), roll("Bör ✓", 4)]);
+        assert_eq!(lines[0], " 1. Ann:    17 (1d20+1)");
+        assert_eq!(lines[1], " 2. 竜王:    9 (1d20+1)");
+        assert_eq!(lines[2], " 3. Bör ✓:   4 (1d20+1)");
+        let columns: Vec<usize> = lines
+            .iter()
+            .map(|line| line.split(" (").next().unwrap().width())
+            .collect();
+        assert!(columns.iter().all(|&width| width == columns[0]));
     }
 
     #[test]
-    fn test_parse_zero_values() {
-        assert!(Dice::parse("0d6").is_err());
-        assert!(Dice::parse("2d0").is_err());
+    fn test_initiative_tie_breaks() {
+        let roll = |name: &str, modifier: i32, result: i32| InitiativeRoll {
+            name: name.to_string(),
+            modifier,
+            result,
+        };
+        let mut rolls = vec![
+            roll("first", 1, 15),
+            roll("second", 3, 15),
+            roll("third", 1, 15),
+            roll("fourth", 0, 18),
+        ];
+        order_initiative(&mut rolls);
+        let names: Vec<&str> = rolls.iter().map(|roll| roll.name.as_str()).collect();
+        assert_eq!(names, vec!["fourth", "second", "first", "third"]);
     }
 }
@@ -0,0 +1,141 @@
+use clap::ValueEnum;
+use droll::Dice;
+
+/// Means and variances closer than this are treated as equal when comparing combinations.
+const TOLERANCE: f64 = 1e-9;
+
+/// What `--optimize` looks for in a combination of dice.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Goal {
+    /// The highest expected total, then the lowest variance on a tie
+    #[default]
+    Mean,
+    /// The lowest variance among the combinations spending the most of the budget,
+    /// then the highest expected total on a tie
+    Variance,
+}
+
+/// A combination of candidate dice rolled together, with the mean and variance of
+/// its total. Rolls of different candidates are independent, so both simply add up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan {
+    /// How many of each candidate are rolled, in the order the candidates were given
+    pub counts: Vec<u32>,
+    /// The number of dice the combination rolls
+    pub cost: u32,
+    pub mean: f64,
+    pub variance: f64,
+}
+
+impl Plan {
+    /// Whether this combination does better than `other` for the goal, when both spend
+    /// the same number of dice.
+    fn beats(&self, other: &Plan, goal: Goal) -> bool {
+        let (mean, variance) = (self.mean - other.mean, self.variance - other.variance);
+        match goal {
+            Goal::Mean => mean > TOLERANCE || (mean.abs() <= TOLERANCE && variance < -TOLERANCE),
+            Goal::Variance => variance < -TOLERANCE || (variance.abs() <= TOLERANCE && mean > TOLERANCE),
+        }
+    }
+
+    /// Describe the combination, e.g. `1 x 1d6 + 2 x 2d8 (5 dice, mean 21.50, variance 23.92)`.
+    pub fn describe(&self, specs: &[String]) -> String {
+        let parts: Vec<String> = specs
+            .iter()
+            .zip(&self.counts)
+            .filter(|&(_, &count)| count > 0)
+            .map(|(spec, count)| format!("{} x {}", count, spec))
+            .collect();
+        format!(
+            "{} ({} {}, mean {:.2}, variance {:.2})",
+            parts.join(" + "),
+            self.cost,
+            if self.cost == 1 { "die" } else { "dice" },
+            self.mean,
+            self.variance
+        )
+    }
+}
+
+/// The number of dice a candidate rolls, counting every term of a mixed pool. This is
+/// what it costs out of the budget.
+fn dice_cost(dice: &Dice) -> u32 {
+    std::iter::once(dice).chain(&dice.extra_terms).map(|term| u32::from(term.count)).sum()
+}
+
+/// The best combination of the candidate dice, each usable any number of times, that
+/// rolls at most `budget` dice, or `None` when no candidate fits the budget.
+///
+/// Because means and variances add, the best combination for every exact number of
+/// dice is built from the best for fewer dice, one candidate at a time.
+pub fn optimize(candidates: &[Dice], budget: u32, goal: Goal) -> Option<Plan> {
+    let moments: Vec<(u32, f64, f64)> = candidates
+        .iter()
+        .map(|dice| (dice_cost(dice), dice.expected_value(), dice.variance()))
+        .collect();
+    let mut best: Vec<Option<Plan>> = vec![None; budget as usize + 1];
+    best[0] = Some(Plan {
+        counts: vec![0; candidates.len()],
+        cost: 0,
+        mean: 0.0,
+        variance: 0.0,
+    });
+    for spent in 1..=budget {
+        for (index, &(cost, mean, variance)) in moments.iter().enumerate() {
+            let Some(before) = spent.checked_sub(cost).and_then(|rest| best[rest as usize].as_ref()) else {
+                continue;
+            };
+            let mut plan = before.clone();
+            plan.counts[index] += 1;
+            plan.cost = spent;
+            plan.mean += mean;
+            plan.variance += variance;
+            if best[spent as usize].as_ref().is_none_or(|current| plan.beats(current, goal)) {
+                best[spent as usize] = Some(plan);
+            }
+        }
+    }
+
+    let plans = best.into_iter().skip(1).flatten();
+    match goal {
+        // Dice with negative totals can make a smaller spend come out ahead, so every spend is compared
+        Goal::Mean => plans.reduce(|chosen, plan| if plan.beats(&chosen, goal) { plan } else { chosen }),
+        // Any variance is more than none, so only the largest spend is fair to compare
+        Goal::Variance => plans.last(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates(specs: &[&str]) -> Vec<Dice> {
+        specs.iter().map(|spec| Dice::parse(spec).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_optimize_highest_mean() {
+        let specs = ["1d4", "1d6", "2d8"];
+        let plan = optimize(&candidates(&specs), 5, Goal::Mean).unwrap();
+        // 2d8 gives the most per die, and the odd die left over is best spent on a d6
+        assert_eq!(plan.counts, vec![0, 1, 2]);
+        assert_eq!(plan.cost, 5);
+        assert!((plan.mean - 21.5).abs() < 1e-9);
+        let specs: Vec<String> = specs.iter().map(|spec| spec.to_string()).collect();
+        assert_eq!(plan.describe(&specs), "1 x 1d6 + 2 x 2d8 (5 dice, mean 21.50, variance 23.92)");
+
+        assert_eq!(optimize(&candidates(&["3d6"]), 2, Goal::Mean), None);
+    }
+
+    #[test]
+    fn test_optimize_lowest_variance() {
+        let dice = candidates(&["1d8", "3d4", "1d6"]);
+        let steady = optimize(&dice, 3, Goal::Variance).unwrap();
+        assert_eq!(steady.counts, vec![0, 1, 0]);
+        assert!((steady.variance - 3.75).abs() < 1e-9);
+
+        let highest = optimize(&dice, 3, Goal::Mean).unwrap();
+        assert_eq!(highest.counts, vec![3, 0, 0]);
+        assert!((highest.mean - 13.5).abs() < 1e-9);
+    }
+}
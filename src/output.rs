@@ -0,0 +1,567 @@
+use crate::history;
+use clap::ValueEnum;
+use droll::{Dice, Keep, Reduce, RollResult};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// How rolls are written to standard output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// One line of text per roll
+    #[default]
+    Text,
+    /// A single JSON array holding every roll, written once all of them are done
+    Json,
+    /// One JSON object per line, written as each roll completes
+    Jsonl,
+    /// One JSON object per line like `jsonl`, each with the time of the roll and the
+    /// seed it was rolled with, for audit logs
+    NdjsonWithMeta,
+    /// A CSV table with a header and a row per roll, written once all of them are done
+    Csv,
+    /// A TOML document with a `[[rolls]]` table per roll (needs the `toml-output` feature)
+    #[cfg(feature = "toml-output")]
+    Toml,
+    /// A YAML sequence holding every roll (needs the `yaml-output` feature)
+    #[cfg(feature = "yaml-output")]
+    Yaml,
+}
+
+impl OutputFormat {
+    /// Whether the format is meant for other programs rather than people.
+    pub fn is_structured(self) -> bool {
+        self != OutputFormat::Text
+    }
+
+    /// The format a file is written in, going by its extension: `.json`, `.jsonl`,
+    /// `.csv`, and `.toml` or `.yaml` with their features. Any other file is text.
+    pub fn from_extension(path: &Path) -> OutputFormat {
+        let extension = path.extension().and_then(|extension| extension.to_str()).map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("json") => OutputFormat::Json,
+            Some("jsonl") => OutputFormat::Jsonl,
+            Some("csv") => OutputFormat::Csv,
+            #[cfg(feature = "toml-output")]
+            Some("toml") => OutputFormat::Toml,
+            #[cfg(feature = "yaml-output")]
+            Some("yaml" | "yml") => OutputFormat::Yaml,
+            _ => OutputFormat::Text,
+        }
+    }
+}
+
+/// A parsed specification, written by `--parse-only` for tools that do their own rolling.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct ParsedSpec {
+    pub spec: String,
+    /// The name given to the roll with a `label:` prefix
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Every term of the pool, each rolled on its own and added to the total
+    pub terms: Vec<ParsedTerm>,
+}
+
+impl ParsedSpec {
+    pub fn new(spec: &str, label: Option<&str>, dice: &Dice) -> Self {
+        ParsedSpec {
+            spec: spec.to_string(),
+            label: label.map(String::from),
+            terms: std::iter::once(dice).chain(&dice.extra_terms).map(ParsedTerm::new).collect(),
+        }
+    }
+}
+
+/// One term of a parsed specification, such as `4d6kh3` or `+2`.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct ParsedTerm {
+    pub count: u8,
+    /// The number of sides, or the most a rolled number of sides can make
+    pub sides: u8,
+    pub modifier: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub success_target: Option<i32>,
+    pub success_weight: i32,
+    /// The face that counts as two successes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub double_face: Option<i32>,
+    /// Faces rerolled by `r` notation
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reroll: Vec<i32>,
+    pub explode: bool,
+    pub percentile: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_faces: Option<Vec<i32>>,
+    /// The name of the custom die
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_name: Option<String>,
+    /// Keep notation in its canonical form, with drops written as the matching keep (`kh3`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub die_floor: Option<i32>,
+    /// How the kept dice are combined: `sum`, `product`, `max` or `min`
+    pub reduce: String,
+    pub no_replacement: bool,
+    /// The terms of the roll deciding the sides of dice like `1d(1d6)`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sides_roll: Option<Vec<ParsedTerm>>,
+}
+
+impl ParsedTerm {
+    /// The term of `dice` itself, leaving out any other terms of a mixed pool.
+    pub fn new(dice: &Dice) -> Self {
+        ParsedTerm {
+            count: dice.count,
+            sides: dice.sides,
+            modifier: dice.modifier,
+            success_target: dice.success_target,
+            success_weight: dice.success_weight,
+            double_face: dice.double_face,
+            reroll: dice.reroll.iter().copied().collect(),
+            explode: dice.explode,
+            percentile: dice.percentile,
+            custom_faces: dice.custom_faces.clone(),
+            custom_name: dice.custom_name.clone(),
+            keep: dice.keep.map(|keep| match keep {
+                Keep::Highest(count) => format!("kh{}", count),
+                Keep::Lowest(count) => format!("kl{}", count),
+            }),
+            die_floor: dice.die_floor,
+            reduce: match dice.reduce {
+                Reduce::Sum => "sum",
+                Reduce::Product => "product",
+                Reduce::Max => "max",
+                Reduce::Min => "min",
+            }
+            .to_string(),
+            no_replacement: dice.no_replacement,
+            sides_roll: dice
+                .sides_roll
+                .as_deref()
+                .map(|inner| ParsedSpec::new("", None, inner).terms),
+        }
+    }
+}
+
+/// The whole distribution of a specification, written by `--distribution-json` as
+/// three arrays that line up index by index.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct DistributionRecord {
+    pub spec: String,
+    /// Every possible total, in ascending order
+    pub values: Vec<i32>,
+    /// The probability (0.0 to 1.0) of each total
+    pub probabilities: Vec<f64>,
+    /// The probability that the total is at most each total
+    pub cumulative: Vec<f64>,
+}
+
+impl DistributionRecord {
+    pub fn new(spec: &str, dice: &Dice) -> Self {
+        let (values, probabilities): (Vec<i32>, Vec<f64>) = dice.distribution_map().into_iter().unzip();
+        let cumulative = probabilities
+            .iter()
+            .scan(0.0, |running, &probability| {
+                *running += probability;
+                Some(*running)
+            })
+            .collect();
+        DistributionRecord {
+            spec: spec.to_string(),
+            values,
+            probabilities,
+            cumulative,
+        }
+    }
+}
+
+/// A single roll in the structured output formats.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct RollRecord {
+    pub spec: String,
+    /// The name given to the roll with a `label:` prefix
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// The face of every die rolled, including dice added by explosions
+    pub rolls: Vec<i32>,
+    /// Whether each die in `rolls` counts towards the total, false for the dice dropped
+    /// by keep notation
+    #[serde(default)]
+    pub kept: Vec<bool>,
+    pub modifier: i32,
+    pub total: i32,
+    /// The totals of earlier rolls when the whole pool was rerolled for being too low
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rerolled_from: Vec<i32>,
+    /// The critical damage rolled, when `--crit-floor-average` raised the total to its average
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub floored_from: Option<i32>,
+    /// The probability of every total, included when the histogram is requested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub distribution: Option<BTreeMap<String, f64>>,
+}
+
+impl RollRecord {
+    pub fn new(spec: &str, result: &RollResult, distribution: Option<&BTreeMap<i32, f64>>) -> Self {
+        RollRecord {
+            spec: spec.to_string(),
+            label: None,
+            rolls: result.dice.iter().map(|die| die.face).collect(),
+            kept: result.dice.iter().map(|die| die.kept).collect(),
+            modifier: result.modifier,
+            total: result.total,
+            rerolled_from: Vec::new(),
+            floored_from: None,
+            // Keyed by strings so that every format can write the map
+            distribution: distribution.map(|totals| {
+                totals
+                    .iter()
+                    .map(|(total, &probability)| (total.to_string(), probability))
+                    .collect()
+            }),
+        }
+    }
+
+    /// The record with the label of a labeled roll filled in.
+    pub fn with_label(mut self, label: Option<&str>) -> Self {
+        self.label = label.map(String::from);
+        self
+    }
+
+    /// The record with the totals of earlier rolls filled in, for a rerolled pool.
+    pub fn with_rerolled_from(mut self, totals: &[i32]) -> Self {
+        self.rerolled_from = totals.to_vec();
+        self
+    }
+
+    /// The record with the critical damage rolled filled in, for a total raised to its average.
+    pub fn with_floored_from(mut self, total: Option<i32>) -> Self {
+        self.floored_from = total;
+        self
+    }
+}
+
+/// A roll with the time it was made and the seed it was rolled with, written by
+/// `--format ndjson-with-meta`.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct MetaRecord<'a> {
+    /// Seconds since the Unix epoch when the roll was written
+    pub timestamp: u64,
+    /// The seed of `--seed` or `--seed-show`, or `null` for an unseeded roll
+    pub seed: Option<u64>,
+    #[serde(flatten)]
+    pub roll: &'a RollRecord,
+}
+
+/// A roll reduced to its total, for clients that need nothing else.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct TotalRecord {
+    pub total: i32,
+}
+
+impl From<&RollRecord> for TotalRecord {
+    fn from(record: &RollRecord) -> Self {
+        TotalRecord { total: record.total }
+    }
+}
+
+/// Figures across every roll of a run, written beside the rolls with `--summary-json`.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct RollSummary {
+    /// The totals of every roll added up
+    pub grand_total: i64,
+    /// How many specifications were rolled
+    pub specs: usize,
+    /// The lowest and highest totals rolled
+    pub min: Option<i32>,
+    pub max: Option<i32>,
+}
+
+impl RollSummary {
+    pub fn new(records: &[RollRecord], specs: usize) -> Self {
+        RollSummary {
+            grand_total: records.iter().map(|record| i64::from(record.total)).sum(),
+            specs,
+            min: records.iter().map(|record| record.total).min(),
+            max: records.iter().map(|record| record.total).max(),
+        }
+    }
+}
+
+/// Write a record as a single line of JSON.
+pub fn write_json_line<W: Write, T: Serialize>(writer: &mut W, record: &T) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, record)?;
+    writeln!(writer)
+}
+
+/// Write a record as a single line of JSON with the time now and the seed rolled with.
+pub fn write_meta_line<W: Write>(writer: &mut W, record: &RollRecord, seed: Option<u64>) -> io::Result<()> {
+    let meta = MetaRecord {
+        timestamp: history::unix_timestamp(),
+        seed,
+        roll: record,
+    };
+    write_json_line(writer, &meta)
+}
+
+/// Write every record as one pretty-printed JSON array.
+pub fn write_json_array<W: Write>(writer: &mut W, records: &[RollRecord]) -> io::Result<()> {
+    serde_json::to_writer_pretty(&mut *writer, records)?;
+    writeln!(writer)
+}
+
+/// Write every record and the summary of them as one pretty-printed JSON object,
+/// `{"rolls": [...], "summary": {...}}`.
+pub fn write_json_with_summary<W: Write>(writer: &mut W, records: &[RollRecord], summary: &RollSummary) -> io::Result<()> {
+    #[derive(Serialize)]
+    struct Document<'a> {
+        rolls: &'a [RollRecord],
+        summary: &'a RollSummary,
+    }
+    serde_json::to_writer_pretty(&mut *writer, &Document { rolls: records, summary })?;
+    writeln!(writer)
+}
+
+/// Write only the total of every record, as one compact JSON array of `{"total": N}` objects.
+pub fn write_totals_json<W: Write>(writer: &mut W, records: &[RollRecord]) -> io::Result<()> {
+    let totals: Vec<TotalRecord> = records.iter().map(TotalRecord::from).collect();
+    serde_json::to_writer(&mut *writer, &totals)?;
+    writeln!(writer)
+}
+
+/// Every record as a row of a CSV table, with the faces rolled separated by spaces
+/// in a single column, under a header row unless `header` is false, for appending to
+/// an existing table. The distribution is left out.
+pub fn write_csv<W: Write>(writer: &mut W, records: &[RollRecord], header: bool) -> io::Result<()> {
+    if header {
+        writeln!(writer, "spec,label,rolls,modifier,total")?;
+    }
+    for record in records {
+        let rolls: Vec<String> = record.rolls.iter().map(i32::to_string).collect();
+        writeln!(
+            writer,
+            "{},{},{},{},{}",
+            csv_field(&record.spec),
+            csv_field(record.label.as_deref().unwrap_or_default()),
+            rolls.join(" "),
+            record.modifier,
+            record.total
+        )?;
+    }
+    Ok(())
+}
+
+/// A CSV field, quoted when it holds a comma, quote or line break, with quotes doubled.
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Every record as a TOML document, one `[[rolls]]` table per roll.
+#[cfg(feature = "toml-output")]
+pub fn write_toml<W: Write>(writer: &mut W, records: &[RollRecord]) -> io::Result<()> {
+    #[derive(Serialize)]
+    struct Document<'a> {
+        rolls: &'a [RollRecord],
+    }
+    let text = toml::to_string(&Document { rolls: records }).map_err(io::Error::other)?;
+    writer.write_all(text.as_bytes())
+}
+
+/// Every record as a YAML sequence.
+#[cfg(feature = "yaml-output")]
+pub fn write_yaml<W: Write>(writer: &mut W, records: &[RollRecord]) -> io::Result<()> {
+    serde_yaml::to_writer(writer, records).map_err(io::Error::other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use droll::Dice;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    /// Two rolls, one carrying its distribution, for the round trip tests.
+    #[cfg(any(feature = "toml-output", feature = "yaml-output"))]
+    fn known_records() -> Vec<RollRecord> {
+        let dice = Dice::parse("2d4+1").unwrap();
+        let mut rng = StdRng::seed_from_u64(236);
+        let distribution = dice.distribution_map();
+        vec![
+            RollRecord::new("2d4+1", &dice.roll_detailed_with(&mut rng), Some(&distribution)),
+            RollRecord::new("2d4+1", &dice.roll_detailed_with(&mut rng), None),
+        ]
+    }
+
+    #[cfg(feature = "toml-output")]
+    #[test]
+    fn test_toml_round_trip() {
+        #[derive(Deserialize)]
+        struct Document {
+            rolls: Vec<RollRecord>,
+        }
+        let records = known_records();
+        let mut buffer = Vec::new();
+        write_toml(&mut buffer, &records).unwrap();
+        let document: Document = toml::from_str(&String::from_utf8(buffer).unwrap()).unwrap();
+        assert_eq!(document.rolls, records);
+    }
+
+    #[cfg(feature = "yaml-output")]
+    #[test]
+    fn test_yaml_round_trip() {
+        let records = known_records();
+        let mut buffer = Vec::new();
+        write_yaml(&mut buffer, &records).unwrap();
+        let parsed: Vec<RollRecord> = serde_yaml::from_slice(&buffer).unwrap();
+        assert_eq!(parsed, records);
+    }
+
+    #[test]
+    fn test_distribution_record_arrays_line_up() {
+        let record = DistributionRecord::new("2d6", &Dice::parse("2d6").unwrap());
+        let json = serde_json::to_value(&record).unwrap();
+        let values = json["values"].as_array().unwrap();
+        let probabilities = json["probabilities"].as_array().unwrap();
+        let cumulative = json["cumulative"].as_array().unwrap();
+        assert_eq!(values.len(), 11);
+        assert_eq!((probabilities.len(), cumulative.len()), (11, 11));
+        assert_eq!((values[0].as_i64(), values[10].as_i64()), (Some(2), Some(12)));
+        assert!((probabilities[5].as_f64().unwrap() - 6.0 / 36.0).abs() < 1e-12);
+
+        let mut running = 0.0;
+        for (probability, at_most) in probabilities.iter().zip(cumulative) {
+            running += probability.as_f64().unwrap();
+            assert!((at_most.as_f64().unwrap() - running).abs() < 1e-12);
+        }
+        assert!((running - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_format_from_extension() {
+        assert_eq!(OutputFormat::from_extension(Path::new("results.json")), OutputFormat::Json);
+        assert_eq!(OutputFormat::from_extension(Path::new("out/results.CSV")), OutputFormat::Csv);
+        assert_eq!(OutputFormat::from_extension(Path::new("rolls.jsonl")), OutputFormat::Jsonl);
+        assert_eq!(OutputFormat::from_extension(Path::new("rolls.txt")), OutputFormat::Text);
+        assert_eq!(OutputFormat::from_extension(Path::new("rolls")), OutputFormat::Text);
+    }
+
+    #[test]
+    fn test_csv_rows() {
+        let dice = Dice::parse("2d6+1").unwrap();
+        let result = dice.roll_detailed_with(&mut StdRng::seed_from_u64(292));
+        let records = [
+            RollRecord::new("2d6+1", &result, None),
+            RollRecord::new("fire, \"big\": 2d6+1", &result, None).with_label(Some("fire, \"big\"")),
+        ];
+        let mut buffer = Vec::new();
+        write_csv(&mut buffer, &records, true).unwrap();
+        let text = String::from_utf8(buffer).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        let rolls = format!("{} {}", result.dice[0].face, result.dice[1].face);
+        assert_eq!(lines[0], "spec,label,rolls,modifier,total");
+        assert_eq!(lines[1], format!("2d6+1,,{},1,{}", rolls, result.total));
+        assert_eq!(
+            lines[2],
+            format!("\"fire, \"\"big\"\": 2d6+1\",\"fire, \"\"big\"\"\",{},1,{}", rolls, result.total)
+        );
+
+        // Without the header only the rows are written
+        let mut buffer = Vec::new();
+        write_csv(&mut buffer, &records, false).unwrap();
+        let text = String::from_utf8(buffer).unwrap();
+        assert_eq!(text.lines().collect::<Vec<_>>(), lines[1..]);
+    }
+
+    #[test]
+    fn test_totals_json_has_only_totals() {
+        let dice = Dice::parse("4d6kh3+2").unwrap();
+        let mut rng = StdRng::seed_from_u64(298);
+        let records: Vec<RollRecord> = (0..3)
+            .map(|_| RollRecord::new("4d6kh3+2", &dice.roll_detailed_with(&mut rng), None).with_label(Some("str")))
+            .collect();
+        let mut buffer = Vec::new();
+        write_totals_json(&mut buffer, &records).unwrap();
+        let expected: Vec<String> = records.iter().map(|record| format!("{{\"total\":{}}}", record.total)).collect();
+        assert_eq!(String::from_utf8(buffer).unwrap(), format!("[{}]\n", expected.join(",")));
+
+        let mut buffer = Vec::new();
+        write_json_line(&mut buffer, &TotalRecord::from(&records[0])).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&buffer).unwrap();
+        let fields: Vec<&String> = value.as_object().unwrap().keys().collect();
+        assert_eq!(fields, vec!["total"]);
+    }
+
+    #[test]
+    fn test_summary_across_specs() {
+        let mut rng = StdRng::seed_from_u64(314);
+        let records: Vec<RollRecord> = ["1d20+5", "2d6", "2d6", "1d4-3"]
+            .iter()
+            .map(|spec| RollRecord::new(spec, &Dice::parse(spec).unwrap().roll_detailed_with(&mut rng), None))
+            .collect();
+        let totals: Vec<i32> = records.iter().map(|record| record.total).collect();
+        let summary = RollSummary::new(&records, 3);
+        assert_eq!(summary.grand_total, totals.iter().map(|&total| i64::from(total)).sum::<i64>());
+        assert_eq!(summary.min, totals.iter().copied().min());
+        assert_eq!(summary.max, totals.iter().copied().max());
+
+        let mut buffer = Vec::new();
+        write_json_with_summary(&mut buffer, &records, &summary).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&buffer).unwrap();
+        assert_eq!(value["rolls"].as_array().unwrap().len(), 4);
+        assert_eq!(value["rolls"][3]["spec"], "1d4-3");
+        assert_eq!(serde_json::from_value::<RollSummary>(value["summary"].clone()).unwrap(), summary);
+        assert_eq!(value["summary"]["specs"], 3);
+
+        assert_eq!(RollSummary::new(&[], 0), RollSummary { grand_total: 0, specs: 0, min: None, max: None });
+    }
+
+    #[test]
+    fn test_json_lines_one_object_per_roll() {
+        let dice = Dice::parse("3d6+1").unwrap();
+        let mut rng = StdRng::seed_from_u64(228);
+        let mut buffer = Vec::new();
+        for _ in 0..25 {
+            let result = dice.roll_detailed_with(&mut rng);
+            write_json_line(&mut buffer, &RollRecord::new("3d6+1", &result, None)).unwrap();
+        }
+
+        let text = String::from_utf8(buffer).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 25);
+        for line in lines {
+            let value: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!(value["spec"], "3d6+1");
+            assert_eq!(value["rolls"].as_array().unwrap().len(), 3);
+            assert!(value.get("distribution").is_none());
+        }
+    }
+
+    #[test]
+    fn test_meta_lines_carry_timestamp_and_seed() {
+        let dice = Dice::parse("2d6").unwrap();
+        let mut rng = StdRng::seed_from_u64(326);
+        let before = history::unix_timestamp();
+        let mut buffer = Vec::new();
+        for seed in [Some(326), None] {
+            let record = RollRecord::new("2d6", &dice.roll_detailed_with(&mut rng), None).with_label(Some("fire"));
+            write_meta_line(&mut buffer, &record, seed).unwrap();
+        }
+
+        let text = String::from_utf8(buffer).unwrap();
+        let lines: Vec<serde_json::Value> = text.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        for line in &lines {
+            assert!(line["timestamp"].as_u64().unwrap() >= before);
+            assert_eq!((line["spec"].as_str(), line["label"].as_str()), (Some("2d6"), Some("fire")));
+            assert_eq!(line["rolls"].as_array().unwrap().len(), 2);
+        }
+        assert_eq!(lines[0]["seed"], 326);
+        assert!(lines[1]["seed"].is_null());
+    }
+}
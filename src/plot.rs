@@ -0,0 +1,94 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// The size of the plot in pixels.
+const WIDTH: f64 = 640.0;
+const HEIGHT: f64 = 400.0;
+/// The space left around the plotted area for the title and axis labels.
+const MARGIN: f64 = 50.0;
+/// The colour of each series, in order.
+const COLOURS: [&str; 2] = ["#1f77b4", "#d62728"];
+
+/// Render the probability distributions as overlaid curves in an SVG image, with a
+/// title naming every series (e.g. `2d6 vs 1d12`) and a legend.
+pub fn comparison_svg(series: &[(&str, BTreeMap<i32, f64>)]) -> String {
+    let totals = series.iter().flat_map(|(_, distribution)| distribution.keys().copied());
+    let (low, high) = totals.fold((i32::MAX, i32::MIN), |(low, high), total| (low.min(total), high.max(total)));
+    let peak = series
+        .iter()
+        .flat_map(|(_, distribution)| distribution.values().copied())
+        .fold(0.0, f64::max);
+    let span = f64::from(high.saturating_sub(low).max(1));
+    let x = |total: i32| MARGIN + f64::from(total - low) / span * (WIDTH - 2.0 * MARGIN);
+    let y = |probability: f64| HEIGHT - MARGIN - probability / peak.max(f64::MIN_POSITIVE) * (HEIGHT - 2.0 * MARGIN);
+
+    let title: Vec<&str> = series.iter().map(|(name, _)| *name).collect();
+    let title = escape(&title.join(" vs "));
+    let mut svg = String::new();
+    // Writing to a String cannot fail
+    let _ = writeln!(svg, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{WIDTH}" height="{HEIGHT}" viewBox="0 0 {WIDTH} {HEIGHT}">"#);
+    let _ = writeln!(svg, "<title>{}</title>", title);
+    let _ = writeln!(svg, r#"<rect width="100%" height="100%" fill="white"/>"#);
+    let _ = writeln!(svg, r#"<text x="{}" y="30" text-anchor="middle" font-size="18">{}</text>"#, WIDTH / 2.0, title);
+    let _ = writeln!(
+        svg,
+        r#"<path d="M {m} {m} V {b} H {r}" fill="none" stroke="black"/>"#,
+        m = MARGIN,
+        b = HEIGHT - MARGIN,
+        r = WIDTH - MARGIN
+    );
+    let _ = writeln!(svg, r#"<text x="{}" y="{}" text-anchor="middle">{}</text>"#, x(low), HEIGHT - MARGIN + 20.0, low);
+    let _ = writeln!(svg, r#"<text x="{}" y="{}" text-anchor="middle">{}</text>"#, x(high), HEIGHT - MARGIN + 20.0, high);
+    let _ = writeln!(svg, r#"<text x="{}" y="{}" text-anchor="end">{:.1}%</text>"#, MARGIN - 5.0, MARGIN + 5.0, peak * 100.0);
+
+    for (index, (name, distribution)) in series.iter().enumerate() {
+        let colour = COLOURS[index % COLOURS.len()];
+        let points: Vec<String> = distribution
+            .iter()
+            .map(|(&total, &probability)| format!("{:.1},{:.1}", x(total), y(probability)))
+            .collect();
+        let _ = writeln!(
+            svg,
+            r#"<polyline points="{}" fill="none" stroke="{}" stroke-width="2"/>"#,
+            points.join(" "),
+            colour
+        );
+        let legend_y = MARGIN + 20.0 * index as f64;
+        let _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{}" text-anchor="end" fill="{}">{}</text>"#,
+            WIDTH - MARGIN,
+            legend_y,
+            colour,
+            escape(name)
+        );
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Escape the characters that are special in SVG text.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use droll::Dice;
+
+    #[test]
+    fn test_comparison_plot_file() {
+        let series = [
+            ("2d6", Dice::parse("2d6").unwrap().distribution_map()),
+            ("1d12", Dice::parse("1d12").unwrap().distribution_map()),
+        ];
+        let path = std::env::temp_dir().join(format!("droll-plot-{}.svg", std::process::id()));
+        std::fs::write(&path, comparison_svg(&series)).unwrap();
+
+        let svg = std::fs::read_to_string(&path).unwrap();
+        assert!(svg.contains("<title>2d6 vs 1d12</title>"));
+        assert_eq!(svg.matches("<polyline").count(), 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,88 @@
+use crate::DiceError;
+use std::collections::BTreeMap;
+
+/// Named custom dice that specifications can use in place of the sides, such as
+/// `fate = [-1, 0, 1]` rolled as `4dfate`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DieRegistry {
+    dice: BTreeMap<String, Vec<i32>>,
+}
+
+impl DieRegistry {
+    /// An empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a custom die showing the given faces, replacing any die with the same name.
+    ///
+    /// Names are case-insensitive, start with a letter and contain only letters,
+    /// digits and underscores. A die needs between 1 and 255 faces; repeating a
+    /// value makes it more likely.
+    pub fn insert(&mut self, name: &str, faces: Vec<i32>) -> Result<(), DiceError> {
+        let invalid = |reason| DiceError::InvalidCustomDie {
+            name: name.to_string(),
+            reason,
+        };
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return Err(invalid("the name must start with a letter"));
+        }
+        if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(invalid("the name may only contain letters, digits and underscores"));
+        }
+        if faces.is_empty() {
+            return Err(invalid("the die needs at least one face"));
+        }
+        if faces.len() > usize::from(u8::MAX) {
+            return Err(invalid("the die can have at most 255 faces"));
+        }
+        self.dice.insert(name.to_lowercase(), faces);
+        Ok(())
+    }
+
+    /// The faces of the named die, if it is registered.
+    pub fn get(&self, name: &str) -> Option<&[i32]> {
+        self.dice.get(&name.to_lowercase()).map(Vec::as_slice)
+    }
+
+    /// The names of every registered die, in alphabetical order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.dice.keys().map(String::as_str)
+    }
+
+    /// The registered die with the longest name that `input` starts with, so that
+    /// `4dfate+1` finds `fate` and a die named `d6x` wins over `d6`.
+    pub(crate) fn longest_prefix(&self, input: &str) -> Option<(&str, &[i32])> {
+        self.dice
+            .iter()
+            .filter(|(name, _)| input.starts_with(name.as_str()))
+            .max_by_key(|(name, _)| name.len())
+            .map(|(name, faces)| (name.as_str(), faces.as_slice()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_rejects_invalid_dice() {
+        let mut registry = DieRegistry::new();
+        assert!(registry.insert("3fate", vec![1]).is_err());
+        assert!(registry.insert("fa-te", vec![1]).is_err());
+        assert!(registry.insert("fate", vec![]).is_err());
+        assert!(registry.insert("big", vec![1; 256]).is_err());
+        assert!(registry.insert("Fate", vec![-1, 0, 1]).is_ok());
+        assert_eq!(registry.get("fate"), Some(&[-1, 0, 1][..]));
+    }
+
+    #[test]
+    fn test_longest_prefix_prefers_longer_names() {
+        let mut registry = DieRegistry::new();
+        registry.insert("avg", vec![3]).unwrap();
+        registry.insert("avg6", vec![2, 3, 3, 4, 4, 5]).unwrap();
+        assert_eq!(registry.longest_prefix("avg6+1").map(|(name, _)| name), Some("avg6"));
+        assert_eq!(registry.longest_prefix("avg+1").map(|(name, _)| name), Some("avg"));
+        assert_eq!(registry.longest_prefix("fate"), None);
+    }
+}
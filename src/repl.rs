@@ -0,0 +1,475 @@
+use crate::Condition;
+use droll::Dice;
+use rand::Rng;
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// The prompt shown before each line when `--prompt` is not given.
+pub const DEFAULT_PROMPT: &str = "droll> ";
+
+/// Where the interactive history is kept when `--repl-history` is not given:
+/// `$XDG_STATE_HOME/droll/history`, falling back to `~/.local/state/droll/history`.
+pub fn default_history_path() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local").join("state"),
+    };
+    Some(base.join("droll").join("history"))
+}
+
+/// How many earlier states of the bank `undo` can go back through.
+const UNDO_DEPTH: usize = 20;
+
+/// The pity timer of `--pity`: once `window` rolls in a row come up below
+/// `threshold`, the next roll is drawn only from the totals that reach it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pity {
+    threshold: i32,
+    window: u32,
+    /// How many rolls in a row have come up below the threshold
+    streak: u32,
+}
+
+impl Pity {
+    pub fn new(threshold: i32, window: u32) -> Self {
+        Pity {
+            threshold,
+            window,
+            streak: 0,
+        }
+    }
+
+    /// Roll the dice, guaranteeing the threshold when the streak of low rolls is
+    /// long enough. Dice that can never reach the threshold get their highest total.
+    pub fn roll<R: Rng + ?Sized>(&mut self, dice: &Dice, rng: &mut R) -> i32 {
+        if self.streak < self.window {
+            let total = dice.roll_with(rng);
+            self.streak = if total < self.threshold { self.streak + 1 } else { 0 };
+            return total;
+        }
+        self.streak = 0;
+        let floor = self.threshold.min(dice.max_roll());
+        let reaching: Vec<(i32, f64)> = dice.distribution_map().into_iter().filter(|&(total, _)| total >= floor).collect();
+        let mut draw = rng.random::<f64>() * reaching.iter().map(|&(_, probability)| probability).sum::<f64>();
+        for &(total, probability) in &reaching {
+            if draw < probability {
+                return total;
+            }
+            draw -= probability;
+        }
+        // Rounding can leave the draw just past the last total
+        reaching.last().map_or(floor, |&(total, _)| total)
+    }
+}
+
+/// The press-your-luck game of `--press-your-luck`: each `roll` adds a roll of the dice
+/// to a running total, a roll that meets the bust condition loses all of it, and
+/// `bank` locks the running total in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PressYourLuck {
+    dice: Dice,
+    bust: Condition,
+    /// The total built up since the last bank or bust
+    pub running: i32,
+    /// The total locked in by every `bank` so far
+    pub banked: i32,
+}
+
+impl PressYourLuck {
+    pub fn new(dice: Dice, bust: Condition) -> Self {
+        PressYourLuck {
+            dice,
+            bust,
+            running: 0,
+            banked: 0,
+        }
+    }
+
+    /// Roll the dice once, adding the total to the running total or, on a bust,
+    /// losing the running total.
+    fn roll<R: Rng + ?Sized>(&mut self, rng: &mut R) -> String {
+        let total = self.dice.roll_with(rng);
+        if self.bust.matches(total) {
+            let lost = std::mem::take(&mut self.running);
+            return format!("Rolled {}: bust, lost {}; {} banked", total, lost, self.banked);
+        }
+        self.running += total;
+        format!("Rolled {}; running total {}, {} banked", total, self.running, self.banked)
+    }
+
+    /// Lock the running total in, starting a new one.
+    fn bank(&mut self) -> String {
+        let running = std::mem::take(&mut self.running);
+        self.banked += running;
+        format!("Banked {}; {} banked", running, self.banked)
+    }
+}
+
+/// A line editor with the history from `path` loaded, so the up arrow recalls the
+/// specifications rolled in earlier sessions. A missing history file is just an
+/// empty history.
+pub fn editor_with_history(path: Option<&Path>) -> rustyline::Result<DefaultEditor> {
+    let mut editor = DefaultEditor::new()?;
+    if let Some(path) = path
+        && path.exists()
+    {
+        editor.load_history(path)?;
+    }
+    Ok(editor)
+}
+
+/// What an interactive session carries from one line to the next.
+#[derive(Debug, Default)]
+pub struct Session {
+    /// The pity timer of `--pity`, with its streak of low rolls
+    pub pity: Option<Pity>,
+    /// The faces of the dice stored with `bank` and not yet spent, oldest first
+    pub bank: Vec<i32>,
+    /// Roll a specification again when it matches its previous total, as with `--no-repeat`
+    pub no_repeat: bool,
+    /// The previous total of each specification rolled, kept for `no_repeat`
+    pub last_totals: BTreeMap<String, i32>,
+    /// The bank as it was before each recent `bank` or `spend`, newest last, for `undo`
+    pub undo: Vec<Vec<i32>>,
+    /// The game of `--press-your-luck`, which takes over `roll` and `bank` alone
+    pub press_your_luck: Option<PressYourLuck>,
+    /// The rerolls left for `reroll`, when the session has a budget of them
+    pub rerolls: Option<u32>,
+    /// The specification rolled last, which `reroll` rolls again
+    pub last_roll: Option<String>,
+}
+
+impl Session {
+    /// Describe the dice in the bank, e.g. `2 dice in the bank: 4, 6`.
+    fn bank_contents(&self) -> String {
+        if self.bank.is_empty() {
+            return "The bank is empty".to_string();
+        }
+        let noun = if self.bank.len() == 1 { "die" } else { "dice" };
+        format!("{} {} in the bank: {}", self.bank.len(), noun, join_faces(&self.bank))
+    }
+
+    /// Describe the rerolls left, e.g. `2 rerolls left`.
+    fn rerolls_left(&self) -> String {
+        let left = self.rerolls.unwrap_or(0);
+        format!("{} {} left", left, if left == 1 { "reroll" } else { "rerolls" })
+    }
+
+    /// Remember the bank as it is now, so that `undo` can restore it.
+    fn save_bank(&mut self) {
+        if self.undo.len() == UNDO_DEPTH {
+            self.undo.remove(0);
+        }
+        self.undo.push(self.bank.clone());
+    }
+}
+
+/// Faces separated by commas, e.g. `4, 2, 6`.
+fn join_faces(faces: &[i32]) -> String {
+    faces.iter().map(i32::to_string).collect::<Vec<_>>().join(", ")
+}
+
+/// Carry out one line of input, describing the result or the error. A line is a
+/// specification to roll, `bank SPEC` to roll dice and store their kept faces,
+/// `spend N` to take the N oldest dice back out of the bank, `bank` alone to list
+/// what is stored, `undo` to put the bank back as it was before the last `bank`
+/// or `spend`, or `reroll` to spend one of the session's rerolls rolling the last
+/// specification again. In a press-your-luck session, `roll` rolls its dice and `bank` alone
+/// locks in the running total instead.
+pub fn evaluate<R: Rng + ?Sized>(
+    line: &str,
+    parse: impl Fn(&str) -> Result<Dice, String>,
+    session: &mut Session,
+    rng: &mut R,
+) -> Result<String, String> {
+    if let Some(game) = session.press_your_luck.as_mut() {
+        match line {
+            "roll" => return Ok(game.roll(rng)),
+            "bank" => return Ok(game.bank()),
+            _ => {}
+        }
+    }
+    let (command, argument) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    match (command, argument.trim()) {
+        ("bank", "") => Ok(session.bank_contents()),
+        ("bank", spec) => {
+            let result = parse(spec)?.roll_detailed_with(rng);
+            let faces: Vec<i32> = result.dice.iter().filter(|die| die.kept).map(|die| die.face).collect();
+            session.save_bank();
+            session.bank.extend(&faces);
+            Ok(format!("Banked {}; {}", join_faces(&faces), session.bank_contents()))
+        }
+        ("spend", count) => {
+            let count: usize = count
+                .parse()
+                .map_err(|_| format!("Invalid spend '{}': give the number of dice to spend (e.g. 'spend 2')", count))?;
+            if count > session.bank.len() {
+                return Err(format!("Cannot spend {} dice: {}", count, session.bank_contents().to_lowercase()));
+            }
+            session.save_bank();
+            let spent: Vec<i32> = session.bank.drain(..count).collect();
+            Ok(format!("Spent {}; {}", join_faces(&spent), session.bank_contents()))
+        }
+        ("undo", "") => {
+            session.bank = session.undo.pop().ok_or("Nothing to undo")?;
+            Ok(format!("Undone; {}", session.bank_contents()))
+        }
+        ("reroll", "") => {
+            let left = session.rerolls.ok_or("No rerolls to spend: give the session a budget with --reroll-budget")?;
+            if left == 0 {
+                return Err("Cannot reroll: no rerolls left".to_string());
+            }
+            let spec = session.last_roll.clone().ok_or("Nothing to reroll")?;
+            let total = roll_spec(&spec, parse(&spec)?, session, rng)?;
+            session.rerolls = Some(left - 1);
+            Ok(format!("Rerolled {}: {}; {}", spec, total, session.rerolls_left()))
+        }
+        _ => {
+            let total = roll_spec(line, parse(line)?, session, rng)?;
+            session.last_roll = Some(line.to_string());
+            Ok(total.to_string())
+        }
+    }
+}
+
+/// Roll a specification typed at the prompt, through the pity timer or avoiding its
+/// previous total when the session asks for either.
+fn roll_spec<R: Rng + ?Sized>(line: &str, dice: Dice, session: &mut Session, rng: &mut R) -> Result<i32, String> {
+    if !session.no_repeat {
+        return Ok(match session.pity.as_mut() {
+            Some(pity) => pity.roll(&dice, rng),
+            None => dice.roll_with(rng),
+        });
+    }
+    if dice.min_roll() == dice.max_roll() {
+        return Err(format!("Cannot avoid repeating '{}': it always rolls {}", line, dice.min_roll()));
+    }
+    let total = dice.roll_avoiding(session.last_totals.get(line).copied(), rng);
+    session.last_totals.insert(line.to_string(), total);
+    Ok(total)
+}
+
+/// Read specifications and bank commands one line at a time, carrying out each in
+/// `session` (rolling through its pity timer, if there is one) until `quit`, `exit`,
+/// Ctrl-C or end of input. Every line is added to the history, which is saved to
+/// `history_path` on the way out.
+pub fn run<R: Rng + ?Sized>(
+    prompt: &str,
+    history_path: Option<&Path>,
+    parse: impl Fn(&str) -> Result<Dice, String>,
+    mut session: Session,
+    rng: &mut R,
+) -> rustyline::Result<()> {
+    let mut editor = editor_with_history(history_path)?;
+    loop {
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+            Err(err) => return Err(err),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line == "quit" || line == "exit" {
+            break;
+        }
+        editor.add_history_entry(line)?;
+        match evaluate(line, &parse, &mut session, rng) {
+            Ok(output) => println!("{}", output),
+            Err(err) => eprintln!("Error: {}", err),
+        }
+    }
+
+    if let Some(path) = history_path {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        editor.save_history(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn test_history_loads_and_recalled_spec_rerolls() {
+        let path = env::temp_dir().join(format!("droll-repl-history-{}.txt", std::process::id()));
+        let mut editor = DefaultEditor::new().unwrap();
+        editor.add_history_entry("1d20+3").unwrap();
+        editor.add_history_entry("2d6").unwrap();
+        editor.save_history(&path).unwrap();
+
+        let editor = editor_with_history(Some(&path)).unwrap();
+        let entries: Vec<&String> = editor.history().iter().collect();
+        assert_eq!(entries, vec!["1d20+3", "2d6"]);
+
+        // Recalling the last line with the up arrow rolls it again
+        let recalled = entries.last().unwrap();
+        let parse = |spec: &str| Dice::parse(spec).map_err(|err| err.to_string());
+        let mut rng = StdRng::seed_from_u64(251);
+        let total: i32 = evaluate(recalled, parse, &mut Session::default(), &mut rng).unwrap().parse().unwrap();
+        assert!((2..=12).contains(&total));
+        assert!(evaluate("2d", parse, &mut Session::default(), &mut rng).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(editor_with_history(Some(&path)).unwrap().history().iter().count(), 0);
+    }
+
+    #[test]
+    fn test_bank_and_spend() {
+        let parse = |spec: &str| Dice::parse(spec).map_err(|err| err.to_string());
+        let mut rng = StdRng::seed_from_u64(289);
+        let mut session = Session::default();
+        assert_eq!(evaluate("bank", parse, &mut session, &mut rng).unwrap(), "The bank is empty");
+
+        let banked = evaluate("bank 3d6", parse, &mut session, &mut rng).unwrap();
+        assert_eq!(session.bank.len(), 3);
+        assert!(session.bank.iter().all(|face| (1..=6).contains(face)));
+        assert!(banked.ends_with(&format!("3 dice in the bank: {}", join_faces(&session.bank))), "{}", banked);
+        // Only the kept dice are stored
+        evaluate("bank 4d6kh1", parse, &mut session, &mut rng).unwrap();
+        assert_eq!(session.bank.len(), 4);
+
+        let stored = session.bank.clone();
+        let spent = evaluate("spend 2", parse, &mut session, &mut rng).unwrap();
+        assert_eq!(session.bank, stored[2..]);
+        assert_eq!(spent, format!("Spent {}; 2 dice in the bank: {}", join_faces(&stored[..2]), join_faces(&stored[2..])));
+
+        assert!(evaluate("spend 3", parse, &mut session, &mut rng).is_err());
+        assert!(evaluate("spend two", parse, &mut session, &mut rng).is_err());
+        assert_eq!(session.bank.len(), 2);
+        evaluate("spend 2", parse, &mut session, &mut rng).unwrap();
+        assert!(session.bank.is_empty());
+    }
+
+    #[test]
+    fn test_undo_restores_bank() {
+        let parse = |spec: &str| Dice::parse(spec).map_err(|err| err.to_string());
+        let mut rng = StdRng::seed_from_u64(310);
+        let mut session = Session::default();
+        assert_eq!(evaluate("undo", parse, &mut session, &mut rng), Err("Nothing to undo".to_string()));
+
+        evaluate("bank 3d6", parse, &mut session, &mut rng).unwrap();
+        let stored = session.bank.clone();
+        evaluate("spend 2", parse, &mut session, &mut rng).unwrap();
+        assert_eq!(session.bank.len(), 1);
+        assert_eq!(
+            evaluate("undo", parse, &mut session, &mut rng).unwrap(),
+            format!("Undone; 3 dice in the bank: {}", join_faces(&stored))
+        );
+        assert_eq!(session.bank, stored);
+        // Rolls and failed commands leave nothing to undo
+        evaluate("1d20", parse, &mut session, &mut rng).unwrap();
+        assert!(evaluate("spend 5", parse, &mut session, &mut rng).is_err());
+        evaluate("undo", parse, &mut session, &mut rng).unwrap();
+        assert!(session.bank.is_empty());
+        assert!(evaluate("undo", parse, &mut session, &mut rng).is_err());
+    }
+
+    #[test]
+    fn test_press_your_luck_busts_and_banks() {
+        let parse = |spec: &str| Dice::parse(spec).map_err(|err| err.to_string());
+        let mut rng = StdRng::seed_from_u64(325);
+        let game = PressYourLuck::new(Dice::parse("1d6").unwrap(), Condition::parse("=1").unwrap());
+        let mut session = Session { press_your_luck: Some(game), ..Default::default() };
+        let (mut running, mut banked, mut busts) = (0, 0, 0);
+        for turn in 0..200 {
+            if turn % 3 == 2 {
+                let output = evaluate("bank", parse, &mut session, &mut rng).unwrap();
+                assert_eq!(output, format!("Banked {}; {} banked", running, banked + running));
+                banked += running;
+                running = 0;
+            } else {
+                let output = evaluate("roll", parse, &mut session, &mut rng).unwrap();
+                let total: i32 = output["Rolled ".len()..].split([':', ';']).next().unwrap().parse().unwrap();
+                if total == 1 {
+                    assert_eq!(output, format!("Rolled 1: bust, lost {}; {} banked", running, banked));
+                    running = 0;
+                    busts += 1;
+                } else {
+                    running += total;
+                }
+            }
+            let game = session.press_your_luck.as_ref().unwrap();
+            assert_eq!((game.running, game.banked), (running, banked));
+        }
+        assert!(busts > 0 && banked > 0);
+
+        // Without a game, 'roll' is not a command and 'bank' lists the dice bank
+        let mut session = Session::default();
+        assert!(evaluate("roll", parse, &mut session, &mut rng).is_err());
+        assert_eq!(evaluate("bank", parse, &mut session, &mut rng).unwrap(), "The bank is empty");
+    }
+
+    #[test]
+    fn test_reroll_spends_budget() {
+        let parse = |spec: &str| Dice::parse(spec).map_err(|err| err.to_string());
+        let mut rng = StdRng::seed_from_u64(335);
+        let mut session = Session { rerolls: Some(2), ..Default::default() };
+        assert_eq!(evaluate("reroll", parse, &mut session, &mut rng), Err("Nothing to reroll".to_string()));
+        assert_eq!(session.rerolls, Some(2));
+
+        evaluate("2d6", parse, &mut session, &mut rng).unwrap();
+        let output = evaluate("reroll", parse, &mut session, &mut rng).unwrap();
+        assert!(output.starts_with("Rerolled 2d6: ") && output.ends_with("; 1 reroll left"), "{}", output);
+        let output = evaluate("reroll", parse, &mut session, &mut rng).unwrap();
+        assert!(output.ends_with("; 0 rerolls left"), "{}", output);
+        assert_eq!(session.rerolls, Some(0));
+        assert_eq!(
+            evaluate("reroll", parse, &mut session, &mut rng),
+            Err("Cannot reroll: no rerolls left".to_string())
+        );
+
+        let mut session = Session::default();
+        evaluate("1d20", parse, &mut session, &mut rng).unwrap();
+        assert!(evaluate("reroll", parse, &mut session, &mut rng).is_err());
+    }
+
+    #[test]
+    fn test_no_repeat_suppresses_identical_totals() {
+        let parse = |spec: &str| Dice::parse(spec).map_err(|err| err.to_string());
+        let mut rng = StdRng::seed_from_u64(297);
+        let mut session = Session { no_repeat: true, ..Default::default() };
+        let mut previous = String::new();
+        for _ in 0..200 {
+            let total = evaluate("1d3", parse, &mut session, &mut rng).unwrap();
+            assert_ne!(total, previous);
+            previous = total;
+        }
+        // Each specification is tracked on its own
+        evaluate("1d2", parse, &mut session, &mut rng).unwrap();
+        assert_eq!(session.last_totals.get("1d3"), Some(&previous.parse().unwrap()));
+        assert!(evaluate("1d1+2", parse, &mut session, &mut rng).is_err());
+    }
+
+    #[test]
+    fn test_pity_guarantees_threshold_after_streak() {
+        let dice = Dice::parse("1d20").unwrap();
+        let mut rng = StdRng::seed_from_u64(277);
+        let mut pity = Pity::new(15, 3);
+        let mut streak = 0;
+        for _ in 0..2000 {
+            let total = pity.roll(&dice, &mut rng);
+            if streak == 3 {
+                assert!(total >= 15, "roll after the streak came up {}", total);
+                streak = 0;
+            } else if total < 15 {
+                streak += 1;
+            } else {
+                streak = 0;
+            }
+        }
+
+        // A threshold the dice cannot reach gives their highest total instead
+        let mut pity = Pity::new(50, 0);
+        assert!((0..20).all(|_| pity.roll(&dice, &mut rng) == 20));
+    }
+}
@@ -0,0 +1,65 @@
+use rand::RngCore;
+use std::cell::RefCell;
+
+thread_local! {
+    static OVERRIDE: RefCell<Option<Box<dyn RngCore>>> = const { RefCell::new(None) };
+}
+
+/// Make [`Dice::roll`](crate::Dice::roll) draw from `rng` on this thread instead of the
+/// thread-local random number generator, until [`clear_rng_override`] is called.
+///
+/// This is only compiled with the `test-rng` feature, for crates testing code that
+/// rolls through droll without threading a seed everywhere. Rolls given a random
+/// number generator of their own are unaffected.
+///
+/// ```
+/// use droll::{Dice, ScriptedRng, clear_rng_override, set_rng_override};
+///
+/// let dice = Dice::parse("2d6").unwrap();
+/// set_rng_override(ScriptedRng::new([3, 5]));
+/// assert_eq!(dice.roll().unwrap().total, 8);
+/// clear_rng_override();
+/// ```
+pub fn set_rng_override(rng: impl RngCore + 'static) {
+    OVERRIDE.with(|current| *current.borrow_mut() = Some(Box::new(rng)));
+}
+
+/// Go back to rolling with the thread-local random number generator on this thread.
+pub fn clear_rng_override() {
+    OVERRIDE.with(|current| *current.borrow_mut() = None);
+}
+
+/// Run `f` with the random number generator set by [`set_rng_override`], or return
+/// `None` when there is none.
+pub(crate) fn with_rng_override<T>(f: impl FnOnce(&mut dyn RngCore) -> T) -> Option<T> {
+    OVERRIDE.with(|current| current.borrow_mut().as_mut().map(|rng| f(rng.as_mut())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Dice, ScriptedRng};
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn test_override_makes_roll_deterministic() {
+        let dice = Dice::parse("4d6+1").unwrap();
+        let totals = |seed| {
+            set_rng_override(StdRng::seed_from_u64(seed));
+            let totals: Vec<i32> = (0..10).map(|_| dice.roll().unwrap().total).collect();
+            clear_rng_override();
+            totals
+        };
+        assert_eq!(totals(7), totals(7));
+        assert_eq!(totals(7), (0..10).scan(StdRng::seed_from_u64(7), |rng, _| Some(dice.roll_with(rng))).collect::<Vec<_>>());
+
+        let dice = Dice::parse("100d6").unwrap();
+        set_rng_override(ScriptedRng::new([1; 100]));
+        assert_eq!(dice.roll().unwrap().total, 100);
+        clear_rng_override();
+        // Every die showing a 1 again would take odds of 6^-100
+        assert_ne!(dice.roll().unwrap().total, 100);
+        assert!(with_rng_override(|_| ()).is_none());
+    }
+}
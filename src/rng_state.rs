@@ -0,0 +1,62 @@
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Where a ChaCha random number generator is in its sequence, saved by
+/// `--dump-rng-state` so that `--load-rng-state` carries on from the same place.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct RngState {
+    seed: [u8; 32],
+    stream: u64,
+    /// The number of 32-bit words already drawn from the stream
+    word_pos: u128,
+}
+
+/// Write the state of the generator to a JSON file, replacing any file already there.
+pub fn save(rng: &ChaCha12Rng, path: &Path) -> io::Result<()> {
+    let state = RngState {
+        seed: rng.get_seed(),
+        stream: rng.get_stream(),
+        word_pos: rng.get_word_pos(),
+    };
+    fs::write(path, serde_json::to_string(&state)? + "\n")
+}
+
+/// A generator that carries on from the state written by [`save`].
+pub fn load(path: &Path) -> io::Result<ChaCha12Rng> {
+    let state: RngState = serde_json::from_str(&fs::read_to_string(path)?)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    let mut rng: ChaCha12Rng = rand::SeedableRng::from_seed(state.seed);
+    rng.set_stream(state.stream);
+    rng.set_word_pos(state.word_pos);
+    Ok(rng)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use droll::Dice;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_saved_state_continues_the_sequence() {
+        let path = std::env::temp_dir().join(format!("droll-rng-state-{}.json", std::process::id()));
+        let dice = Dice::parse("3d6!").unwrap();
+        let mut uninterrupted = ChaCha12Rng::seed_from_u64(334);
+        let expected: Vec<i32> = (0..20).map(|_| dice.roll_with(&mut uninterrupted)).collect();
+
+        let mut first = ChaCha12Rng::seed_from_u64(334);
+        let mut rolled: Vec<i32> = (0..7).map(|_| dice.roll_with(&mut first)).collect();
+        save(&first, &path).unwrap();
+        let mut resumed = load(&path).unwrap();
+        rolled.extend((7..20).map(|_| dice.roll_with(&mut resumed)));
+        assert_eq!(rolled, expected);
+
+        fs::write(&path, "not a state").unwrap();
+        let err = load(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
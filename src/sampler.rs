@@ -0,0 +1,64 @@
+use crate::Dice;
+use rand::Rng;
+
+/// Draws totals with exactly the probabilities of [`Dice::distribution_map`], using a
+/// cumulative distribution worked out once. Each sample is a single uniform draw and a
+/// binary search, however many dice are kept, dropped or rerolled.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiceSampler {
+    /// Every possible total, in ascending order
+    totals: Vec<i32>,
+    /// The probability that the total is at most the total at the same index
+    at_most: Vec<f64>,
+}
+
+impl DiceSampler {
+    /// A sampler for the totals of the dice.
+    pub fn new(dice: &Dice) -> Self {
+        let (totals, mut at_most): (Vec<i32>, Vec<f64>) = dice.cdf().points().unzip();
+        // Rounding can leave the last entry just short of certainty
+        if let Some(last) = at_most.last_mut() {
+            *last = 1.0;
+        }
+        DiceSampler { totals, at_most }
+    }
+
+    /// Draw one total.
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> i32 {
+        let draw: f64 = rng.random();
+        // The first total whose cumulative probability is above the draw
+        let index = self.at_most.partition_point(|&probability| probability <= draw);
+        self.totals[index.min(self.totals.len() - 1)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_empirical_distribution_matches_exact() {
+        let dice = Dice::parse("4d6dl1").unwrap();
+        let sampler = dice.sampler();
+        let mut rng = StdRng::seed_from_u64(254);
+        let samples = 200_000;
+        let mut counts: BTreeMap<i32, u32> = BTreeMap::new();
+        for _ in 0..samples {
+            *counts.entry(sampler.sample(&mut rng)).or_insert(0) += 1;
+        }
+
+        let exact = dice.distribution_map();
+        assert!(counts.keys().all(|total| exact.contains_key(total)));
+        for (total, probability) in exact {
+            let observed = f64::from(counts.get(&total).copied().unwrap_or(0)) / f64::from(samples);
+            assert!((observed - probability).abs() < 0.005, "{}: {} vs {}", total, observed, probability);
+        }
+
+        // A single possible total is always drawn
+        let flat = Dice::parse("1d1+4").unwrap().sampler();
+        assert_eq!(flat.sample(&mut rng), 5);
+    }
+}
@@ -0,0 +1,205 @@
+use crate::{Dice, DiceError, DiceRollError};
+use rand::RngCore;
+use std::collections::VecDeque;
+
+/// How a single draw picks a face of the die being scripted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Draw {
+    /// A face from 1 to the number of sides
+    Sides(u8),
+    /// One of the faces of a custom die, drawn by its position
+    Custom(Vec<i32>),
+    /// A tens d10 and then a units d10, read as 1 to 100
+    Percentile,
+}
+
+impl Draw {
+    fn of(term: &Dice) -> Self {
+        match &term.custom_faces {
+            Some(faces) => Draw::Custom(faces.clone()),
+            None if term.percentile => Draw::Percentile,
+            None => Draw::Sides(term.sides),
+        }
+    }
+}
+
+/// The random value that makes `random_range` pick `index` (from 0) out of `range`
+/// possibilities. The range is mapped onto a `u32` by taking the high half of
+/// `value * range`, so the smallest value for each index lands on it exactly.
+fn value_for(index: u32, range: u32) -> u32 {
+    (u64::from(index) << 32).div_ceil(u64::from(range)) as u32
+}
+
+/// A random number generator that makes dice show a predetermined sequence of faces,
+/// for scripted demos and tests.
+///
+/// Point it at the dice with [`ScriptedRng::set_die`] before rolling them. Every die
+/// drawn, including rerolls and explosions, takes the next face of the script in
+/// order. A script that runs out, or a face the die cannot show, is reported by
+/// [`ScriptedRng::check`] after the roll, since a random number generator cannot
+/// fail part way through.
+///
+/// ```
+/// use droll::{Dice, ScriptedRng};
+///
+/// let dice = Dice::parse("3d6+1").unwrap();
+/// let mut rng = ScriptedRng::new([6, 2, 4]);
+/// rng.set_die(&dice).unwrap();
+/// assert_eq!(dice.roll_with(&mut rng), 13);
+/// assert!(rng.check().is_ok());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptedRng {
+    faces: VecDeque<i32>,
+    draw: Draw,
+    /// The units digit still to give for a percentile face
+    pending: Option<u32>,
+    error: Option<DiceRollError>,
+}
+
+impl ScriptedRng {
+    /// A generator giving `faces` in order, to dice with six sides until
+    /// [`ScriptedRng::set_die`] says otherwise.
+    pub fn new(faces: impl IntoIterator<Item = i32>) -> Self {
+        ScriptedRng {
+            faces: faces.into_iter().collect(),
+            draw: Draw::Sides(6),
+            pending: None,
+            error: None,
+        }
+    }
+
+    /// Script the faces of `dice` with the faces still to come. Every term must roll
+    /// the same kind of die, so that each face is read the same way, and dice whose
+    /// sides are rolled or that are drawn without replacement cannot be scripted.
+    pub fn set_die(&mut self, dice: &Dice) -> Result<(), DiceError> {
+        let invalid = |reason| DiceError::InvalidScript {
+            spec: dice.to_string(),
+            reason,
+        };
+        let draw = Draw::of(dice);
+        for term in std::iter::once(dice).chain(&dice.extra_terms) {
+            if term.sides_roll.is_some() {
+                return Err(invalid("the sides of the dice are rolled"));
+            }
+            if term.no_replacement {
+                return Err(invalid("the dice are drawn without replacement"));
+            }
+            if Draw::of(term) != draw {
+                return Err(invalid("every term must roll the same kind of die"));
+            }
+        }
+        self.draw = draw;
+        self.pending = None;
+        Ok(())
+    }
+
+    /// The faces of the script not drawn yet.
+    pub fn remaining(&self) -> usize {
+        self.faces.len()
+    }
+
+    /// Whether every die drawn so far showed its scripted face: an error when the
+    /// script ran out or gave a face the die cannot show.
+    pub fn check(&self) -> Result<(), DiceRollError> {
+        self.error.clone().map_or(Ok(()), Err)
+    }
+
+    /// The random value for the next face of the script, remembering the first error.
+    fn next_face(&mut self) -> u32 {
+        if let Some(units) = self.pending.take() {
+            return value_for(units, 10);
+        }
+        let Some(face) = self.faces.pop_front() else {
+            self.error.get_or_insert(DiceRollError::ScriptExhausted);
+            return 0;
+        };
+        let index = match &self.draw {
+            Draw::Sides(sides) => (1..=i32::from(*sides)).contains(&face).then(|| (face - 1) as u32),
+            Draw::Custom(faces) => faces.iter().position(|&shown| shown == face).map(|index| index as u32),
+            Draw::Percentile => (1..=100).contains(&face).then(|| {
+                // 00 and 0 together read as 100
+                let face = (face % 100) as u32;
+                self.pending = Some(face % 10);
+                face / 10
+            }),
+        };
+        let range = match &self.draw {
+            Draw::Sides(sides) => u32::from(*sides),
+            Draw::Custom(faces) => faces.len() as u32,
+            Draw::Percentile => 10,
+        };
+        match index {
+            Some(index) => value_for(index, range),
+            None => {
+                self.error.get_or_insert(DiceRollError::ScriptedFace { face });
+                0
+            }
+        }
+    }
+}
+
+impl RngCore for ScriptedRng {
+    fn next_u32(&mut self) -> u32 {
+        self.next_face()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        u64::from(self.next_u32())
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        rand::rand_core::impls::fill_bytes_via_next(self, dest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DieRegistry;
+
+    #[test]
+    fn test_scripted_faces_are_rolled_in_order() {
+        let dice = Dice::parse("4d6r1!").unwrap();
+        // The 1 is rerolled into a 6, which explodes into a 3
+        let mut rng = ScriptedRng::new([5, 1, 6, 3, 2, 4, 4]);
+        rng.set_die(&dice).unwrap();
+        let result = dice.roll_detailed_with(&mut rng);
+        let faces: Vec<i32> = result.dice.iter().map(|die| die.face).collect();
+        assert_eq!(faces, vec![5, 6, 3, 2, 4]);
+        assert_eq!(result.total, 20);
+        assert_eq!(rng.remaining(), 1);
+        assert!(rng.check().is_ok());
+
+        let mut registry = DieRegistry::new();
+        registry.insert("fate", vec![-1, 0, 1]).unwrap();
+        let fate = Dice::parse_with("4dfate", &registry).unwrap();
+        let percentile = Dice::parse("2d%").unwrap();
+        let mut rng = ScriptedRng::new([1, -1, 0, 1, 100, 37]);
+        rng.set_die(&fate).unwrap();
+        assert_eq!(fate.roll_with(&mut rng), 1);
+        rng.set_die(&percentile).unwrap();
+        assert_eq!(percentile.roll_with(&mut rng), 137);
+        assert!(rng.check().is_ok());
+    }
+
+    #[test]
+    fn test_script_errors() {
+        let dice = Dice::parse("3d6").unwrap();
+        let mut rng = ScriptedRng::new([2, 3]);
+        rng.set_die(&dice).unwrap();
+        dice.roll_with(&mut rng);
+        assert_eq!(rng.check(), Err(DiceRollError::ScriptExhausted));
+
+        let mut rng = ScriptedRng::new([2, 7, 3]);
+        rng.set_die(&dice).unwrap();
+        dice.roll_with(&mut rng);
+        assert_eq!(rng.check(), Err(DiceRollError::ScriptedFace { face: 7 }));
+
+        let mut rng = ScriptedRng::new([]);
+        for spec in ["1d6 + 1d8", "1d(1d6)"] {
+            assert!(matches!(rng.set_die(&Dice::parse(spec).unwrap()), Err(DiceError::InvalidScript { .. })), "{}", spec);
+        }
+        assert!(rng.set_die(&Dice::parse("2d6>=5 + 1d6+1").unwrap()).is_ok());
+    }
+}
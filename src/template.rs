@@ -0,0 +1,138 @@
+use droll::RollResult;
+
+/// A value from each roll that a template can fill in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Spec,
+    Label,
+    Total,
+    Rolls,
+    Modifier,
+}
+
+impl Field {
+    /// The field named by a placeholder, without its braces.
+    fn from_name(name: &str) -> Option<Field> {
+        match name {
+            "spec" => Some(Field::Spec),
+            "label" => Some(Field::Label),
+            "total" => Some(Field::Total),
+            "rolls" => Some(Field::Rolls),
+            "modifier" => Some(Field::Modifier),
+            _ => None,
+        }
+    }
+}
+
+/// One piece of a template: text written as it is, or a field filled in from each roll.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    Text(String),
+    Field(Field),
+}
+
+/// A user-supplied output line such as `{spec} => {total} {rolls}`, filled in for each roll.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    pieces: Vec<Piece>,
+}
+
+impl Template {
+    /// Parse a template with `{spec}`, `{label}`, `{total}`, `{rolls}` and `{modifier}`
+    /// placeholders, where `{{` and `}}` stand for literal braces. Any other placeholder
+    /// or an unmatched brace is an error.
+    pub fn parse(text: &str) -> Result<Template, String> {
+        let mut pieces = Vec::new();
+        let mut literal = String::new();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("Invalid template '{}': unclosed '{{' (write '{{{{' for a brace)", text)),
+                        }
+                    }
+                    let field = Field::from_name(&name).ok_or_else(|| {
+                        format!(
+                            "Invalid template '{}': unknown placeholder '{{{}}}' (use spec, label, total, rolls or modifier)",
+                            text, name
+                        )
+                    })?;
+                    if !literal.is_empty() {
+                        pieces.push(Piece::Text(std::mem::take(&mut literal)));
+                    }
+                    pieces.push(Piece::Field(field));
+                }
+                '}' => return Err(format!("Invalid template '{}': unmatched '}}' (write '}}}}' for a brace)", text)),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            pieces.push(Piece::Text(literal));
+        }
+        Ok(Template { pieces })
+    }
+
+    /// Fill in the template for a roll of `spec`. The label is empty for an unlabeled
+    /// roll, and the rolls are the faces of every die separated by commas.
+    pub fn render(&self, spec: &str, label: Option<&str>, result: &RollResult) -> String {
+        self.pieces
+            .iter()
+            .map(|piece| match piece {
+                Piece::Text(text) => text.clone(),
+                Piece::Field(Field::Spec) => spec.to_string(),
+                Piece::Field(Field::Label) => label.unwrap_or_default().to_string(),
+                Piece::Field(Field::Total) => result.total.to_string(),
+                Piece::Field(Field::Rolls) => {
+                    let faces: Vec<String> = result.dice.iter().map(|die| die.face.to_string()).collect();
+                    faces.join(",")
+                }
+                Piece::Field(Field::Modifier) => result.modifier.to_string(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use droll::Dice;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn test_template_arranges_fields() {
+        let dice = Dice::parse("3d6+2").unwrap();
+        let result = dice.roll_detailed_with(&mut StdRng::seed_from_u64(270));
+        let faces: Vec<String> = result.dice.iter().map(|die| die.face.to_string()).collect();
+
+        let template = Template::parse("{total} <- {spec} [{rolls}] {{mod {modifier}}}").unwrap();
+        assert_eq!(
+            template.render("3d6+2", None, &result),
+            format!("{} <- 3d6+2 [{}] {{mod 2}}", result.total, faces.join(","))
+        );
+        let labeled = Template::parse("{label}={total}").unwrap();
+        assert_eq!(labeled.render("fire: 3d6+2", Some("fire"), &result), format!("fire={}", result.total));
+    }
+
+    #[test]
+    fn test_template_rejects_unknown_placeholders() {
+        let err = Template::parse("{spec}: {sum}").unwrap_err();
+        assert!(err.contains("unknown placeholder '{sum}'"), "{}", err);
+        assert!(Template::parse("{total").is_err());
+        assert!(Template::parse("total}").is_err());
+        assert!(Template::parse("{}").is_err());
+    }
+}